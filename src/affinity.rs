@@ -0,0 +1,181 @@
+use crate::value::Value;
+
+// a column's affinity is the type sqlite prefers for its values. it comes from
+// the declared type, and it decides how values are converted when they're
+// compared with the column: AlbumId = '5' compares the integer column with
+// the number 5, not the text '5'.
+//
+// BLOB affinity never converts anything, but it's still an affinity: a
+// column declared without a type compared with a TEXT column stays as it is.
+// expressions other than columns, like literals, +x or what a function
+// returns, have none at all and take the other side's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+    #[default]
+    None,
+}
+
+impl Affinity {
+    // the rules sqlite uses, in this order:
+    // 1. INT anywhere in the type - INTEGER
+    // 2. CHAR, CLOB or TEXT - TEXT
+    // 3. BLOB, or no type at all - BLOB
+    // 4. REAL, FLOA or DOUB - REAL
+    // 5. anything else - NUMERIC
+    //
+    // so "VARCHAR(20)" is TEXT, "DOUBLE PRECISION" is REAL and, oddly,
+    // "FLOATING POINT" is INTEGER.
+    pub fn from_type(declared: &str) -> Affinity {
+        let declared = declared.to_ascii_uppercase();
+        let has = |word: &str| declared.contains(word);
+
+        if has("INT") {
+            Affinity::Integer
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            Affinity::Text
+        } else if has("BLOB") || declared.trim().is_empty() {
+            Affinity::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+
+    // the affinity both sides of a comparison are converted with. a side with
+    // a numeric affinity makes it NUMERIC, otherwise a side with no affinity
+    // takes the other one's. TEXT against TEXT or BLOB doesn't convert.
+    pub fn comparison(left: Affinity, right: Affinity) -> Affinity {
+        match (left, right) {
+            (left, right) if left.is_numeric() || right.is_numeric() => Affinity::Numeric,
+            (Affinity::None, other) | (other, Affinity::None) => other,
+            _ => Affinity::Blob,
+        }
+    }
+
+    // whether converting the values of an expression with this affinity to
+    // `other` can change them. a column keeps its values in its own affinity
+    // already, e.g. an INTEGER column only holds text that isn't a number.
+    pub fn converts(self, other: Affinity) -> bool {
+        match other {
+            Affinity::Blob | Affinity::None => false,
+            Affinity::Text => self != Affinity::Text,
+            _ => !self.is_numeric(),
+        }
+    }
+
+    pub fn apply(self, value: Value) -> Value {
+        match (self, value) {
+            (Affinity::Text, value @ (Value::Integer(_) | Value::Float(_))) => {
+                Value::Text(value.to_text().unwrap_or_default())
+            }
+            (Affinity::Text | Affinity::Blob | Affinity::None, value) => value,
+            (_, Value::Text(text)) => parse_number(&text).unwrap_or(Value::Text(text)),
+            (Affinity::Real, Value::Integer(i)) => Value::Float(i as f64),
+            (_, value) => value,
+        }
+    }
+}
+
+// text that is a number and nothing else, apart from spaces around it. unlike
+// arithmetic, '12abc' isn't converted, and neither is hex.
+fn parse_number(text: &str) -> Option<Value> {
+    let number = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let bytes = number.as_bytes();
+    let mut end = 0;
+    let digits = |end: &mut usize| {
+        let start = *end;
+        while *end < bytes.len() && bytes[*end].is_ascii_digit() {
+            *end += 1;
+        }
+        *end - start
+    };
+
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let mut mantissa = digits(&mut end);
+    if bytes.get(end) == Some(&b'.') {
+        end += 1;
+        mantissa += digits(&mut end);
+    }
+    if mantissa == 0 {
+        return None;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        end += 1;
+        if matches!(bytes.get(end), Some(b'+' | b'-')) {
+            end += 1;
+        }
+        if digits(&mut end) == 0 {
+            return None;
+        }
+    }
+    if end != bytes.len() {
+        return None;
+    }
+
+    match number.parse::<i64>() {
+        Ok(i) => Some(Value::Integer(i)),
+        Err(_) => number.parse::<f64>().ok().map(Value::Float),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_from_type() {
+        assert_eq!(Affinity::from_type("INTEGER"), Affinity::Integer);
+        assert_eq!(Affinity::from_type("tinyint"), Affinity::Integer);
+        assert_eq!(Affinity::from_type("NVARCHAR(120)"), Affinity::Text);
+        assert_eq!(Affinity::from_type("CLOB"), Affinity::Text);
+        assert_eq!(Affinity::from_type(""), Affinity::Blob);
+        assert_eq!(Affinity::from_type("BLOB"), Affinity::Blob);
+        assert_eq!(Affinity::from_type("DOUBLE PRECISION"), Affinity::Real);
+        assert_eq!(Affinity::from_type("FLOATING POINT"), Affinity::Integer);
+        assert_eq!(Affinity::from_type("NUMERIC(10,2)"), Affinity::Numeric);
+        assert_eq!(Affinity::from_type("DATETIME"), Affinity::Numeric);
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Affinity::Numeric.apply(text("5")), Value::Integer(5));
+        assert_eq!(Affinity::Integer.apply(text(" -5 ")), Value::Integer(-5));
+        assert_eq!(Affinity::Numeric.apply(text("2.5e1")), Value::Float(25.0));
+        assert_eq!(Affinity::Real.apply(Value::Integer(5)), Value::Float(5.0));
+        assert_eq!(Affinity::Numeric.apply(text("12abc")), text("12abc"));
+        assert_eq!(Affinity::Numeric.apply(text("0x5")), text("0x5"));
+        assert_eq!(Affinity::Numeric.apply(text("inf")), text("inf"));
+        assert_eq!(Affinity::Numeric.apply(text(".")), text("."));
+        assert_eq!(Affinity::Text.apply(Value::Integer(5)), text("5"));
+        assert_eq!(Affinity::Text.apply(Value::Float(0.5)), text("0.5"));
+        assert_eq!(Affinity::Blob.apply(text("5")), text("5"));
+    }
+
+    #[test]
+    fn test_comparison() {
+        use Affinity::*;
+        assert_eq!(Affinity::comparison(Integer, Blob), Numeric);
+        assert_eq!(Affinity::comparison(Text, Real), Numeric);
+        assert_eq!(Affinity::comparison(None, Text), Text);
+        assert_eq!(Affinity::comparison(Blob, Text), Blob);
+        assert_eq!(Affinity::comparison(Text, Text), Blob);
+        assert_eq!(Affinity::comparison(None, Blob), Blob);
+        assert_eq!(Affinity::comparison(Blob, Integer), Numeric);
+    }
+}
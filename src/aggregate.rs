@@ -0,0 +1,734 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufWriter, Write},
+    iter,
+    rc::Rc,
+};
+
+use crate::{
+    cell::{self, Row},
+    collation::Collation,
+    error::{Error, Result},
+    expr::Expr,
+    json,
+    sort::{self, SortKey},
+    spill::{self, RecordReader, TempFile},
+    value::Value,
+};
+
+// how many files the groups that don't fit in memory are split between
+const PARTITIONS: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+    JsonGroupArray,
+    JsonGroupObject,
+}
+
+impl Function {
+    // None if the name isn't an aggregate function. min and max with more than
+    // one argument are ordinary functions that work on a single row.
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Function>> {
+        let (function, arities): (Function, &[usize]) = match name.to_ascii_lowercase().as_str() {
+            "count" => (Function::Count, &[0, 1]),
+            "sum" => (Function::Sum, &[1]),
+            "total" => (Function::Total, &[1]),
+            "avg" => (Function::Avg, &[1]),
+            "min" if num_args < 2 => (Function::Min, &[1]),
+            "max" if num_args < 2 => (Function::Max, &[1]),
+            "group_concat" => (Function::GroupConcat, &[1, 2]),
+            "json_group_array" => (Function::JsonGroupArray, &[1]),
+            "json_group_object" => (Function::JsonGroupObject, &[2]),
+            _ => return Ok(None),
+        };
+
+        if !arities.contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        Ok(Some(function))
+    }
+}
+
+// one aggregate function call in a query, e.g. count(DISTINCT ArtistId)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub function: Function,
+    pub args: Vec<Expr>,
+    pub distinct: bool,
+}
+
+impl Aggregate {
+    pub fn new(function: Function, args: Vec<Expr>, distinct: bool) -> Result<Aggregate> {
+        if distinct && args.len() != 1 {
+            return Err(Error::Invalid(String::from(
+                "DISTINCT aggregates must have exactly one argument",
+            )));
+        }
+
+        Ok(Aggregate {
+            function,
+            args,
+            distinct,
+        })
+    }
+
+    pub fn bind(&self, parameters: &[Value]) -> Aggregate {
+        Aggregate {
+            args: self.args.iter().map(|arg| arg.bind(parameters)).collect(),
+            ..self.clone()
+        }
+    }
+
+    // min, max and DISTINCT compare values the way the argument asks to
+    fn collation(&self) -> Collation {
+        self.args
+            .first()
+            .and_then(Expr::collation)
+            .unwrap_or_default()
+    }
+
+    pub fn start(&self) -> State {
+        let accumulator = match self.function {
+            Function::Count => Accumulator::Count(0),
+            Function::Sum | Function::Total | Function::Avg => Accumulator::Sum(Sum::default()),
+            Function::Min | Function::Max => Accumulator::Extreme(None),
+            Function::GroupConcat => Accumulator::Concat(None),
+            Function::JsonGroupArray | Function::JsonGroupObject => Accumulator::Json(Ok(vec![])),
+        };
+
+        State {
+            accumulator,
+            seen: self.distinct.then(HashSet::new),
+        }
+    }
+
+    // adds one row's arguments to the state. returns true if the row became
+    // the new min or max. `size` grows by whatever extra memory the state
+    // now takes up.
+    pub fn step(&self, state: &mut State, args: &[Value], size: &mut usize) -> bool {
+        // every aggregate skips NULLs, apart from count(*) which has no
+        // arguments to look at, and json_group_array which turns them into
+        // JSON nulls. json_group_object only skips NULL labels.
+        if self.function != Function::JsonGroupArray && args.first().is_some_and(Value::is_null) {
+            return false;
+        }
+
+        if let Some(seen) = &mut state.seen {
+            let value = self.collation().fold(args[0].clone());
+            *size += spill::values_size(std::slice::from_ref(&value));
+            if !seen.insert(value) {
+                return false;
+            }
+        }
+
+        match &mut state.accumulator {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(sum) => sum.add(&args[0]),
+            Accumulator::Extreme(best) => {
+                let wanted = match self.function {
+                    Function::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                // on a tie the first value stays
+                if best
+                    .as_ref()
+                    .is_none_or(|best| self.collation().compare(&args[0], best) == wanted)
+                {
+                    *best = Some(args[0].clone());
+                    return true;
+                }
+            }
+            Accumulator::Concat(text) => {
+                let value = args[0].to_text().unwrap_or_default();
+                *size += value.len();
+                match text {
+                    None => *text = Some(value),
+                    Some(text) => {
+                        // a NULL separator is the same as an empty one
+                        match args.get(1) {
+                            Some(separator) => {
+                                text.push_str(&separator.to_text().unwrap_or_default())
+                            }
+                            None => text.push(','),
+                        }
+                        text.push_str(&value);
+                    }
+                }
+            }
+            // the first value that can't be JSON makes the result an error
+            Accumulator::Json(Ok(items)) => {
+                let value = match self.function {
+                    Function::JsonGroupObject => json::to_json(&args[1], self.is_json(1, &args[1]))
+                        .map(|value| format!("{}:{}", json::to_label(&args[0]), value)),
+                    _ => json::to_json(&args[0], self.is_json(0, &args[0])),
+                };
+                match value {
+                    Ok(value) => {
+                        *size += value.len();
+                        items.push(value);
+                    }
+                    Err(e) => state.accumulator = Accumulator::Json(Err(e.to_string())),
+                }
+            }
+            Accumulator::Json(Err(_)) => {}
+        }
+
+        false
+    }
+
+    pub fn finish(&self, state: &State) -> Result<Value> {
+        Ok(match &state.accumulator {
+            Accumulator::Count(n) => Value::Integer(*n),
+            Accumulator::Sum(sum) => match self.function {
+                Function::Sum => sum.sum()?,
+                Function::Total => Value::Float(sum.total()),
+                _ if sum.count == 0 => Value::Null,
+                _ => Value::Float(sum.total() / sum.count as f64),
+            },
+            Accumulator::Extreme(best) => best.clone().unwrap_or(Value::Null),
+            Accumulator::Concat(text) => text.clone().into(),
+            Accumulator::Json(Ok(items)) => Value::Text(match self.function {
+                Function::JsonGroupObject => format!("{{{}}}", items.join(",")),
+                _ => format!("[{}]", items.join(",")),
+            }),
+            Accumulator::Json(Err(message)) => return Err(Error::Invalid(message.clone())),
+        })
+    }
+
+    // whether an argument is JSON from a JSON function, which is nested
+    // rather than turned into a string
+    fn is_json(&self, i: usize, value: &Value) -> bool {
+        self.args.get(i).is_some_and(|arg| arg.returns_json(value))
+    }
+}
+
+pub struct State {
+    accumulator: Accumulator,
+    // the values seen so far, for DISTINCT
+    seen: Option<HashSet<Value>>,
+}
+
+enum Accumulator {
+    Count(i64),
+    // sum, total and avg
+    Sum(Sum),
+    // min and max
+    Extreme(Option<Value>),
+    // group_concat
+    Concat(Option<String>),
+    // json_group_array and json_group_object: the JSON text of each element
+    // or member so far, or why there can't be a result
+    Json(std::result::Result<Vec<String>, String>),
+}
+
+// sum() of integers is an integer, and overflowing it is an error. as soon as
+// a value that isn't an integer turns up, the sum switches to floating point
+// instead, adding with Kahan-Babuska-Neumaier summation to keep the rounding
+// errors down - the same way sqlite does it, so the results match to the bit.
+#[derive(Default)]
+struct Sum {
+    count: i64,
+    integer: i64,
+    float: f64,
+    // the rounding error left over from the float sum
+    error: f64,
+    approximate: bool,
+    overflow: bool,
+}
+
+impl Sum {
+    fn add(&mut self, value: &Value) {
+        self.count += 1;
+
+        match (sum_operand(value), self.approximate) {
+            (Value::Integer(i), false) => match self.integer.checked_add(i) {
+                Some(sum) => self.integer = sum,
+                None => {
+                    self.overflow = true;
+                    self.start_float();
+                    self.add_integer(i);
+                }
+            },
+            (Value::Integer(i), true) => self.add_integer(i),
+            (value, approximate) => {
+                if !approximate {
+                    self.start_float();
+                } else {
+                    // a float after an overflow makes the result a float
+                    // rather than an error
+                    self.overflow = false;
+                }
+                self.add_float(value.to_float().unwrap_or(0.0));
+            }
+        }
+    }
+
+    fn start_float(&mut self) {
+        self.approximate = true;
+        (self.float, self.error) = split_integer(self.integer);
+    }
+
+    fn add_float(&mut self, f: f64) {
+        let sum = self.float + f;
+        if self.float.abs() > f.abs() {
+            self.error += (self.float - sum) + f;
+        } else {
+            self.error += (f - sum) + self.float;
+        }
+        self.float = sum;
+    }
+
+    // large integers don't fit in a float exactly, so they're added in two
+    // parts
+    fn add_integer(&mut self, i: i64) {
+        let (big, small) = split_integer(i);
+        self.add_float(big);
+        if small != 0.0 {
+            self.add_float(small);
+        }
+    }
+
+    fn sum(&self) -> Result<Value> {
+        if self.count == 0 {
+            return Ok(Value::Null);
+        }
+
+        match (self.approximate, self.overflow) {
+            (false, _) => Ok(Value::Integer(self.integer)),
+            (true, true) => Err(Error::Invalid(String::from("integer overflow"))),
+            (true, false) => Ok(Value::Float(self.total())),
+        }
+    }
+
+    fn total(&self) -> f64 {
+        if !self.approximate {
+            self.integer as f64
+        } else if self.error.is_finite() {
+            self.float + self.error
+        } else {
+            self.float
+        }
+    }
+}
+
+// splits an integer into two floats that add up to it exactly
+fn split_integer(i: i64) -> (f64, f64) {
+    if !(-4503599627370496..4503599627370496).contains(&i) {
+        let small = i % 16384;
+        ((i - small) as f64, small as f64)
+    } else {
+        (i as f64, 0.0)
+    }
+}
+
+// text counts as an integer if all of it is one, e.g. ' 3 ', and anything
+// else that isn't already a number is added as a float: 'abc' adds 0.0
+fn sum_operand(value: &Value) -> Value {
+    match value {
+        Value::Integer(_) | Value::Float(_) => value.clone(),
+        Value::Text(text) if let Ok(i) = text.trim().parse::<i64>() => Value::Integer(i),
+        _ => Value::Float(value.to_float().unwrap_or(0.0)),
+    }
+}
+
+// groups, hash joins and IN subqueries look values up by this encoding of
+// them. values that compare as equal have to encode the same, so 1.0 becomes
+// 1 and text is folded the way its collation compares it.
+pub fn encode_key(values: &[Value], collations: &[Collation]) -> Vec<u8> {
+    let values: Vec<Value> = values
+        .iter()
+        .zip(collations)
+        .map(|(value, collation)| match (value, collation) {
+            (Value::Float(f), _)
+                if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 =>
+            {
+                Value::Integer(*f as i64)
+            }
+            (value, collation) => collation.fold(value.clone()),
+        })
+        .collect();
+
+    cell::encode_record(&values)
+}
+
+// what an aggregate query groups its rows by, and what it works out for each
+// group
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grouping {
+    pub group_by: Vec<Expr>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+impl Grouping {
+    pub fn bind(&self, parameters: &[Value]) -> Grouping {
+        Grouping {
+            group_by: self
+                .group_by
+                .iter()
+                .map(|expr| expr.bind(parameters))
+                .collect(),
+            aggregates: self
+                .aggregates
+                .iter()
+                .map(|aggregate| aggregate.bind(parameters))
+                .collect(),
+        }
+    }
+
+    fn collations(&self) -> Vec<Collation> {
+        self.group_by
+            .iter()
+            .map(|expr| expr.collation().unwrap_or_default())
+            .collect()
+    }
+
+    // how many argument values one row has, across all the aggregates
+    fn num_args(&self) -> usize {
+        self.aggregates
+            .iter()
+            .map(|aggregate| aggregate.args.len())
+            .sum()
+    }
+
+    fn entry(&self, row: Vec<Value>) -> Result<Entry> {
+        Ok(Entry {
+            key: self
+                .group_by
+                .iter()
+                .map(|expr| expr.eval(&row))
+                .collect::<Result<_>>()?,
+            args: self
+                .aggregates
+                .iter()
+                .flat_map(|aggregate| &aggregate.args)
+                .map(|arg| arg.eval(&row))
+                .collect::<Result<_>>()?,
+            sample: row,
+        })
+    }
+}
+
+// groups the input rows and works out the aggregates for each group. each
+// output row is the group's sample row followed by the aggregates' results,
+// which is what the rest of the query is compiled against: a column outside
+// of an aggregate takes its value from the sample row. that's the first row
+// of the group, or the row that set the min or max if the query has one.
+//
+// the groups come out sorted by the GROUP BY values, like they do in sqlite.
+// without a GROUP BY there is exactly one group, even when there are no rows.
+pub fn grouped(
+    input: impl Iterator<Item = Result<Vec<Value>>>,
+    grouping: Grouping,
+    width: usize,
+    memory: usize,
+) -> impl Iterator<Item = Result<Vec<Value>>> {
+    let keys = grouping
+        .collations()
+        .into_iter()
+        .map(|collation| SortKey {
+            descending: false,
+            nulls_first: true,
+            collation,
+        })
+        .collect();
+
+    let grouping = Rc::new(grouping);
+    let mut input = Some(input);
+    let mut output: Option<Groups> = None;
+
+    let groups = iter::from_fn(move || {
+        if output.is_none() {
+            let mut table = GroupTable::new(grouping.clone(), 0, memory);
+            for row in input.take()? {
+                let result = row.and_then(|row| table.push(grouping.entry(row)?));
+                if let Err(e) = result {
+                    return Some(Err(e));
+                }
+            }
+
+            if grouping.group_by.is_empty() && table.groups.is_empty() {
+                let group = Group::new(&grouping, vec![], vec![Value::Null; width]);
+                table.groups.insert(vec![], group);
+            }
+
+            match table.finish() {
+                Ok(groups) => output = Some(groups),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        output.as_mut()?.next()
+    });
+
+    sort::sorted(groups, keys, memory).map(|row| row.map(|row| row.values))
+}
+
+// finished groups, each with its GROUP BY values to sort by
+type Groups = Box<dyn Iterator<Item = Result<(Vec<Value>, Row)>>>;
+
+// one input row, split into the parts grouping needs
+struct Entry {
+    key: Vec<Value>,
+    // the arguments of every aggregate, one after the other
+    args: Vec<Value>,
+    sample: Vec<Value>,
+}
+
+struct Group {
+    key: Vec<Value>,
+    sample: Vec<Value>,
+    states: Vec<State>,
+}
+
+impl Group {
+    fn new(grouping: &Grouping, key: Vec<Value>, sample: Vec<Value>) -> Group {
+        Group {
+            key,
+            sample,
+            states: grouping.aggregates.iter().map(Aggregate::start).collect(),
+        }
+    }
+
+    // returns how much more memory the group takes up afterwards
+    fn step(&mut self, grouping: &Grouping, args: &[Value], sample: Option<Vec<Value>>) -> usize {
+        let mut size = 0;
+        let mut new_sample = false;
+
+        let mut args = args;
+        for (aggregate, state) in grouping.aggregates.iter().zip(&mut self.states) {
+            let (these, rest) = args.split_at(aggregate.args.len());
+            new_sample |= aggregate.step(state, these, &mut size);
+            args = rest;
+        }
+
+        if new_sample && let Some(sample) = sample {
+            self.sample = sample;
+        }
+
+        size
+    }
+
+    fn finish(self, grouping: &Grouping) -> Result<(Vec<Value>, Row)> {
+        let mut values = self.sample;
+        for (aggregate, state) in grouping.aggregates.iter().zip(&self.states) {
+            values.push(aggregate.finish(state)?);
+        }
+
+        Ok((self.key, Row { rowid: 0, values }))
+    }
+}
+
+// hash aggregation: each row is added to its group in a hash table. once the
+// table takes up more than the memory budget, rows belonging to groups we
+// haven't seen yet are written to one of several partition files instead,
+// picked by the hash of their key. every group ends up whole in either the
+// table or one partition, and each partition is then grouped on its own, with
+// a different hash in case it's still too big.
+struct GroupTable {
+    grouping: Rc<Grouping>,
+    collations: Vec<Collation>,
+    // how many times the rows have been partitioned already
+    level: u32,
+    memory: usize,
+    // roughly how much memory `groups` takes up
+    size: usize,
+    groups: HashMap<Vec<u8>, Group>,
+    partitions: Vec<(TempFile, BufWriter<File>)>,
+}
+
+impl GroupTable {
+    fn new(grouping: Rc<Grouping>, level: u32, memory: usize) -> GroupTable {
+        GroupTable {
+            collations: grouping.collations(),
+            grouping,
+            level,
+            memory,
+            size: 0,
+            groups: HashMap::new(),
+            partitions: vec![],
+        }
+    }
+
+    fn push(&mut self, entry: Entry) -> Result<()> {
+        let hash_key = encode_key(&entry.key, &self.collations);
+
+        if let Some(group) = self.groups.get_mut(&hash_key) {
+            self.size += group.step(&self.grouping, &entry.args, Some(entry.sample));
+            return Ok(());
+        }
+
+        if self.size > self.memory {
+            return self.spill(&hash_key, entry);
+        }
+
+        self.size += size_of::<Group>()
+            + hash_key.len()
+            + spill::values_size(&entry.key)
+            + spill::values_size(&entry.sample);
+
+        let mut group = Group::new(&self.grouping, entry.key, entry.sample);
+        self.size += group.step(&self.grouping, &entry.args, None);
+        self.groups.insert(hash_key, group);
+        Ok(())
+    }
+
+    // each entry is written to its partition as a record of its key, its
+    // arguments and its sample row
+    fn spill(&mut self, hash_key: &[u8], entry: Entry) -> Result<()> {
+        if self.partitions.is_empty() {
+            for _ in 0..PARTITIONS {
+                let partition = TempFile::create()?;
+                let writer = BufWriter::new(partition.file.try_clone()?);
+                self.partitions.push((partition, writer));
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.level.hash(&mut hasher);
+        hash_key.hash(&mut hasher);
+        let (_, writer) = &mut self.partitions[(hasher.finish() % PARTITIONS) as usize];
+
+        let mut values = entry.key;
+        values.extend(entry.args);
+        values.extend(entry.sample);
+        spill::write_record(writer, &values)?;
+        Ok(())
+    }
+
+    // the finished groups in memory come first, then each partition's as it
+    // gets grouped
+    fn finish(self) -> Result<Groups> {
+        let grouping = self.grouping;
+        let (level, memory) = (self.level, self.memory);
+
+        let mut partitions = vec![];
+        for (partition, mut writer) in self.partitions {
+            writer.flush()?;
+            partitions.push(partition);
+        }
+
+        let finished = {
+            let grouping = grouping.clone();
+            self.groups
+                .into_values()
+                .map(move |group| group.finish(&grouping))
+        };
+
+        let regrouped = partitions.into_iter().flat_map(move |partition| {
+            match regroup(partition, grouping.clone(), level + 1, memory) {
+                Ok(groups) => groups,
+                Err(e) => Box::new(iter::once(Err(e))),
+            }
+        });
+
+        Ok(Box::new(finished.chain(regrouped)))
+    }
+}
+
+fn regroup(
+    partition: TempFile,
+    grouping: Rc<Grouping>,
+    level: u32,
+    memory: usize,
+) -> Result<Groups> {
+    let (key_len, args_len) = (grouping.group_by.len(), grouping.num_args());
+
+    let mut reader = RecordReader::new(partition)?;
+    let mut table = GroupTable::new(grouping, level, memory);
+
+    while let Some(mut values) = reader.read()? {
+        if values.len() < key_len + args_len {
+            return Err(spill::corrupt().into());
+        }
+
+        let sample = values.split_off(key_len + args_len);
+        let args = values.split_off(key_len);
+        table.push(Entry {
+            key: values,
+            args,
+            sample,
+        })?;
+    }
+
+    table.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum(values: &[Value]) -> Sum {
+        let mut sum = Sum::default();
+        for value in values {
+            sum.add(value);
+        }
+        sum
+    }
+
+    #[test]
+    fn test_sum() {
+        // integers stay integers, and so does text that is one
+        let result = sum(&[Value::Integer(2), Value::Text(String::from(" 3 "))]);
+        assert_eq!(result.sum().unwrap(), Value::Integer(5));
+        assert_eq!(result.total(), 5.0);
+
+        // anything else makes it a float
+        let result = sum(&[Value::Integer(2), Value::Text(String::from("abc"))]);
+        assert!(matches!(result.sum().unwrap(), Value::Float(2.0)));
+
+        // the rounding error is added back at the end
+        let result = sum(&[Value::Float(0.1), Value::Float(0.2), Value::Float(0.3)]);
+        assert_eq!(result.sum().unwrap(), Value::Float(0.6));
+
+        // overflowing is an error, unless a float comes along later
+        let mut result = sum(&[Value::Integer(i64::MAX), Value::Integer(1)]);
+        assert!(result.sum().is_err());
+        result.add(&Value::Integer(-5));
+        assert!(result.sum().is_err());
+        result.add(&Value::Float(1.5));
+        assert_eq!(result.sum().unwrap(), Value::Float(9.223372036854776e18));
+
+        assert_eq!(sum(&[]).sum().unwrap(), Value::Null);
+        assert_eq!(sum(&[]).total(), 0.0);
+    }
+
+    #[test]
+    fn test_equal_values_have_equal_keys() {
+        let key = |value: Value, collation| encode_key(&[value], &[collation]);
+
+        assert_eq!(
+            key(Value::Integer(1), Collation::Binary),
+            key(Value::Float(1.0), Collation::Binary)
+        );
+        assert_ne!(
+            key(Value::Integer(1), Collation::Binary),
+            key(Value::Text(String::from("1")), Collation::Binary)
+        );
+        assert_eq!(
+            key(Value::Text(String::from("ABC")), Collation::NoCase),
+            key(Value::Text(String::from("abc")), Collation::NoCase)
+        );
+        assert_ne!(
+            key(Value::Text(String::from("ABC")), Collation::Binary),
+            key(Value::Text(String::from("abc")), Collation::Binary)
+        );
+        assert_eq!(
+            key(Value::Text(String::from("a  ")), Collation::RTrim),
+            key(Value::Text(String::from("a")), Collation::RTrim)
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+
+use crate::value::Value;
+
+// The parser turns SQL text into these types. They describe what the query
+// says, not how to run it - e.g. a column is still just a name here, it's up
+// to the query layer to work out which table it belongs to.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+}
+
+// [WITH <tables>] SELECT [DISTINCT] <columns> FROM <table> WHERE <condition>
+// GROUP BY <exprs> HAVING <condition> WINDOW <windows> [UNION SELECT ...]
+// ORDER BY <terms> LIMIT <limit> OFFSET <offset>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub with: Vec<Cte>,
+    // SELECT DISTINCT, which drops rows that repeat an earlier one
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    // the windows named by the WINDOW clause
+    pub windows: Vec<NamedWindow>,
+    // the SELECTs this one is combined with by UNION, INTERSECT or EXCEPT,
+    // in order. ORDER BY and LIMIT are then for the combined rows.
+    pub compound: Vec<Compound>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+}
+
+// UNION [ALL] | INTERSECT | EXCEPT <select>. the select has no WITH, ORDER BY
+// or LIMIT of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compound {
+    pub op: CompoundOperator,
+    pub select: Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl fmt::Display for CompoundOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CompoundOperator::Union => "UNION",
+            CompoundOperator::UnionAll => "UNION ALL",
+            CompoundOperator::Intersect => "INTERSECT",
+            CompoundOperator::Except => "EXCEPT",
+        })
+    }
+}
+
+// [RECURSIVE] name (columns) AS (select), one of the tables of a WITH clause.
+// a select that ends in `UNION [ALL] step`, where step reads the table
+// itself, makes a recursive table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    // empty when the table takes its column names from the select
+    pub columns: Vec<String>,
+    pub select: Box<Select>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    // SELECT *
+    Star,
+    // SELECT albums.*
+    TableStar(String),
+    // SELECT Title AS name. text is the expression exactly as it was written
+    // in the query, which becomes the column name when there's no alias.
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        text: String,
+    },
+}
+
+// WINDOW name AS (definition)
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedWindow {
+    pub name: String,
+    pub definition: WindowDefinition,
+}
+
+// OVER ([base] PARTITION BY <exprs> ORDER BY <terms> <frame>). OVER name is
+// written as a definition with just the base.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WindowDefinition {
+    // the named window this one adds to
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>,
+}
+
+// ROWS|RANGE|GROUPS BETWEEN <start> AND <end>, or just <start>, which ends at
+// the current row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameUnits {
+    // offsets count rows
+    Rows,
+    // offsets are differences between the values of the ORDER BY term
+    Range,
+    // offsets count groups of rows with equal ORDER BY values
+    Groups,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+// ORDER BY Title [COLLATE NOCASE] [ASC|DESC] [NULLS FIRST|LAST]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+    // None means the default: NULLs come first when ascending, last when
+    // descending
+    pub nulls_first: Option<bool>,
+}
+
+// LIMIT 10 OFFSET 20, which can also be written LIMIT 20, 10
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
+
+// FROM albums a JOIN artists USING (ArtistId), genres
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub table: TableOrSubquery,
+    pub joins: Vec<Join>,
+}
+
+// [NATURAL] [LEFT [OUTER] | INNER | CROSS] JOIN table [ON expr | USING (columns)].
+// a comma is an inner join without a constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub natural: bool,
+    pub table: TableOrSubquery,
+    pub constraint: Option<JoinConstraint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    // also CROSS JOIN, which only differs in that sqlite never changes the
+    // order it reads the tables in. we always read them in the order given.
+    Inner,
+    // a row of the left side with no match on the right is still returned,
+    // with NULLs for the right side's columns
+    Left,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+// FROM albums [AS] a
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableName {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableOrSubquery {
+    Table(TableName),
+    // FROM (SELECT ...) [AS] t, also known as a derived table
+    Subquery {
+        select: Box<Select>,
+        alias: Option<String>,
+    },
+    // a table-valued function, like FROM json_each(t.data) AS j
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        alias: Option<String>,
+    },
+}
+
+// CREATE [UNIQUE] INDEX name ON table (columns...) [WHERE condition]
+//
+// we never run these, but we need to understand the ones stored in
+// sqlite_master to know what each index contains
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    pub where_clause: Option<Expr>,
+}
+
+// ArtistId [COLLATE NOCASE] [ASC|DESC]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    // a value bound when the statement is run. parameters are numbered from 1,
+    // the same way sqlite numbers them.
+    Parameter(usize),
+    // Title or albums.Title
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        op: UnaryOperator,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    // x [NOT] BETWEEN low AND high
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    // x COLLATE NOCASE - compare x using a different collation
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    // x [NOT] IN (1, 2, 3)
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    // count(DISTINCT x). count(*) has no arguments. a call with OVER is a
+    // window function.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+        over: Option<Box<WindowDefinition>>,
+    },
+    // (SELECT ...) as a value: the first column of the first row it returns
+    Subquery(Box<Select>),
+    // x [NOT] IN (SELECT ...)
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<Select>,
+        negated: bool,
+    },
+    // EXISTS (SELECT ...). NOT EXISTS is NOT applied to this.
+    Exists(Box<Select>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    // IS and IS NOT are like = and != except that NULL IS NULL is true
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    // x -> '$.a' is the JSON at the path, x ->> '$.a' its SQL value
+    Extract,
+    ExtractValue,
+}
@@ -1,28 +1,674 @@
-use std::fs::File;
+use std::cmp::Ordering;
 
 use crate::{
-    cell::{self, Row},
+    cell::{self, BtreeKind, IndexCell, Row, RowFormat},
+    collation::Collation,
+    error::{Error, Result},
     page::Page,
+    pager::Pager,
+    value::Value,
+    varint::parse_varint,
 };
 
-// follow the cell references in interior pages and fetch values from
-// linked leaf pages
-pub fn traverse(file: &mut File, page_num: u32, page_size: u16, rows: &mut Vec<Row>) {
-    let page = Page::read(file, page_num, page_size);
+// a cursor over the rows of a table b-tree, in rowid order. it can jump
+// straight to a rowid and then move forwards or backwards from there, reading
+// only the pages on the way. nothing is read until the cursor is moved, and
+// only the pages on the path to the current row are held in memory, so walking
+// a huge table doesn't need much more memory than walking a small one.
+//
+// like IndexCursor, it keeps the path from the root down to the current row
+// as a stack of (page, i). on a leaf page i is the cell the cursor is on. on an
+// interior page i is the child we went down: the left child of cell i, or the
+// rightmost child when i == num_cells. when the stack is empty the cursor has
+// run off one end of the table.
+//
+// every interior cell has a rowid key, and its left child only holds rows with
+// a rowid <= that key. the cells are sorted, so finding the child that might
+// hold a rowid is a binary search over the page's cell pointers.
+pub struct TableCursor {
+    pager: Pager,
+    root_page: u32,
+    format: RowFormat,
+    stack: Vec<(Page, u16)>,
+}
+
+impl TableCursor {
+    pub fn new(pager: Pager, root_page: u32, format: RowFormat) -> TableCursor {
+        TableCursor {
+            pager,
+            root_page,
+            format,
+            stack: vec![],
+        }
+    }
+
+    // moves to the row with the smallest rowid
+    pub fn first(&mut self) -> Result<Option<Row>> {
+        self.stack.clear();
+        self.push(self.root_page, |_| 0)?;
+        self.settle_forward()
+    }
+
+    // moves to the row with the largest rowid
+    pub fn last(&mut self) -> Result<Option<Row>> {
+        self.stack.clear();
+        self.push(self.root_page, end)?;
+        self.prev()
+    }
+
+    // moves to the row with this rowid. if there isn't one the cursor ends up
+    // on the next row after it, but nothing is returned.
+    pub fn seek(&mut self, rowid: i64) -> Result<Option<Row>> {
+        Ok(self.seek_ge(rowid)?.filter(|row| row.rowid == rowid))
+    }
+
+    // moves to the first row whose rowid is >= rowid
+    pub fn seek_ge(&mut self, rowid: i64) -> Result<Option<Row>> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+
+        loop {
+            let page = read_child(&self.pager, &self.stack, page_num, BtreeKind::Table)?;
+
+            if page.is_leaf() {
+                let i = partition_point(page.num_cells, |i| Ok(leaf_rowid(&page, i) < rowid))?;
+                self.stack.push((page, i));
+                return self.settle_forward();
+            }
+
+            let i = partition_point(page.num_cells, |i| Ok(interior_rowid(&page, i)? < rowid))?;
+            page_num = table_child(&page, i)?;
+            self.stack.push((page, i));
+        }
+    }
+
+    // moves to the last row whose rowid is <= rowid
+    pub fn seek_le(&mut self, rowid: i64) -> Result<Option<Row>> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+
+        loop {
+            let page = read_child(&self.pager, &self.stack, page_num, BtreeKind::Table)?;
+
+            // on the leaf, stop just after the row we want so that prev
+            // lands on it
+            if page.is_leaf() {
+                let i = partition_point(page.num_cells, |i| Ok(leaf_rowid(&page, i) <= rowid))?;
+                self.stack.push((page, i));
+                return self.prev();
+            }
+
+            let i = partition_point(page.num_cells, |i| Ok(interior_rowid(&page, i)? < rowid))?;
+            page_num = table_child(&page, i)?;
+            self.stack.push((page, i));
+        }
+    }
+
+    // moves to the next row
+    pub fn next(&mut self) -> Result<Option<Row>> {
+        let Some((_, i)) = self.stack.last_mut() else {
+            return Ok(None);
+        };
+        *i += 1;
+        self.settle_forward()
+    }
+
+    // moves to the previous row
+    pub fn prev(&mut self) -> Result<Option<Row>> {
+        loop {
+            let Some((page, i)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+
+            // nothing left on this page, carry on in the parent
+            if *i == 0 {
+                self.stack.pop();
+                continue;
+            }
+
+            *i -= 1;
+            let i = *i;
+
+            if page.is_leaf() {
+                return self.row(i).map(Some);
+            }
+
+            // the previous child, starting from its far right
+            let child = table_child(page, i)?;
+            self.push(child, end)?;
+        }
+    }
+
+    // the cursor might not be on a row yet: it could be past the last cell of
+    // a leaf, or on an interior page. walk forwards until it is.
+    fn settle_forward(&mut self) -> Result<Option<Row>> {
+        loop {
+            let Some((page, i)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+
+            if page.is_leaf() && *i < page.num_cells {
+                let i = *i;
+                return self.row(i).map(Some);
+            }
+
+            if !page.is_leaf() && *i <= page.num_cells {
+                let child = table_child(page, *i)?;
+                self.push(child, |_| 0)?;
+                continue;
+            }
+
+            // done with this page, move on to the parent's next child
+            self.stack.pop();
+            if let Some((_, i)) = self.stack.last_mut() {
+                *i += 1;
+            }
+        }
+    }
+
+    fn push(&mut self, page_num: u32, position: impl Fn(&Page) -> u16) -> Result<()> {
+        let page = read_child(&self.pager, &self.stack, page_num, BtreeKind::Table)?;
+        let i = position(&page);
+        self.stack.push((page, i));
+        Ok(())
+    }
+
+    // the row in cell i of the leaf page on top of the stack
+    fn row(&self, i: u16) -> Result<Row> {
+        let (page, _) = self.stack.last().unwrap();
+        cell::parse_leaf_cell(&self.pager, page, page.cell_pointer(i), &self.format)
+    }
+}
+
+// counts the rows of a table, or the entries of an index, without decoding a
+// single cell. a table keeps its rows on the leaf pages only, so those are
+// just the leaves' cell counts added up. an index keeps entries on its
+// interior pages too, so there every cell counts.
+//
+// interior pages are still read to find their children, but only the child
+// pointers - the first four bytes of each cell - are looked at.
+pub fn count_entries(pager: &Pager, root_page: u32, kind: BtreeKind) -> Result<u64> {
+    let mut count = 0;
+    // each page with how deep it is. the first `depth` pages of the path are
+    // the ones above it.
+    let mut pending = vec![(root_page, 0)];
+    let mut path: Vec<(Page, u16)> = vec![];
+
+    while let Some((page_num, depth)) = pending.pop() {
+        path.truncate(depth);
+        let page = read_child(pager, &path, page_num, kind)?;
+
+        if page.is_leaf() || kind == BtreeKind::Index {
+            count += page.num_cells as u64;
+        }
+
+        if !page.is_leaf() {
+            for i in 0..page.num_cells {
+                pending.push((left_child(&page, i)?, depth + 1));
+            }
+            pending.push((page.rightmost_child(), depth + 1));
+            path.push((page, 0));
+        }
+    }
+
+    Ok(count)
+}
+
+// every interior cell, in table and index b-trees alike, starts with the page
+// number of its left child
+fn left_child(page: &Page, i: u16) -> Result<u32> {
+    let pointer = page.cell_pointer(i);
+
+    match page.data.get(pointer..pointer + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(Error::CorruptPage {
+            page: page.number,
+            offset: pointer,
+        }),
+    }
+}
+
+// sqlite doesn't go more than 20 pages deep into a b-tree either. even with
+// the smallest pages, that's far more rows than a file can hold.
+const MAX_DEPTH: usize = 20;
+
+// reads the child of the page on top of the stack (or the root, when the
+// stack is empty). a child that is already on the stack, or a b-tree deeper
+// than MAX_DEPTH, means the child pointers of a corrupt file go round in a
+// loop, which we'd otherwise follow forever.
+fn read_child(
+    pager: &Pager,
+    stack: &[(Page, u16)],
+    page_num: u32,
+    kind: BtreeKind,
+) -> Result<Page> {
+    if let Some((parent, _)) = stack.last()
+        && (stack.len() >= MAX_DEPTH || stack.iter().any(|(page, _)| page.number == page_num))
+    {
+        return Err(Error::CorruptPage {
+            page: parent.number,
+            offset: parent.offset,
+        });
+    }
+
+    read_page(pager, page_num, kind)
+}
 
+// reads a page that should belong to a table (or index) b-tree. a child
+// pointer leading to the other kind of page means the file is corrupt.
+fn read_page(pager: &Pager, page_num: u32, kind: BtreeKind) -> Result<Page> {
+    let page = pager.read_page(page_num)?;
+
+    let expected: &[u8] = match kind {
+        BtreeKind::Table => &[0x05, 0x0D],
+        BtreeKind::Index => &[0x02, 0x0A],
+    };
+
+    if !expected.contains(&page.page_type) {
+        return Err(Error::CorruptPage {
+            page: page_num,
+            offset: page.offset,
+        });
+    }
+
+    Ok(page)
+}
+
+// one past the last position on a page - for an interior page the rightmost
+// child counts as a position
+fn end(page: &Page) -> u16 {
     if page.is_leaf() {
-        for i in 0..page.num_cells {
-            let row = cell::parse_leaf_cell(page.cell_pointer(i), &page.data);
+        page.num_cells
+    } else {
+        page.num_cells + 1
+    }
+}
+
+// the child to the left of interior table cell i, or the rightmost child when
+// i is past the last cell
+fn table_child(page: &Page, i: u16) -> Result<u32> {
+    if i < page.num_cells {
+        Ok(cell::parse_interior_cell(page, page.cell_pointer(i))?.child_page_number)
+    } else {
+        Ok(page.rightmost_child())
+    }
+}
+
+// the rowid of interior cell i
+fn interior_rowid(page: &Page, i: u16) -> Result<i64> {
+    Ok(cell::parse_interior_cell(page, page.cell_pointer(i))?.rowid)
+}
+
+// the rowid of a leaf cell, without decoding the rest of the cell
+fn leaf_rowid(page: &Page, i: u16) -> i64 {
+    let pointer = page.cell_pointer(i);
+    let (_, payload_bytes_read) = parse_varint(&page.data[pointer..]);
+    parse_varint(&page.data[pointer + payload_bytes_read..]).0 as i64
+}
+
+// binary search: the index of the first cell for which `before` is false,
+// assuming `before` is true for some cells at the start and false for the rest
+fn partition_point(num_cells: u16, before: impl Fn(u16) -> Result<bool>) -> Result<u16> {
+    let (mut low, mut high) = (0, num_cells);
+
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if before(middle)? {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    Ok(low)
+}
+
+// compares an index key with a (possibly shorter) probe, one column at a time,
+// each with the collation its index column sorts by. columns that are sorted
+// DESC in the index compare the other way round.
+pub fn compare_keys(
+    key: &[Value],
+    probe: &[Value],
+    descending: &[bool],
+    collations: &[Collation],
+) -> Ordering {
+    for (i, (a, b)) in key.iter().zip(probe).enumerate() {
+        let ordering = collations.get(i).copied().unwrap_or_default().compare(a, b);
+        let ordering = if descending.get(i).copied().unwrap_or(false) {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+// Walks an index b-tree in key order, one entry at a time.
+//
+// unlike table b-trees, interior pages of an index also hold entries, so an
+// in-order walk looks like:
+//
+//   left child of cell 0, cell 0, left child of cell 1, cell 1, ..., rightmost child
+//
+// instead of recursing, the cursor keeps a stack of the pages it is part way
+// through. for every page on the stack we remember the next cell to look at.
+// the walk starts at the first entry unless the cursor is moved with seek.
+pub struct IndexCursor {
+    pager: Pager,
+    root_page: u32,
+    stack: Vec<(Page, u16)>,
+    started: bool,
+}
+
+impl IndexCursor {
+    pub fn new(pager: Pager, root_page: u32) -> IndexCursor {
+        IndexCursor {
+            pager,
+            root_page,
+            stack: vec![],
+            started: false,
+        }
+    }
+
+    // follow the leftmost path down from page_num to a leaf
+    fn descend(&mut self, page_num: u32) -> Result<()> {
+        let mut page_num = page_num;
+
+        loop {
+            let page = read_child(&self.pager, &self.stack, page_num, BtreeKind::Index)?;
+
+            if page.is_leaf() {
+                self.stack.push((page, 0));
+                return Ok(());
+            }
+
+            page_num = child_page(&page, 0)?;
+            self.stack.push((page, 0));
+        }
+    }
 
-            rows.push(row);
+    // moves the cursor so that the next entry it returns is the first one whose
+    // key is >= probe (or > probe if not inclusive). only the first
+    // probe.len() columns of each key are compared, so a probe of [5] finds
+    // the first entry for 5 whatever the other columns hold.
+    //
+    // on every page we binary search for the first cell that isn't before the
+    // probe. everything to its left sorts before it, so the entries we are
+    // looking for are either in its left child or the cell itself.
+    pub fn seek(
+        &mut self,
+        probe: &[Value],
+        descending: &[bool],
+        collations: &[Collation],
+        inclusive: bool,
+    ) -> Result<()> {
+        self.stack.clear();
+        self.started = true;
+
+        let is_before = |key: &[Value]| match compare_keys(key, probe, descending, collations) {
+            Ordering::Less => true,
+            Ordering::Equal => !inclusive,
+            Ordering::Greater => false,
+        };
+
+        let mut page_num = self.root_page;
+
+        loop {
+            let page = read_child(&self.pager, &self.stack, page_num, BtreeKind::Index)?;
+
+            let i = partition_point(page.num_cells, |i| {
+                let cell = cell::parse_index_cell(&self.pager, &page, page.cell_pointer(i))?;
+                Ok(is_before(&cell.key))
+            })?;
+
+            if page.is_leaf() {
+                self.stack.push((page, i));
+                return Ok(());
+            }
+
+            page_num = child_page(&page, i)?;
+            self.stack.push((page, i));
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<IndexCell>> {
+        if !self.started {
+            self.started = true;
+            self.descend(self.root_page)?;
+        }
+
+        loop {
+            let Some((page, i)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+
+            if *i >= page.num_cells {
+                // we've been through everything on this page (and below it)
+                self.stack.pop();
+                continue;
+            }
+
+            let entry = cell::parse_index_cell(&self.pager, page, page.cell_pointer(*i))?;
+            *i += 1;
+
+            // on an interior page, the left child of this cell has been visited
+            // by now. before the next cell we have to visit its left child (or
+            // the rightmost child after the last cell).
+            if !page.is_leaf() {
+                let next_child = child_page(page, *i)?;
+                self.descend(next_child)?;
+            }
+
+            return Ok(Some(entry));
         }
+    }
+}
+
+// the child to the left of cell i, or the rightmost child when i is past the
+// last cell
+fn child_page(page: &Page, i: u16) -> Result<u32> {
+    if i < page.num_cells {
+        left_child(page, i)
     } else {
-        for i in 0..page.num_cells {
-            let cell = cell::parse_interior_cell(page.cell_pointer(i), &page.data);
+        Ok(page.rightmost_child())
+    }
+}
 
-            traverse(file, cell.child_page_number, page_size, rows);
+impl Iterator for IndexCursor {
+    type Item = Result<IndexCell>;
+
+    fn next(&mut self) -> Option<Result<IndexCell>> {
+        self.advance().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+    use crate::schema::parse_schema;
+
+    #[test]
+    fn test_index_cursor_walks_keys_in_order() {
+        let pager = Pager::open("tests/chinook.db").unwrap();
+
+        // IFK_TrackAlbumId on tracks(AlbumId) spans several pages
+        let entries: Vec<IndexCell> = IndexCursor::new(pager, 30).collect::<Result<_>>().unwrap();
+
+        assert_eq!(entries.len(), 3503);
+        for pair in entries.windows(2) {
+            let order = pair[0].key[0]
+                .compare(&pair[1].key[0])
+                .then(pair[0].rowid.cmp(&pair[1].rowid));
+            assert_eq!(order, Ordering::Less);
+        }
+    }
+
+    #[test]
+    fn test_table_cursor_seeks() {
+        let pager = Pager::open("tests/chinook.db").unwrap();
+        let (tables, _) = parse_schema(&pager).unwrap();
+        let tracks = tables.iter().find(|t| t.name == "tracks").unwrap();
+        let mut cursor = TableCursor::new(pager, tracks.rootpage as u32, tracks.row_format(None));
+
+        let row = cursor.seek(3000).unwrap().unwrap();
+        assert_eq!(row.rowid, 3000);
+        assert_eq!(row.values[0], Value::Integer(3000));
+
+        let rowid = |row: Result<Option<Row>>| row.unwrap().map(|row| row.rowid);
+        assert_eq!(rowid(cursor.next()), Some(3001));
+        assert_eq!(rowid(cursor.prev()), Some(3000));
+        assert_eq!(rowid(cursor.prev()), Some(2999));
+
+        assert_eq!(rowid(cursor.seek(3504)), None);
+        assert_eq!(rowid(cursor.seek(-1)), None);
+        assert_eq!(rowid(cursor.seek_ge(-1)), Some(1));
+        assert_eq!(rowid(cursor.seek_ge(3504)), None);
+        assert_eq!(rowid(cursor.seek_le(i64::MAX)), Some(3503));
+        assert_eq!(rowid(cursor.prev()), Some(3502));
+        assert_eq!(rowid(cursor.seek_le(0)), None);
+        for target in (1..=3503).step_by(7) {
+            assert_eq!(rowid(cursor.seek_le(target)), Some(target));
+        }
+
+        assert_eq!(rowid(cursor.first()), Some(1));
+        assert_eq!(rowid(cursor.prev()), None);
+        assert_eq!(rowid(cursor.last()), Some(3503));
+        assert_eq!(rowid(cursor.next()), None);
+    }
+
+    // negative.db has rowids from i64::MIN to i64::MAX, stored as 9 byte
+    // varints, spread over several pages
+    #[test]
+    fn test_table_cursor_negative_rowids() {
+        let pager = Pager::open("tests/negative.db").unwrap();
+        let (tables, _) = parse_schema(&pager).unwrap();
+        let numbers = tables.iter().find(|t| t.name == "numbers").unwrap();
+        let mut cursor = TableCursor::new(pager, numbers.rootpage as u32, numbers.row_format(None));
+
+        let rowid = |row: Result<Option<Row>>| row.unwrap().map(|row| row.rowid);
+        assert_eq!(rowid(cursor.first()), Some(i64::MIN));
+        assert_eq!(rowid(cursor.next()), Some(-50000150));
+        assert_eq!(rowid(cursor.next()), Some(-1000));
+        assert_eq!(rowid(cursor.last()), Some(i64::MAX));
+        assert_eq!(rowid(cursor.prev()), Some(990));
+
+        let row = cursor.seek(-50000150).unwrap().unwrap();
+        assert_eq!(row.values[0], Value::Integer(-50000150));
+        assert_eq!(rowid(cursor.seek_ge(-15)), Some(-10));
+        assert_eq!(rowid(cursor.seek_le(-15)), Some(-20));
+        assert_eq!(rowid(cursor.seek_ge(-1)), Some(0));
+        assert_eq!(rowid(cursor.seek_le(-1001)), Some(-50000150));
+
+        let mut count = 0;
+        let mut row = cursor.seek_ge(i64::MIN).unwrap();
+        while let Some(r) = row {
+            if r.rowid >= 0 {
+                break;
+            }
+            count += 1;
+            row = cursor.next().unwrap();
+        }
+        assert_eq!(count, 102);
+    }
+
+    #[test]
+    fn test_count_entries() {
+        let pager = Pager::open("tests/chinook.db").unwrap();
+        let (tables, indexes) = parse_schema(&pager).unwrap();
+
+        let tracks = tables.iter().find(|t| t.name == "tracks").unwrap();
+        let count = count_entries(&pager, tracks.rootpage as u32, BtreeKind::Table);
+        assert_eq!(count.unwrap(), 3503);
+
+        // the index spans several pages, with entries on the interior ones
+        let index = indexes
+            .iter()
+            .find(|i| i.name == "IFK_TrackAlbumId")
+            .unwrap();
+        let count = count_entries(&pager, index.rootpage as u32, BtreeKind::Index);
+        assert_eq!(count.unwrap(), 3503);
+
+        assert!(count_entries(&pager, index.rootpage as u32, BtreeKind::Table).is_err());
+    }
+
+    #[test]
+    fn test_table_cursor_walks_every_row() {
+        let pager = Pager::open("tests/chinook.db").unwrap();
+        let (tables, _) = parse_schema(&pager).unwrap();
+        let tracks = tables.iter().find(|t| t.name == "tracks").unwrap();
+        let mut cursor = TableCursor::new(pager, tracks.rootpage as u32, tracks.row_format(None));
+
+        // forwards and backwards across every page boundary
+        let mut forwards = vec![];
+        let mut row = cursor.first().unwrap();
+        while let Some(r) = row {
+            forwards.push(r.rowid);
+            row = cursor.next().unwrap();
         }
+        assert_eq!(forwards, (1..=3503).collect::<Vec<i64>>());
+
+        let mut backwards = vec![];
+        let mut row = cursor.last().unwrap();
+        while let Some(r) = row {
+            backwards.push(r.rowid);
+            row = cursor.prev().unwrap();
+        }
+        assert_eq!(backwards, (1..=3503).rev().collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn test_index_cursor_seek() {
+        let pager = Pager::open("tests/chinook.db").unwrap();
+        let mut cursor = IndexCursor::new(pager.clone(), 30);
+
+        // IFK_TrackAlbumId, AlbumId 100 has tracks 1268 to 1276
+        cursor
+            .seek(&[Value::Integer(100)], &[false], &[Collation::Binary], true)
+            .unwrap();
+        let rowids: Vec<i64> = cursor
+            .map(|entry| entry.unwrap())
+            .take_while(|entry| entry.key[0] == Value::Integer(100))
+            .map(|entry| entry.rowid)
+            .collect();
+        assert_eq!(rowids, (1268..=1276).collect::<Vec<i64>>());
+
+        let mut cursor = IndexCursor::new(pager, 30);
+        cursor
+            .seek(
+                &[Value::Integer(100)],
+                &[false],
+                &[Collation::Binary],
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            cursor.next().unwrap().unwrap().key,
+            vec![Value::Integer(101)]
+        );
+    }
+
+    #[test]
+    fn test_index_cursor_reads_overflowing_keys() {
+        let pager = Pager::open("tests/overflow.db").unwrap();
+        let (_, indexes) = parse_schema(&pager).unwrap();
+        let index = indexes.iter().find(|i| i.name == "notes_body").unwrap();
+
+        let entries: Vec<IndexCell> = IndexCursor::new(pager, index.rootpage as u32)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 40);
 
-        traverse(file, page.rightmost_child(), page_size, rows);
+        let longest = entries.iter().find(|entry| entry.rowid == 39).unwrap();
+        let mut body = "x".repeat(3900);
+        body.push_str("39");
+        assert_eq!(longest.key, vec![Value::Text(body)]);
     }
 }
@@ -1,32 +1,95 @@
-use crate::value::{Value, parse_type_code};
-use crate::varint::parse_varint;
+use std::borrow::Cow;
+
+use crate::affinity::Affinity;
+use crate::error::{Error, Result};
+use crate::page::Page;
+use crate::pager::Pager;
+use crate::value::{Value, parse_type_code, type_code_size, write_type_code};
+use crate::varint::{parse_varint, write_varint};
 
 pub struct Cell {
     pub child_page_number: u32,
-    pub rowid: u64,
+    pub rowid: i64,
 }
 
-pub fn parse_interior_cell(index: usize, page: &[u8]) -> Cell {
+pub fn parse_interior_cell(page: &Page, index: usize) -> Result<Cell> {
     // the child page number is a u32 (4 bytes)
-    let child_page = u32::from_be_bytes([
-        page[index],
-        page[index + 1],
-        page[index + 2],
-        page[index + 3],
-    ]);
+    let child_page = read_u32(page, index)?;
 
     // rowid is a varint (which means we don't know how many bytes the value takes up)
     // it can be up to 9 bytes. the parse_varint function takes all bytes from the current
     // offset (i.e. after we've read the 4 bytes which contains the child page number) up to
     // the end of the buffer (hence [cell_offset + 4..]).
-    let (rowid, _bytes_read) = parse_varint(&page[index + 4..]);
+    let (rowid, _bytes_read) = parse_varint(bytes(page, index + 4, 0)?);
 
-    Cell {
+    Ok(Cell {
         child_page_number: child_page,
-        rowid,
+        rowid: rowid as i64,
+    })
+}
+
+// the bytes from `start` to the end of the page, as long as there are at least
+// `len` of them. reading past the end of a page means the page is corrupt.
+fn bytes(page: &Page, start: usize, len: usize) -> Result<&[u8]> {
+    match page.data.get(start..) {
+        Some(bytes) if bytes.len() >= len => Ok(bytes),
+        _ => Err(corrupt(page, start)),
     }
 }
 
+fn read_u32(page: &Page, start: usize) -> Result<u32> {
+    let b = bytes(page, start, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn corrupt(page: &Page, offset: usize) -> Error {
+    Error::CorruptPage {
+        page: page.number,
+        offset,
+    }
+}
+
+// Index b-trees store their entries ("keys") in the cells of every page, not
+// just the leaves. each key is a record of the indexed columns with the rowid
+// of the row it points to as the last value.
+//
+// leaf index cell:     [varint: payload size] [payload]
+// interior index cell: [u32: left child page] [varint: payload size] [payload]
+//
+// the left child page holds the keys that sort before this cell's key.
+#[derive(PartialEq, Debug)]
+pub struct IndexCell {
+    pub left_child: Option<u32>,
+    pub key: Vec<Value>,
+    pub rowid: i64,
+}
+
+pub fn parse_index_cell(pager: &Pager, page: &Page, pointer: usize) -> Result<IndexCell> {
+    let (left_child, offset) = if page.is_leaf() {
+        (None, pointer)
+    } else {
+        (Some(read_u32(page, pointer)?), pointer + 4)
+    };
+
+    let (payload_size, payload_bytes_read) = parse_varint(bytes(page, offset, 0)?);
+    let payload = read_payload(
+        pager,
+        page,
+        offset + payload_bytes_read,
+        payload_size as usize,
+        BtreeKind::Index,
+    )?;
+
+    let mut key = parse_record(&payload, None).ok_or_else(|| corrupt(page, pointer))?;
+    let rowid = key.pop().and_then(|rowid| rowid.as_integer()).unwrap_or(0);
+
+    Ok(IndexCell {
+        left_child,
+        key,
+        rowid,
+    })
+}
+
 // the leaf page contains a header just like the first iterior page
 // the structure is the same:
 //
@@ -68,26 +131,79 @@ pub fn parse_interior_cell(index: usize, page: &[u8]) -> Cell {
 //   0x05            → column 1 value: 5
 //   "Alice"         → column 2 value: Alice
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Row {
-    pub rowid: u64,
+    pub rowid: i64,
     pub values: Vec<Value>,
 }
 
+// tells parse_leaf_cell how to turn a record into a row
+#[derive(Debug, Clone, Default)]
+pub struct RowFormat {
+    // which columns to decode (e.g. [false, true] decodes the second column
+    // only) - None decodes all of them. the other columns come back as NULL.
+    pub wanted: Option<Vec<bool>>,
+    // the INTEGER PRIMARY KEY column. it's stored as NULL in the record, so we
+    // fill in the rowid instead.
+    pub rowid_alias: Option<usize>,
+    // the affinity of each column. sqlite stores whole numbers in REAL
+    // columns as integers to save space, so those are turned back into floats.
+    pub affinities: Vec<Affinity>,
+}
+
 // The index comes from the cell pointer array (which starts at offset 8)
 // The header also contains number_of_cells
 // Each index is a 2-byte pointer, so you need to fetch the two bytes and cast them
 // together using big-endian.
-pub fn parse_leaf_cell(pointer: usize, page: &[u8]) -> Row {
+//
+// columns that the format doesn't want are skipped. we still have to read every
+// type code to know how many bytes to skip, but skipping a long text value is
+// much cheaper than copying it.
+pub fn parse_leaf_cell(
+    pager: &Pager,
+    page: &Page,
+    pointer: usize,
+    format: &RowFormat,
+) -> Result<Row> {
     // lets say the pointer is 300
     // Cell structure: [payload_size][rowid][payload]
-    let (_payload_size, payload_bytes_read) = parse_varint(&page[pointer..]);
-    let (rowid, rowid_bytes_read) = parse_varint(&page[(pointer + payload_bytes_read)..]);
+    let (payload_size, payload_bytes_read) = parse_varint(bytes(page, pointer, 0)?);
+    let (rowid, rowid_bytes_read) = parse_varint(bytes(page, pointer + payload_bytes_read, 0)?);
+    let rowid = rowid as i64;
 
     let payload_start = pointer + payload_bytes_read + rowid_bytes_read;
+    let payload = read_payload(
+        pager,
+        page,
+        payload_start,
+        payload_size as usize,
+        BtreeKind::Table,
+    )?;
+
+    let mut values =
+        parse_record(&payload, format.wanted.as_deref()).ok_or_else(|| corrupt(page, pointer))?;
+
+    if let Some(alias) = format.rowid_alias
+        && alias < values.len()
+    {
+        values[alias] = Value::Integer(rowid);
+    }
 
-    // Payload structure: [header_size][type_codes...][values...]
-    let (header_size, header_bytes_read) = parse_varint(&page[payload_start..]);
+    for (value, affinity) in values.iter_mut().zip(&format.affinities) {
+        if let (Value::Integer(i), Affinity::Real) = (&value, affinity) {
+            *value = Value::Float(*i as f64);
+        }
+    }
+
+    Ok(Row { rowid, values })
+}
+
+// Payload structure: [header_size][type_codes...][values...]
+//
+// returns None if the record is malformed, e.g. the header says there are more
+// bytes than the payload has
+pub fn parse_record(payload: &[u8], wanted: Option<&[bool]>) -> Option<Vec<Value>> {
+    let (header_size, header_bytes_read) = parse_varint(payload);
 
     // a type code goes up to 64 bytes
     let mut type_codes: Vec<u64> = vec![];
@@ -98,8 +214,12 @@ pub fn parse_leaf_cell(pointer: usize, page: &[u8]) -> Row {
     // - Type codes — One varint per column, tells you the type and size
     let mut offset = header_bytes_read;
 
+    if header_size as usize > payload.len() {
+        return None;
+    }
+
     while offset < header_size as usize {
-        let (type_code, n) = parse_varint(&page[payload_start + offset..]);
+        let (type_code, n) = parse_varint(&payload[offset..]);
         type_codes.push(type_code);
         offset += n;
     }
@@ -107,22 +227,155 @@ pub fn parse_leaf_cell(pointer: usize, page: &[u8]) -> Row {
     let mut values: Vec<Value> = vec![];
 
     // now we have the type codes, we can start reading values
-    // values start right after the header (which is at payload_start + header_size bytes)
-    let mut values_offset = payload_start + header_size as usize;
-
-    for type_code in type_codes {
-        let (value, size) = parse_type_code(type_code, &page[values_offset..]);
+    // values start right after the header (which is header_size bytes into the payload)
+    let mut values_offset = header_size as usize;
+
+    for (i, type_code) in type_codes.into_iter().enumerate() {
+        if let Some(wanted) = wanted
+            && !wanted.get(i).copied().unwrap_or(false)
+        {
+            values.push(Value::Null);
+            values_offset += type_code_size(type_code)?;
+            continue;
+        }
+
+        let (value, size) = parse_type_code(type_code, payload.get(values_offset..)?)?;
         values.push(value);
         values_offset += size;
     }
 
-    Row { rowid, values }
+    Some(values)
+}
+
+// the opposite of parse_record. nothing writes to the database yet, but the
+// sorter uses this to write rows to its temp files.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut type_codes = vec![];
+    let mut data = vec![];
+    for value in values {
+        write_varint(write_type_code(value, &mut data), &mut type_codes);
+    }
+
+    // the header size counts itself, and it's a varint, so its own size
+    // depends on its value. it's at most 9 bytes.
+    let mut header_size = type_codes.len() + 1;
+    loop {
+        let mut size = vec![];
+        write_varint(header_size as u64, &mut size);
+        if size.len() + type_codes.len() == header_size {
+            size.extend(type_codes);
+            size.extend(data);
+            return size;
+        }
+        header_size = size.len() + type_codes.len();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BtreeKind {
+    Table,
+    Index,
+}
+
+// Overflow pages
+//
+// A payload that is too big to fit in the cell is split up: the first part
+// stays on the page ("local" bytes) and the rest is stored in a linked list of
+// overflow pages. the cell then looks like:
+//
+//   [payload_size][rowid][local payload bytes...][u32: first overflow page]
+//
+// and every overflow page looks like:
+//
+//   [u32: next overflow page (0 for the last one)][usable_size - 4 bytes of payload]
+//
+// How many bytes stay local is worked out from the usable size of a page (U)
+// and the payload size (P):
+//
+//   X = U - 35 for table leaves, ((U - 12) * 64 / 255) - 23 for index pages
+//   M = ((U - 12) * 32 / 255) - 23
+//   K = M + ((P - M) % (U - 4))
+//
+// if P <= X everything is local. otherwise K bytes are local when K <= X, and
+// M bytes when it isn't.
+pub fn local_payload_size(payload_size: usize, usable_size: usize, kind: BtreeKind) -> usize {
+    let max_local = match kind {
+        BtreeKind::Table => usable_size - 35,
+        BtreeKind::Index => ((usable_size - 12) * 64 / 255) - 23,
+    };
+
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let k = min_local + ((payload_size - min_local) % (usable_size - 4));
+
+    if k <= max_local { k } else { min_local }
+}
+
+// returns the whole payload starting at `start` on the page. when it all fits
+// on the page we can borrow it from the page buffer, otherwise we have to copy
+// it together from the overflow pages.
+pub fn read_payload<'a>(
+    pager: &Pager,
+    page: &'a Page,
+    start: usize,
+    payload_size: usize,
+    kind: BtreeKind,
+) -> Result<Cow<'a, [u8]>> {
+    let local_size = local_payload_size(payload_size, pager.usable_size, kind);
+
+    if local_size == payload_size {
+        return Ok(Cow::Borrowed(
+            &bytes(page, start, payload_size)?[..payload_size],
+        ));
+    }
+
+    let mut payload = bytes(page, start, local_size)?[..local_size].to_vec();
+
+    let mut next_page = read_u32(page, start + local_size)?;
+
+    while payload.len() < payload_size && next_page != 0 {
+        let data = pager.read_overflow_page(next_page)?;
+
+        next_page = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+
+        let remaining = payload_size - payload.len();
+        let content_size = remaining.min(pager.usable_size - 4);
+        payload.extend_from_slice(&data[4..4 + content_size]);
+    }
+
+    // the chain ended before we had the whole payload
+    if payload.len() < payload_size {
+        return Err(corrupt(page, start));
+    }
+
+    Ok(Cow::Owned(payload))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    // none of the fake cells below overflow, so the pager is never used. it
+    // just needs to have the same usable size as the fake pages.
+    fn pager() -> Pager {
+        Pager::open("tests/chinook.db").unwrap()
+    }
+
+    // wraps the fake bytes up as page 2 of the given type (0x0D = leaf table,
+    // 0x05 = interior table, 0x0A = leaf index, 0x02 = interior index)
+    fn page(data: &[u8], page_type: u8) -> Page {
+        Page {
+            number: 2,
+            data: data.to_vec(),
+            page_type,
+            num_cells: 0,
+            offset: 0,
+        }
+    }
+
     #[test]
     fn test_parse_interior_cell() {
         // Build a fake page. Fill it with 0s (0u8)
@@ -156,7 +409,7 @@ mod test {
         };
 
         // parse_cell expects the actual cell offset (800), not the pointer array index
-        let result = parse_interior_cell(800, &fake_page);
+        let result = parse_interior_cell(&page(&fake_page, 0x05), 800).unwrap();
 
         assert_eq!(result.child_page_number, target_cell.child_page_number);
         assert_eq!(result.rowid, target_cell.rowid);
@@ -176,11 +429,70 @@ mod test {
             0x02, // value = 2
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(2)]);
     }
 
+    #[test]
+    fn test_encode_record() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(0),
+            Value::Integer(1),
+            Value::Integer(-2),
+            Value::Integer(1000),
+            Value::Integer(-1 << 40),
+            Value::Integer(i64::MIN),
+            Value::Float(2.5),
+            Value::Text(String::from("héllo")),
+            Value::Blob(vec![1, 2, 3]),
+            Value::Text("x".repeat(200)),
+        ];
+
+        let record = encode_record(&values);
+        assert_eq!(parse_record(&record, None), Some(values));
+
+        // 127 type codes and a one byte size would make a 128 byte header,
+        // but 128 needs two bytes as a varint, so the header is 129
+        let values = vec![Value::Null; 127];
+        let record = encode_record(&values);
+        assert_eq!(&record[..2], [0x81, 0x01]);
+        assert_eq!(parse_record(&record, None), Some(values));
+    }
+
+    #[test]
+    fn test_parse_leaf_cell_corrupt_record() {
+        let mut fake_page = [0u8; 1024];
+        fake_page[300..305].copy_from_slice(&[
+            0x03, // payload_size = 3
+            0x01, // rowid = 1
+            0x02, // header_size = 2
+            0x04, // type_code = 4 (i32), but only one byte of payload is left
+            0x02,
+        ]);
+
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::CorruptPage {
+                page: 2,
+                offset: 300
+            })
+        ));
+    }
+
     #[test]
     fn test_parse_leaf_cell_i16() {
         let mut fake_page = [0u8; 1024];
@@ -192,7 +504,13 @@ mod test {
             0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(514)]);
     }
@@ -208,7 +526,13 @@ mod test {
             0x00, 0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(514)]);
     }
@@ -224,7 +548,13 @@ mod test {
             0x00, 0x00, 0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(514)]);
     }
@@ -240,7 +570,13 @@ mod test {
             0x00, 0x00, 0x00, 0x00, 0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(514)]);
     }
@@ -256,7 +592,13 @@ mod test {
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(514)]);
     }
@@ -274,7 +616,13 @@ mod test {
         // size of the value is (300-12)/2 = 144
         fake_page[306..450].fill(b'C');
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Blob(vec![b'C'; 144])]);
     }
@@ -292,11 +640,103 @@ mod test {
             0x02, 0x02, // value = 514
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(2), Value::Integer(514)]);
     }
 
+    #[test]
+    fn test_parse_leaf_cell_wanted_columns() {
+        let mut fake_page = [0u8; 1024];
+        fake_page[300..305].copy_from_slice(&[
+            0x0A, // payload_size = 10
+            0x01, // _rowid = 1
+            0x03, // header_size = 3
+            0x17, // type_code = 23 (text, len = 5)
+            0x02, // type_code = 2 (i16)
+        ]);
+        fake_page[305..310].copy_from_slice(b"Alice");
+        fake_page[310..312].copy_from_slice(&[0x02, 0x02]);
+
+        let format = RowFormat {
+            wanted: Some(vec![false, true]),
+            rowid_alias: None,
+            affinities: vec![],
+        };
+
+        let result = parse_leaf_cell(&pager(), &page(&fake_page, 0x0D), 300, &format).unwrap();
+        assert_eq!(result.values, vec![Value::Null, Value::Integer(514)]);
+    }
+
+    #[test]
+    fn test_parse_leaf_cell_rowid_alias() {
+        let mut fake_page = [0u8; 1024];
+        fake_page[300..306].copy_from_slice(&[
+            0x04, // payload_size = 4
+            0x07, // rowid = 7
+            0x03, // header_size = 3
+            0x00, // type_code = 0 (NULL, the rowid alias)
+            0x01, // type_code = 1 (i8)
+            0x02, // value = 2
+        ]);
+
+        let format = RowFormat {
+            wanted: None,
+            rowid_alias: Some(0),
+            affinities: vec![],
+        };
+
+        let result = parse_leaf_cell(&pager(), &page(&fake_page, 0x0D), 300, &format).unwrap();
+        assert_eq!(result.values, vec![Value::Integer(7), Value::Integer(2)]);
+    }
+
+    #[test]
+    fn test_local_payload_size() {
+        // fits on the page
+        assert_eq!(local_payload_size(989, 1024, BtreeKind::Table), 989);
+        // M = 103, K = 103 + ((990 - 103) % 1020) = 990 > X, so only M bytes stay
+        assert_eq!(local_payload_size(990, 1024, BtreeKind::Table), 103);
+        // K = 103 + ((3000 - 103) % 1020) = 960 <= X
+        assert_eq!(local_payload_size(3000, 1024, BtreeKind::Table), 960);
+        // index pages keep less on the page: X = 230
+        assert_eq!(local_payload_size(230, 1024, BtreeKind::Index), 230);
+        assert_eq!(local_payload_size(231, 1024, BtreeKind::Index), 103);
+    }
+
+    #[test]
+    fn test_parse_index_cell() {
+        let mut fake_page = [0u8; 1024];
+        fake_page[300..312].copy_from_slice(&[
+            0x00, 0x00, 0x00, 0x09, // left child page = 9
+            0x09, // payload_size = 9
+            0x03, // header_size = 3
+            0x17, // type_code = 23 (text, len = 5)
+            0x01, // type_code = 1 (i8, the rowid)
+            b'A', b'l', b'i', b'c',
+        ]);
+        fake_page[312..314].copy_from_slice(&[b'e', 0x2A]);
+
+        let result = parse_index_cell(&pager(), &page(&fake_page, 0x02), 300).unwrap();
+        assert_eq!(
+            result,
+            IndexCell {
+                left_child: Some(9),
+                key: vec![Value::Text(String::from("Alice"))],
+                rowid: 42,
+            }
+        );
+
+        let result = parse_index_cell(&pager(), &page(&fake_page, 0x0A), 304).unwrap();
+        assert_eq!(result.left_child, None);
+        assert_eq!(result.rowid, 42);
+    }
+
     #[test]
     fn test_parse_leaf_cell_null() {
         let mut fake_page = [0u8; 1024];
@@ -307,7 +747,13 @@ mod test {
             0x00, // type_code = 0 (NULL)
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Null]);
     }
@@ -323,7 +769,13 @@ mod test {
         ]);
         fake_page[304..312].copy_from_slice(&3.12_f64.to_be_bytes());
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Float(3.12)]);
     }
@@ -338,7 +790,13 @@ mod test {
             0x08, // type_code = 8 (literal 0)
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(0)]);
     }
@@ -353,7 +811,13 @@ mod test {
             0x09, // type_code = 9 (literal 1)
         ]);
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Integer(1)]);
     }
@@ -369,7 +833,13 @@ mod test {
         ]);
         fake_page[304..309].copy_from_slice(b"Alice");
 
-        let result = parse_leaf_cell(300, &fake_page);
+        let result = parse_leaf_cell(
+            &pager(),
+            &page(&fake_page, 0x0D),
+            300,
+            &RowFormat::default(),
+        )
+        .unwrap();
         assert_eq!(result.rowid, 1);
         assert_eq!(result.values, vec![Value::Text("Alice".to_string())]);
     }
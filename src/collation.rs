@@ -0,0 +1,113 @@
+use std::cmp::Ordering;
+
+use crate::{
+    error::{Error, Result},
+    value::Value,
+};
+
+// a collation decides how two pieces of text compare. it only matters when
+// both values are text - numbers, blobs and NULLs always compare the same way.
+//
+// sqlite has three built in:
+// - BINARY compares the bytes, so 'B' < 'a'. this is the default.
+// - NOCASE ignores the case of ascii letters, so 'a' = 'A'. other characters
+//   are compared as they are, e.g. 'é' != 'É'.
+// - RTRIM ignores spaces at the end, so 'a' = 'a  '.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Collation {
+    #[default]
+    Binary,
+    NoCase,
+    RTrim,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Result<Collation> {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Ok(Collation::Binary),
+            "NOCASE" => Ok(Collation::NoCase),
+            "RTRIM" => Ok(Collation::RTrim),
+            _ => Err(Error::Invalid(format!(
+                "no such collation sequence: {}",
+                name
+            ))),
+        }
+    }
+
+    pub fn compare(self, left: &Value, right: &Value) -> Ordering {
+        let (Value::Text(left), Value::Text(right)) = (left, right) else {
+            return left.compare(right);
+        };
+
+        match self {
+            Collation::Binary => left.as_bytes().cmp(right.as_bytes()),
+            Collation::NoCase => left
+                .bytes()
+                .map(|b| b.to_ascii_lowercase())
+                .cmp(right.bytes().map(|b| b.to_ascii_lowercase())),
+            Collation::RTrim => left
+                .trim_end_matches(' ')
+                .as_bytes()
+                .cmp(right.trim_end_matches(' ').as_bytes()),
+        }
+    }
+
+    // a value that is equal (==) to another one's fold exactly when the
+    // collation compares the two as equal, for looking values up in hash
+    // tables
+    pub fn fold(self, value: Value) -> Value {
+        match (value, self) {
+            (Value::Text(text), Collation::NoCase) => Value::Text(text.to_ascii_lowercase()),
+            (Value::Text(text), Collation::RTrim) => {
+                Value::Text(text.trim_end_matches(' ').to_string())
+            }
+            (value, _) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_collations() {
+        assert_eq!(
+            Collation::Binary.compare(&text("B"), &text("a")),
+            Ordering::Less
+        );
+        assert_eq!(
+            Collation::NoCase.compare(&text("B"), &text("a")),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Collation::NoCase.compare(&text("ABC"), &text("abc")),
+            Ordering::Equal
+        );
+        assert_eq!(
+            Collation::NoCase.compare(&text("É"), &text("é")),
+            Ordering::Less
+        );
+        assert_eq!(
+            Collation::RTrim.compare(&text("a  "), &text("a")),
+            Ordering::Equal
+        );
+        assert_eq!(
+            Collation::RTrim.compare(&text(" a"), &text("a")),
+            Ordering::Less
+        );
+
+        // only text is affected
+        assert_eq!(
+            Collation::NoCase.compare(&Value::Integer(1), &text("a")),
+            Ordering::Less
+        );
+
+        assert_eq!(Collation::from_name("nocase").unwrap(), Collation::NoCase);
+        assert!(Collation::from_name("french").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    datetime::Clock,
+    db::Db,
+    error::{Error, Result},
+    pager::Pager,
+    query::{self, Query, Rows},
+    schema::parse_schema,
+    sort::DEFAULT_SORT_MEMORY,
+    value::Value,
+};
+
+// an open database. opening reads the header and the schema once, so
+// preparing and running queries afterwards doesn't have to.
+pub struct Connection {
+    db: Db,
+}
+
+impl Connection {
+    pub fn open(file_path: &str) -> Result<Connection> {
+        let pager = Pager::open(file_path)?;
+
+        let (tables, indexes) = parse_schema(&pager)?;
+
+        Ok(Connection {
+            db: Db {
+                pager,
+                tables,
+                indexes,
+                sort_memory: DEFAULT_SORT_MEMORY,
+                clock: Clock::default(),
+            },
+        })
+    }
+
+    // how many bytes of rows ORDER BY and GROUP BY keep in memory before they
+    // start writing them to temp files
+    pub fn set_sort_memory(&mut self, bytes: usize) {
+        self.db.sort_memory = bytes;
+    }
+
+    // fixes the current time and the local time zone the date and time
+    // functions see, which are the system's by default. queries prepared
+    // before this keep the clock they had.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.db.clock = clock;
+    }
+
+    // parses and plans a query without running it. the statement can then be
+    // run as many times as you like.
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let query = query::prepare(&self.db, sql)?;
+        let bindings = vec![None; query.parameters().len()];
+
+        Ok(Statement { query, bindings })
+    }
+}
+
+// a prepared query, tied to the connection it was prepared on.
+//
+// values for the query's parameters are bound by number (?1 is 1) or by name
+// (":id", including the prefix), and stay bound until they're replaced or
+// cleared, so a statement can be run again with only some values changed.
+pub struct Statement<'conn> {
+    query: Query<'conn>,
+    // the value bound to each parameter. bindings[0] is ?1.
+    bindings: Vec<Option<Value>>,
+}
+
+impl Statement<'_> {
+    pub fn column_count(&self) -> usize {
+        self.query.column_names().len()
+    }
+
+    pub fn column_name(&self, index: usize) -> Option<&str> {
+        self.query.column_names().get(index).map(String::as_str)
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        self.query.column_names()
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.bindings.len()
+    }
+
+    // the name of a parameter as written, e.g. ":id". None for ? and ?NNN.
+    pub fn parameter_name(&self, index: usize) -> Option<&str> {
+        self.query
+            .parameters()
+            .get(index.checked_sub(1)?)?
+            .as_deref()
+    }
+
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.query
+            .parameters()
+            .iter()
+            .position(|parameter| parameter.as_deref() == Some(name))
+            .map(|i| i + 1)
+    }
+
+    pub fn bind(&mut self, index: usize, value: impl Into<Value>) -> Result<()> {
+        if index == 0 || index > self.bindings.len() {
+            return Err(Error::Parameter(format!(
+                "parameter index {} out of range: the statement has {} parameters",
+                index,
+                self.bindings.len()
+            )));
+        }
+
+        self.bindings[index - 1] = Some(value.into());
+        Ok(())
+    }
+
+    pub fn bind_named(&mut self, name: &str, value: impl Into<Value>) -> Result<()> {
+        match self.parameter_index(name) {
+            Some(index) => self.bind(index, value),
+            None => Err(Error::Parameter(format!("no such parameter: {}", name))),
+        }
+    }
+
+    pub fn clear_bindings(&mut self) {
+        self.bindings.fill(None);
+    }
+
+    // runs the statement. rows are read from the file as you iterate, and the
+    // iterator keeps its own handle on the file, so it can outlive the
+    // statement. every parameter has to be bound first.
+    pub fn query(&self) -> Result<Rows> {
+        let values = self
+            .bindings
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                value.clone().ok_or_else(|| {
+                    let name = match self.parameter_name(i + 1) {
+                        Some(name) => name.to_string(),
+                        None => format!("?{}", i + 1),
+                    };
+                    Error::Parameter(format!("parameter {} is not bound", name))
+                })
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        self.query.run(&values)
+    }
+
+    // runs the statement to the end, throwing the rows away, and returns how
+    // many there were
+    pub fn execute(&self) -> Result<usize> {
+        let mut count = 0;
+        for row in self.query()? {
+            row?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,1296 @@
+use std::{
+    env, fs,
+    ops::RangeInclusive,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    printf,
+    value::Value,
+};
+
+// the date and time functions. they work the way sqlite's do: a time value
+// (text like '2024-03-05 12:34:56', a julian day number or 'now') followed by
+// modifiers like '+1 month' or 'start of year', with NULL for anything that
+// doesn't parse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Date,
+    Time,
+    DateTime,
+    JulianDay,
+    UnixEpoch,
+    Strftime,
+    TimeDiff,
+}
+
+impl Function {
+    // None if the name isn't one of these functions
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Function>> {
+        let (function, arities): (Function, RangeInclusive<usize>) =
+            match name.to_ascii_lowercase().as_str() {
+                "date" => (Function::Date, 0..=usize::MAX),
+                "time" => (Function::Time, 0..=usize::MAX),
+                "datetime" => (Function::DateTime, 0..=usize::MAX),
+                "julianday" => (Function::JulianDay, 0..=usize::MAX),
+                "unixepoch" => (Function::UnixEpoch, 0..=usize::MAX),
+                "strftime" => (Function::Strftime, 0..=usize::MAX),
+                "timediff" => (Function::TimeDiff, 2..=2),
+                _ => return Ok(None),
+            };
+
+        if !arities.contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        Ok(Some(function))
+    }
+
+    pub fn call(self, args: &[Value], clock: Clock) -> Value {
+        let result = match self {
+            Function::Date => DateTime::new(args, clock).map(|mut time| Value::Text(time.date())),
+            Function::Time => DateTime::new(args, clock).map(|mut time| Value::Text(time.time())),
+            Function::DateTime => DateTime::new(args, clock).map(|mut time| {
+                let date = time.date();
+                Value::Text(format!("{} {}", date, time.time()))
+            }),
+            Function::JulianDay => {
+                DateTime::new(args, clock).map(|time| Value::Float(time.jd as f64 / DAY as f64))
+            }
+            Function::UnixEpoch => DateTime::new(args, clock).map(|time| match time.subsec {
+                true => Value::Float((time.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+                false => Value::Integer(time.jd / 1000 - UNIX_EPOCH_JD / 1000),
+            }),
+            Function::Strftime => args.first().and_then(Value::to_text).and_then(|format| {
+                let time = DateTime::new(&args[1..], clock)?;
+                strftime(&format, time).map(Value::Text)
+            }),
+            Function::TimeDiff => timediff(&args[0], &args[1], clock).map(Value::Text),
+        };
+        result.unwrap_or(Value::Null)
+    }
+}
+
+// where 'now' and the local time zone come from. both are the system's
+// unless they're fixed, e.g. to make tests repeatable.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Clock {
+    // milliseconds since 1970-01-01 00:00:00 UTC
+    pub now: Option<i64>,
+    // seconds east of UTC, for 'localtime' and 'utc'
+    pub utc_offset: Option<i32>,
+}
+
+impl Clock {
+    // the same clock stopped at the current time. a statement freezes its
+    // clock each time it runs, so every row sees the same 'now'.
+    pub fn freeze(self) -> Clock {
+        Clock {
+            now: Some(self.now()),
+            ..self
+        }
+    }
+
+    fn now(&self) -> i64 {
+        self.now.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as i64)
+        })
+    }
+
+    // seconds east of UTC at a unix time
+    fn offset_at(&self, unix: i64) -> i32 {
+        self.utc_offset
+            .unwrap_or_else(|| system_zone().map_or(0, |zone| zone.offset_at(unix)))
+    }
+}
+
+// a time zone, as read from a TZif file like /etc/localtime
+struct Zone {
+    // the unix times at which the offset changes, with the offset from then on
+    transitions: Vec<(i64, i32)>,
+    // the offset before the first transition
+    initial: i32,
+}
+
+impl Zone {
+    fn parse(data: &[u8]) -> Option<Zone> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let counts = |data: &[u8]| -> Option<Vec<usize>> {
+            data.get(20..44)?
+                .chunks(4)
+                .map(|count| Some(u32::from_be_bytes(count.try_into().ok()?) as usize))
+                .collect()
+        };
+
+        // version 2 and later files repeat the data with 64 bit times after
+        // the 32 bit version, which is the one to read
+        let mut data = data;
+        let mut time_size = 4;
+        if *data.get(4)? >= b'2' {
+            let [utc, standard, leaps, times, types, chars] = counts(data)?[..] else {
+                return None;
+            };
+            let skip = 44 + times * 5 + types * 6 + chars + leaps * 8 + standard + utc;
+            data = data.get(skip..)?;
+            time_size = 8;
+        }
+
+        let [.., times, types, _] = counts(data)?[..] else {
+            return None;
+        };
+        let (at, data) = data.get(44..)?.split_at_checked(times * time_size)?;
+        let (indices, data) = data.split_at_checked(times)?;
+        let infos = data.get(..types * 6)?;
+        let offset = |i: usize| -> Option<i32> {
+            Some(i32::from_be_bytes(
+                infos.get(i * 6..i * 6 + 4)?.try_into().ok()?,
+            ))
+        };
+
+        let transitions = at
+            .chunks(time_size)
+            .zip(indices)
+            .map(|(at, &i)| {
+                let at = match at.try_into() {
+                    Ok(at) => i64::from_be_bytes(at),
+                    Err(_) => i32::from_be_bytes(at.try_into().ok()?) as i64,
+                };
+                Some((at, offset(i as usize)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Zone {
+            transitions,
+            initial: offset(0)?,
+        })
+    }
+
+    fn offset_at(&self, unix: i64) -> i32 {
+        match self.transitions.partition_point(|(at, _)| *at <= unix) {
+            0 => self.initial,
+            i => self.transitions[i - 1].1,
+        }
+    }
+}
+
+// the zone TZ names, or /etc/localtime without it. None means UTC.
+fn system_zone() -> Option<&'static Zone> {
+    static ZONE: OnceLock<Option<Zone>> = OnceLock::new();
+
+    ZONE.get_or_init(|| {
+        let path = match env::var("TZ") {
+            Ok(tz) => match tz.trim_start_matches(':') {
+                tz if tz.starts_with('/') => tz.to_string(),
+                tz => format!("/usr/share/zoneinfo/{}", tz),
+            },
+            Err(_) => String::from("/etc/localtime"),
+        };
+        Zone::parse(&fs::read(path).ok()?)
+    })
+    .as_ref()
+}
+
+// times are kept as julian days in milliseconds: the number of milliseconds
+// since noon on November 24, 4714 BC
+const DAY: i64 = 86_400_000;
+const UNIX_EPOCH_JD: i64 = 210_866_760_000_000;
+// 9999-12-31 23:59:59.999, the last time we can show
+const MAX_JD: i64 = 464_269_060_799_999;
+
+// a point in time while it's worked out: the julian day and the calendar
+// fields are filled in as they're needed, and modifiers change one or the
+// other. this follows sqlite's date.c closely, down to the order things are
+// rounded in, so that the results match to the millisecond.
+#[derive(Debug, Clone, Copy, Default)]
+struct DateTime {
+    jd: i64,
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: f64,
+    // minutes east of UTC given with the time, e.g. 2024-03-05 12:00+02:00
+    tz: i64,
+    valid_jd: bool,
+    valid_ymd: bool,
+    valid_hms: bool,
+    // a number we don't know the meaning of yet, kept in `second` until a
+    // modifier like 'unixepoch' says what it is
+    raw: bool,
+    error: bool,
+    // show milliseconds, see the 'subsec' modifier
+    subsec: bool,
+    is_utc: bool,
+    is_local: bool,
+    // how many days a day past the end of the month rolled over into the
+    // next, e.g. 2 for February 31 in a leap year. 'floor' takes them back.
+    floor: i64,
+}
+
+impl DateTime {
+    // the time value and modifiers of a call, or None if any of them is bad.
+    // no arguments at all means 'now'.
+    fn new(args: &[Value], clock: Clock) -> Option<DateTime> {
+        let mut time = DateTime::default();
+        match args.first() {
+            None => time.set_now(clock),
+            Some(Value::Null) => return None,
+            Some(Value::Integer(i)) => time.set_raw(*i as f64),
+            Some(Value::Float(f)) => time.set_raw(*f),
+            Some(value) => {
+                if !time.parse(value.to_text()?.as_bytes(), clock) {
+                    return None;
+                }
+            }
+        }
+
+        for (i, modifier) in args.iter().enumerate().skip(1) {
+            if !time.modify(modifier.to_text()?.as_bytes(), i, clock) {
+                return None;
+            }
+        }
+
+        time.compute_jd();
+        if time.error || !(0..=MAX_JD).contains(&time.jd) {
+            return None;
+        }
+        // without modifiers February 31 still has to become March 2 or 3
+        if args.len() == 1 && time.valid_ymd && time.day > 28 {
+            time.valid_ymd = false;
+        }
+        Some(time)
+    }
+
+    fn set_now(&mut self, clock: Clock) {
+        self.jd = clock.now() + UNIX_EPOCH_JD;
+        self.valid_jd = true;
+        self.is_utc = true;
+        self.is_local = false;
+        self.clear_fields();
+    }
+
+    // a number is a julian day unless a modifier says otherwise
+    fn set_raw(&mut self, f: f64) {
+        self.second = f;
+        self.raw = true;
+        if (0.0..5373484.5).contains(&f) {
+            self.jd = (f * DAY as f64 + 0.5) as i64;
+            self.valid_jd = true;
+        }
+    }
+
+    fn parse(&mut self, z: &[u8], clock: Clock) -> bool {
+        if self.parse_date(z) || self.parse_time(z) {
+            return true;
+        }
+        let text = String::from_utf8_lossy(z);
+        if text.eq_ignore_ascii_case("now") {
+            self.set_now(clock);
+        } else if let Some(f) = number(z) {
+            self.set_raw(f);
+        } else if text.eq_ignore_ascii_case("subsec") || text.eq_ignore_ascii_case("subsecond") {
+            self.subsec = true;
+            self.set_now(clock);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    // YYYY-MM-DD, optionally followed by a time
+    fn parse_date(&mut self, mut z: &[u8]) -> bool {
+        let negative = at(z, 0) == b'-';
+        if negative {
+            z = &z[1..];
+        }
+        let [year, month, day] = digits(z, "40f-21a-21d")[..] else {
+            return false;
+        };
+
+        let mut i = 10;
+        while is_space(at(z, i)) || at(z, i) == b'T' {
+            i += 1;
+        }
+        let rest = z.get(i..).unwrap_or_default();
+        if !self.parse_time(rest) {
+            if !rest.is_empty() {
+                return false;
+            }
+            self.valid_hms = false;
+        }
+
+        self.valid_jd = false;
+        self.valid_ymd = true;
+        self.year = if negative { -year } else { year };
+        self.month = month;
+        self.day = day;
+        self.compute_floor();
+        if self.tz != 0 {
+            self.compute_jd();
+        }
+        true
+    }
+
+    // HH:MM, HH:MM:SS or HH:MM:SS.SSS, optionally followed by a time zone
+    fn parse_time(&mut self, z: &[u8]) -> bool {
+        let [hour, minute] = digits(z, "20c:20e")[..] else {
+            return false;
+        };
+
+        let mut i = 5;
+        let mut second = 0.0;
+        if at(z, i) == b':' {
+            let [s] = digits(&z[i + 1..], "20e")[..] else {
+                return false;
+            };
+            i += 3;
+            second = s as f64;
+
+            if at(z, i) == b'.' && at(z, i + 1).is_ascii_digit() {
+                let (mut fraction, mut scale) = (0.0, 1.0);
+                i += 1;
+                while at(z, i).is_ascii_digit() {
+                    fraction = fraction * 10.0 + (at(z, i) - b'0') as f64;
+                    scale *= 10.0;
+                    i += 1;
+                }
+                // truncated so that it can't round up to the next second
+                second += (fraction / scale).min(0.999);
+            }
+        }
+
+        self.valid_jd = false;
+        self.raw = false;
+        self.valid_hms = true;
+        self.hour = hour;
+        self.minute = minute;
+        self.second = second;
+        self.parse_timezone(z.get(i..).unwrap_or_default())
+    }
+
+    // nothing, Z, or +HH:MM / -HH:MM, with spaces around it
+    fn parse_timezone(&mut self, z: &[u8]) -> bool {
+        let mut i = 0;
+        while is_space(at(z, i)) {
+            i += 1;
+        }
+        self.tz = 0;
+
+        let sign = match at(z, i) {
+            b'-' => -1,
+            b'+' => 1,
+            b'Z' | b'z' => {
+                i += 1;
+                self.is_local = false;
+                self.is_utc = true;
+                0
+            }
+            c => return c == 0,
+        };
+        if sign != 0 {
+            let [hours, minutes] = digits(&z[i + 1..], "20b:20e")[..] else {
+                return false;
+            };
+            i += 6;
+            self.tz = sign * (hours * 60 + minutes);
+        }
+
+        while is_space(at(z, i)) {
+            i += 1;
+        }
+        at(z, i) == 0
+    }
+
+    // applies one modifier. `index` is which argument it is: some modifiers
+    // only work right after the time value.
+    fn modify(&mut self, z: &[u8], index: usize, clock: Clock) -> bool {
+        let text = String::from_utf8_lossy(z).to_ascii_lowercase();
+        let text = text.as_str();
+
+        match text {
+            "auto" | "julianday" | "unixepoch" if index > 1 => false,
+            // a number that's in range for a unix time is one, otherwise it's
+            // a julian day
+            "auto" => {
+                if !self.raw || self.valid_jd {
+                    self.raw = false;
+                    return true;
+                }
+                if !(-210_866_760_000.0..=253_402_300_799.0).contains(&self.second) {
+                    return false;
+                }
+                self.set_unix(self.second);
+                true
+            }
+            "julianday" => {
+                let julian = self.valid_jd && self.raw;
+                self.raw = false;
+                julian
+            }
+            "unixepoch" if self.raw => {
+                let jd = self.second * 1000.0 + UNIX_EPOCH_JD as f64;
+                if !(0.0..(MAX_JD + 1) as f64).contains(&jd) {
+                    return false;
+                }
+                self.set_unix(self.second);
+                true
+            }
+            // day 31 of a 30 day month is the 1st of the next, or with
+            // 'floor' the 30th
+            "ceiling" => {
+                self.compute_jd();
+                self.clear_fields();
+                self.floor = 0;
+                true
+            }
+            "floor" => {
+                self.compute_jd();
+                self.jd -= self.floor * DAY;
+                self.clear_fields();
+                true
+            }
+            "localtime" => {
+                if !self.is_local {
+                    self.make_local(clock);
+                }
+                self.is_utc = false;
+                self.is_local = true;
+                true
+            }
+            "utc" => {
+                if !self.is_utc {
+                    self.make_utc(clock);
+                }
+                true
+            }
+            "subsec" | "subsecond" => {
+                self.subsec = true;
+                true
+            }
+            _ if text.starts_with("weekday ") => {
+                let Some(weekday) =
+                    number(&z[8..]).filter(|n| (0.0..7.0).contains(n) && n.fract() == 0.0)
+                else {
+                    return false;
+                };
+                self.compute_ymd_hms();
+                self.tz = 0;
+                self.valid_jd = false;
+                self.compute_jd();
+                let mut day = ((self.jd + 129_600_000) / DAY) % 7;
+                if day > weekday as i64 {
+                    day -= 7;
+                }
+                self.jd += (weekday as i64 - day) * DAY;
+                self.clear_fields();
+                true
+            }
+            _ if text.starts_with("start of ") => {
+                if !self.valid_jd && !self.valid_ymd && !self.valid_hms {
+                    return false;
+                }
+                self.compute_ymd();
+                self.valid_hms = true;
+                self.hour = 0;
+                self.minute = 0;
+                self.second = 0.0;
+                self.raw = false;
+                self.tz = 0;
+                self.valid_jd = false;
+                match &text[9..] {
+                    "month" => self.day = 1,
+                    "year" => {
+                        self.month = 1;
+                        self.day = 1;
+                    }
+                    "day" => {}
+                    _ => return false,
+                }
+                true
+            }
+            _ if matches!(at(z, 0), b'+' | b'-' | b'0'..=b'9') => self.shift(z),
+            _ => false,
+        }
+    }
+
+    // '+N units', '+HH:MM:SS' or '+YYYY-MM-DD HH:MM:SS', or with a minus
+    fn shift(&mut self, z: &[u8]) -> bool {
+        let sign = at(z, 0);
+
+        // the number runs up to a space or a colon, or a dash after a year
+        let mut n = 1;
+        while at(z, n) != 0 && at(z, n) != b':' && !is_space(at(z, n)) {
+            if at(z, n) == b'-'
+                && ((n == 5 && digits(&z[1..], "40f").len() == 1)
+                    || (n == 6 && digits(&z[1..], "50f").len() == 1))
+            {
+                break;
+            }
+            n += 1;
+        }
+        let Some(amount) = number(&z[..n]) else {
+            return false;
+        };
+
+        let mut time = z;
+        if at(z, n) == b'-' {
+            // years, months (0 to 11) and days (0 to 30), e.g. +0001-02-03
+            if sign != b'+' && sign != b'-' {
+                return false;
+            }
+            let format = if n == 5 { "40f-20a-20d" } else { "50f-20a-20d" };
+            let [years, months, mut days] = digits(&z[1..], format)[..] else {
+                return false;
+            };
+            // a five digit year pushes the rest along by one
+            let z = &z[n - 5..];
+            if months >= 12 || days >= 31 {
+                return false;
+            }
+
+            self.compute_ymd_hms();
+            self.valid_jd = false;
+            if sign == b'-' {
+                self.year -= years;
+                self.month -= months;
+                days = -days;
+            } else {
+                self.year += years;
+                self.month += months;
+            }
+            self.normalize_month();
+            self.compute_floor();
+            self.compute_jd();
+            self.valid_hms = false;
+            self.valid_ymd = false;
+            self.jd += days * DAY;
+
+            if at(z, 11) == 0 {
+                return true;
+            }
+            if !is_space(at(z, 11)) || digits(&z[12..], "20c:20e").len() != 2 {
+                return false;
+            }
+            time = &z[12..];
+            n = 2;
+        }
+
+        if at(time, n) == b':' {
+            // a time of day to add, e.g. -01:30
+            if !at(time, 0).is_ascii_digit() {
+                time = &time[1..];
+            }
+            let mut delta = DateTime::default();
+            if !delta.parse_time(time) {
+                return false;
+            }
+            delta.compute_jd();
+            delta.jd -= DAY / 2;
+            delta.jd -= delta.jd / DAY * DAY;
+            if sign == b'-' {
+                delta.jd = -delta.jd;
+            }
+            self.compute_jd();
+            self.clear_fields();
+            self.jd += delta.jd;
+            return true;
+        }
+
+        // a number of units, e.g. '+5 days' or '-1.5 hours'
+        let mut rest = &z[n..];
+        while is_space(at(rest, 0)) {
+            rest = &rest[1..];
+        }
+        let mut unit = String::from_utf8_lossy(rest).to_ascii_lowercase();
+        if unit.len() < 3 || unit.len() > 10 {
+            return false;
+        }
+        if unit.ends_with('s') {
+            unit.pop();
+        }
+
+        self.compute_jd();
+        self.floor = 0;
+        let mut amount = amount;
+        let rounder = if amount < 0.0 { -0.5 } else { 0.5 };
+        let found = UNITS.iter().find(|(name, limit, _)| {
+            *name == unit && amount > -(*limit as f64) && amount < *limit as f64
+        });
+        if let Some((name, _, seconds)) = found {
+            match *name {
+                // months and years go by the calendar, and only what's left
+                // after the whole ones is added as 30 or 365 days
+                "month" => {
+                    self.compute_ymd_hms();
+                    self.month += amount as i64;
+                    self.normalize_month();
+                    self.compute_floor();
+                    self.valid_jd = false;
+                    amount = amount.fract();
+                }
+                "year" => {
+                    self.compute_ymd_hms();
+                    self.year += amount as i64;
+                    self.compute_floor();
+                    self.valid_jd = false;
+                    amount = amount.fract();
+                }
+                _ => {}
+            }
+            self.compute_jd();
+            self.jd += (amount * 1000.0 * seconds + rounder) as i64;
+        }
+        self.clear_fields();
+        found.is_some()
+    }
+
+    // the unix time `seconds` as the julian day
+    fn set_unix(&mut self, seconds: f64) {
+        self.clear_fields();
+        self.jd = (seconds * 1000.0 + UNIX_EPOCH_JD as f64 + 0.5) as i64;
+        self.valid_jd = true;
+        self.raw = false;
+    }
+
+    // brings the month back into 1 to 12, carrying into the year
+    fn normalize_month(&mut self) {
+        let years = match self.month > 0 {
+            true => (self.month - 1) / 12,
+            false => (self.month - 12) / 12,
+        };
+        self.year += years;
+        self.month -= years * 12;
+    }
+
+    // treats the time as UTC and shows it in local time instead
+    fn make_local(&mut self, clock: Clock) {
+        self.compute_jd();
+        // like sqlite, a time outside 1970 to 2037 takes the offset of the
+        // same day in a year from 2000 to 2003 that's as much a leap year
+        let mut jd = self.jd;
+        if !(UNIX_EPOCH_JD..=213_014_145_600_000).contains(&jd) {
+            let mut same_day = *self;
+            same_day.compute_ymd_hms();
+            same_day.year = 2000 + same_day.year % 4;
+            same_day.valid_jd = false;
+            same_day.compute_jd();
+            jd = same_day.jd;
+        }
+        let offset = clock.offset_at(jd / 1000 - UNIX_EPOCH_JD / 1000) as i64;
+        let mut local = DateTime {
+            jd: self.jd + offset * 1000,
+            valid_jd: true,
+            ..DateTime::default()
+        };
+        local.compute_ymd_hms();
+
+        self.year = local.year;
+        self.month = local.month;
+        self.day = local.day;
+        self.hour = local.hour;
+        self.minute = local.minute;
+        self.second = local.second;
+        self.valid_ymd = true;
+        self.valid_hms = true;
+        self.valid_jd = false;
+        self.raw = false;
+        self.tz = 0;
+        self.error = local.error;
+    }
+
+    // treats the time as local time and shows it in UTC instead. the offset
+    // depends on the UTC time we're looking for, so guess until the guess
+    // shows as the local time we have.
+    fn make_utc(&mut self, clock: Clock) {
+        self.compute_jd();
+        let local = self.jd;
+        let mut guess = local;
+        let mut error = 0;
+        for _ in 0..4 {
+            guess -= error;
+            let mut shown = DateTime {
+                jd: guess,
+                valid_jd: true,
+                ..DateTime::default()
+            };
+            shown.make_local(clock);
+            shown.compute_jd();
+            error = shown.jd - local;
+            if error == 0 {
+                break;
+            }
+        }
+
+        *self = DateTime {
+            jd: guess,
+            valid_jd: true,
+            is_utc: true,
+            subsec: self.subsec,
+            ..DateTime::default()
+        };
+    }
+
+    fn compute_jd(&mut self) {
+        if self.valid_jd {
+            return;
+        }
+        let (mut year, mut month, day) = match self.valid_ymd {
+            true => (self.year, self.month, self.day),
+            false => (2000, 1, 1),
+        };
+        if !(-4713..=9999).contains(&year) || self.raw {
+            self.set_error();
+            return;
+        }
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = (year + 4800) / 100;
+        let b = 38 - a + a / 4;
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        self.jd = (((x1 + x2 + day + b) as f64 - 1524.5) * DAY as f64) as i64;
+        self.valid_jd = true;
+
+        if self.valid_hms {
+            self.jd +=
+                self.hour * 3_600_000 + self.minute * 60_000 + (self.second * 1000.0 + 0.5) as i64;
+            if self.tz != 0 {
+                self.jd -= self.tz * 60_000;
+                self.valid_ymd = false;
+                self.valid_hms = false;
+                self.tz = 0;
+                self.is_utc = true;
+                self.is_local = false;
+            }
+        }
+    }
+
+    fn compute_ymd(&mut self) {
+        if self.valid_ymd {
+            return;
+        }
+        if !self.valid_jd {
+            self.year = 2000;
+            self.month = 1;
+            self.day = 1;
+        } else if !(0..=MAX_JD).contains(&self.jd) {
+            self.set_error();
+            return;
+        } else {
+            let z = (self.jd + DAY / 2) / DAY;
+            let alpha = ((z as f64 + 32044.75) / 36524.25) as i64 - 52;
+            let a = z + 1 + alpha - (alpha + 100) / 4 + 25;
+            let b = a + 1524;
+            let c = ((b as f64 - 122.1) / 365.25) as i64;
+            let d = (36525 * (c & 32767)) / 100;
+            let e = ((b - d) as f64 / 30.6001) as i64;
+            let x1 = (30.6001 * e as f64) as i64;
+            self.day = b - d - x1;
+            self.month = if e < 14 { e - 1 } else { e - 13 };
+            self.year = if self.month > 2 { c - 4716 } else { c - 4715 };
+        }
+        self.valid_ymd = true;
+    }
+
+    fn compute_hms(&mut self) {
+        if self.valid_hms {
+            return;
+        }
+        self.compute_jd();
+        let day_ms = (self.jd + DAY / 2) % DAY;
+        self.second = (day_ms % 60_000) as f64 / 1000.0;
+        let day_minutes = day_ms / 60_000;
+        self.minute = day_minutes % 60;
+        self.hour = day_minutes / 60;
+        self.raw = false;
+        self.valid_hms = true;
+    }
+
+    fn compute_ymd_hms(&mut self) {
+        self.compute_ymd();
+        self.compute_hms();
+    }
+
+    // forgets the calendar fields, once a modifier has changed the julian day
+    fn clear_fields(&mut self) {
+        self.valid_ymd = false;
+        self.valid_hms = false;
+        self.tz = 0;
+    }
+
+    fn compute_floor(&mut self) {
+        self.floor = if self.day <= 28 {
+            0
+        } else if (1 << self.month) & 0x15aa != 0 {
+            // a month with 31 days
+            0
+        } else if self.month != 2 {
+            (self.day == 31) as i64
+        } else if self.year % 4 != 0 || (self.year % 100 == 0 && self.year % 400 != 0) {
+            self.day - 28
+        } else {
+            self.day - 29
+        };
+    }
+
+    fn set_error(&mut self) {
+        *self = DateTime {
+            error: true,
+            ..DateTime::default()
+        };
+    }
+
+    // YYYY-MM-DD
+    fn date(&mut self) -> String {
+        self.compute_ymd();
+        let sign = if self.year < 0 { "-" } else { "" };
+        format!(
+            "{}{:04}-{:02}-{:02}",
+            sign,
+            self.year.abs(),
+            self.month,
+            self.day
+        )
+    }
+
+    // HH:MM:SS, or HH:MM:SS.SSS with 'subsec'
+    fn time(&mut self) -> String {
+        self.compute_hms();
+        if self.subsec {
+            let ms = (self.second * 1000.0 + 0.5) as i64;
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                self.hour,
+                self.minute,
+                ms / 1000,
+                ms % 1000
+            )
+        } else {
+            format!(
+                "{:02}:{:02}:{:02}",
+                self.hour, self.minute, self.second as i64
+            )
+        }
+    }
+
+    // 0 for January 1st
+    fn days_after_jan01(&self) -> i64 {
+        let mut jan01 = DateTime {
+            valid_jd: false,
+            month: 1,
+            day: 1,
+            ..*self
+        };
+        jan01.compute_jd();
+        (self.jd - jan01.jd + DAY / 2) / DAY
+    }
+
+    // 0 for Monday
+    fn days_after_monday(&self) -> i64 {
+        ((self.jd + DAY / 2) / DAY) % 7
+    }
+
+    // 0 for Sunday
+    fn days_after_sunday(&self) -> i64 {
+        ((self.jd + DAY * 3 / 2) / DAY) % 7
+    }
+
+    // the Thursday of the same week, which decides the ISO 8601 year and week
+    fn thursday(&self) -> DateTime {
+        let mut thursday = DateTime {
+            jd: self.jd + (3 - self.days_after_monday()) * DAY,
+            valid_ymd: false,
+            ..*self
+        };
+        thursday.compute_ymd();
+        thursday
+    }
+}
+
+// the units of '+N units', how far N can go and how many seconds each is
+const UNITS: &[(&str, f32, f64)] = &[
+    ("second", 4.6427e14, 1.0),
+    ("minute", 7.7379e12, 60.0),
+    ("hour", 1.2897e11, 3600.0),
+    ("day", 5373485.0, 86400.0),
+    ("month", 176546.0, 2592000.0),
+    ("year", 14713.0, 31536000.0),
+];
+
+// strftime's substitutions, or None for one it doesn't know
+fn strftime(format: &str, mut time: DateTime) -> Option<String> {
+    time.compute_jd();
+    time.compute_ymd_hms();
+    let float = |format: &str, f: f64| printf::format(format, &[Value::Float(f)]);
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let text = match chars.next()? {
+            'd' => format!("{:02}", time.day),
+            'e' => format!("{:2}", time.day),
+            'f' => float("%06.3f", time.second.min(59.999)),
+            'F' => format!("{:04}-{:02}-{:02}", time.year, time.month, time.day),
+            'G' => format!("{:04}", time.thursday().year),
+            'g' => format!("{:02}", time.thursday().year % 100),
+            'H' => format!("{:02}", time.hour),
+            'k' => format!("{:2}", time.hour),
+            c @ ('I' | 'l') => {
+                let hour = match time.hour {
+                    0 => 12,
+                    hour if hour > 12 => hour - 12,
+                    hour => hour,
+                };
+                match c {
+                    'I' => format!("{:02}", hour),
+                    _ => format!("{:2}", hour),
+                }
+            }
+            'j' => format!("{:03}", time.days_after_jan01() + 1),
+            'J' => float("%.16g", time.jd as f64 / DAY as f64),
+            'm' => format!("{:02}", time.month),
+            'M' => format!("{:02}", time.minute),
+            'p' => String::from(if time.hour >= 12 { "PM" } else { "AM" }),
+            'P' => String::from(if time.hour >= 12 { "pm" } else { "am" }),
+            'R' => format!("{:02}:{:02}", time.hour, time.minute),
+            's' => match time.subsec {
+                true => float("%.3f", (time.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+                false => (time.jd / 1000 - UNIX_EPOCH_JD / 1000).to_string(),
+            },
+            'S' => format!("{:02}", time.second as i64),
+            'T' => format!(
+                "{:02}:{:02}:{:02}",
+                time.hour, time.minute, time.second as i64
+            ),
+            'u' => match time.days_after_sunday() {
+                0 => String::from("7"),
+                day => day.to_string(),
+            },
+            'w' => time.days_after_sunday().to_string(),
+            'U' => format!(
+                "{:02}",
+                (time.days_after_jan01() - time.days_after_sunday() + 7) / 7
+            ),
+            'V' => format!("{:02}", time.thursday().days_after_jan01() / 7 + 1),
+            'W' => format!(
+                "{:02}",
+                (time.days_after_jan01() - time.days_after_monday() + 7) / 7
+            ),
+            'Y' => format!("{:04}", time.year),
+            '%' => String::from("%"),
+            _ => return None,
+        };
+        out.push_str(&text);
+    }
+    Some(out)
+}
+
+// how long after `to` `from` is, as +YYYY-MM-DD HH:MM:SS.SSS, counting whole
+// years and months by the calendar. negative when `from` is earlier.
+fn timediff(from: &Value, to: &Value, clock: Clock) -> Option<String> {
+    let mut from = DateTime::new(std::slice::from_ref(from), clock)?;
+    let mut to = DateTime::new(std::slice::from_ref(to), clock)?;
+    from.compute_ymd_hms();
+    to.compute_ymd_hms();
+
+    // move `to` by whole years and months until it's as close to `from` as
+    // it gets without passing it
+    let later = from.jd >= to.jd;
+    let mut years = match later {
+        true => from.year - to.year,
+        false => to.year - from.year,
+    };
+    if years != 0 {
+        to.year = from.year;
+        to.valid_jd = false;
+        to.compute_jd();
+    }
+    let mut months = match later {
+        true => from.month - to.month,
+        false => to.month - from.month,
+    };
+    if months < 0 {
+        years -= 1;
+        months += 12;
+    }
+    if months != 0 {
+        to.month = from.month;
+        to.valid_jd = false;
+        to.compute_jd();
+    }
+    while (later && from.jd < to.jd) || (!later && from.jd > to.jd) {
+        months -= 1;
+        if months < 0 {
+            months = 11;
+            years -= 1;
+        }
+        if later {
+            to.month -= 1;
+            if to.month < 1 {
+                to.month = 12;
+                to.year -= 1;
+            }
+        } else {
+            to.month += 1;
+            if to.month > 12 {
+                to.month = 1;
+                to.year += 1;
+            }
+        }
+        to.valid_jd = false;
+        to.compute_jd();
+    }
+
+    // what's left is less than a month, shown as days since 0000-01-01
+    let rest = match later {
+        true => from.jd - to.jd,
+        false => to.jd - from.jd,
+    };
+    let mut rest = DateTime {
+        jd: rest + 148_699_540_800_000,
+        valid_jd: true,
+        ..DateTime::default()
+    };
+    rest.compute_ymd_hms();
+
+    let sign = if later { '+' } else { '-' };
+    Some(format!(
+        "{}{:04}-{:02}-{:02} {:02}:{:02}:{}",
+        sign,
+        years,
+        months,
+        rest.day - 1,
+        rest.hour,
+        rest.minute,
+        printf::format("%06.3f", &[Value::Float(rest.second)])
+    ))
+}
+
+// byte i of a string, or 0 past its end, the way sqlite walks its
+// nul-terminated strings
+fn at(z: &[u8], i: usize) -> u8 {
+    z.get(i).copied().unwrap_or(0)
+}
+
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r')
+}
+
+// reads fixed width numbers. the format has four characters for each: how
+// many digits it has, the smallest value, the largest value as a letter (see
+// MAXIMUMS) and the character that has to follow it, if any. e.g.
+// "40f-21a-21d" is YYYY-MM-DD. stops at the first number that doesn't fit.
+fn digits(mut z: &[u8], format: &str) -> Vec<i64> {
+    const MAXIMUMS: [i64; 6] = [12, 14, 24, 31, 59, 14712];
+
+    let mut values = vec![];
+    for spec in format.as_bytes().chunks(4) {
+        let count = (spec[0] - b'0') as usize;
+        let min = (spec[1] - b'0') as i64;
+        let max = MAXIMUMS[(spec[2] - b'a') as usize];
+        let next = spec.get(3).copied();
+
+        let mut value = 0;
+        for i in 0..count {
+            if !at(z, i).is_ascii_digit() {
+                return values;
+            }
+            value = value * 10 + (at(z, i) - b'0') as i64;
+        }
+        if value < min || value > max || next.is_some_and(|next| at(z, count) != next) {
+            return values;
+        }
+        values.push(value);
+        z = z.get(count + 1..).unwrap_or_default();
+    }
+    values
+}
+
+// a number that fills the whole string, give or take spaces around it
+fn number(z: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(z)
+        .ok()?
+        .trim_matches(|c| is_space(c as u8));
+    if !text
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+    {
+        return None;
+    }
+    text.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-05 12:34:56.789 UTC, in a zone an hour ahead
+    const CLOCK: Clock = Clock {
+        now: Some(1_709_642_096_789),
+        utc_offset: Some(3600),
+    };
+
+    fn call(function: Function, args: &[&str]) -> Value {
+        let args: Vec<Value> = args.iter().map(|arg| Value::from(*arg)).collect();
+        function.call(&args, CLOCK)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(String::from(s))
+    }
+
+    #[test]
+    fn test_parse() {
+        let datetime = |time: &str| call(Function::DateTime, &[time]);
+
+        assert_eq!(datetime("2024-03-05"), text("2024-03-05 00:00:00"));
+        assert_eq!(datetime("2024-03-05T10:20"), text("2024-03-05 10:20:00"));
+        assert_eq!(
+            datetime("2024-03-05 10:20:30+02:00"),
+            text("2024-03-05 08:20:30")
+        );
+        assert_eq!(datetime("2023-02-31"), text("2023-03-03 00:00:00"));
+        assert_eq!(datetime("12:30"), text("2000-01-01 12:30:00"));
+        assert_eq!(datetime("2460375.5"), text("2024-03-06 00:00:00"));
+        assert_eq!(datetime("now"), text("2024-03-05 12:34:56"));
+        assert_eq!(datetime("2024-13-01"), Value::Null);
+        assert_eq!(datetime("yesterday"), Value::Null);
+        assert_eq!(Function::DateTime.call(&[Value::Null], CLOCK), Value::Null);
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let datetime = |args: &[&str]| call(Function::DateTime, args);
+
+        assert_eq!(
+            datetime(&["2024-01-31", "+1 month"]),
+            text("2024-03-02 00:00:00")
+        );
+        assert_eq!(
+            datetime(&["2024-01-31", "+1 month", "floor"]),
+            text("2024-02-29 00:00:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05 12:00", "-1.5 hours"]),
+            text("2024-03-05 10:30:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05 12:00", "+01:30"]),
+            text("2024-03-05 13:30:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05", "+0001-02-03 04:05"]),
+            text("2025-05-08 04:05:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05 10:00", "start of month"]),
+            text("2024-03-01 00:00:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05", "weekday 0"]),
+            text("2024-03-10 00:00:00")
+        );
+        assert_eq!(
+            datetime(&["1709642096", "unixepoch"]),
+            text("2024-03-05 12:34:56")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05 12:00", "localtime"]),
+            text("2024-03-05 13:00:00")
+        );
+        assert_eq!(
+            datetime(&["2024-03-05 12:00", "utc"]),
+            text("2024-03-05 11:00:00")
+        );
+        assert_eq!(
+            datetime(&["now", "subsec"]),
+            text("2024-03-05 12:34:56.789")
+        );
+        assert_eq!(datetime(&["2024-03-05", "+1 fortnight"]), Value::Null);
+        assert_eq!(datetime(&["2024-03-05", "unixepoch"]), Value::Null);
+    }
+
+    #[test]
+    fn test_strftime() {
+        let strftime = |format: &str, time: &str| call(Function::Strftime, &[format, time]);
+
+        assert_eq!(
+            strftime("%Y-%m-%d %H:%M:%f", "2024-03-05 12:34:56.789"),
+            text("2024-03-05 12:34:56.789")
+        );
+        assert_eq!(
+            strftime("%j %w %u %U %W %V %G", "2024-01-01"),
+            text("001 1 1 00 01 01 2024")
+        );
+        assert_eq!(strftime("%V %G %g", "2021-01-03"), text("53 2020 20"));
+        assert_eq!(
+            strftime("%I %l %p %P", "2024-03-05 00:05"),
+            text("12 12 AM am")
+        );
+        assert_eq!(
+            strftime("%s %J", "2000-01-01 12:00"),
+            text("946728000 2451545")
+        );
+        assert_eq!(strftime("%Q", "2024-03-05"), Value::Null);
+        assert_eq!(
+            call(Function::UnixEpoch, &["2024-03-05 12:34:56.789", "subsec"]),
+            Value::Float(1709642096.789)
+        );
+        assert_eq!(
+            call(Function::JulianDay, &["2000-01-01 12:00"]),
+            Value::Float(2451545.0)
+        );
+    }
+
+    #[test]
+    fn test_timediff() {
+        let timediff = |from: &str, to: &str| call(Function::TimeDiff, &[from, to]);
+
+        assert_eq!(
+            timediff("2024-03-05", "2023-01-31 10:00"),
+            text("+0001-01-02 14:00:00.000")
+        );
+        assert_eq!(
+            timediff("2023-01-31 10:00", "2024-03-05"),
+            text("-0001-01-04 14:00:00.000")
+        );
+        assert_eq!(timediff("2024-03-05", "bad"), Value::Null);
+    }
+
+    #[test]
+    fn test_zone() {
+        // a version 1 TZif file with one transition, from UTC+1 to UTC+2
+        let mut data = b"TZif\0".to_vec();
+        data.extend([0; 15]);
+        for count in [0u32, 0, 0, 1, 2, 0] {
+            data.extend(count.to_be_bytes());
+        }
+        data.extend(1000i32.to_be_bytes());
+        data.push(1);
+        data.extend(3600i32.to_be_bytes());
+        data.extend([0, 0]);
+        data.extend(7200i32.to_be_bytes());
+        data.extend([1, 0]);
+
+        let zone = Zone::parse(&data).unwrap();
+        assert_eq!(zone.offset_at(999), 3600);
+        assert_eq!(zone.offset_at(1000), 7200);
+        assert!(Zone::parse(b"not a zone").is_none());
+    }
+}
@@ -1,9 +1,16 @@
-use std::fs::File;
-
-use crate::schema::Table;
+use crate::{
+    datetime::Clock,
+    pager::Pager,
+    schema::{Index, Table},
+};
 
 pub struct Db {
-    pub file: File,
-    pub page_size: u16,
+    pub pager: Pager,
     pub tables: Vec<Table>,
+    pub indexes: Vec<Index>,
+    // how much memory ORDER BY and GROUP BY can use before they spill rows to
+    // temp files
+    pub sort_memory: usize,
+    // what 'now' and 'localtime' mean to the date and time functions
+    pub clock: Clock,
 }
@@ -0,0 +1,76 @@
+use std::{fmt, io};
+
+use crate::parser::ParseError;
+
+// everything that can go wrong while opening a database or running a query.
+// nothing in the crate panics on bad input: a broken file or a query we can't
+// run comes back as one of these instead.
+#[derive(Debug)]
+pub enum Error {
+    // reading the file failed
+    Io(io::Error),
+    // the file doesn't start with a valid sqlite header
+    NotADatabase,
+    // a page holds something that can't be right, e.g. a cell pointer past the
+    // end of the page. offset is where on the page we found the problem.
+    CorruptPage { page: u32, offset: usize },
+    // the sql couldn't be parsed. position is the byte offset of the problem.
+    Parse { message: String, position: usize },
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    // a query that parses but doesn't make sense, e.g. an ambiguous column name
+    Invalid(String),
+    // binding a parameter the statement doesn't have, or running a statement
+    // with a parameter left unbound
+    Parameter(String),
+    // valid sqlite that we can't handle (yet), e.g. WITHOUT ROWID tables
+    Unsupported(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// the messages follow sqlite's where there is one
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::NotADatabase => write!(f, "file is not a database"),
+            Error::CorruptPage { page, offset } => write!(
+                f,
+                "database disk image is malformed (page {}, offset {})",
+                page, offset
+            ),
+            Error::Parse { message, position } => {
+                write!(f, "{} (at position {})", message, position)
+            }
+            Error::NoSuchTable(name) => write!(f, "no such table: {}", name),
+            Error::NoSuchColumn(name) => write!(f, "no such column: {}", name),
+            Error::Invalid(message) | Error::Parameter(message) => write!(f, "{}", message),
+            Error::Unsupported(feature) => write!(f, "not supported: {}", feature),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse {
+            message: e.message,
+            position: e.position,
+        }
+    }
+}
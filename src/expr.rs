@@ -0,0 +1,1576 @@
+use std::{cmp::Ordering, rc::Rc};
+
+use crate::{
+    affinity::Affinity,
+    aggregate::{self, Aggregate},
+    ast::{self, BinaryOperator, UnaryOperator},
+    collation::Collation,
+    datetime::{self, Clock},
+    db::Db,
+    error::{Error, Result},
+    function::Function,
+    json,
+    query::{self, CommonTables, Subquery},
+    schema::Table,
+    sort::SortKey,
+    value::Value,
+    window::{self, Bound, Call, Frame, Spec, Window, Windows},
+};
+
+// An expression that is ready to be evaluated against a row.
+//
+// The parser gives us column references by name (ast::Expr::Column). Looking
+// names up for every single row would be slow, so before running a query we
+// "compile" the ast into this type, which refers to columns by their position
+// in the row instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column(usize),
+    // replaced with the bound value before the expression is evaluated, see
+    // bind
+    Parameter(usize),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    // evaluates to the inner value. the collation is picked up by whatever
+    // compares it, see Expr::collation.
+    Collate(Box<Expr>, Collation),
+    // the inner value converted with the affinity, for the sides of a
+    // comparison that need it, see Scope::converted
+    Affinity(Box<Expr>, Affinity),
+    // the first column of the subquery's first row, or NULL without one
+    Subquery(Rc<Subquery>),
+    InSubquery {
+        expr: Box<Expr>,
+        subquery: Rc<Subquery>,
+        // what the subquery's values are converted with before they're
+        // compared with x
+        affinity: Affinity,
+        negated: bool,
+    },
+    Exists(Rc<Subquery>),
+    // a call to an ordinary function, like upper(Name)
+    Function(Function, Vec<Expr>),
+    // a call to a date and time function, with the clock that 'now' and
+    // 'localtime' read
+    DateTime(datetime::Function, Clock, Vec<Expr>),
+    // a call to a JSON function, or the -> and ->> operators
+    Json(json::Function, Vec<Expr>),
+}
+
+// A scope knows which tables a query reads from and where each table's
+// columns end up in the row that expressions are evaluated against.
+//
+// Every table contributes its columns followed by its rowid:
+//
+//   albums: [AlbumId, Title, ArtistId, rowid]
+//            0        1      2         3
+//
+// a join lines its tables up one after the other, so in albums JOIN artists
+// the columns of artists start at 4.
+//
+// a subquery can refer to the tables of the queries around it, so its scope
+// starts with theirs (see nest) and its rows start with the row of the query
+// it's in.
+#[derive(Clone)]
+pub struct Scope<'a> {
+    tables: Vec<ScopeTable>,
+    // where aggregate calls are collected while compiling with aggregates
+    // allowed, see compile_with_aggregates
+    aggregates: Option<Vec<Aggregate>>,
+    // whether we're compiling the arguments of an aggregate
+    in_aggregate: bool,
+    // where window function calls are collected while compiling with them
+    // allowed, see compile_with_windows
+    windows: Option<Windows>,
+    // subqueries are planned as they're compiled, which needs the schema.
+    // None where subqueries can't be used.
+    db: Option<&'a Db>,
+    // how many queries this one is nested in
+    depth: usize,
+    // how much of the row belongs to the queries this one is nested in
+    outer_width: usize,
+    // the columns of those queries this one refers to
+    outer_columns: Vec<usize>,
+    // the tables defined by WITH clauses that this query can read
+    common_tables: CommonTables,
+}
+
+#[derive(Clone)]
+struct ScopeTable {
+    // the alias if the query gave one, otherwise the table name
+    name: String,
+    columns: Vec<String>,
+    // the collation each column was declared with
+    collations: Vec<Collation>,
+    // the affinity each column's declared type gives it
+    affinities: Vec<Affinity>,
+    offset: usize,
+    // which columns the query refers to, so that we only decode those
+    used: Vec<bool>,
+    // columns that USING or NATURAL joined to a column of an earlier table.
+    // they only appear once in * and unqualified names mean the earlier one,
+    // but table.* and table.column still reach them.
+    merged: Vec<bool>,
+    // the depth of the query the table belongs to
+    depth: usize,
+    // the rows of a subquery in FROM don't have a rowid that can be referred
+    // to, but they still take up a place for one
+    rowid: bool,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new() -> Scope<'a> {
+        Scope {
+            tables: vec![],
+            aggregates: None,
+            in_aggregate: false,
+            windows: None,
+            db: None,
+            depth: 0,
+            outer_width: 0,
+            outer_columns: vec![],
+            common_tables: CommonTables::default(),
+        }
+    }
+
+    // a scope where expressions can contain subqueries
+    pub fn with_db(db: &'a Db) -> Scope<'a> {
+        Scope {
+            db: Some(db),
+            ..Scope::new()
+        }
+    }
+
+    // the scope of a subquery of this query. it starts with all of our tables.
+    pub fn nest(&self) -> Scope<'a> {
+        Scope {
+            tables: self.tables.clone(),
+            db: self.db,
+            depth: self.depth + 1,
+            outer_width: self.width(),
+            common_tables: self.common_tables.nested(),
+            ..Scope::new()
+        }
+    }
+
+    // the scope of a subquery that can't see our tables, like one in FROM.
+    // it can still read the tables WITH defines.
+    pub fn detach(&self) -> Scope<'a> {
+        Scope {
+            db: self.db,
+            common_tables: self.common_tables.nested(),
+            ..Scope::new()
+        }
+    }
+
+    pub fn common_tables(&self) -> &CommonTables {
+        &self.common_tables
+    }
+
+    pub fn common_tables_mut(&mut self) -> &mut CommonTables {
+        &mut self.common_tables
+    }
+
+    pub fn add_table(&mut self, name: &str, table: &Table) {
+        self.push_table(name, table, true);
+    }
+
+    pub fn add_subquery(&mut self, name: &str, table: &Table) {
+        self.push_table(name, table, false);
+    }
+
+    fn push_table(&mut self, name: &str, table: &Table, rowid: bool) {
+        let offset = self.width();
+        let columns = &table.column_names;
+
+        self.tables.push(ScopeTable {
+            name: name.to_string(),
+            columns: columns.clone(),
+            collations: table.collations.clone(),
+            affinities: table.affinities.clone(),
+            offset,
+            used: vec![false; columns.len()],
+            merged: vec![false; columns.len()],
+            depth: self.depth,
+            rowid,
+        });
+    }
+
+    pub fn merge_column(&mut self, table: usize, column: usize) {
+        self.tables[table].merged[column] = true;
+    }
+
+    // the number of tables in scope, including those of enclosing queries
+    pub fn num_tables(&self) -> usize {
+        self.tables.len()
+    }
+
+    // the number of values in a row of this scope
+    pub fn width(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|table| table.columns.len() + 1)
+            .sum()
+    }
+
+    pub fn outer_columns(&self) -> &[usize] {
+        &self.outer_columns
+    }
+
+    // takes note of the outer columns referred to by a query planned in a
+    // copy of this scope, like one of the SELECTs of a compound
+    pub fn add_outer_columns(&mut self, other: &Scope) {
+        for &index in &other.outer_columns {
+            if !self.outer_columns.contains(&index) {
+                self.outer_columns.push(index);
+            }
+        }
+    }
+
+    // which columns of the nth table have been referred to so far
+    pub fn used_columns(&self, table: usize) -> &[bool] {
+        &self.tables[table].used
+    }
+
+    // the name (or alias) of the table this position in the row belongs to
+    pub fn table_name(&self, index: usize) -> Option<&str> {
+        self.table_at(index).map(|table| table.name.as_str())
+    }
+
+    fn table_at(&self, index: usize) -> Option<&ScopeTable> {
+        self.tables
+            .iter()
+            .find(|table| (table.offset..=table.offset + table.columns.len()).contains(&index))
+    }
+
+    // the name of the column at this position in the row, as it was declared
+    // in the CREATE TABLE statement. rowids don't have one.
+    pub fn column_name(&self, index: usize) -> Option<&str> {
+        let table = self.table_at(index)?;
+        table
+            .columns
+            .get(index - table.offset)
+            .map(|name| name.as_str())
+    }
+
+    // the collation declared on the column an expression reads, when it's a
+    // plain column of one of our tables (+x counts too). a COLLATE wins over
+    // it, see compile_compared.
+    pub fn column_collation(&self, expr: &Expr) -> Option<Collation> {
+        let index = match expr {
+            Expr::Column(index) => index,
+            Expr::Unary(UnaryOperator::Plus, expr) | Expr::Affinity(expr, _) => {
+                return self.column_collation(expr);
+            }
+            _ => return None,
+        };
+        let table = self.table_at(*index)?;
+        Some(
+            table
+                .collations
+                .get(index - table.offset)
+                .copied()
+                .unwrap_or_default(),
+        )
+    }
+
+    // a column declared with a collation, marked with it as if it had a
+    // COLLATE. this is for where its values are compared among themselves,
+    // like GROUP BY and DISTINCT.
+    pub fn with_column_collation(&self, expr: Expr) -> Expr {
+        match (expr.collation(), self.column_collation(&expr)) {
+            (None, Some(collation)) if collation != Collation::Binary => {
+                Expr::Collate(Box::new(expr), collation)
+            }
+            _ => expr,
+        }
+    }
+
+    // the affinity of the values an expression gives: a column's comes from
+    // its declared type and a rowid is an integer. a COLLATE keeps the one of
+    // what it's on, anything else has none (not even +x).
+    pub fn affinity(&self, expr: &Expr) -> Affinity {
+        match expr {
+            Expr::Column(index) => {
+                let Some(table) = self.table_at(*index) else {
+                    return Affinity::None;
+                };
+                match table.affinities.get(index - table.offset) {
+                    Some(affinity) => *affinity,
+                    None if index - table.offset == table.columns.len() && table.rowid => {
+                        Affinity::Integer
+                    }
+                    None => Affinity::Blob,
+                }
+            }
+            Expr::Collate(expr, _) => self.affinity(expr),
+            Expr::Affinity(_, affinity) => *affinity,
+            Expr::Subquery(subquery) => subquery.affinity(),
+            _ => Affinity::None,
+        }
+    }
+
+    // the expression with its values converted to `affinity` before they're
+    // compared, unless they have it already. constants are converted now.
+    pub fn converted(&self, expr: Expr, affinity: Affinity) -> Expr {
+        if !self.affinity(&expr).converts(affinity) {
+            return expr;
+        }
+        match expr {
+            Expr::Literal(value) => Expr::Literal(affinity.apply(value)),
+            expr => Expr::Affinity(Box::new(expr), affinity),
+        }
+    }
+
+    // sqlite compares two values with the collation of a COLLATE on the left,
+    // or else one on the right, or else the collation declared on the left
+    // column, or else the right column's. evaluating only looks for COLLATE,
+    // so a collation that comes from a column is written onto the right hand
+    // side as one.
+    fn compile_compared(&mut self, left: &Expr, right: &ast::Expr) -> Result<Expr> {
+        let right = self.compile(right)?;
+        if left.collation().is_some() || right.collation().is_some() {
+            return Ok(right);
+        }
+
+        Ok(
+            match self
+                .column_collation(left)
+                .or_else(|| self.column_collation(&right))
+            {
+                Some(collation) if collation != Collation::Binary => {
+                    Expr::Collate(Box::new(right), collation)
+                }
+                _ => right,
+            },
+        )
+    }
+
+    // expands * (table is None) or table.* into the columns it stands for
+    pub fn expand_star(&mut self, table_name: Option<&str>) -> Result<Vec<(String, usize)>> {
+        let depth = self.depth;
+        let mut tables = self
+            .tables
+            .iter_mut()
+            .filter(|table| table.depth == depth)
+            .peekable();
+        if tables.peek().is_none() {
+            return Err(Error::Invalid(String::from("no tables specified")));
+        }
+
+        let mut columns = vec![];
+
+        for table in tables {
+            if let Some(table_name) = table_name
+                && !table.name.eq_ignore_ascii_case(table_name)
+            {
+                continue;
+            }
+
+            for (i, name) in table.columns.iter().enumerate() {
+                if table_name.is_none() && table.merged[i] {
+                    continue;
+                }
+                table.used[i] = true;
+                columns.push((name.clone(), table.offset + i));
+            }
+        }
+
+        if let Some(table_name) = table_name
+            && columns.is_empty()
+        {
+            return Err(Error::NoSuchTable(table_name.to_string()));
+        }
+
+        Ok(columns)
+    }
+
+    // names are looked up in our own tables first, then in the tables of the
+    // query we're nested in, and so on outwards
+    pub fn resolve(&mut self, table_name: Option<&str>, name: &str) -> Result<usize> {
+        for depth in (0..=self.depth).rev() {
+            let Some(index) = self.resolve_at(depth, table_name, name)? else {
+                continue;
+            };
+
+            if depth < self.depth {
+                self.outer_columns.push(index);
+            }
+            return Ok(index);
+        }
+
+        match table_name {
+            Some(table_name) => Err(Error::NoSuchColumn(format!("{}.{}", table_name, name))),
+            None => Err(Error::NoSuchColumn(name.to_string())),
+        }
+    }
+
+    fn resolve_at(
+        &mut self,
+        depth: usize,
+        table_name: Option<&str>,
+        name: &str,
+    ) -> Result<Option<usize>> {
+        let tables = self.tables.iter_mut().filter(|table| {
+            table.depth == depth
+                && match table_name {
+                    Some(table_name) => table.name.eq_ignore_ascii_case(table_name),
+                    None => true,
+                }
+        });
+
+        let mut found = None;
+
+        // identifiers are case-insensitive in sqlite
+        for table in tables {
+            let index = match table
+                .columns
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(name))
+            {
+                Some(index) if table_name.is_none() && table.merged[index] => continue,
+                Some(index) => {
+                    table.used[index] = true;
+                    table.offset + index
+                }
+                // rowid, oid and _rowid_ refer to the rowid unless the table has a
+                // real column with that name
+                None if table.rowid && is_rowid_name(name) => table.offset + table.columns.len(),
+                None => continue,
+            };
+
+            if found.is_some() {
+                return Err(Error::Invalid(format!("ambiguous column name: {}", name)));
+            }
+            found = Some(index);
+        }
+
+        Ok(found)
+    }
+
+    // the first column called `name` in any of our tables, for USING and
+    // NATURAL. a name that more than one table has isn't ambiguous here: the
+    // leftmost table wins. rowids don't count.
+    pub fn resolve_using(&mut self, name: &str) -> Option<usize> {
+        let depth = self.depth;
+        self.tables
+            .iter_mut()
+            .filter(|table| table.depth == depth)
+            .find_map(|table| {
+                let index = table
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .position(|(i, c)| !table.merged[i] && c.eq_ignore_ascii_case(name))?;
+                table.used[index] = true;
+                Some(table.offset + index)
+            })
+    }
+
+    // takes note of the columns a subquery compiled in `inner` (see nest)
+    // refers to: we have to read them, and if they belong to a query around
+    // us, we refer to them too
+    fn absorb(&mut self, inner: &Scope) {
+        for &index in &inner.outer_columns {
+            if let Some(table) = self
+                .tables
+                .iter_mut()
+                .find(|table| (table.offset..table.offset + table.columns.len()).contains(&index))
+            {
+                table.used[index - table.offset] = true;
+            }
+
+            if index < self.outer_width {
+                self.outer_columns.push(index);
+            }
+        }
+    }
+
+    // compiles an expression that may call aggregate functions, like a result
+    // column of an aggregate query. each call is added to `aggregates` and
+    // compiles to the column where its result will be: aggregate queries are
+    // evaluated against rows that hold a row of the scope followed by the
+    // result of each aggregate.
+    pub fn compile_with_aggregates(
+        &mut self,
+        expr: &ast::Expr,
+        aggregates: &mut Vec<Aggregate>,
+    ) -> Result<Expr> {
+        self.aggregates = Some(std::mem::take(aggregates));
+        let compiled = self.compile(expr);
+        *aggregates = self.aggregates.take().unwrap_or_default();
+        compiled
+    }
+
+    // compiles an expression that may call window functions, like a result
+    // column. each call is added to `windows` and compiles to a placeholder
+    // column, see window::PLACEHOLDER.
+    pub fn compile_with_windows(
+        &mut self,
+        expr: &ast::Expr,
+        aggregates: Option<&mut Vec<Aggregate>>,
+        windows: &mut Windows,
+    ) -> Result<Expr> {
+        self.windows = Some(std::mem::take(windows));
+        let compiled = match aggregates {
+            Some(aggregates) => self.compile_with_aggregates(expr, aggregates),
+            None => self.compile(expr),
+        };
+        *windows = self.windows.take().unwrap_or_default();
+        compiled
+    }
+
+    pub fn compile(&mut self, expr: &ast::Expr) -> Result<Expr> {
+        Ok(match expr {
+            ast::Expr::Literal(value) => Expr::Literal(value.clone()),
+            ast::Expr::Parameter(number) => Expr::Parameter(*number),
+            ast::Expr::Column { table, name } => {
+                Expr::Column(self.resolve(table.as_deref(), name)?)
+            }
+            ast::Expr::Unary { op, expr } => Expr::Unary(*op, Box::new(self.compile(expr)?)),
+            ast::Expr::Binary {
+                op: op @ (BinaryOperator::Extract | BinaryOperator::ExtractValue),
+                left,
+                right,
+            } => Expr::Json(
+                match op {
+                    BinaryOperator::Extract => json::Function::Arrow,
+                    _ => json::Function::DoubleArrow,
+                },
+                vec![self.compile(left)?, self.compile(right)?],
+            ),
+            ast::Expr::Binary { op, left, right } => {
+                let left = self.compile(left)?;
+                if !is_comparison(*op) {
+                    return Ok(Expr::Binary(
+                        *op,
+                        Box::new(left),
+                        Box::new(self.compile(right)?),
+                    ));
+                }
+
+                // AlbumId = '5' is true for album 5: both sides are converted
+                // to the affinity they have in common first
+                let right = self.compile_compared(&left, right)?;
+                let affinity = Affinity::comparison(self.affinity(&left), self.affinity(&right));
+                Expr::Binary(
+                    *op,
+                    Box::new(self.converted(left, affinity)),
+                    Box::new(self.converted(right, affinity)),
+                )
+            }
+            ast::Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                // x BETWEEN low AND high is x >= low AND x <= high, and each of
+                // those picks its own affinity
+                let expr = self.compile(expr)?;
+                let low = self.compile_compared(&expr, low)?;
+                let high = self.compile_compared(&expr, high)?;
+                let affinity = self.affinity(&expr);
+                let low_affinity = Affinity::comparison(affinity, self.affinity(&low));
+                let high_affinity = Affinity::comparison(affinity, self.affinity(&high));
+                let low = self.converted(low, low_affinity);
+                let high = self.converted(high, high_affinity);
+
+                let low_expr = self.converted(expr.clone(), low_affinity);
+                let high_expr = self.converted(expr, high_affinity);
+                // when x is converted one way for low and another for high,
+                // it has to be two comparisons
+                if low_expr != high_expr {
+                    let between = Expr::Binary(
+                        BinaryOperator::And,
+                        Box::new(Expr::Binary(
+                            BinaryOperator::GtEq,
+                            Box::new(low_expr),
+                            Box::new(low),
+                        )),
+                        Box::new(Expr::Binary(
+                            BinaryOperator::LtEq,
+                            Box::new(high_expr),
+                            Box::new(high),
+                        )),
+                    );
+                    return Ok(match negated {
+                        true => Expr::Unary(UnaryOperator::Not, Box::new(between)),
+                        false => between,
+                    });
+                }
+
+                Expr::Between {
+                    expr: Box::new(low_expr),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated: *negated,
+                }
+            }
+            ast::Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // the items are converted to x's affinity, x itself never is
+                let expr = self.compile(expr)?;
+                let affinity = Affinity::comparison(self.affinity(&expr), Affinity::None);
+                Expr::InList {
+                    list: list
+                        .iter()
+                        .map(|item| {
+                            let item = self.compile_compared(&expr, item)?;
+                            Ok(self.converted(item, affinity))
+                        })
+                        .collect::<Result<_>>()?,
+                    expr: Box::new(expr),
+                    negated: *negated,
+                }
+            }
+            ast::Expr::Collate { expr, collation } => Expr::Collate(
+                Box::new(self.compile(expr)?),
+                Collation::from_name(collation)?,
+            ),
+            ast::Expr::Function {
+                name,
+                args,
+                distinct,
+                over: None,
+            } => self.compile_function(name, args, *distinct)?,
+            ast::Expr::Function {
+                name,
+                args,
+                distinct,
+                over: Some(over),
+            } => self.compile_window(name, args, *distinct, over)?,
+            ast::Expr::Subquery(select) => Expr::Subquery(self.compile_subquery(select, true)?),
+            ast::Expr::InSelect {
+                expr,
+                select,
+                negated,
+            } => {
+                // the values the subquery returns are compared with x's
+                // collation, whether it has a COLLATE or was declared with one
+                let expr = self.compile(expr)?;
+                let expr = self.with_column_collation(expr);
+                let subquery = self.compile_subquery(select, true)?;
+                let affinity = Affinity::comparison(self.affinity(&expr), subquery.affinity());
+                Expr::InSubquery {
+                    expr: Box::new(self.converted(expr, affinity)),
+                    subquery,
+                    affinity,
+                    negated: *negated,
+                }
+            }
+            ast::Expr::Exists(select) => Expr::Exists(self.compile_subquery(select, false)?),
+        })
+    }
+
+    // `single` is for subqueries that stand for a value, which have to return
+    // exactly one column
+    fn compile_subquery(&mut self, select: &ast::Select, single: bool) -> Result<Rc<Subquery>> {
+        let Some(db) = self.db else {
+            return Err(Error::Invalid(String::from(
+                "subqueries are not supported here",
+            )));
+        };
+
+        let mut inner = self.nest();
+        let subquery = query::prepare_subquery(db, select, &mut inner)?;
+        self.absorb(&inner);
+
+        let columns = subquery.num_columns();
+        if single && columns != 1 {
+            return Err(Error::Invalid(format!(
+                "sub-select returns {} columns - expected 1",
+                columns
+            )));
+        }
+
+        Ok(Rc::new(subquery))
+    }
+
+    fn compile_function(&mut self, name: &str, args: &[ast::Expr], distinct: bool) -> Result<Expr> {
+        if window::Function::find(name, args.len())?.is_some() {
+            return Err(Error::Invalid(format!(
+                "misuse of window function {}()",
+                name
+            )));
+        }
+        let Some(function) = aggregate::Function::find(name, args.len())? else {
+            let function = Function::find(name, args.len())?;
+            let datetime = match function {
+                Some(_) => None,
+                None => datetime::Function::find(name, args.len())?,
+            };
+            let json = match (function, datetime) {
+                (None, None) => json::Function::find(name, args.len())?,
+                _ => None,
+            };
+            if function.is_none() && datetime.is_none() && json.is_none() {
+                return Err(Error::Invalid(format!("no such function: {}", name)));
+            }
+            let args = args
+                .iter()
+                .map(|arg| self.compile(arg))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(match (function, datetime, json) {
+                // nullif, min and max compare their arguments, with the
+                // collation of one of them
+                (Some(function), _, _) => Expr::Function(
+                    function,
+                    args.into_iter()
+                        .map(|arg| self.with_column_collation(arg))
+                        .collect(),
+                ),
+                (_, Some(function), _) => {
+                    let clock = self.db.map(|db| db.clock).unwrap_or_default();
+                    Expr::DateTime(function, clock, args)
+                }
+                (_, _, Some(function)) => Expr::Json(function, args),
+                _ => unreachable!("checked above"),
+            });
+        };
+
+        // aggregates can't be used outside of compile_with_aggregates, e.g. in
+        // a WHERE clause, and they can't be nested. taking the list away while
+        // the arguments are compiled takes care of both.
+        let Some(mut aggregates) = self.aggregates.take() else {
+            return Err(Error::Invalid(match self.in_aggregate {
+                true => format!("misuse of aggregate function {}()", name),
+                false => format!("misuse of aggregate: {}()", name),
+            }));
+        };
+
+        // nor can window functions be used in their arguments
+        let windows = self.windows.take();
+        self.in_aggregate = true;
+        // min, max and DISTINCT compare the values of their argument
+        let args = args
+            .iter()
+            .map(|arg| {
+                let arg = self.compile(arg)?;
+                Ok(self.with_column_collation(arg))
+            })
+            .collect::<Result<Vec<_>>>();
+        self.in_aggregate = false;
+        self.windows = windows;
+        aggregates.push(Aggregate::new(function, args?, distinct)?);
+
+        let column = self.width() + aggregates.len() - 1;
+        self.aggregates = Some(aggregates);
+        Ok(json_result(function, Expr::Column(column)))
+    }
+
+    // a call with OVER. the arguments and the window are worked out from the
+    // rows the query outputs, so they can use aggregates in an aggregate
+    // query, but not other window functions.
+    fn compile_window(
+        &mut self,
+        name: &str,
+        args: &[ast::Expr],
+        distinct: bool,
+        over: &ast::WindowDefinition,
+    ) -> Result<Expr> {
+        let function = window::Function::find(name, args.len())?;
+        let aggregate = match function {
+            Some(_) => None,
+            None => aggregate::Function::find(name, args.len())?,
+        };
+        if function.is_none() && aggregate.is_none() {
+            let scalar = Function::find(name, args.len())?.is_some()
+                || datetime::Function::find(name, args.len())?.is_some()
+                || json::Function::find(name, args.len())?.is_some();
+            return Err(Error::Invalid(match scalar {
+                true => format!("{}() may not be used as a window function", name),
+                false => format!("no such function: {}", name),
+            }));
+        }
+
+        let Some(mut windows) = self.windows.take() else {
+            return Err(Error::Invalid(format!(
+                "misuse of window function {}()",
+                name
+            )));
+        };
+        if distinct {
+            return Err(Error::Invalid(String::from(
+                "DISTINCT is not supported for window functions",
+            )));
+        }
+
+        // like everything else here, the arguments, partitions and order are
+        // compared with the collation a column was declared with
+        let definition = windows.resolve(over)?;
+        let args = args
+            .iter()
+            .map(|arg| {
+                let arg = self.compile(arg)?;
+                Ok(self.with_column_collation(arg))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let call = match (function, aggregate) {
+            (Some(function), _) => Call::Function(function, args),
+            (_, Some(function)) => Call::Aggregate(Aggregate::new(function, args, false)?),
+            _ => unreachable!("checked above"),
+        };
+
+        let partition_by = definition
+            .partition_by
+            .iter()
+            .map(|expr| {
+                let expr = self.compile(expr)?;
+                Ok(self.with_column_collation(expr))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut order_by = vec![];
+        for term in &definition.order_by {
+            let expr = self.compile(&term.expr)?;
+            let expr = self.with_column_collation(expr);
+            let collation = match &term.collation {
+                Some(name) => Collation::from_name(name)?,
+                None => expr.collation().unwrap_or_default(),
+            };
+            let key = SortKey {
+                descending: term.descending,
+                nulls_first: term.nulls_first.unwrap_or(!term.descending),
+                collation,
+            };
+            order_by.push((expr, key));
+        }
+
+        // frame offsets are constants
+        let frame = match &definition.frame {
+            None => Frame::default_frame(),
+            Some(frame) => {
+                let bound = |bound: &ast::FrameBound| -> Result<Bound> {
+                    Ok(match bound {
+                        ast::FrameBound::UnboundedPreceding => Bound::UnboundedPreceding,
+                        ast::FrameBound::Preceding(offset) => {
+                            Bound::Preceding(self.detach().compile(offset)?)
+                        }
+                        ast::FrameBound::CurrentRow => Bound::CurrentRow,
+                        ast::FrameBound::Following(offset) => {
+                            Bound::Following(self.detach().compile(offset)?)
+                        }
+                        ast::FrameBound::UnboundedFollowing => Bound::UnboundedFollowing,
+                    })
+                };
+                let (start, end) = (bound(&frame.start)?, bound(&frame.end)?);
+                Frame::new(frame.units, start, end, order_by.len())?
+            }
+        };
+
+        windows.calls.push(Window {
+            call,
+            spec: Spec {
+                partition_by,
+                order_by,
+                frame,
+            },
+        });
+
+        let column = window::PLACEHOLDER + windows.calls.len() - 1;
+        self.windows = Some(windows);
+        Ok(match aggregate {
+            Some(function) => json_result(function, Expr::Column(column)),
+            None => Expr::Column(column),
+        })
+    }
+}
+
+// the result of json_group_array and json_group_object is JSON, which the
+// JSON functions it's passed to have to know. going through json() marks it
+// as such, see json::Function::returns_json.
+fn json_result(function: aggregate::Function, column: Expr) -> Expr {
+    match function {
+        aggregate::Function::JsonGroupArray | aggregate::Function::JsonGroupObject => {
+            Expr::Json(json::Function::Json, vec![column])
+        }
+        _ => column,
+    }
+}
+
+// the operators that compare their operands, with a collation
+fn is_comparison(op: BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Is
+            | BinaryOperator::IsNot
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq
+    )
+}
+
+pub fn is_rowid_name(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"]
+        .iter()
+        .any(|rowid| rowid.eq_ignore_ascii_case(name))
+}
+
+impl Expr {
+    // evaluating an expression fails when a subquery fails to read its rows,
+    // or when a function has no answer, like abs() of the smallest integer
+    pub fn eval(&self, row: &[Value]) -> Result<Value> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Column(index) => row[*index].clone(),
+            Expr::Collate(expr, _) => expr.eval(row)?,
+            Expr::Affinity(expr, affinity) => affinity.apply(expr.eval(row)?),
+            // statements check that every parameter is bound before they run,
+            // so this is never reached
+            Expr::Parameter(_) => Value::Null,
+            Expr::Unary(op, expr) => eval_unary(*op, expr.eval(row)?),
+            // AND and OR don't always need to look at the right hand side
+            Expr::Binary(BinaryOperator::And, left, right) => match left.eval(row)?.truthiness() {
+                Some(false) => Value::Integer(0),
+                left => and(left, right.eval(row)?.truthiness()),
+            },
+            Expr::Binary(BinaryOperator::Or, left, right) => match left.eval(row)?.truthiness() {
+                Some(true) => Value::Integer(1),
+                left => or(left, right.eval(row)?.truthiness()),
+            },
+            Expr::Binary(op, left, right) => eval_binary(
+                *op,
+                left.eval(row)?,
+                right.eval(row)?,
+                comparison_collation(left, right),
+            ),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                // x BETWEEN low AND high is the same as x >= low AND x <= high
+                let value = expr.eval(row)?;
+                let above = compare(
+                    &value,
+                    &low.eval(row)?,
+                    comparison_collation(expr, low),
+                    |o| o != Ordering::Less,
+                );
+                let below = compare(
+                    &value,
+                    &high.eval(row)?,
+                    comparison_collation(expr, high),
+                    |o| o != Ordering::Greater,
+                );
+                negate(and(above.truthiness(), below.truthiness()), *negated)
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                let list = list
+                    .iter()
+                    .map(|item| Ok((item.eval(row)?, comparison_collation(expr, item))))
+                    .collect::<Result<Vec<_>>>()?;
+                negate(in_list(value, list.into_iter()), *negated)
+            }
+            Expr::Subquery(subquery) => subquery.value(row)?,
+            Expr::InSubquery {
+                expr,
+                subquery,
+                affinity,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                negate(
+                    subquery.contains(value, expr.collation(), *affinity, row)?,
+                    *negated,
+                )
+            }
+            Expr::Exists(subquery) => boolean(subquery.exists(row)?),
+            // coalesce, ifnull and iif stop at the argument that decides
+            // the result
+            Expr::Function(Function::Coalesce | Function::IfNull, args) => {
+                for arg in args {
+                    let value = arg.eval(row)?;
+                    if !value.is_null() {
+                        return Ok(value);
+                    }
+                }
+                Value::Null
+            }
+            Expr::Function(Function::Iif, args) => match args[0].eval(row)?.truthiness() {
+                Some(true) => args[1].eval(row)?,
+                _ => match args.get(2) {
+                    Some(otherwise) => otherwise.eval(row)?,
+                    None => Value::Null,
+                },
+            },
+            Expr::Function(function, args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>>>()?;
+                let collation = args.iter().find_map(Expr::collation).unwrap_or_default();
+                function.call(&values, collation)?
+            }
+            Expr::DateTime(function, clock, args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>>>()?;
+                function.call(&values, *clock)
+            }
+            Expr::Json(function, args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>>>()?;
+                let json: Vec<bool> = args
+                    .iter()
+                    .zip(&values)
+                    .map(|(arg, value)| arg.returns_json(value))
+                    .collect();
+                function.call(&values, &json)?
+            }
+        })
+    }
+
+    // returns a copy of the expression with each parameter replaced by its
+    // value. parameters[0] is the value of ?1.
+    pub fn bind(&self, parameters: &[Value]) -> Expr {
+        let bind = |expr: &Expr| Box::new(expr.bind(parameters));
+
+        match self {
+            Expr::Parameter(number) => {
+                Expr::Literal(parameters.get(number - 1).cloned().unwrap_or(Value::Null))
+            }
+            Expr::Literal(_) | Expr::Column(_) => self.clone(),
+            Expr::Unary(op, expr) => Expr::Unary(*op, bind(expr)),
+            Expr::Binary(op, left, right) => Expr::Binary(*op, bind(left), bind(right)),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expr::Between {
+                expr: bind(expr),
+                low: bind(low),
+                high: bind(high),
+                negated: *negated,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: bind(expr),
+                list: list.iter().map(|item| item.bind(parameters)).collect(),
+                negated: *negated,
+            },
+            Expr::Collate(expr, collation) => Expr::Collate(bind(expr), *collation),
+            Expr::Affinity(expr, affinity) => Expr::Affinity(bind(expr), *affinity),
+            Expr::Subquery(subquery) => Expr::Subquery(Rc::new(subquery.bind(parameters))),
+            Expr::InSubquery {
+                expr,
+                subquery,
+                affinity,
+                negated,
+            } => Expr::InSubquery {
+                expr: bind(expr),
+                affinity: *affinity,
+                subquery: Rc::new(subquery.bind(parameters)),
+                negated: *negated,
+            },
+            Expr::Exists(subquery) => Expr::Exists(Rc::new(subquery.bind(parameters))),
+            Expr::Function(function, args) => Expr::Function(
+                *function,
+                args.iter().map(|arg| arg.bind(parameters)).collect(),
+            ),
+            // every row of a run sees the same 'now'
+            Expr::DateTime(function, clock, args) => Expr::DateTime(
+                *function,
+                clock.freeze(),
+                args.iter().map(|arg| arg.bind(parameters)).collect(),
+            ),
+            Expr::Json(function, args) => Expr::Json(
+                *function,
+                args.iter().map(|arg| arg.bind(parameters)).collect(),
+            ),
+        }
+    }
+
+    // a copy of the expression with each column moved to where `map` says.
+    // subqueries can't see the columns this is for, so they stay as they are.
+    pub fn map_columns(&self, map: &impl Fn(usize) -> usize) -> Expr {
+        let each = |expr: &Expr| Box::new(expr.map_columns(map));
+
+        match self {
+            Expr::Column(index) => Expr::Column(map(*index)),
+            Expr::Literal(_) | Expr::Parameter(_) | Expr::Subquery(_) | Expr::Exists(_) => {
+                self.clone()
+            }
+            Expr::Unary(op, expr) => Expr::Unary(*op, each(expr)),
+            Expr::Binary(op, left, right) => Expr::Binary(*op, each(left), each(right)),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expr::Between {
+                expr: each(expr),
+                low: each(low),
+                high: each(high),
+                negated: *negated,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: each(expr),
+                list: list.iter().map(|item| item.map_columns(map)).collect(),
+                negated: *negated,
+            },
+            Expr::Collate(expr, collation) => Expr::Collate(each(expr), *collation),
+            Expr::Affinity(expr, affinity) => Expr::Affinity(each(expr), *affinity),
+            Expr::InSubquery {
+                expr,
+                subquery,
+                affinity,
+                negated,
+            } => Expr::InSubquery {
+                expr: each(expr),
+                affinity: *affinity,
+                subquery: subquery.clone(),
+                negated: *negated,
+            },
+            Expr::Function(function, args) => Expr::Function(
+                *function,
+                args.iter().map(|arg| arg.map_columns(map)).collect(),
+            ),
+            Expr::DateTime(function, clock, args) => Expr::DateTime(
+                *function,
+                *clock,
+                args.iter().map(|arg| arg.map_columns(map)).collect(),
+            ),
+            Expr::Json(function, args) => Expr::Json(
+                *function,
+                args.iter().map(|arg| arg.map_columns(map)).collect(),
+            ),
+        }
+    }
+
+    // whether the expression reads a column that `test` is true for
+    pub fn uses_column(&self, test: &impl Fn(usize) -> bool) -> bool {
+        match self {
+            Expr::Column(index) => test(*index),
+            Expr::Literal(_) | Expr::Parameter(_) => false,
+            Expr::Unary(_, expr) | Expr::Collate(expr, _) | Expr::Affinity(expr, _) => {
+                expr.uses_column(test)
+            }
+            Expr::Binary(_, left, right) => left.uses_column(test) || right.uses_column(test),
+            Expr::Between {
+                expr, low, high, ..
+            } => expr.uses_column(test) || low.uses_column(test) || high.uses_column(test),
+            Expr::InList { expr, list, .. } => {
+                expr.uses_column(test) || list.iter().any(|item| item.uses_column(test))
+            }
+            Expr::Subquery(subquery) | Expr::Exists(subquery) => subquery.uses_column(test),
+            Expr::InSubquery { expr, subquery, .. } => {
+                expr.uses_column(test) || subquery.uses_column(test)
+            }
+            Expr::Function(_, args) | Expr::DateTime(_, _, args) | Expr::Json(_, args) => {
+                args.iter().any(|arg| arg.uses_column(test))
+            }
+        }
+    }
+
+    // whether the value the expression gave is JSON that a JSON function
+    // returned, rather than text that happens to look like it
+    pub fn returns_json(&self, value: &Value) -> bool {
+        matches!(self, Expr::Json(function, _) if function.returns_json(value))
+    }
+
+    // the collation the expression asked for with COLLATE, if any
+    pub fn collation(&self) -> Option<Collation> {
+        match self {
+            Expr::Collate(_, collation) => Some(*collation),
+            Expr::Affinity(expr, _) => expr.collation(),
+            _ => None,
+        }
+    }
+}
+
+// when two values are compared, a COLLATE on the left wins over one on the
+// right. without either the comparison is BINARY.
+fn comparison_collation(left: &Expr, right: &Expr) -> Collation {
+    left.collation()
+        .or_else(|| right.collation())
+        .unwrap_or_default()
+}
+
+fn boolean(b: bool) -> Value {
+    Value::Integer(b as i64)
+}
+
+// applies the NOT of NOT IN and NOT BETWEEN
+fn negate(value: Value, negated: bool) -> Value {
+    match negated {
+        true => eval_unary(UnaryOperator::Not, value),
+        false => value,
+    }
+}
+
+fn from_truthiness(b: Option<bool>) -> Value {
+    match b {
+        Some(b) => boolean(b),
+        None => Value::Null,
+    }
+}
+
+// sqlite uses three-valued logic: NULL means "unknown", so
+//
+//   NULL AND false = false   (false whatever the unknown value is)
+//   NULL AND true  = NULL
+//   NULL OR true   = true
+//   NULL OR false  = NULL
+fn and(left: Option<bool>, right: Option<bool>) -> Value {
+    match (left, right) {
+        (Some(false), _) | (_, Some(false)) => boolean(false),
+        (Some(true), Some(true)) => boolean(true),
+        _ => Value::Null,
+    }
+}
+
+fn or(left: Option<bool>, right: Option<bool>) -> Value {
+    match (left, right) {
+        (Some(true), _) | (_, Some(true)) => boolean(true),
+        (Some(false), Some(false)) => boolean(false),
+        _ => Value::Null,
+    }
+}
+
+// comparing anything with NULL gives NULL
+fn compare(
+    left: &Value,
+    right: &Value,
+    collation: Collation,
+    test: impl Fn(Ordering) -> bool,
+) -> Value {
+    if left.is_null() || right.is_null() {
+        return Value::Null;
+    }
+    boolean(test(collation.compare(left, right)))
+}
+
+// x IN (a, b, c) is true if x equals one of the items. if it doesn't, and one of
+// the items was NULL, we can't know for sure that x isn't in the list so the
+// result is NULL.
+pub fn in_list(value: Value, list: impl Iterator<Item = (Value, Collation)>) -> Value {
+    let mut saw_null = false;
+    let mut empty = true;
+
+    for (item, collation) in list {
+        empty = false;
+        if item.is_null() {
+            saw_null = true;
+        } else if !value.is_null() && collation.compare(&value, &item) == Ordering::Equal {
+            return boolean(true);
+        }
+    }
+
+    if empty {
+        boolean(false)
+    } else if saw_null || value.is_null() {
+        Value::Null
+    } else {
+        boolean(false)
+    }
+}
+
+fn eval_unary(op: UnaryOperator, value: Value) -> Value {
+    match op {
+        UnaryOperator::Not => from_truthiness(value.truthiness().map(|b| !b)),
+        UnaryOperator::Plus => value,
+        UnaryOperator::Negate => match value.to_numeric() {
+            Value::Integer(i) => match i.checked_neg() {
+                Some(negated) => Value::Integer(negated),
+                None => Value::Float(-(i as f64)),
+            },
+            Value::Float(f) => Value::Float(-f),
+            _ => Value::Null,
+        },
+        UnaryOperator::BitNot => match value.to_integer() {
+            Some(i) => Value::Integer(!i),
+            None => Value::Null,
+        },
+    }
+}
+
+// the collation only matters for comparisons
+fn eval_binary(op: BinaryOperator, left: Value, right: Value, collation: Collation) -> Value {
+    match op {
+        BinaryOperator::Eq => compare(&left, &right, collation, |o| o == Ordering::Equal),
+        BinaryOperator::NotEq => compare(&left, &right, collation, |o| o != Ordering::Equal),
+        BinaryOperator::Lt => compare(&left, &right, collation, |o| o == Ordering::Less),
+        BinaryOperator::LtEq => compare(&left, &right, collation, |o| o != Ordering::Greater),
+        BinaryOperator::Gt => compare(&left, &right, collation, |o| o == Ordering::Greater),
+        BinaryOperator::GtEq => compare(&left, &right, collation, |o| o != Ordering::Less),
+        BinaryOperator::Is => boolean(collation.compare(&left, &right) == Ordering::Equal),
+        BinaryOperator::IsNot => boolean(collation.compare(&left, &right) != Ordering::Equal),
+        BinaryOperator::And => and(left.truthiness(), right.truthiness()),
+        BinaryOperator::Or => or(left.truthiness(), right.truthiness()),
+        // these are compiled into calls of the JSON functions
+        BinaryOperator::Extract | BinaryOperator::ExtractValue => {
+            unreachable!("compiled to Expr::Json")
+        }
+        BinaryOperator::Concat => match (left.to_text(), right.to_text()) {
+            (Some(left), Some(right)) => Value::Text(left + &right),
+            _ => Value::Null,
+        },
+        BinaryOperator::BitAnd
+        | BinaryOperator::BitOr
+        | BinaryOperator::ShiftLeft
+        | BinaryOperator::ShiftRight => match (left.to_integer(), right.to_integer()) {
+            (Some(left), Some(right)) => Value::Integer(bitwise(op, left, right)),
+            _ => Value::Null,
+        },
+        BinaryOperator::Add
+        | BinaryOperator::Subtract
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => arithmetic(op, left.to_numeric(), right.to_numeric()),
+    }
+}
+
+fn bitwise(op: BinaryOperator, left: i64, right: i64) -> i64 {
+    // shifting by a negative amount shifts the other way
+    let (op, right) = match op {
+        BinaryOperator::ShiftLeft if right < 0 => (BinaryOperator::ShiftRight, -right),
+        BinaryOperator::ShiftRight if right < 0 => (BinaryOperator::ShiftLeft, -right),
+        _ => (op, right),
+    };
+
+    match op {
+        BinaryOperator::BitAnd => left & right,
+        BinaryOperator::BitOr => left | right,
+        BinaryOperator::ShiftLeft if right >= 64 => 0,
+        BinaryOperator::ShiftLeft => left << right,
+        BinaryOperator::ShiftRight if right >= 64 => {
+            if left < 0 {
+                -1
+            } else {
+                0
+            }
+        }
+        BinaryOperator::ShiftRight => left >> right,
+        _ => unreachable!(),
+    }
+}
+
+// integer arithmetic stays integer unless it overflows, in which case sqlite
+// switches to floating point. dividing by zero gives NULL rather than an error.
+fn arithmetic(op: BinaryOperator, left: Value, right: Value) -> Value {
+    match (left, right) {
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match op {
+                BinaryOperator::Add => a.checked_add(b),
+                BinaryOperator::Subtract => a.checked_sub(b),
+                BinaryOperator::Multiply => a.checked_mul(b),
+                BinaryOperator::Divide if b == 0 => return Value::Null,
+                BinaryOperator::Divide => a.checked_div(b),
+                BinaryOperator::Modulo if b == 0 => return Value::Null,
+                BinaryOperator::Modulo => Some(a.wrapping_rem(b)),
+                _ => unreachable!(),
+            };
+
+            match result {
+                Some(result) => Value::Integer(result),
+                None => arithmetic(op, Value::Float(a as f64), Value::Float(b as f64)),
+            }
+        }
+        (Value::Null, _) | (_, Value::Null) => Value::Null,
+        (left, right) => {
+            let a = left.to_float().unwrap();
+            let b = right.to_float().unwrap();
+
+            match op {
+                BinaryOperator::Add => Value::Float(a + b),
+                BinaryOperator::Subtract => Value::Float(a - b),
+                BinaryOperator::Multiply => Value::Float(a * b),
+                BinaryOperator::Divide if b == 0.0 => Value::Null,
+                BinaryOperator::Divide => Value::Float(a / b),
+                // the remainder of two floats is worked out on their integer
+                // parts, but the result stays a float: 5.5 % 2 == 1.0
+                BinaryOperator::Modulo => {
+                    let (a, b) = (a as i64, b as i64);
+                    if b == 0 {
+                        Value::Null
+                    } else {
+                        Value::Float(a.wrapping_rem(b) as f64)
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(value: Value) -> Box<Expr> {
+        Box::new(Expr::Literal(value))
+    }
+
+    fn eval_binary_literals(op: BinaryOperator, left: Value, right: Value) -> Value {
+        Expr::Binary(op, literal(left), literal(right))
+            .eval(&[])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        use BinaryOperator::{And, Or};
+
+        let t = Value::Integer(1);
+        let f = Value::Integer(0);
+
+        assert_eq!(eval_binary_literals(And, Value::Null, f.clone()), f);
+        assert_eq!(
+            eval_binary_literals(And, Value::Null, t.clone()),
+            Value::Null
+        );
+        assert_eq!(eval_binary_literals(Or, Value::Null, t.clone()), t);
+        assert_eq!(
+            eval_binary_literals(Or, Value::Null, f.clone()),
+            Value::Null
+        );
+        assert_eq!(
+            Expr::Unary(UnaryOperator::Not, literal(Value::Null))
+                .eval(&[])
+                .unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_comparisons_with_null() {
+        use BinaryOperator::{Eq, Is, IsNot};
+
+        assert_eq!(
+            eval_binary_literals(Eq, Value::Null, Value::Null),
+            Value::Null
+        );
+        assert_eq!(
+            eval_binary_literals(Is, Value::Null, Value::Null),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            eval_binary_literals(IsNot, Value::Integer(1), Value::Null),
+            Value::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        use BinaryOperator::{Add, Concat, Divide, Modulo, Multiply};
+
+        assert_eq!(
+            eval_binary_literals(Divide, Value::Integer(7), Value::Integer(2)),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            eval_binary_literals(Divide, Value::Integer(7), Value::Integer(0)),
+            Value::Null
+        );
+        assert!(matches!(
+            eval_binary_literals(Modulo, Value::Float(5.5), Value::Integer(2)),
+            Value::Float(1.0)
+        ));
+        assert_eq!(
+            eval_binary_literals(Multiply, Value::Integer(i64::MAX), Value::Integer(2)),
+            Value::Float(i64::MAX as f64 * 2.0)
+        );
+        assert_eq!(
+            eval_binary_literals(Add, Value::Text(String::from("12abc")), Value::Integer(1)),
+            Value::Integer(13)
+        );
+        assert_eq!(
+            eval_binary_literals(Concat, Value::Text(String::from("a")), Value::Float(2.0)),
+            Value::Text(String::from("a2.0"))
+        );
+    }
+
+    #[test]
+    fn test_in_list() {
+        let in_list = |value: Value, list: Vec<Value>| Expr::InList {
+            expr: literal(value),
+            list: list.into_iter().map(Expr::Literal).collect(),
+            negated: false,
+        };
+
+        assert_eq!(
+            in_list(Value::Integer(1), vec![Value::Null, Value::Float(1.0)])
+                .eval(&[])
+                .unwrap(),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            in_list(Value::Integer(2), vec![Value::Null, Value::Integer(1)])
+                .eval(&[])
+                .unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            in_list(Value::Null, vec![]).eval(&[]).unwrap(),
+            Value::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_scope_resolves_columns_and_rowid() {
+        let mut scope = Scope::new();
+        scope.add_table(
+            "albums",
+            &Table {
+                name: String::from("albums"),
+                rootpage: 2,
+                column_names: vec![String::from("AlbumId"), String::from("Title")],
+                collations: vec![Collation::Binary; 2],
+                affinities: vec![Affinity::Integer, Affinity::Text],
+                rowid_alias: None,
+                unsupported: None,
+            },
+        );
+
+        assert_eq!(scope.resolve(None, "title").unwrap(), 1);
+        assert_eq!(scope.resolve(None, "rowid").unwrap(), 2);
+        assert_eq!(scope.used_columns(0), [false, true]);
+
+        assert_eq!(scope.resolve(Some("Albums"), "AlbumId").unwrap(), 0);
+        assert_eq!(scope.used_columns(0), [true, true]);
+        assert_eq!(scope.column_name(1), Some("Title"));
+        assert_eq!(scope.column_name(2), None);
+
+        assert!(matches!(
+            scope.resolve(None, "Name"),
+            Err(Error::NoSuchColumn(name)) if name == "Name"
+        ));
+    }
+}
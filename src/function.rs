@@ -0,0 +1,455 @@
+use std::{
+    cmp::Ordering,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    ops::RangeInclusive,
+};
+
+use crate::{
+    collation::Collation,
+    error::{Error, Result},
+    printf,
+    value::{self, Value},
+};
+
+// the ordinary functions, which work on the values of a single row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Length,
+    Lower,
+    Upper,
+    Substr,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Replace,
+    Instr,
+    Printf,
+    Hex,
+    Quote,
+    Abs,
+    Round,
+    Coalesce,
+    IfNull,
+    NullIf,
+    Iif,
+    TypeOf,
+    Random,
+    RandomBlob,
+    ZeroBlob,
+    Unicode,
+    Char,
+    Min,
+    Max,
+}
+
+// every function by name, with how many arguments it takes
+const FUNCTIONS: &[(&str, Function, RangeInclusive<usize>)] = &[
+    ("length", Function::Length, 1..=1),
+    ("lower", Function::Lower, 1..=1),
+    ("upper", Function::Upper, 1..=1),
+    ("substr", Function::Substr, 2..=3),
+    ("substring", Function::Substr, 2..=3),
+    ("trim", Function::Trim, 1..=2),
+    ("ltrim", Function::Ltrim, 1..=2),
+    ("rtrim", Function::Rtrim, 1..=2),
+    ("replace", Function::Replace, 3..=3),
+    ("instr", Function::Instr, 2..=2),
+    ("printf", Function::Printf, 1..=usize::MAX),
+    ("format", Function::Printf, 1..=usize::MAX),
+    ("hex", Function::Hex, 1..=1),
+    ("quote", Function::Quote, 1..=1),
+    ("abs", Function::Abs, 1..=1),
+    ("round", Function::Round, 1..=2),
+    ("coalesce", Function::Coalesce, 2..=usize::MAX),
+    ("ifnull", Function::IfNull, 2..=2),
+    ("nullif", Function::NullIf, 2..=2),
+    ("iif", Function::Iif, 2..=3),
+    ("typeof", Function::TypeOf, 1..=1),
+    ("random", Function::Random, 0..=0),
+    ("randomblob", Function::RandomBlob, 1..=1),
+    ("zeroblob", Function::ZeroBlob, 1..=1),
+    ("unicode", Function::Unicode, 1..=1),
+    ("char", Function::Char, 0..=usize::MAX),
+    // with one argument these are aggregates, see aggregate::Function
+    ("min", Function::Min, 2..=usize::MAX),
+    ("max", Function::Max, 2..=usize::MAX),
+];
+
+impl Function {
+    // None if there's no function with the name
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Function>> {
+        let Some((_, function, arities)) = FUNCTIONS
+            .iter()
+            .find(|(function_name, ..)| function_name.eq_ignore_ascii_case(name))
+        else {
+            return Ok(None);
+        };
+
+        if !arities.contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        Ok(Some(*function))
+    }
+
+    // `collation` is the one min, max and nullif compare values with: the
+    // first one an argument asks for
+    pub fn call(self, args: &[Value], collation: Collation) -> Result<Value> {
+        // most functions give NULL for a NULL argument
+        let text = |i: usize| args.get(i).and_then(Value::to_text);
+        let integer = |i: usize| args.get(i).and_then(Value::to_integer);
+
+        Ok(match self {
+            Function::Length => match &args[0] {
+                Value::Null => Value::Null,
+                Value::Blob(bytes) => Value::Integer(bytes.len() as i64),
+                value => Value::Integer(value.to_text().unwrap_or_default().chars().count() as i64),
+            },
+            Function::Lower => text(0).map(|text| text.to_ascii_lowercase()).into(),
+            Function::Upper => text(0).map(|text| text.to_ascii_uppercase()).into(),
+            Function::Substr => substr(args),
+            Function::Trim | Function::Ltrim | Function::Rtrim => {
+                let (Some(text), Some(characters)) = (
+                    text(0),
+                    match args.get(1) {
+                        Some(value) => value.to_text(),
+                        None => Some(String::from(" ")),
+                    },
+                ) else {
+                    return Ok(Value::Null);
+                };
+
+                let trimmed = |c: char| characters.contains(c);
+                let text = match self {
+                    Function::Ltrim => text.trim_start_matches(trimmed),
+                    Function::Rtrim => text.trim_end_matches(trimmed),
+                    _ => text.trim_matches(trimmed),
+                };
+                Value::Text(text.to_string())
+            }
+            Function::Replace => match (text(0), text(1), text(2)) {
+                (Some(text), Some(pattern), _) if pattern.is_empty() => Value::Text(text),
+                (Some(text), Some(pattern), Some(replacement)) => {
+                    Value::Text(text.replace(&pattern, &replacement))
+                }
+                _ => Value::Null,
+            },
+            Function::Instr => instr(&args[0], &args[1]),
+            Function::Printf => match text(0) {
+                Some(format) => Value::Text(printf::format(&format, &args[1..])),
+                None => Value::Null,
+            },
+            Function::Hex => {
+                let bytes = match &args[0] {
+                    Value::Blob(bytes) => bytes.clone(),
+                    value => value.to_text().unwrap_or_default().into_bytes(),
+                };
+                Value::Text(bytes.iter().map(|b| format!("{:02X}", b)).collect())
+            }
+            Function::Quote => Value::Text(quote(&args[0])),
+            Function::Abs => match &args[0] {
+                Value::Null => Value::Null,
+                Value::Integer(i) => Value::Integer(
+                    i.checked_abs()
+                        .ok_or_else(|| Error::Invalid(String::from("integer overflow")))?,
+                ),
+                value => Value::Float(value.to_float().unwrap_or(0.0).abs()),
+            },
+            Function::Round => round(args),
+            Function::Coalesce | Function::IfNull => args
+                .iter()
+                .find(|value| !value.is_null())
+                .cloned()
+                .unwrap_or(Value::Null),
+            Function::NullIf => match collation.compare(&args[0], &args[1]) {
+                Ordering::Equal => Value::Null,
+                _ => args[0].clone(),
+            },
+            Function::Iif => match args[0].truthiness() {
+                Some(true) => args[1].clone(),
+                _ => args.get(2).cloned().unwrap_or(Value::Null),
+            },
+            Function::TypeOf => Value::Text(String::from(match &args[0] {
+                Value::Null => "null",
+                Value::Integer(_) => "integer",
+                Value::Float(_) => "real",
+                Value::Text(_) => "text",
+                Value::Blob(_) => "blob",
+            })),
+            Function::Random => Value::Integer(random() as i64),
+            Function::RandomBlob => {
+                let len = integer(0).unwrap_or(1).max(1) as usize;
+                let bytes = (0..len.div_ceil(8)).flat_map(|_| random().to_le_bytes());
+                Value::Blob(bytes.take(len).collect())
+            }
+            Function::ZeroBlob => Value::Blob(vec![0; integer(0).unwrap_or(0).max(0) as usize]),
+            Function::Unicode => match text(0).and_then(|text| text.chars().next()) {
+                Some(c) => Value::Integer(c as i64),
+                None => Value::Null,
+            },
+            Function::Char => Value::Text(
+                args.iter()
+                    .map(|value| {
+                        u32::try_from(value.to_integer().unwrap_or(0))
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or(char::REPLACEMENT_CHARACTER)
+                    })
+                    .collect(),
+            ),
+            Function::Min | Function::Max => {
+                if args.iter().any(Value::is_null) {
+                    return Ok(Value::Null);
+                }
+                let wanted = match self {
+                    Function::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                // on a tie the first value stays
+                let mut best = &args[0];
+                for value in &args[1..] {
+                    if collation.compare(value, best) == wanted {
+                        best = value;
+                    }
+                }
+                best.clone()
+            }
+        })
+    }
+}
+
+// substr(text, start, length) counts characters, but bytes for a blob. the
+// first character is 1, and a negative start counts back from the end. a
+// negative length takes the characters before the start instead.
+fn substr(args: &[Value]) -> Value {
+    if args.iter().any(Value::is_null) {
+        return Value::Null;
+    }
+
+    let start = args[1].to_integer().unwrap_or(0);
+    let length = match args.get(2) {
+        Some(length) => length.to_integer().unwrap_or(0),
+        None => i64::MAX,
+    };
+
+    let range = |len: usize| {
+        let len = len as i64;
+        let negative = length < 0;
+        let (mut start, mut length) = (start, length.saturating_abs());
+
+        if start < 0 {
+            start += len;
+            if start < 0 {
+                length = (length + start).max(0);
+                start = 0;
+            }
+        } else if start > 0 {
+            start -= 1;
+        } else if length > 0 {
+            // substr(x, 0, n) is one character shorter than substr(x, 1, n)
+            length -= 1;
+        }
+
+        if negative {
+            start -= length;
+            if start < 0 {
+                length = (length + start).max(0);
+                start = 0;
+            }
+        }
+
+        let start = start.min(len);
+        let end = start.saturating_add(length).min(len);
+        start as usize..end as usize
+    };
+
+    match &args[0] {
+        Value::Blob(bytes) => Value::Blob(bytes[range(bytes.len())].to_vec()),
+        value => {
+            let text = value.to_text().unwrap_or_default();
+            let chars: Vec<char> = text.chars().collect();
+            Value::Text(chars[range(chars.len())].iter().collect())
+        }
+    }
+}
+
+// where the first `needle` is in `haystack`, counting from 1, or 0 if it
+// isn't there. characters are counted, or bytes if both are blobs.
+fn instr(haystack: &Value, needle: &Value) -> Value {
+    match (haystack, needle) {
+        (Value::Null, _) | (_, Value::Null) => Value::Null,
+        (Value::Blob(haystack), Value::Blob(needle)) => {
+            let position = match needle.is_empty() {
+                true => Some(0),
+                false => haystack
+                    .windows(needle.len())
+                    .position(|window| window == &needle[..]),
+            };
+            Value::Integer(position.map_or(0, |i| i as i64 + 1))
+        }
+        (haystack, needle) => {
+            let haystack = haystack.to_text().unwrap_or_default();
+            let needle = needle.to_text().unwrap_or_default();
+            Value::Integer(match haystack.find(&needle) {
+                Some(i) => haystack[..i].chars().count() as i64 + 1,
+                None => 0,
+            })
+        }
+    }
+}
+
+// a value as an SQL literal
+fn quote(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("NULL"),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => {
+            // 15 digits unless it takes more to get the same value back
+            let text = value::format_float(*f);
+            match text.parse::<f64>() {
+                Ok(parsed) if parsed == *f || !f.is_finite() => text,
+                _ => {
+                    let shortest = format!("{:e}", f);
+                    let (mantissa, exponent) = shortest.split_once('e').unwrap_or((&shortest, "0"));
+                    let exponent: i32 = exponent.parse().unwrap_or(0);
+                    let sign = if exponent < 0 { '-' } else { '+' };
+                    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+                }
+            }
+        }
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        Value::Blob(bytes) => {
+            let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("X'{}'", hex)
+        }
+    }
+}
+
+// round(x, digits) rounds to the nearest, halfway away from zero. it always
+// gives a float. like sqlite, rounding to a whole number adds a half and cuts
+// the fraction off, so 0.49999999999999994 rounds up (adding the half rounds
+// it to 1.0 already). other digits go through printf's %!.*f.
+fn round(args: &[Value]) -> Value {
+    let digits = match args.get(1) {
+        Some(Value::Null) => return Value::Null,
+        Some(digits) => digits.to_integer().unwrap_or(0).clamp(0, 30),
+        None => 0,
+    };
+    let Some(f) = args[0].to_float() else {
+        return Value::Null;
+    };
+
+    // past 2^52 a float has no fraction left to round
+    if f.abs() > 4503599627370496.0 {
+        return Value::Float(f);
+    }
+    if digits == 0 {
+        let half = if f < 0.0 { -0.5 } else { 0.5 };
+        return Value::Float((f + half) as i64 as f64);
+    }
+    let rounded = printf::format("%!.*f", &[Value::Integer(digits), Value::Float(f)]);
+    Value::Float(rounded.parse().unwrap_or(f))
+}
+
+// a random number. std hashes with random keys, which is all we need.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        let function = Function::find(name, args.len()).unwrap().unwrap();
+        function.call(args, Collation::Binary).unwrap()
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(String::from(s))
+    }
+
+    #[test]
+    fn test_substr() {
+        let substr = |start: i64, length: Option<i64>| {
+            let mut args = vec![text("hello"), Value::Integer(start)];
+            args.extend(length.map(Value::Integer));
+            call("substr", &args)
+        };
+
+        assert_eq!(substr(2, None), text("ello"));
+        assert_eq!(substr(-3, Some(2)), text("ll"));
+        assert_eq!(substr(0, Some(2)), text("h"));
+        assert_eq!(substr(2, Some(-1)), text("h"));
+        assert_eq!(substr(4, Some(-10)), text("hel"));
+        assert_eq!(substr(10, None), text(""));
+        assert_eq!(
+            call("substr", &[Value::Blob(vec![1, 2, 3]), Value::Integer(2)]),
+            Value::Blob(vec![2, 3])
+        );
+        assert_eq!(
+            call(
+                "substr",
+                &[text("héllo"), Value::Integer(2), Value::Integer(2)]
+            ),
+            text("él")
+        );
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(call("length", &[text("héllo")]), Value::Integer(5));
+        assert_eq!(call("length", &[Value::Float(12.5)]), Value::Integer(4));
+        assert_eq!(call("upper", &[text("àbc")]), text("àBC"));
+        assert_eq!(call("rtrim", &[text("xxabxx"), text("xa")]), text("xxab"));
+        assert_eq!(
+            call("replace", &[text("abcabc"), text("b"), text("XX")]),
+            text("aXXcaXXc")
+        );
+        assert_eq!(
+            call("instr", &[text("héllo"), text("l")]),
+            Value::Integer(3)
+        );
+        assert_eq!(call("hex", &[Value::Integer(12)]), text("3132"));
+        assert_eq!(call("quote", &[text("it's")]), text("'it''s'"));
+        assert_eq!(
+            call("quote", &[Value::Blob(vec![10, 255])]),
+            text("X'0AFF'")
+        );
+        assert_eq!(call("char", &[72, 105].map(Value::Integer)), text("Hi"));
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        assert_eq!(call("abs", &[text("-5")]), Value::Float(5.0));
+        assert!(
+            Function::Abs
+                .call(&[Value::Integer(i64::MIN)], Collation::Binary)
+                .is_err()
+        );
+        assert!(matches!(
+            call("round", &[Value::Float(-2.5)]),
+            Value::Float(-3.0)
+        ));
+        assert!(matches!(
+            call("round", &[Value::Float(2.675), Value::Integer(2)]),
+            Value::Float(2.67)
+        ));
+        assert!(matches!(
+            call("round", &[Value::Float(0.49999999999999994)]),
+            Value::Float(1.0)
+        ));
+        assert_eq!(
+            call("max", &[Value::Integer(1), text("a"), Value::Float(2.5)]),
+            text("a")
+        );
+        assert_eq!(call("min", &[Value::Integer(1), Value::Null]), Value::Null);
+
+        assert!(Function::find("abs", 2).is_err());
+        assert_eq!(Function::find("no_such_function", 1).unwrap(), None);
+    }
+}
@@ -1,13 +1,23 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{self, Read},
+};
 
-// for now, all we care about is page_size
+use crate::error::{Error, Result};
+
+// for now, all we care about is the page size and the reserved space
 pub struct Header {
     pub page_size: u16,
+    pub reserved_space: u8,
 }
 
 // offset 0-16 = magic string "SQLite format 3/000"
 // offfset 16-18 = page size in bytes
-pub fn parse_header(file: &mut File) -> Header {
+// offset 20 = bytes of unused "reserved" space at the end of each page
+//
+// anything that doesn't look like this (including a file too short to have a
+// header at all) isn't a database we can read.
+pub fn parse_header(file: &mut File) -> Result<Header> {
     let mut header = [0u8; 100];
 
     // &mut means "give read_exact temporary permission to mutate header without
@@ -19,13 +29,36 @@ pub fn parse_header(file: &mut File) -> Header {
     //
     // read_exact mutates header in place so there's no need to reassign it
     match file.read_exact(&mut header) {
-        Ok(buffer) => buffer,
-        Err(e) => panic!("{}", e),
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(Error::NotADatabase),
+        Err(e) => return Err(e.into()),
+    }
+
+    if &header[0..16] != b"SQLite format 3\0" {
+        return Err(Error::NotADatabase);
     }
 
+    // the page size is a power of two between 512 and 65536 (which is stored
+    // as 1). sqlite also insists on at least 480 usable bytes per page.
     let page_size = u16::from_be_bytes([header[16], header[17]]);
+    let reserved_space = header[20];
+
+    let real_size = if page_size == 1 {
+        65536
+    } else {
+        page_size as u32
+    };
+
+    if !(real_size.is_power_of_two() && real_size >= 512)
+        || real_size - (reserved_space as u32) < 480
+    {
+        return Err(Error::NotADatabase);
+    }
 
-    Header { page_size }
+    Ok(Header {
+        page_size,
+        reserved_space,
+    })
 }
 
 // this is just an arbitrary module to group tests in the file. not needed.
@@ -40,8 +73,19 @@ mod tests {
     fn test_parse_header() {
         let mut file = File::open("tests/chinook.db").unwrap();
 
-        let result = parse_header(&mut file);
+        let result = parse_header(&mut file).unwrap();
+
+        assert_eq!(result.page_size, 1024);
+        assert_eq!(result.reserved_space, 0);
+    }
+
+    #[test]
+    fn test_parse_header_rejects_other_files() {
+        let mut file = File::open("Cargo.toml").unwrap();
 
-        assert_eq!(result.page_size, 1024)
+        assert!(matches!(
+            parse_header(&mut file),
+            Err(crate::error::Error::NotADatabase)
+        ));
     }
 }
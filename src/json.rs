@@ -0,0 +1,1369 @@
+use std::{fmt, ops::RangeInclusive, str::Chars};
+
+use crate::{
+    error::{Error, Result},
+    value::{self, Value},
+};
+
+// how deeply arrays and objects can nest, the same limit as sqlite's
+const MAX_DEPTH: usize = 1000;
+
+// the JSON functions, after sqlite's json1. JSON comes from text, or from a
+// blob in sqlite's binary JSONB format, and always comes out as minified
+// text. only strict RFC 8259 JSON is understood, not the JSON5 extensions
+// sqlite accepts as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Json,
+    Valid,
+    Extract,
+    // x -> path
+    Arrow,
+    // x ->> path
+    DoubleArrow,
+    Object,
+    Array,
+    Set,
+    Insert,
+    Replace,
+    Remove,
+    Type,
+    ArrayLength,
+    Quote,
+}
+
+impl Function {
+    // None if the name isn't one of these functions
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Function>> {
+        let name = name.to_ascii_lowercase();
+        let (function, arities): (Function, RangeInclusive<usize>) = match name.as_str() {
+            "json" => (Function::Json, 1..=1),
+            "json_valid" => (Function::Valid, 1..=2),
+            "json_extract" => (Function::Extract, 1..=usize::MAX),
+            "json_object" => (Function::Object, 0..=usize::MAX),
+            "json_array" => (Function::Array, 0..=usize::MAX),
+            "json_set" => (Function::Set, 1..=usize::MAX),
+            "json_insert" => (Function::Insert, 1..=usize::MAX),
+            "json_replace" => (Function::Replace, 1..=usize::MAX),
+            "json_remove" => (Function::Remove, 1..=usize::MAX),
+            "json_type" => (Function::Type, 1..=2),
+            "json_array_length" => (Function::ArrayLength, 1..=2),
+            "json_quote" => (Function::Quote, 1..=1),
+            _ => return Ok(None),
+        };
+
+        if !arities.contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        // these take labels and values, or paths and values, in pairs
+        match function {
+            Function::Object if !num_args.is_multiple_of(2) => Err(Error::Invalid(String::from(
+                "json_object() requires an even number of arguments",
+            ))),
+            Function::Set | Function::Insert | Function::Replace if num_args.is_multiple_of(2) => {
+                Err(Error::Invalid(format!(
+                    "{}() needs an odd number of arguments",
+                    name
+                )))
+            }
+            _ => Ok(Some(function)),
+        }
+    }
+
+    // sqlite marks the text these functions return as JSON, so that
+    // json_array(json_array(1)) nests an array rather than a string. a Value
+    // has no room for the mark, so instead the functions that embed their
+    // arguments ask whether each one is a call to a function that returns
+    // JSON, see Expr::returns_json.
+    pub fn returns_json(self, value: &Value) -> bool {
+        match self {
+            Function::Valid | Function::DoubleArrow | Function::Type | Function::ArrayLength => {
+                false
+            }
+            // json_extract only gives JSON for arrays and objects. a string
+            // that looks like one is taken for one too.
+            Function::Extract => value
+                .as_text()
+                .is_some_and(|text| text.starts_with(['[', '{'])),
+            _ => true,
+        }
+    }
+
+    // `json[i]` says whether args[i] is JSON another function returned, see
+    // returns_json
+    pub fn call(self, args: &[Value], json: &[bool]) -> Result<Value> {
+        let is_json = |i: usize| json.get(i).copied().unwrap_or(false);
+        // the JSON a function works on comes first, and NULL has none
+        let root = || input(&args[0]);
+
+        Ok(match self {
+            Function::Json => match root()? {
+                Some(root) => Value::Text(root.to_string()),
+                None => Value::Null,
+            },
+            Function::Valid => valid(args)?,
+            Function::Extract => extract(args)?,
+            Function::Arrow | Function::DoubleArrow => {
+                let (Some(root), Some(path)) = (root()?, abbreviated_path(&args[1])) else {
+                    return Ok(Value::Null);
+                };
+                match (root.get(&parse_path(&path)?.steps), self) {
+                    (None, _) => Value::Null,
+                    (Some(json), Function::Arrow) => Value::Text(json.to_string()),
+                    (Some(json), _) => json.to_value(),
+                }
+            }
+            Function::Object => {
+                let mut members = vec![];
+                for (i, pair) in args.chunks(2).enumerate() {
+                    let Value::Text(label) = &pair[0] else {
+                        return Err(Error::Invalid(String::from(
+                            "json_object() labels must be TEXT",
+                        )));
+                    };
+                    members.push((escape(label), from_value(&pair[1], is_json(2 * i + 1))?));
+                }
+                Value::Text(Json::Object(members).to_string())
+            }
+            Function::Array => {
+                let elements = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| from_value(arg, is_json(i)))
+                    .collect::<Result<_>>()?;
+                Value::Text(Json::Array(elements).to_string())
+            }
+            Function::Set | Function::Insert | Function::Replace => {
+                let Some(mut root) = root()? else {
+                    return Ok(Value::Null);
+                };
+                for (i, pair) in args[1..].chunks(2).enumerate() {
+                    let Some(path) = pair[0].to_text() else {
+                        continue;
+                    };
+                    let steps = parse_path(&path)?.steps;
+                    root.edit(&steps, from_value(&pair[1], is_json(2 * i + 2))?, self);
+                }
+                Value::Text(root.to_string())
+            }
+            Function::Remove => {
+                let Some(mut root) = root()? else {
+                    return Ok(Value::Null);
+                };
+                for path in &args[1..] {
+                    let Some(path) = path.to_text() else {
+                        return Ok(Value::Null);
+                    };
+                    // removing the whole document leaves nothing
+                    let steps = parse_path(&path)?.steps;
+                    if steps.is_empty() {
+                        return Ok(Value::Null);
+                    }
+                    root.remove(&steps);
+                }
+                Value::Text(root.to_string())
+            }
+            Function::Type | Function::ArrayLength => {
+                let Some(root) = root()? else {
+                    return Ok(Value::Null);
+                };
+                let steps = match args.get(1) {
+                    None => vec![],
+                    Some(path) => match path.to_text() {
+                        Some(path) => parse_path(&path)?.steps,
+                        None => return Ok(Value::Null),
+                    },
+                };
+                match (root.get(&steps), self) {
+                    (None, _) => Value::Null,
+                    (Some(json), Function::Type) => Value::from(json.type_name()),
+                    (Some(Json::Array(elements)), _) => Value::Integer(elements.len() as i64),
+                    (Some(_), _) => Value::Integer(0),
+                }
+            }
+            Function::Quote => Value::Text(from_value(&args[0], is_json(0))?.to_string()),
+        })
+    }
+}
+
+// json_valid(X, flags): whether X is JSON text (flags 1 and 2) or JSONB
+// (flags 4 and 8)
+fn valid(args: &[Value]) -> Result<Value> {
+    let flags = match args.get(1) {
+        None => 1,
+        Some(flags) => match flags.to_integer() {
+            Some(flags @ 1..=15) => flags,
+            _ => {
+                return Err(Error::Invalid(String::from(
+                    "FLAGS parameter to json_valid() must be between 1 and 15",
+                )));
+            }
+        },
+    };
+
+    Ok(match &args[0] {
+        Value::Null => Value::Null,
+        Value::Blob(blob) => Value::from(flags & 0x0c != 0 && decode(blob).is_some()),
+        value => {
+            Value::from(flags & 0x03 != 0 && parse(&value.to_text().unwrap_or_default()).is_some())
+        }
+    })
+}
+
+// json_extract(X, path, ...) gives the SQL value at a single path, and a JSON
+// array of what's at each path when there are several
+fn extract(args: &[Value]) -> Result<Value> {
+    let Some(root) = input(&args[0])? else {
+        return Ok(Value::Null);
+    };
+    let Some(paths) = args[1..]
+        .iter()
+        .map(Value::to_text)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(Value::Null);
+    };
+
+    Ok(match &paths[..] {
+        [] => Value::Null,
+        [path] => match root.get(&parse_path(path)?.steps) {
+            Some(json) => json.to_value(),
+            None => Value::Null,
+        },
+        paths => {
+            let mut found = vec![];
+            for path in paths {
+                let json = root.get(&parse_path(path)?.steps);
+                found.push(json.cloned().unwrap_or(Json::Null));
+            }
+            Value::Text(Json::Array(found).to_string())
+        }
+    })
+}
+
+// the right hand side of -> and ->> can be a path, or be short for one: 2 is
+// $[2], -1 is $[#-1] and a is $.a
+fn abbreviated_path(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Integer(n) if *n < 0 => format!("$[#{}]", n),
+        Value::Integer(n) => format!("$[{}]", n),
+        value => {
+            let text = value.to_text()?;
+            if text.starts_with('$') {
+                text
+            } else if text.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                format!("$.{}", text)
+            } else if text.len() >= 3 && text.starts_with('[') && text.ends_with(']') {
+                format!("${}", text)
+            } else {
+                format!("$.\"{}\"", text)
+            }
+        }
+    })
+}
+
+// the JSON an argument holds. NULL holds none, and anything that isn't JSON
+// is an error.
+fn input(value: &Value) -> Result<Option<Json>> {
+    let json = match value {
+        Value::Null => return Ok(None),
+        Value::Blob(blob) => decode(blob),
+        value => parse(&value.to_text().unwrap_or_default()),
+    };
+    match json {
+        Some(json) => Ok(Some(json)),
+        None => Err(Error::Invalid(String::from("malformed JSON"))),
+    }
+}
+
+// the JSON for an SQL value that's put into JSON, e.g. by json_array. text
+// becomes a string, unless it's JSON that another function returned.
+fn from_value(value: &Value, is_json: bool) -> Result<Json> {
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Integer(i) => Json::Integer(i.to_string()),
+        Value::Float(f) if f.is_nan() => Json::Null,
+        // JSON has no infinity, but a number too big for a double reads as one
+        Value::Float(f) if f.is_infinite() => Json::Real(String::from(match *f > 0.0 {
+            true => "9.0e+999",
+            false => "-9.0e+999",
+        })),
+        Value::Float(f) => Json::Real(value::format_float(*f)),
+        Value::Text(text) => match is_json.then(|| parse(text)).flatten() {
+            Some(json) => json,
+            None => Json::Text(escape(text)),
+        },
+        Value::Blob(blob) => decode(blob)
+            .ok_or_else(|| Error::Invalid(String::from("JSON cannot hold BLOB values")))?,
+    })
+}
+
+// the JSON text for a value of json_group_array or json_group_object
+pub fn to_json(value: &Value, is_json: bool) -> Result<String> {
+    Ok(from_value(value, is_json)?.to_string())
+}
+
+// a label of json_group_object, quoted
+pub fn to_label(value: &Value) -> String {
+    format!("\"{}\"", escape(&value.to_text().unwrap_or_default()))
+}
+
+// a JSON value. numbers and strings keep the text they were written with,
+// escapes and all, so that they come back out the way they went in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    True,
+    False,
+    Integer(String),
+    Real(String),
+    // what's between the quotes
+    Text(String),
+    Array(Vec<Json>),
+    // the members keep their order, and a label can appear more than once
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::True => "true",
+            Json::False => "false",
+            Json::Integer(_) => "integer",
+            Json::Real(_) => "real",
+            Json::Text(_) => "text",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    // numbers and strings become INTEGER, REAL and TEXT, true and false 1 and
+    // 0, and arrays and objects their JSON text
+    fn to_value(&self) -> Value {
+        match self {
+            Json::Null => Value::Null,
+            Json::True => Value::Integer(1),
+            Json::False => Value::Integer(0),
+            // an integer too big for 64 bits is read as a real
+            Json::Integer(text) => match text.parse::<i64>() {
+                Ok(i) => Value::Integer(i),
+                Err(_) => Value::Float(text.parse().unwrap_or(0.0)),
+            },
+            Json::Real(text) => Value::Float(text.parse().unwrap_or(0.0)),
+            Json::Text(text) => Value::Text(unescape(text)),
+            Json::Array(_) | Json::Object(_) => Value::Text(self.to_string()),
+        }
+    }
+
+    // the value at the end of the path
+    fn get(&self, steps: &[Step]) -> Option<&Json> {
+        let mut json = self;
+        for step in steps {
+            let i = json.position(step)?;
+            json = match json {
+                Json::Array(elements) => &elements[i],
+                Json::Object(members) => &members[i].1,
+                _ => return None,
+            };
+        }
+        Some(json)
+    }
+
+    // where the member or element a step refers to is among this value's
+    // children, if it's there. on duplicate labels the first one counts.
+    fn position(&self, step: &Step) -> Option<usize> {
+        match (step, self) {
+            (Step::Label(label), Json::Object(members)) => {
+                members.iter().position(|(key, _)| unescape(key) == *label)
+            }
+            (step, Json::Array(elements)) => {
+                step.index(elements.len()).filter(|&i| i < elements.len())
+            }
+            _ => None,
+        }
+    }
+
+    fn child_mut(&mut self, i: usize) -> Option<&mut Json> {
+        match self {
+            Json::Array(elements) => elements.get_mut(i),
+            Json::Object(members) => members.get_mut(i).map(|(_, json)| json),
+            _ => None,
+        }
+    }
+
+    // puts the value at the end of the path. json_insert only adds what isn't
+    // there yet and json_replace only changes what is, json_set does both.
+    fn edit(&mut self, steps: &[Step], value: Json, function: Function) {
+        let Some((step, rest)) = steps.split_first() else {
+            if function != Function::Insert {
+                *self = value;
+            }
+            return;
+        };
+
+        if let Some(i) = self.position(step) {
+            if let Some(child) = self.child_mut(i) {
+                child.edit(rest, value, function);
+            }
+            return;
+        }
+        if function == Function::Replace {
+            return;
+        }
+
+        // what's missing is added, along with whatever the rest of the path
+        // needs
+        match (step, self) {
+            (Step::Label(label), Json::Object(members)) => {
+                if let Some(value) = build(rest, value) {
+                    members.push((escape(label), value));
+                }
+            }
+            (step, Json::Array(elements)) if step.index(elements.len()) == Some(elements.len()) => {
+                if let Some(value) = build(rest, value) {
+                    elements.push(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn remove(&mut self, steps: &[Step]) {
+        let Some((step, rest)) = steps.split_first() else {
+            return;
+        };
+        let Some(i) = self.position(step) else {
+            return;
+        };
+
+        match (rest.is_empty(), self) {
+            (true, Json::Array(elements)) => {
+                elements.remove(i);
+            }
+            (true, Json::Object(members)) => {
+                members.remove(i);
+            }
+            (_, json) => {
+                if let Some(child) = json.child_mut(i) {
+                    child.remove(rest);
+                }
+            }
+        }
+    }
+
+    // how many bytes the value takes up in JSONB, see decode
+    fn size(&self) -> usize {
+        let payload = self.payload_size();
+        header_size(payload) + payload
+    }
+
+    fn payload_size(&self) -> usize {
+        match self {
+            Json::Null | Json::True | Json::False => 0,
+            Json::Integer(text) | Json::Real(text) | Json::Text(text) => text.len(),
+            Json::Array(elements) => elements.iter().map(Json::size).sum(),
+            Json::Object(members) => members
+                .iter()
+                .map(|(label, json)| header_size(label.len()) + label.len() + json.size())
+                .sum(),
+        }
+    }
+}
+
+// minified JSON text
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::True => write!(f, "true"),
+            Json::False => write!(f, "false"),
+            Json::Integer(text) | Json::Real(text) => write!(f, "{}", text),
+            Json::Text(text) => write!(f, "\"{}\"", text),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (i, json) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", json)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (label, json)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "\"{}\":{}", label, json)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// wraps the value in whatever the rest of a path needs that isn't there yet.
+// only objects and arrays with a single element can be made up, so there's
+// no building $.a[1].
+fn build(steps: &[Step], value: Json) -> Option<Json> {
+    steps.iter().rev().try_fold(value, |json, step| match step {
+        Step::Label(label) => Some(Json::Object(vec![(escape(label), json)])),
+        Step::Index(0) | Step::FromEnd(0) => Some(Json::Array(vec![json])),
+        _ => None,
+    })
+}
+
+// parses JSON text. None if it isn't JSON.
+pub fn parse(text: &str) -> Option<Json> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    parser.skip_whitespace();
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    (parser.pos == text.len()).then_some(json)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Option<Json> {
+        match *self.bytes.get(self.pos)? {
+            open @ (b'[' | b'{') => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return None;
+                }
+                let close = if open == b'[' { b']' } else { b'}' };
+                self.pos += 1;
+                self.skip_whitespace();
+
+                let (mut elements, mut members) = (vec![], vec![]);
+                if !self.consume(close) {
+                    loop {
+                        if open == b'{' {
+                            let label = self.string()?;
+                            self.skip_whitespace();
+                            if !self.consume(b':') {
+                                return None;
+                            }
+                            self.skip_whitespace();
+                            members.push((label, self.value()?));
+                        } else {
+                            elements.push(self.value()?);
+                        }
+                        self.skip_whitespace();
+                        if self.consume(close) {
+                            break;
+                        }
+                        if !self.consume(b',') {
+                            return None;
+                        }
+                        self.skip_whitespace();
+                    }
+                }
+
+                self.depth -= 1;
+                Some(match open {
+                    b'[' => Json::Array(elements),
+                    _ => Json::Object(members),
+                })
+            }
+            b'"' => self.string().map(Json::Text),
+            b't' => self.literal("true", Json::True),
+            b'f' => self.literal("false", Json::False),
+            b'n' => self.literal("null", Json::Null),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => None,
+        }
+    }
+
+    // a string, returned as the text between its quotes
+    fn string(&mut self) -> Option<String> {
+        if !self.consume(b'"') {
+            return None;
+        }
+        let start = self.pos;
+        loop {
+            match *self.bytes.get(self.pos)? {
+                b'"' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    match *self.bytes.get(self.pos)? {
+                        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => {}
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos + 1..self.pos + 5)?;
+                            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                                return None;
+                            }
+                            self.pos += 4;
+                        }
+                        _ => return None,
+                    }
+                }
+                // control characters have to be escaped
+                0..=0x1f => return None,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Some(self.text[start..self.pos - 1].to_string())
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        self.consume(b'-');
+        // no leading zeros
+        match self.bytes.get(self.pos)? {
+            b'0' => self.pos += 1,
+            b'1'..=b'9' => self.digits(),
+            _ => return None,
+        }
+
+        let mut real = false;
+        if self.consume(b'.') {
+            real = true;
+            if !self.bytes.get(self.pos)?.is_ascii_digit() {
+                return None;
+            }
+            self.digits();
+        }
+        if self.consume(b'e') || self.consume(b'E') {
+            real = true;
+            if !self.consume(b'+') {
+                self.consume(b'-');
+            }
+            if !self.bytes.get(self.pos)?.is_ascii_digit() {
+                return None;
+            }
+            self.digits();
+        }
+
+        let text = self.text[start..self.pos].to_string();
+        Some(if real {
+            Json::Real(text)
+        } else {
+            Json::Integer(text)
+        })
+    }
+
+    fn digits(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Option<Json> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return None;
+        }
+        self.pos += word.len();
+        Some(json)
+    }
+
+    fn consume(&mut self, byte: u8) -> bool {
+        let found = self.bytes.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.bytes.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+}
+
+// reads a blob of JSONB, the binary format sqlite's jsonb functions keep JSON
+// in. every element starts with a header: the low four bits of its first byte
+// are the type, and the high four the size of the payload after the header -
+// or, from 12 to 15, that the size is in the next 1, 2, 4 or 8 bytes.
+// arrays and objects hold their elements, and objects alternate between
+// labels and values. numbers are kept as text.
+//
+// None if the blob isn't JSONB, or uses the JSON5 types.
+pub fn decode(blob: &[u8]) -> Option<Json> {
+    let (json, size) = decode_element(blob, 0)?;
+    (size == blob.len()).then_some(json)
+}
+
+// the element at the start of the blob, and how many bytes it takes up
+fn decode_element(blob: &[u8], depth: usize) -> Option<(Json, usize)> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let first = *blob.first()?;
+    let (header, size) = match first >> 4 {
+        size @ 0..=11 => (1, size as usize),
+        n => {
+            let len = 1usize << (n - 12);
+            let bytes = blob.get(1..1 + len)?;
+            let size = bytes.iter().fold(0u64, |size, &b| size << 8 | b as u64);
+            (1 + len, usize::try_from(size).ok()?)
+        }
+    };
+    let payload = blob.get(header..header.checked_add(size)?)?;
+    let text = || std::str::from_utf8(payload).ok();
+
+    let json = match first & 0x0f {
+        0 if size == 0 => Json::Null,
+        1 if size == 0 => Json::True,
+        2 if size == 0 => Json::False,
+        3 => match parse(text()?)? {
+            json @ Json::Integer(_) => json,
+            _ => return None,
+        },
+        5 => match parse(text()?)? {
+            Json::Integer(text) | Json::Real(text) => Json::Real(text),
+            _ => return None,
+        },
+        // text with JSON escapes, or text that might need some
+        8 => match parse(&format!("\"{}\"", text()?))? {
+            json @ Json::Text(_) => json,
+            _ => return None,
+        },
+        7 | 10 => Json::Text(escape(text()?)),
+        11 | 12 => {
+            let mut elements = vec![];
+            let mut pos = 0;
+            while pos < payload.len() {
+                let (json, size) = decode_element(&payload[pos..], depth + 1)?;
+                elements.push(json);
+                pos += size;
+            }
+            if first & 0x0f == 11 {
+                Json::Array(elements)
+            } else {
+                let mut members = vec![];
+                let mut elements = elements.into_iter();
+                while let Some(label) = elements.next() {
+                    let Json::Text(label) = label else {
+                        return None;
+                    };
+                    members.push((label, elements.next()?));
+                }
+                Json::Object(members)
+            }
+        }
+        _ => return None,
+    };
+
+    Some((json, header + size))
+}
+
+// how many bytes the header of a JSONB element with a payload of this size
+// takes up
+fn header_size(payload: usize) -> usize {
+    match payload {
+        0..=11 => 1,
+        12..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+// the text of a string, with its escapes replaced by what they stand for
+fn unescape(text: &str) -> String {
+    if !text.contains('\\') {
+        return text.to_string();
+    }
+
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => unescaped.push('\u{8}'),
+            Some('f') => unescaped.push('\u{c}'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('u') => {
+                let mut code = hex_code(&mut chars);
+                // characters outside the basic plane are written as a pair of
+                // surrogates
+                if (0xd800..0xdc00).contains(&code) && chars.as_str().starts_with("\\u") {
+                    let mut rest = chars.clone();
+                    rest.nth(1);
+                    let low = hex_code(&mut rest);
+                    if (0xdc00..0xe000).contains(&low) {
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        chars = rest;
+                    }
+                }
+                unescaped.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+// the four hex digits of a \u escape
+fn hex_code(chars: &mut Chars) -> u32 {
+    (0..4)
+        .filter_map(|_| chars.next()?.to_digit(16))
+        .fold(0, |code, digit| code * 16 + digit)
+}
+
+// text as it goes between the quotes of a string
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// one step of a path like $.a[2]
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    // .a or ."a b"
+    Label(String),
+    // [2]
+    Index(usize),
+    // [#-2] counts from the end. [#] is just past the last element, where
+    // json_insert appends.
+    FromEnd(usize),
+}
+
+impl Step {
+    // the index the step refers to in an array of this length
+    fn index(&self, len: usize) -> Option<usize> {
+        match self {
+            Step::Label(_) => None,
+            Step::Index(i) => Some(*i),
+            Step::FromEnd(n) => len.checked_sub(*n),
+        }
+    }
+}
+
+struct Path {
+    steps: Vec<Step>,
+    // how much of the path's text leads up to the last step
+    parent: usize,
+}
+
+// a path starts with $, the whole document, followed by the steps into it
+fn parse_path(path: &str) -> Result<Path> {
+    let bad = || Error::Invalid(format!("bad JSON path: '{}'", path));
+    if !path.starts_with('$') {
+        return Err(bad());
+    }
+
+    let mut steps = vec![];
+    let mut parent = path.len();
+    let mut pos = 1;
+    while pos < path.len() {
+        parent = pos;
+        let rest = &path[pos..];
+        let step = if let Some(quoted) = rest.strip_prefix(".\"") {
+            let end = quoted.find('"').ok_or_else(bad)?;
+            pos += end + 3;
+            Step::Label(unescape(&quoted[..end]))
+        } else if let Some(label) = rest.strip_prefix('.') {
+            // an unquoted label runs up to the next step
+            let end = label.find(['.', '[']).unwrap_or(label.len());
+            if end == 0 {
+                return Err(bad());
+            }
+            pos += end + 1;
+            Step::Label(label[..end].to_string())
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or_else(bad)?;
+            pos += end + 2;
+            let number = |digits: &str| match digits.bytes().all(|b| b.is_ascii_digit()) {
+                true => digits.parse::<usize>().ok(),
+                false => None,
+            };
+            match index[..end].strip_prefix('#') {
+                Some("") => Step::FromEnd(0),
+                Some(from_end) => Step::FromEnd(
+                    from_end
+                        .strip_prefix('-')
+                        .and_then(number)
+                        .ok_or_else(bad)?,
+                ),
+                None => Step::Index(number(&index[..end]).ok_or_else(bad)?),
+            }
+        } else {
+            return Err(bad());
+        };
+        steps.push(step);
+    }
+
+    Ok(Path { steps, parent })
+}
+
+// json_each and json_tree, the table-valued functions that turn JSON into
+// rows: json_each gives a row for each child of the top level array or
+// object, and json_tree one for everything in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Table {
+    Each,
+    Tree,
+}
+
+pub const TABLE_COLUMNS: [&str; 8] = [
+    "key", "value", "type", "atom", "id", "parent", "fullkey", "path",
+];
+
+impl Table {
+    // None if the name isn't one of these functions
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Table>> {
+        let table = match name.to_ascii_lowercase().as_str() {
+            "json_each" => Table::Each,
+            "json_tree" => Table::Tree,
+            _ => return Ok(None),
+        };
+
+        if !(1..=2).contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        Ok(Some(table))
+    }
+
+    // the rows for json_each(X) or json_each(X, path), which start at the
+    // value the path leads to. NULL gives no rows, and so does a path that
+    // leads nowhere.
+    pub fn rows(self, args: &[Value]) -> Result<Vec<Vec<Value>>> {
+        let Some(root) = input(&args[0])? else {
+            return Ok(vec![]);
+        };
+        let path = match args.get(1) {
+            None => String::from("$"),
+            Some(path) => match path.to_text() {
+                Some(path) => path,
+                None => return Ok(vec![]),
+            },
+        };
+        let Path { steps, parent } = parse_path(&path)?;
+
+        let mut node = Node {
+            json: &root,
+            key: Value::Null,
+            id: 0,
+            offset: 0,
+            fullkey: String::from("$"),
+        };
+        for step in &steps {
+            let Some(i) = node.json.position(step) else {
+                return Ok(vec![]);
+            };
+            node = node.children().swap_remove(i);
+        }
+        node.fullkey = path.clone();
+
+        let mut rows = vec![];
+        match (self, node.json) {
+            (Table::Each, Json::Array(_) | Json::Object(_)) => {
+                for child in node.children() {
+                    rows.push(child.row(None, &path));
+                }
+            }
+            (Table::Each, _) => {
+                node.key = Value::Null;
+                rows.push(node.row(None, &path));
+            }
+            (Table::Tree, _) => tree(node, None, &path[..parent], &mut rows),
+        }
+        Ok(rows)
+    }
+}
+
+// a row for the node and one for everything in it
+fn tree(node: Node, parent: Option<usize>, path: &str, rows: &mut Vec<Vec<Value>>) {
+    rows.push(node.row(parent, path));
+    for child in node.children() {
+        tree(child, Some(node.id), &node.fullkey, rows);
+    }
+}
+
+// a value json_each or json_tree gives a row for
+struct Node<'a> {
+    json: &'a Json,
+    // the label of an object's member, the index of an array's element
+    key: Value,
+    // where the value starts in the JSONB encoding of the document, which is
+    // what sqlite identifies it by. a member goes by where its label starts.
+    id: usize,
+    offset: usize,
+    // the path to the value
+    fullkey: String,
+}
+
+impl<'a> Node<'a> {
+    fn children(&self) -> Vec<Node<'a>> {
+        let mut offset = self.offset + header_size(self.json.payload_size());
+        let mut children = vec![];
+        match self.json {
+            Json::Array(elements) => {
+                for (i, json) in elements.iter().enumerate() {
+                    children.push(Node {
+                        json,
+                        key: Value::Integer(i as i64),
+                        id: offset,
+                        offset,
+                        fullkey: format!("{}[{}]", self.fullkey, i),
+                    });
+                    offset += json.size();
+                }
+            }
+            Json::Object(members) => {
+                for (label, json) in members {
+                    let start = offset + header_size(label.len()) + label.len();
+                    children.push(Node {
+                        json,
+                        key: Value::Text(unescape(label)),
+                        id: offset,
+                        offset: start,
+                        fullkey: format!("{}.{}", self.fullkey, path_label(label)),
+                    });
+                    offset = start + json.size();
+                }
+            }
+            _ => {}
+        }
+        children
+    }
+
+    // key, value, type, atom, id, parent, fullkey, path
+    fn row(&self, parent: Option<usize>, path: &str) -> Vec<Value> {
+        let value = self.json.to_value();
+        let atom = match self.json {
+            Json::Array(_) | Json::Object(_) => Value::Null,
+            _ => value.clone(),
+        };
+        vec![
+            self.key.clone(),
+            value,
+            Value::from(self.json.type_name()),
+            atom,
+            Value::Integer(self.id as i64),
+            parent.map(|id| id as i64).into(),
+            Value::Text(self.fullkey.clone()),
+            Value::from(path),
+        ]
+    }
+}
+
+// a label in a fullkey. one that isn't a plain name is quoted.
+fn path_label(label: &str) -> String {
+    let mut bytes = label.bytes();
+    match bytes.next() {
+        Some(first) if first.is_ascii_alphabetic() && bytes.all(|b| b.is_ascii_alphanumeric()) => {
+            label.to_string()
+        }
+        _ => format!("\"{}\"", label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(function: Function, args: &[Value]) -> Result<Value> {
+        function.call(args, &[])
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let minified = |s: &str| parse(s).map(|json| json.to_string());
+
+        assert_eq!(
+            minified(" [1, 1.0e2, -0, \"a\\u0041\\n\", {\"b\" : [true,false,null]} ] "),
+            Some(String::from(
+                "[1,1.0e2,-0,\"a\\u0041\\n\",{\"b\":[true,false,null]}]"
+            ))
+        );
+        assert_eq!(minified("{}"), Some(String::from("{}")));
+
+        for invalid in [
+            "", "[1,]", "{\"a\"}", "01", "1.", ".5", "+1", "\"\t\"", "\"\\x\"", "[1] 2", "tru",
+            "{a:1}", "'a'",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+
+        let deep = |n: usize| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        assert!(parse(&deep(MAX_DEPTH)).is_some());
+        assert!(parse(&deep(MAX_DEPTH + 1)).is_none());
+    }
+
+    #[test]
+    fn test_decode() {
+        // {"a":[1,true]}
+        let blob = [0x6c, 0x17, b'a', 0x3b, 0x13, b'1', 0x01];
+        assert_eq!(decode(&blob).unwrap().to_string(), "{\"a\":[1,true]}");
+        // a string that needs escaping
+        assert_eq!(
+            decode(&[0x2a, b'"', b'\n']).unwrap().to_string(),
+            "\"\\\"\\n\""
+        );
+        // the size in the byte after the header
+        assert_eq!(decode(&[0xc3, 0x01, b'7']).unwrap().to_string(), "7");
+
+        assert_eq!(decode(&[]), None);
+        assert_eq!(decode(&[0x5b, 0x13]), None);
+        assert_eq!(decode(&[0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn test_paths() {
+        let doc = text("{\"a\":[10,{\"b c\":2.5}],\"a\":3,\"\\u0064\":\"x\\ty\"}");
+        let extract = |path: &str| call(Function::Extract, &[doc.clone(), text(path)]).unwrap();
+
+        assert_eq!(extract("$.a[0]"), Value::Integer(10));
+        assert_eq!(extract("$.a[#-1].\"b c\""), Value::Float(2.5));
+        assert_eq!(extract("$.d"), text("x\ty"));
+        assert_eq!(extract("$.a[2]"), Value::Null);
+        assert_eq!(extract("$.a[#]"), Value::Null);
+        assert_eq!(extract("$.a[1]"), text("{\"b c\":2.5}"));
+
+        for bad in ["a", "$.", "$[", "$[x]", "$ .a", "$[#-]"] {
+            assert_eq!(
+                call(Function::Extract, &[doc.clone(), text(bad)])
+                    .unwrap_err()
+                    .to_string(),
+                format!("bad JSON path: '{}'", bad)
+            );
+        }
+
+        let arrow = |path: Value| call(Function::Arrow, &[text("{\"a\":[1,2],\"1\":3}"), path]);
+        assert_eq!(arrow(text("a")).unwrap(), text("[1,2]"));
+        assert_eq!(arrow(text("$.a[1]")).unwrap(), text("2"));
+        assert_eq!(arrow(text("1")).unwrap(), text("3"));
+        let arrow = |path: Value| call(Function::DoubleArrow, &[text("[1,\"x\"]"), path]);
+        assert_eq!(arrow(Value::Integer(0)).unwrap(), Value::Integer(1));
+        assert_eq!(arrow(Value::Integer(-1)).unwrap(), text("x"));
+        assert_eq!(arrow(text("[1]")).unwrap(), text("x"));
+    }
+
+    #[test]
+    fn test_edit() {
+        let edit = |function: Function, doc: &str, args: &[&str]| {
+            let mut values = vec![text(doc)];
+            for pair in args.chunks(2) {
+                values.push(text(pair[0]));
+                values.push(Value::Integer(pair[1].parse().unwrap()));
+            }
+            call(function, &values).unwrap()
+        };
+
+        assert_eq!(
+            edit(
+                Function::Set,
+                "{\"a\":1,\"a\":2}",
+                &["$.a", "3", "$.b.c[#].d", "4"]
+            ),
+            text("{\"a\":3,\"a\":2,\"b\":{\"c\":[{\"d\":4}]}}")
+        );
+        assert_eq!(
+            edit(
+                Function::Set,
+                "[1]",
+                &["$[1]", "2", "$[5]", "3", "$.a", "4"]
+            ),
+            text("[1,2]")
+        );
+        assert_eq!(edit(Function::Set, "{}", &["$.a[1]", "1"]), text("{}"));
+        assert_eq!(
+            edit(Function::Insert, "{\"a\":1}", &["$.a", "2", "$.b", "3"]),
+            text("{\"a\":1,\"b\":3}")
+        );
+        assert_eq!(
+            edit(Function::Replace, "{\"a\":1}", &["$.a", "2", "$.b", "3"]),
+            text("{\"a\":2}")
+        );
+        assert_eq!(edit(Function::Replace, "[1]", &["$", "2"]), text("2"));
+        assert_eq!(edit(Function::Insert, "[1]", &["$", "2"]), text("[1]"));
+
+        let remove = |paths: &[&str]| {
+            let mut args = vec![text("[1,2,{\"a\":3,\"b\":4}]")];
+            args.extend(paths.iter().map(|path| text(path)));
+            call(Function::Remove, &args).unwrap()
+        };
+        assert_eq!(remove(&["$[0]", "$[0]"]), text("[{\"a\":3,\"b\":4}]"));
+        assert_eq!(remove(&["$[#-1].a", "$[9]"]), text("[1,2,{\"b\":4}]"));
+        assert_eq!(remove(&["$"]), Value::Null);
+    }
+
+    #[test]
+    fn test_values() {
+        let array = |args: &[Value], json: &[bool]| Function::Array.call(args, json);
+
+        assert_eq!(
+            array(
+                &[
+                    Value::Integer(1),
+                    Value::Float(1e20),
+                    Value::Float(f64::INFINITY),
+                    text("a\"b\n\u{1f}"),
+                    Value::Null,
+                    Value::Blob(vec![0x01]),
+                ],
+                &[]
+            )
+            .unwrap(),
+            text("[1,1.0e+20,9.0e+999,\"a\\\"b\\n\\u001f\",null,true]")
+        );
+        // JSON from another function is nested, unless it isn't JSON after all
+        assert_eq!(
+            array(&[text("[1]"), text("[2]"), text("[")], &[true, false, true]).unwrap(),
+            text("[[1],\"[2]\",\"[\"]")
+        );
+        assert_eq!(
+            array(&[Value::Blob(vec![0xff])], &[])
+                .unwrap_err()
+                .to_string(),
+            "JSON cannot hold BLOB values"
+        );
+
+        assert_eq!(
+            call(
+                Function::Object,
+                &[text("a"), Value::Integer(1), text("b"), Value::Null]
+            )
+            .unwrap(),
+            text("{\"a\":1,\"b\":null}")
+        );
+        assert!(call(Function::Object, &[Value::Integer(1), Value::Integer(1)]).is_err());
+
+        let valid = |args: &[Value]| call(Function::Valid, args).unwrap();
+        assert_eq!(valid(&[text(" 1 ")]), Value::Integer(1));
+        assert_eq!(valid(&[text("")]), Value::Integer(0));
+        assert_eq!(valid(&[Value::Blob(vec![0x00])]), Value::Integer(0));
+        assert_eq!(
+            valid(&[Value::Blob(vec![0x00]), Value::Integer(4)]),
+            Value::Integer(1)
+        );
+        assert!(call(Function::Valid, &[text("1"), Value::Integer(16)]).is_err());
+    }
+
+    #[test]
+    fn test_tables() {
+        let doc = text("{\"a\":[1,2.5,\"x\\u0041\"],\"b\":{\"c\":null,\"d e\":true}}");
+        let rows = Table::Tree.rows(std::slice::from_ref(&doc)).unwrap();
+        let column = |rows: &[Vec<Value>], i: usize| -> Vec<Value> {
+            rows.iter().map(|row| row[i].clone()).collect()
+        };
+
+        assert_eq!(
+            column(&rows, 4),
+            [0, 2, 6, 8, 12, 20, 23, 26].map(Value::Integer)
+        );
+        assert_eq!(
+            column(&rows, 5),
+            vec![
+                Value::Null,
+                Value::Integer(0),
+                Value::Integer(2),
+                Value::Integer(2),
+                Value::Integer(2),
+                Value::Integer(0),
+                Value::Integer(20),
+                Value::Integer(20),
+            ]
+        );
+        assert_eq!(
+            column(&rows, 6),
+            [
+                "$",
+                "$.a",
+                "$.a[0]",
+                "$.a[1]",
+                "$.a[2]",
+                "$.b",
+                "$.b.c",
+                "$.b.\"d e\""
+            ]
+            .map(text)
+        );
+        assert_eq!(
+            rows[4],
+            vec![
+                Value::Integer(2),
+                text("xA"),
+                text("text"),
+                text("xA"),
+                Value::Integer(12),
+                Value::Integer(2),
+                text("$.a[2]"),
+                text("$.a"),
+            ]
+        );
+
+        let rows = Table::Each.rows(&[doc.clone(), text("$.b")]).unwrap();
+        assert_eq!(column(&rows, 0), [text("c"), text("d e")]);
+        assert_eq!(column(&rows, 1), [Value::Null, Value::Integer(1)]);
+        assert_eq!(column(&rows, 5), [Value::Null, Value::Null]);
+        assert_eq!(column(&rows, 7), [text("$.b"), text("$.b")]);
+
+        // a scalar is a row of its own
+        let rows = Table::Each.rows(&[doc.clone(), text("$.a[0]")]).unwrap();
+        assert_eq!(
+            rows,
+            vec![vec![
+                Value::Null,
+                Value::Integer(1),
+                text("integer"),
+                Value::Integer(1),
+                Value::Integer(6),
+                Value::Null,
+                text("$.a[0]"),
+                text("$.a[0]"),
+            ]]
+        );
+
+        assert!(
+            Table::Each
+                .rows(&[doc.clone(), text("$.z")])
+                .unwrap()
+                .is_empty()
+        );
+        assert!(Table::Tree.rows(&[Value::Null]).unwrap().is_empty());
+        assert!(Table::Each.rows(&[text("[")]).is_err());
+    }
+}
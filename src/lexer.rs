@@ -0,0 +1,432 @@
+use crate::parser::ParseError;
+
+// The lexer turns the raw SQL text into a flat list of tokens. The parser then
+// walks over this list instead of the raw characters, which means it never has
+// to worry about whitespace, comments or quoting rules.
+//
+// Every token remembers where it started and ended in the original text so that
+// errors can point at the offending position, e.g.
+//
+//   SELECT * FROM albums WHERE
+//                        ^ position 21
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // a bare word. keywords are also words - the parser decides whether a word
+    // is a keyword or an identifier, comparing case-insensitively.
+    Word(String),
+    // "name", [name] or `name`. always an identifier, never a keyword.
+    QuotedIdentifier(String),
+    String(String),
+    // numbers are kept as text and converted by the parser, because whether
+    // "9223372036854775808" is an integer or a float depends on its size.
+    Number(String),
+    Blob(Vec<u8>),
+    // a parameter to be bound later: ?, ?3, :name, @name or $name, kept as
+    // written
+    Variable(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Dot,
+    Semicolon,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    // -> and ->>, which pick a value out of JSON
+    Arrow,
+    DoubleArrow,
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn tokenize(sql: &str) -> Result<Vec<Token>, ParseError> {
+    let bytes = sql.as_bytes();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+
+        // skip whitespace
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        // -- comments run until the end of the line
+        if bytes[pos..].starts_with(b"--") {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+
+        // /* comments */ run until the closing */ (or the end of the input,
+        // which is what sqlite does too)
+        if bytes[pos..].starts_with(b"/*") {
+            pos += 2;
+            while pos < bytes.len() && !bytes[pos..].starts_with(b"*/") {
+                pos += 1;
+            }
+            pos = (pos + 2).min(bytes.len());
+            continue;
+        }
+
+        let kind = match c {
+            b'(' => single(&mut pos, TokenKind::LeftParen),
+            b')' => single(&mut pos, TokenKind::RightParen),
+            b',' => single(&mut pos, TokenKind::Comma),
+            b';' => single(&mut pos, TokenKind::Semicolon),
+            b'*' => single(&mut pos, TokenKind::Star),
+            b'+' => single(&mut pos, TokenKind::Plus),
+            b'-' => match (bytes.get(pos + 1), bytes.get(pos + 2)) {
+                (Some(b'>'), Some(b'>')) => {
+                    pos += 3;
+                    TokenKind::DoubleArrow
+                }
+                (Some(b'>'), _) => double(&mut pos, TokenKind::Arrow),
+                _ => single(&mut pos, TokenKind::Minus),
+            },
+            b'/' => single(&mut pos, TokenKind::Slash),
+            b'%' => single(&mut pos, TokenKind::Percent),
+            b'&' => single(&mut pos, TokenKind::BitAnd),
+            b'~' => single(&mut pos, TokenKind::BitNot),
+            b'|' => {
+                if bytes.get(pos + 1) == Some(&b'|') {
+                    pos += 2;
+                    TokenKind::Concat
+                } else {
+                    single(&mut pos, TokenKind::BitOr)
+                }
+            }
+            // sqlite accepts both = and == for equality
+            b'=' => {
+                pos += if bytes.get(pos + 1) == Some(&b'=') {
+                    2
+                } else {
+                    1
+                };
+                TokenKind::Eq
+            }
+            b'!' => {
+                if bytes.get(pos + 1) == Some(&b'=') {
+                    pos += 2;
+                    TokenKind::NotEq
+                } else {
+                    return Err(ParseError::new("unrecognized token: \"!\"", start));
+                }
+            }
+            b'<' => match bytes.get(pos + 1) {
+                Some(b'=') => double(&mut pos, TokenKind::LtEq),
+                Some(b'>') => double(&mut pos, TokenKind::NotEq),
+                Some(b'<') => double(&mut pos, TokenKind::ShiftLeft),
+                _ => single(&mut pos, TokenKind::Lt),
+            },
+            b'>' => match bytes.get(pos + 1) {
+                Some(b'=') => double(&mut pos, TokenKind::GtEq),
+                Some(b'>') => double(&mut pos, TokenKind::ShiftRight),
+                _ => single(&mut pos, TokenKind::Gt),
+            },
+            b'\'' => TokenKind::String(read_quoted(sql, &mut pos, b'\'')?),
+            b'"' => TokenKind::QuotedIdentifier(read_quoted(sql, &mut pos, b'"')?),
+            b'`' => TokenKind::QuotedIdentifier(read_quoted(sql, &mut pos, b'`')?),
+            b'[' => {
+                // [bracketed] identifiers can't contain an escaped ], they simply
+                // end at the first one
+                let Some(len) = sql[pos + 1..].find(']') else {
+                    return Err(ParseError::new("unrecognized token: \"[\"", start));
+                };
+                let name = sql[pos + 1..pos + 1 + len].to_string();
+                pos += len + 2;
+                TokenKind::QuotedIdentifier(name)
+            }
+            // X'0A1B' is a blob literal
+            b'x' | b'X' if bytes.get(pos + 1) == Some(&b'\'') => {
+                pos += 1;
+                let hex = read_quoted(sql, &mut pos, b'\'')?;
+                TokenKind::Blob(decode_hex(&hex).ok_or_else(|| {
+                    ParseError::new(format!("malformed blob literal: X'{}'", hex), start)
+                })?)
+            }
+            b'?' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                TokenKind::Variable(sql[start..pos].to_string())
+            }
+            b':' | b'@' | b'$' if bytes.get(pos + 1).is_some_and(|&c| is_word_char(c)) => {
+                pos += 1;
+                while pos < bytes.len() && is_word_char(bytes[pos]) {
+                    pos += 1;
+                }
+                TokenKind::Variable(sql[start..pos].to_string())
+            }
+            b'0'..=b'9' => TokenKind::Number(read_number(sql, &mut pos)?),
+            b'.' if bytes.get(pos + 1).is_some_and(|b| b.is_ascii_digit()) => {
+                TokenKind::Number(read_number(sql, &mut pos)?)
+            }
+            b'.' => single(&mut pos, TokenKind::Dot),
+            c if is_word_char(c) => {
+                while pos < bytes.len() && (is_word_char(bytes[pos]) || bytes[pos] == b'$') {
+                    pos += 1;
+                }
+                TokenKind::Word(sql[start..pos].to_string())
+            }
+            _ => {
+                let ch = sql[pos..].chars().next().unwrap();
+                return Err(ParseError::new(
+                    format!("unrecognized token: \"{}\"", ch),
+                    start,
+                ));
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            start,
+            end: pos,
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        start: bytes.len(),
+        end: bytes.len(),
+    });
+
+    Ok(tokens)
+}
+
+fn single(pos: &mut usize, kind: TokenKind) -> TokenKind {
+    *pos += 1;
+    kind
+}
+
+fn double(pos: &mut usize, kind: TokenKind) -> TokenKind {
+    *pos += 2;
+    kind
+}
+
+// identifiers can contain letters, digits, underscores and any non-ascii
+// character (so unicode table names work)
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+// reads a string which starts and ends with `quote`. a doubled quote inside the
+// string stands for a single quote character, e.g. 'it''s' == it's
+fn read_quoted(sql: &str, pos: &mut usize, quote: u8) -> Result<String, ParseError> {
+    let bytes = sql.as_bytes();
+    let start = *pos;
+    let mut value = String::new();
+    let mut segment_start = start + 1;
+    let mut i = start + 1;
+
+    loop {
+        if i >= bytes.len() {
+            return Err(ParseError::new("unterminated quoted string", start));
+        }
+
+        if bytes[i] == quote {
+            value.push_str(&sql[segment_start..i]);
+
+            if bytes.get(i + 1) == Some(&quote) {
+                // escaped quote - keep one of them and carry on
+                value.push(quote as char);
+                i += 2;
+                segment_start = i;
+            } else {
+                *pos = i + 1;
+                return Ok(value);
+            }
+        } else {
+            i += 1;
+        }
+    }
+}
+
+// numbers look like 42, 3.14, .5, 1e10, 2.5E-3 or 0x1F
+fn read_number(sql: &str, pos: &mut usize) -> Result<String, ParseError> {
+    let bytes = sql.as_bytes();
+    let start = *pos;
+
+    if bytes[start] == b'0' && matches!(bytes.get(start + 1), Some(b'x' | b'X')) {
+        *pos += 2;
+        while *pos < bytes.len() && bytes[*pos].is_ascii_hexdigit() {
+            *pos += 1;
+        }
+        if *pos == start + 2 {
+            return Err(ParseError::new("malformed hex literal", start));
+        }
+    } else {
+        while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == b'.' {
+            *pos += 1;
+            while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+                *pos += 1;
+            }
+        }
+        if *pos < bytes.len() && (bytes[*pos] == b'e' || bytes[*pos] == b'E') {
+            let mut exponent = *pos + 1;
+            if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+                exponent += 1;
+            }
+            if exponent >= bytes.len() || !bytes[exponent].is_ascii_digit() {
+                return Err(ParseError::new("malformed number", start));
+            }
+            *pos = exponent;
+            while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+                *pos += 1;
+            }
+        }
+    }
+
+    // 123abc is not a number followed by a word, it's just invalid
+    if *pos < bytes.len() && is_word_char(bytes[*pos]) {
+        let mut end = *pos;
+        while end < bytes.len() && is_word_char(bytes[end]) {
+            end += 1;
+        }
+        return Err(ParseError::new(
+            format!("unrecognized token: \"{}\"", &sql[start..end]),
+            start,
+        ));
+    }
+
+    Ok(sql[start..*pos].to_string())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<TokenKind> {
+        tokenize(sql)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_select() {
+        assert_eq!(
+            kinds("select *FROM albums;"),
+            vec![
+                TokenKind::Word(String::from("select")),
+                TokenKind::Star,
+                TokenKind::Word(String::from("FROM")),
+                TokenKind::Word(String::from("albums")),
+                TokenKind::Semicolon,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_quoted_identifiers() {
+        assert_eq!(
+            kinds("\"a \"\"b\"\"\" [c d] `e`"),
+            vec![
+                TokenKind::QuotedIdentifier(String::from("a \"b\"")),
+                TokenKind::QuotedIdentifier(String::from("c d")),
+                TokenKind::QuotedIdentifier(String::from("e")),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        assert_eq!(
+            kinds("'it''s' 42 3.5 .5 1e3 0xFF x'0aFF'"),
+            vec![
+                TokenKind::String(String::from("it's")),
+                TokenKind::Number(String::from("42")),
+                TokenKind::Number(String::from("3.5")),
+                TokenKind::Number(String::from(".5")),
+                TokenKind::Number(String::from("1e3")),
+                TokenKind::Number(String::from("0xFF")),
+                TokenKind::Blob(vec![0x0a, 0xff]),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_operators() {
+        assert_eq!(
+            kinds("<> != == <= >= << >> || -> ->> - >"),
+            vec![
+                TokenKind::NotEq,
+                TokenKind::NotEq,
+                TokenKind::Eq,
+                TokenKind::LtEq,
+                TokenKind::GtEq,
+                TokenKind::ShiftLeft,
+                TokenKind::ShiftRight,
+                TokenKind::Concat,
+                TokenKind::Arrow,
+                TokenKind::DoubleArrow,
+                TokenKind::Minus,
+                TokenKind::Gt,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_skips_comments() {
+        assert_eq!(
+            kinds("SELECT -- the columns\n* /* everything */ FROM t"),
+            kinds("SELECT * FROM t")
+        );
+    }
+
+    #[test]
+    fn test_tokenize_positions() {
+        let tokens = tokenize("SELECT  name").unwrap();
+        assert_eq!((tokens[1].start, tokens[1].end), (8, 12));
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_string() {
+        let error = tokenize("SELECT 'abc").unwrap_err();
+        assert_eq!(error.position, 7);
+    }
+}
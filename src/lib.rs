@@ -1,34 +1,45 @@
-use std::fs::File;
-
-use crate::{db::Db, query::execute, schema::parse_tables};
-
+mod affinity;
+mod aggregate;
+mod ast;
 mod btree;
 mod cell;
+mod collation;
+mod connection;
+mod datetime;
 mod db;
+mod error;
+mod expr;
+mod function;
 mod header;
+mod json;
+mod lexer;
 mod page;
+mod pager;
+mod parser;
+mod planner;
+mod printf;
 mod query;
 mod schema;
+mod sort;
+mod spill;
 mod value;
 mod varint;
+mod window;
 
 pub use cell::Row;
+pub use connection::{Connection, Statement};
+pub use datetime::Clock;
+pub use error::{Error, Result};
+pub use query::Rows;
 pub use value::Value;
 
-pub fn run(file_path: &String, query: &String) -> (Vec<String>, Vec<Row>) {
-    let mut file = File::open(file_path).expect("Failed to open file: {}");
-
-    let header = header::parse_header(&mut file);
-
-    let tables = parse_tables(&mut file, header.page_size);
-
-    // println!("Tables: {:?}", tables);
-
-    let mut db = Db {
-        file,
-        page_size: header.page_size,
-        tables,
-    };
+// runs a single query and returns the names of the result columns along with
+// an iterator over the rows. the rows are read from the file as you iterate,
+// so the database stays open until the iterator is dropped. to run more than
+// one query, open a Connection once and prepare each query on it.
+pub fn run(file_path: &str, query: &str) -> Result<(Vec<String>, Rows)> {
+    let connection = Connection::open(file_path)?;
+    let statement = connection.prepare(query)?;
 
-    execute(&mut db, String::from(query))
+    Ok((statement.column_names().to_vec(), statement.query()?))
 }
@@ -1,6 +1,9 @@
-use std::env::args;
+use std::{
+    env::args,
+    io::{self, BufWriter, Write},
+    process::exit,
+};
 
-use comfy_table::Table;
 use sqlite::run;
 
 fn main() {
@@ -13,32 +16,58 @@ fn main() {
     //   clustered index (i.e. values are within the b-tree)
     //
     // Example:
-    // sqlite_oz "SELECT * FROM albums WHERE name = "something" chinook.db
+    // sqlite_oz "SELECT * FROM albums WHERE Title = 'Facelift'" chinook.db
 
     // collect all args into a list
     let args: Vec<String> = args().collect();
 
     // the first item is the query
     // the second item is the database name
-    let query = args
-        .get(1)
-        .expect("Usage: sqlite_oz query database_file_path");
-    let file_path = args
-        .get(2)
-        .expect("Usage: sqlite_oz query database_file_path");
+    let (Some(query), Some(file_path)) = (args.get(1), args.get(2)) else {
+        eprintln!("Usage: sqlite_oz query database_file_path");
+        exit(1);
+    };
 
-    let (column_names, rows) = run(file_path, query);
+    let (column_names, rows) = match run(file_path, query) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            exit(1);
+        }
+    };
 
-    let mut output_table = Table::new();
+    // rows are printed as soon as they are read, the same way the sqlite3
+    // shell does by default: one row per line, values separated by |. a table
+    // with lined up columns would mean reading every row before printing any.
+    let mut out = BufWriter::new(io::stdout().lock());
 
-    output_table.set_header(column_names);
+    // if stdout goes away (e.g. piping into `head`) there's no one left to
+    // print to, so just stop
+    if writeln!(out, "{}", column_names.join("|")).is_err() {
+        return;
+    }
+
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                let _ = out.flush();
+                eprintln!("Error: {}", e);
+                exit(1);
+            }
+        };
 
-    for row in &rows {
-        let mut values = vec![format!("{:?}", row.rowid)];
-        values.extend(row.values.iter().skip(1).map(|v| format!("{:?}", v)));
+        // NULL prints as nothing
+        let values: Vec<String> = row
+            .values
+            .iter()
+            .map(|v| v.to_text().unwrap_or_default())
+            .collect();
 
-        output_table.add_row(values);
+        if writeln!(out, "{}", values.join("|")).is_err() {
+            return;
+        }
     }
 
-    println!("{}", output_table);
+    let _ = out.flush();
 }
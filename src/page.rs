@@ -1,9 +1,12 @@
 use std::{
     fs::File,
-    io::{Read, Seek, SeekFrom},
+    io::{self, Read, Seek, SeekFrom},
 };
 
+use crate::error::{Error, Result};
+
 pub struct Page {
+    pub number: u32,
     pub data: Vec<u8>,
     pub page_type: u8,
     pub num_cells: u16,
@@ -22,36 +25,80 @@ pub struct Page {
 // - Offset 112 for interior pages (12-byte header)
 
 impl Page {
-    pub fn read(file: &mut File, page_num: u32, page_size: u16) -> Page {
+    // reads the raw bytes of a page. this takes &File rather than &mut File so
+    // that several readers can share the same open file. reading through &File
+    // still moves the file position, which is fine because we always seek
+    // before reading.
+    pub fn read_bytes(mut file: &File, page_num: u32, page_size: u32) -> Result<Vec<u8>> {
         let mut page = vec![0u8; page_size as usize];
 
         // go back to the start of the page
         let offset = (page_num - 1) as u64 * page_size as u64;
-        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.seek(SeekFrom::Start(offset))?;
 
-        // read only the bytes of the page
+        // read only the bytes of the page. a page past the end of the file can
+        // only be reached through a broken pointer.
         match file.read_exact(&mut page) {
-            Ok(b) => b,
-            Err(e) => panic!("{}", e),
+            Ok(()) => Ok(page),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::CorruptPage {
+                page: page_num,
+                offset: 0,
+            }),
+            Err(e) => Err(e.into()),
         }
+    }
+
+    pub fn read(file: &File, page_num: u32, page_size: u32, usable_size: usize) -> Result<Page> {
+        let data = Page::read_bytes(file, page_num, page_size)?;
+        Page::new(page_num, data, usable_size)
+    }
+
+    // parses the b-tree page header. the header and the cell pointer array are
+    // checked here, so the rest of the code can use cell_pointer without
+    // worrying about reading past the end of the page.
+    pub fn new(number: u32, data: Vec<u8>, usable_size: usize) -> Result<Page> {
+        let corrupt = |offset| Error::CorruptPage {
+            page: number,
+            offset,
+        };
 
         let mut offset: usize = 0;
 
         // adjust for the 100 byte header on the first page.
         // b-tree header starts after that.
-        if page_num == 1 {
+        if number == 1 {
             offset = 100
         }
 
-        let num_cells = u16::from_be_bytes([page[offset + 3], page[offset + 4]]);
-        let page_type = page[offset];
+        let page_type = data[offset];
+        if ![0x02, 0x05, 0x0A, 0x0D].contains(&page_type) {
+            return Err(corrupt(offset));
+        }
+
+        let num_cells = u16::from_be_bytes([data[offset + 3], data[offset + 4]]);
 
-        Page {
-            data: page,
+        let page = Page {
+            number,
+            data,
             page_type,
             num_cells,
             offset,
+        };
+
+        let header_size = if page.is_leaf() { 8 } else { 12 };
+        let content_start = offset + header_size + num_cells as usize * 2;
+        if content_start > usable_size {
+            return Err(corrupt(offset + 3));
         }
+
+        for i in 0..num_cells {
+            let pointer = page.cell_pointer(i);
+            if pointer < content_start || pointer >= usable_size {
+                return Err(corrupt(offset + header_size + i as usize * 2));
+            }
+        }
+
+        Ok(page)
     }
 
     pub fn is_leaf(&self) -> bool {
@@ -0,0 +1,68 @@
+use std::{fs::File, rc::Rc};
+
+use crate::{
+    error::{Error, Result},
+    header,
+    page::Page,
+};
+
+// The pager is how the rest of the code reads pages from the database file. It
+// remembers the page size, and the "usable size": the part of each page that
+// can hold data. some sqlite extensions (e.g. encryption) reserve a few bytes
+// at the end of every page, which the header tells us about.
+//
+// cloning a pager is cheap: the clones share the open file. this lets every
+// cursor own a pager, so a query's rows can outlive the code that started it.
+#[derive(Clone)]
+pub struct Pager {
+    file: Rc<File>,
+    pub page_size: u32,
+    pub usable_size: usize,
+}
+
+impl Pager {
+    pub fn open(file_path: &str) -> Result<Pager> {
+        let mut file = File::open(file_path)?;
+
+        let header = header::parse_header(&mut file)?;
+
+        // 65536 doesn't fit in the two bytes the header has for the page
+        // size, so it's stored as 1 instead
+        let page_size = match header.page_size {
+            1 => 65536,
+            page_size => page_size as u32,
+        };
+
+        Ok(Pager {
+            file: Rc::new(file),
+            page_size,
+            usable_size: page_size as usize - header.reserved_space as usize,
+        })
+    }
+
+    // pages are numbered from 1. page numbers come from the file itself (child
+    // pointers, overflow chains), so a broken file can point us at page 0 or
+    // past the end - both are reported as corruption.
+    pub fn read_page(&self, page_num: u32) -> Result<Page> {
+        if page_num == 0 {
+            return Err(Error::CorruptPage {
+                page: page_num,
+                offset: 0,
+            });
+        }
+
+        Page::read(&self.file, page_num, self.page_size, self.usable_size)
+    }
+
+    // overflow pages don't have a b-tree header, so they are read as raw bytes
+    pub fn read_overflow_page(&self, page_num: u32) -> Result<Vec<u8>> {
+        if page_num == 0 {
+            return Err(Error::CorruptPage {
+                page: page_num,
+                offset: 0,
+            });
+        }
+
+        Page::read_bytes(&self.file, page_num, self.page_size)
+    }
+}
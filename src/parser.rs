@@ -0,0 +1,1826 @@
+use std::fmt;
+
+use crate::{
+    ast::{
+        BinaryOperator, Compound, CompoundOperator, CreateIndex, Cte, Expr, Frame, FrameBound,
+        FrameUnits, FromClause, IndexedColumn, Join, JoinConstraint, JoinKind, Limit, NamedWindow,
+        OrderingTerm, ResultColumn, Select, Statement, TableName, TableOrSubquery, UnaryOperator,
+        WindowDefinition,
+    },
+    lexer::{Token, TokenKind, tokenize},
+    value::Value,
+};
+
+// position is the byte offset into the SQL text where things went wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, position: usize) -> ParseError {
+        ParseError {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+// an index column or ORDER BY term can end with COLLATE, which the expression
+// parser treats as part of the expression. this takes it back off.
+fn split_collation(expr: Expr) -> (Expr, Option<String>) {
+    match expr {
+        Expr::Collate { expr, collation } => (*expr, Some(collation)),
+        expr => (expr, None),
+    }
+}
+
+// sqlite's default limit on the number of parameters
+const MAX_PARAMETER: usize = 32766;
+
+// words that can never be used as an unquoted identifier. without this list
+// "SELECT * FROM albums WHERE ..." would treat WHERE as an alias for albums.
+const RESERVED: &[&str] = &[
+    "ALL",
+    "AND",
+    "AS",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "COLLATE",
+    "CROSS",
+    "DISTINCT",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXISTS",
+    "FROM",
+    "GLOB",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INNER",
+    "INTERSECT",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NATURAL",
+    "NOT",
+    "NOTNULL",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "SELECT",
+    "THEN",
+    "UNION",
+    "USING",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+];
+
+// also returns the names of the statement's parameters: the first entry is the
+// name of ?1 and so on. ? and ?NNN have no name.
+pub fn parse(sql: &str) -> Result<(Statement, Vec<Option<String>>), ParseError> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: vec![],
+    };
+
+    let statement = parser.parse_statement()?;
+    parser.expect_end()?;
+
+    Ok((statement, parser.parameters))
+}
+
+// used to read the index definitions stored in sqlite_master
+pub fn parse_create_index(sql: &str) -> Result<CreateIndex, ParseError> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: vec![],
+    };
+
+    let create_index = parser.parse_create_index()?;
+    parser.expect_end()?;
+
+    Ok(create_index)
+}
+
+// a recursive descent parser: every grammar rule gets its own method, and rules
+// call each other in the same way the grammar refers to other rules. e.g.
+// parse_select calls parse_result_column for every column in the select list.
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    // the parameters seen so far, see parse_parameter
+    parameters: Vec<Option<String>>,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        if self.is_keyword("SELECT") || self.is_keyword("WITH") {
+            Ok(Statement::Select(self.parse_select()?))
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        // a trailing semicolon is fine, anything else means we didn't understand
+        // part of the query
+        self.consume(&TokenKind::Semicolon);
+        if self.peek().kind != TokenKind::Eof {
+            return Err(self.unexpected());
+        }
+        Ok(())
+    }
+
+    fn parse_create_index(&mut self) -> Result<CreateIndex, ParseError> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.consume_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+
+        if self.consume_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+
+        // the index name can be prefixed with a schema name: main.my_index
+        let mut name = self.parse_identifier()?;
+        if self.consume(&TokenKind::Dot) {
+            name = self.parse_identifier()?;
+        }
+
+        self.expect_keyword("ON")?;
+        let table = self.parse_identifier()?;
+
+        self.expect(&TokenKind::LeftParen)?;
+        let mut columns = vec![self.parse_indexed_column()?];
+        while self.consume(&TokenKind::Comma) {
+            columns.push(self.parse_indexed_column()?);
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        let where_clause = if self.consume_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        Ok(CreateIndex {
+            name,
+            table,
+            unique,
+            columns,
+            where_clause,
+        })
+    }
+
+    fn parse_indexed_column(&mut self) -> Result<IndexedColumn, ParseError> {
+        let (expr, collation) = split_collation(self.parse_expr()?);
+
+        let descending = if self.consume_keyword("DESC") {
+            true
+        } else {
+            self.consume_keyword("ASC");
+            false
+        };
+
+        Ok(IndexedColumn {
+            expr,
+            collation,
+            descending,
+        })
+    }
+
+    fn parse_select(&mut self) -> Result<Select, ParseError> {
+        let with = match self.consume_keyword("WITH") {
+            true => self.parse_with()?,
+            false => vec![],
+        };
+
+        let mut select = self.parse_select_core()?;
+        select.with = with;
+
+        while let Some(op) = self.peek_compound_operator() {
+            self.pos += if op == CompoundOperator::UnionAll {
+                2
+            } else {
+                1
+            };
+            select.compound.push(Compound {
+                op,
+                select: self.parse_select_core()?,
+            });
+        }
+
+        if self.consume_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            select.order_by.push(self.parse_ordering_term()?);
+            while self.consume(&TokenKind::Comma) {
+                select.order_by.push(self.parse_ordering_term()?);
+            }
+        }
+
+        if self.consume_keyword("LIMIT") {
+            select.limit = Some(self.parse_limit()?);
+        }
+
+        // ORDER BY and LIMIT are for the whole compound, so they can't come
+        // in the middle of one
+        if let Some(op) = self.peek_compound_operator()
+            && (!select.order_by.is_empty() || select.limit.is_some())
+        {
+            let clause = match select.order_by.is_empty() {
+                true => "LIMIT",
+                false => "ORDER BY",
+            };
+            return Err(ParseError::new(
+                format!("{} clause should come after {} not before", clause, op),
+                self.peek().start,
+            ));
+        }
+
+        Ok(select)
+    }
+
+    // a SELECT up to and including its HAVING clause
+    fn parse_select_core(&mut self) -> Result<Select, ParseError> {
+        self.expect_keyword("SELECT")?;
+        let distinct = self.consume_keyword("DISTINCT");
+        if !distinct {
+            self.consume_keyword("ALL");
+        }
+
+        let mut columns = vec![self.parse_result_column()?];
+        while self.consume(&TokenKind::Comma) {
+            columns.push(self.parse_result_column()?);
+        }
+
+        let from = if self.consume_keyword("FROM") {
+            Some(self.parse_from()?)
+        } else {
+            None
+        };
+
+        let where_clause = if self.consume_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = vec![];
+        if self.consume_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by.push(self.parse_expr()?);
+            while self.consume(&TokenKind::Comma) {
+                group_by.push(self.parse_expr()?);
+            }
+        }
+
+        let having = if self.consume_keyword("HAVING") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut windows = vec![];
+        if self.consume_keyword("WINDOW") {
+            windows.push(self.parse_named_window()?);
+            while self.consume(&TokenKind::Comma) {
+                windows.push(self.parse_named_window()?);
+            }
+        }
+
+        Ok(Select {
+            with: vec![],
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+        })
+    }
+
+    // name AS (definition)
+    fn parse_named_window(&mut self) -> Result<NamedWindow, ParseError> {
+        let name = self.parse_identifier()?;
+        self.expect_keyword("AS")?;
+        self.expect(&TokenKind::LeftParen)?;
+
+        Ok(NamedWindow {
+            name,
+            definition: self.parse_window_definition()?,
+        })
+    }
+
+    // the rest of ([base] PARTITION BY ... ORDER BY ... <frame>), after the
+    // opening parenthesis
+    fn parse_window_definition(&mut self) -> Result<WindowDefinition, ParseError> {
+        let mut definition = WindowDefinition::default();
+
+        // PARTITION, ROWS, RANGE and GROUPS aren't reserved, so they could
+        // otherwise be the name of a window
+        if self.peek_identifier().is_some()
+            && !self.is_keyword("PARTITION")
+            && self.peek_frame_units().is_none()
+        {
+            definition.base = Some(self.parse_identifier()?);
+        }
+
+        if self.consume_keyword("PARTITION") {
+            self.expect_keyword("BY")?;
+            definition.partition_by.push(self.parse_expr()?);
+            while self.consume(&TokenKind::Comma) {
+                definition.partition_by.push(self.parse_expr()?);
+            }
+        }
+
+        if self.consume_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            definition.order_by.push(self.parse_ordering_term()?);
+            while self.consume(&TokenKind::Comma) {
+                definition.order_by.push(self.parse_ordering_term()?);
+            }
+        }
+
+        if let Some(units) = self.peek_frame_units() {
+            self.pos += 1;
+
+            let (start, end) = if self.consume_keyword("BETWEEN") {
+                let start = self.parse_frame_bound()?;
+                self.expect_keyword("AND")?;
+                (start, self.parse_frame_bound()?)
+            } else {
+                (self.parse_frame_bound()?, FrameBound::CurrentRow)
+            };
+            definition.frame = Some(Frame { units, start, end });
+        }
+
+        self.expect(&TokenKind::RightParen)?;
+        Ok(definition)
+    }
+
+    fn peek_frame_units(&self) -> Option<FrameUnits> {
+        if self.is_keyword("ROWS") {
+            Some(FrameUnits::Rows)
+        } else if self.is_keyword("RANGE") {
+            Some(FrameUnits::Range)
+        } else if self.is_keyword("GROUPS") {
+            Some(FrameUnits::Groups)
+        } else {
+            None
+        }
+    }
+
+    // UNBOUNDED PRECEDING, <expr> PRECEDING, CURRENT ROW, <expr> FOLLOWING or
+    // UNBOUNDED FOLLOWING
+    fn parse_frame_bound(&mut self) -> Result<FrameBound, ParseError> {
+        if self.consume_keyword("UNBOUNDED") {
+            if self.consume_keyword("PRECEDING") {
+                return Ok(FrameBound::UnboundedPreceding);
+            }
+            self.expect_keyword("FOLLOWING")?;
+            return Ok(FrameBound::UnboundedFollowing);
+        }
+
+        if self.consume_keyword("CURRENT") {
+            self.expect_keyword("ROW")?;
+            return Ok(FrameBound::CurrentRow);
+        }
+
+        let offset = self.parse_expr()?;
+        if self.consume_keyword("PRECEDING") {
+            return Ok(FrameBound::Preceding(offset));
+        }
+        self.expect_keyword("FOLLOWING")?;
+        Ok(FrameBound::Following(offset))
+    }
+
+    // UNION [ALL], INTERSECT or EXCEPT, if that's what comes next
+    fn peek_compound_operator(&self) -> Option<CompoundOperator> {
+        if self.is_keyword("UNION") {
+            match self.is_keyword_at(1, "ALL") {
+                true => Some(CompoundOperator::UnionAll),
+                false => Some(CompoundOperator::Union),
+            }
+        } else if self.is_keyword("INTERSECT") {
+            Some(CompoundOperator::Intersect)
+        } else if self.is_keyword("EXCEPT") {
+            Some(CompoundOperator::Except)
+        } else {
+            None
+        }
+    }
+
+    // the tables of a WITH clause. RECURSIVE makes no difference: like sqlite
+    // we let any table refer to itself if it's written as a UNION.
+    fn parse_with(&mut self) -> Result<Vec<Cte>, ParseError> {
+        self.consume_keyword("RECURSIVE");
+
+        let mut tables = vec![];
+        loop {
+            let name = self.parse_identifier()?;
+
+            let mut columns = vec![];
+            if self.consume(&TokenKind::LeftParen) {
+                columns.push(self.parse_identifier()?);
+                while self.consume(&TokenKind::Comma) {
+                    columns.push(self.parse_identifier()?);
+                }
+                self.expect(&TokenKind::RightParen)?;
+            }
+
+            // [NOT] MATERIALIZED is only a hint, which we ignore
+            self.expect_keyword("AS")?;
+            if self.consume_keyword("NOT") {
+                self.expect_keyword("MATERIALIZED")?;
+            } else {
+                self.consume_keyword("MATERIALIZED");
+            }
+
+            self.expect(&TokenKind::LeftParen)?;
+            let select = Box::new(self.parse_select()?);
+            self.expect(&TokenKind::RightParen)?;
+
+            tables.push(Cte {
+                name,
+                columns,
+                select,
+            });
+
+            if !self.consume(&TokenKind::Comma) {
+                return Ok(tables);
+            }
+        }
+    }
+
+    fn parse_limit(&mut self) -> Result<Limit, ParseError> {
+        let limit = self.parse_expr()?;
+
+        if self.consume_keyword("OFFSET") {
+            return Ok(Limit {
+                limit,
+                offset: Some(self.parse_expr()?),
+            });
+        }
+
+        // LIMIT <offset>, <limit> - note the offset comes first
+        if self.consume(&TokenKind::Comma) {
+            return Ok(Limit {
+                limit: self.parse_expr()?,
+                offset: Some(limit),
+            });
+        }
+
+        Ok(Limit {
+            limit,
+            offset: None,
+        })
+    }
+
+    fn parse_ordering_term(&mut self) -> Result<OrderingTerm, ParseError> {
+        let (expr, collation) = split_collation(self.parse_expr()?);
+
+        let descending = if self.consume_keyword("DESC") {
+            true
+        } else {
+            self.consume_keyword("ASC");
+            false
+        };
+
+        let nulls_first = if self.consume_keyword("NULLS") {
+            if self.consume_keyword("FIRST") {
+                Some(true)
+            } else {
+                self.expect_keyword("LAST")?;
+                Some(false)
+            }
+        } else {
+            None
+        };
+
+        Ok(OrderingTerm {
+            expr,
+            collation,
+            descending,
+            nulls_first,
+        })
+    }
+
+    fn parse_result_column(&mut self) -> Result<ResultColumn, ParseError> {
+        if self.consume(&TokenKind::Star) {
+            return Ok(ResultColumn::Star);
+        }
+
+        // table.* - we need to look two tokens ahead to tell it apart from
+        // table.column
+        if self.peek_identifier().is_some()
+            && self.peek_at(1).kind == TokenKind::Dot
+            && self.peek_at(2).kind == TokenKind::Star
+        {
+            let table = self.parse_identifier()?;
+            self.pos += 2;
+            return Ok(ResultColumn::TableStar(table));
+        }
+
+        let start = self.peek().start;
+        let expr = self.parse_expr()?;
+        let text = self.sql[start..self.tokens[self.pos - 1].end].to_string();
+        let alias = self.parse_alias()?;
+
+        Ok(ResultColumn::Expr { expr, alias, text })
+    }
+
+    fn parse_from(&mut self) -> Result<FromClause, ParseError> {
+        let table = self.parse_table_or_subquery()?;
+
+        let mut joins = vec![];
+        while let Some((kind, natural)) = self.parse_join_operator()? {
+            let table = self.parse_table_or_subquery()?;
+
+            let constraint = if self.consume_keyword("ON") {
+                Some(JoinConstraint::On(self.parse_expr()?))
+            } else if self.consume_keyword("USING") {
+                self.expect(&TokenKind::LeftParen)?;
+                let mut columns = vec![self.parse_identifier()?];
+                while self.consume(&TokenKind::Comma) {
+                    columns.push(self.parse_identifier()?);
+                }
+                self.expect(&TokenKind::RightParen)?;
+                Some(JoinConstraint::Using(columns))
+            } else {
+                None
+            };
+
+            if natural && constraint.is_some() {
+                return Err(ParseError::new(
+                    "a NATURAL join may not have an ON or USING clause",
+                    self.peek().start,
+                ));
+            }
+
+            joins.push(Join {
+                kind,
+                natural,
+                table,
+                constraint,
+            });
+        }
+
+        Ok(FromClause { table, joins })
+    }
+
+    // a comma or [NATURAL] [LEFT [OUTER] | INNER | CROSS] JOIN. returns None
+    // when the next token doesn't start a join.
+    fn parse_join_operator(&mut self) -> Result<Option<(JoinKind, bool)>, ParseError> {
+        if self.consume(&TokenKind::Comma) {
+            return Ok(Some((JoinKind::Inner, false)));
+        }
+
+        let start = self.pos;
+        let natural = self.consume_keyword("NATURAL");
+
+        let kind = if self.consume_keyword("LEFT") {
+            self.consume_keyword("OUTER");
+            JoinKind::Left
+        } else if self.is_unsupported_join() {
+            return Err(ParseError::new(
+                "RIGHT and FULL OUTER JOINs are not currently supported",
+                self.peek().start,
+            ));
+        } else {
+            if !self.consume_keyword("INNER") {
+                self.consume_keyword("CROSS");
+            }
+            JoinKind::Inner
+        };
+
+        if self.consume_keyword("JOIN") {
+            return Ok(Some((kind, natural)));
+        }
+
+        // NATURAL, LEFT and so on can only be followed by JOIN
+        if self.pos > start {
+            return Err(self.unexpected());
+        }
+        Ok(None)
+    }
+
+    fn parse_table_or_subquery(&mut self) -> Result<TableOrSubquery, ParseError> {
+        if self.is_subquery() {
+            return Ok(TableOrSubquery::Subquery {
+                select: self.parse_subquery()?,
+                alias: self.parse_table_alias()?,
+            });
+        }
+
+        let name = self.parse_identifier()?;
+
+        // json_each(...) and the like
+        if self.consume(&TokenKind::LeftParen) {
+            let mut args = vec![];
+            if self.peek().kind != TokenKind::RightParen {
+                args.push(self.parse_expr()?);
+                while self.consume(&TokenKind::Comma) {
+                    args.push(self.parse_expr()?);
+                }
+            }
+            self.expect(&TokenKind::RightParen)?;
+            return Ok(TableOrSubquery::Function {
+                name,
+                args,
+                alias: self.parse_table_alias()?,
+            });
+        }
+
+        let alias = self.parse_table_alias()?;
+        Ok(TableOrSubquery::Table(TableName { name, alias }))
+    }
+
+    // RIGHT and FULL aren't reserved, so "a RIGHT JOIN b" would otherwise
+    // make RIGHT the alias of a
+    fn parse_table_alias(&mut self) -> Result<Option<String>, ParseError> {
+        match self.is_unsupported_join() {
+            true => Ok(None),
+            false => self.parse_alias(),
+        }
+    }
+
+    // a parenthesis that starts a SELECT rather than an expression
+    fn is_subquery(&self) -> bool {
+        self.peek().kind == TokenKind::LeftParen
+            && (self.is_keyword_at(1, "SELECT") || self.is_keyword_at(1, "WITH"))
+    }
+
+    // (SELECT ...)
+    fn parse_subquery(&mut self) -> Result<Box<Select>, ParseError> {
+        self.expect(&TokenKind::LeftParen)?;
+        let select = self.parse_select()?;
+        self.expect(&TokenKind::RightParen)?;
+        Ok(Box::new(select))
+    }
+
+    fn is_unsupported_join(&self) -> bool {
+        (self.is_keyword("RIGHT") || self.is_keyword("FULL"))
+            && (self.is_keyword_at(1, "JOIN") || self.is_keyword_at(1, "OUTER"))
+    }
+
+    // [AS] alias. the AS keyword is optional.
+    fn parse_alias(&mut self) -> Result<Option<String>, ParseError> {
+        if self.consume_keyword("AS") {
+            if let TokenKind::String(alias) = &self.peek().kind {
+                let alias = alias.clone();
+                self.pos += 1;
+                return Ok(Some(alias));
+            }
+            return Ok(Some(self.parse_identifier()?));
+        }
+
+        match self.peek_identifier() {
+            Some(_) => Ok(Some(self.parse_identifier()?)),
+            None => Ok(None),
+        }
+    }
+
+    // Expressions are parsed by precedence, loosest first. each level parses
+    // the level below it and then loops while it sees one of its own operators:
+    //
+    //   OR
+    //   AND
+    //   NOT
+    //   = == != <> IS [NOT] [NOT] IN [NOT] BETWEEN ISNULL NOTNULL
+    //   < <= > >=
+    //   & | << >>
+    //   + -
+    //   * / %
+    //   ||
+    //   unary - + ~
+    //
+    // so 1 + 2 * 3 parses as 1 + (2 * 3) because * is handled by a deeper level.
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.consume_keyword("OR") {
+            let right = self.parse_and()?;
+            left = binary(BinaryOperator::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.consume_keyword("AND") {
+            let right = self.parse_not()?;
+            left = binary(BinaryOperator::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.consume_keyword("NOT") {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOperator::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_equality()
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Eq => Some(BinaryOperator::Eq),
+                TokenKind::NotEq => Some(BinaryOperator::NotEq),
+                _ => None,
+            };
+
+            if let Some(op) = op {
+                self.pos += 1;
+                let right = self.parse_comparison()?;
+                left = binary(op, left, right);
+            } else if self.consume_keyword("IS") {
+                let op = if self.consume_keyword("NOT") {
+                    BinaryOperator::IsNot
+                } else {
+                    BinaryOperator::Is
+                };
+                let right = self.parse_comparison()?;
+                left = binary(op, left, right);
+            } else if self.consume_keyword("ISNULL") {
+                // x ISNULL, x NOTNULL and x NOT NULL are shorthands for IS [NOT] NULL
+                left = binary(BinaryOperator::Is, left, Expr::Literal(Value::Null));
+            } else if self.consume_keyword("NOTNULL") {
+                left = binary(BinaryOperator::IsNot, left, Expr::Literal(Value::Null));
+            } else if self.is_keyword("NOT") && self.is_keyword_at(1, "NULL") {
+                self.pos += 2;
+                left = binary(BinaryOperator::IsNot, left, Expr::Literal(Value::Null));
+            } else {
+                // IN and BETWEEN can both be negated with a NOT in front
+                let negated = self.is_keyword("NOT")
+                    && (self.is_keyword_at(1, "IN") || self.is_keyword_at(1, "BETWEEN"));
+                if negated {
+                    self.pos += 1;
+                }
+
+                if self.consume_keyword("IN") {
+                    left = self.parse_in(left, negated)?;
+                } else if self.consume_keyword("BETWEEN") {
+                    left = self.parse_between(left, negated)?;
+                } else {
+                    return Ok(left);
+                }
+            }
+        }
+    }
+
+    // the IN keyword has already been consumed
+    fn parse_in(&mut self, expr: Expr, negated: bool) -> Result<Expr, ParseError> {
+        if self.is_subquery() {
+            return Ok(Expr::InSelect {
+                expr: Box::new(expr),
+                select: self.parse_subquery()?,
+                negated,
+            });
+        }
+
+        self.expect(&TokenKind::LeftParen)?;
+
+        let mut list = vec![];
+        if !self.consume(&TokenKind::RightParen) {
+            list.push(self.parse_expr()?);
+            while self.consume(&TokenKind::Comma) {
+                list.push(self.parse_expr()?);
+            }
+            self.expect(&TokenKind::RightParen)?;
+        }
+
+        Ok(Expr::InList {
+            expr: Box::new(expr),
+            list,
+            negated,
+        })
+    }
+
+    // the BETWEEN keyword has already been consumed. the bounds are parsed one
+    // level below equality so that the AND in "BETWEEN 1 AND 5" isn't mistaken
+    // for a logical AND.
+    fn parse_between(&mut self, expr: Expr, negated: bool) -> Result<Expr, ParseError> {
+        let low = self.parse_comparison()?;
+        self.expect_keyword("AND")?;
+        let high = self.parse_comparison()?;
+
+        Ok(Expr::Between {
+            expr: Box::new(expr),
+            low: Box::new(low),
+            high: Box::new(high),
+            negated,
+        })
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_bitwise()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Lt => BinaryOperator::Lt,
+                TokenKind::LtEq => BinaryOperator::LtEq,
+                TokenKind::Gt => BinaryOperator::Gt,
+                TokenKind::GtEq => BinaryOperator::GtEq,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_bitwise()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_bitwise(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::BitAnd => BinaryOperator::BitAnd,
+                TokenKind::BitOr => BinaryOperator::BitOr,
+                TokenKind::ShiftLeft => BinaryOperator::ShiftLeft,
+                TokenKind::ShiftRight => BinaryOperator::ShiftRight,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_additive()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Plus => BinaryOperator::Add,
+                TokenKind::Minus => BinaryOperator::Subtract,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Star => BinaryOperator::Multiply,
+                TokenKind::Slash => BinaryOperator::Divide,
+                TokenKind::Percent => BinaryOperator::Modulo,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_concat()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    // || and the JSON operators -> and ->> bind the tightest of the binary
+    // operators
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Concat => BinaryOperator::Concat,
+                TokenKind::Arrow => BinaryOperator::Extract,
+                TokenKind::DoubleArrow => BinaryOperator::ExtractValue,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek().kind {
+            TokenKind::Minus => UnaryOperator::Negate,
+            TokenKind::Plus => UnaryOperator::Plus,
+            TokenKind::BitNot => UnaryOperator::BitNot,
+            _ => return self.parse_collate(),
+        };
+        self.pos += 1;
+        let operand = self.tokens[self.pos..]
+            .iter()
+            .find(|token| token.kind != TokenKind::LeftParen)
+            .map(|token| token.kind.clone());
+        let expr = self.parse_unary()?;
+
+        // 9223372036854775808 is too big to be an integer, but its negative
+        // is the smallest one there is. -(9223372036854775808) counts too.
+        if op == UnaryOperator::Negate
+            && matches!(expr, Expr::Literal(Value::Float(_)))
+            && operand == Some(TokenKind::Number(String::from("9223372036854775808")))
+        {
+            return Ok(Expr::Literal(Value::Integer(i64::MIN)));
+        }
+
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    // COLLATE binds tighter than anything else, so -x COLLATE NOCASE is
+    // -(x COLLATE NOCASE)
+    fn parse_collate(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_primary()?;
+
+        while self.consume_keyword("COLLATE") {
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation: self.parse_identifier()?,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.peek().clone();
+
+        match &token.kind {
+            TokenKind::Number(text) => {
+                self.pos += 1;
+                Ok(Expr::Literal(parse_number(text, token.start)?))
+            }
+            TokenKind::String(text) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Text(text.clone())))
+            }
+            TokenKind::Blob(bytes) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Blob(bytes.clone())))
+            }
+            TokenKind::Variable(text) => {
+                self.pos += 1;
+                Ok(Expr::Parameter(self.parse_parameter(text, token.start)?))
+            }
+            TokenKind::LeftParen if self.is_subquery() => {
+                Ok(Expr::Subquery(self.parse_subquery()?))
+            }
+            TokenKind::LeftParen => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect(&TokenKind::RightParen)?;
+                Ok(expr)
+            }
+            TokenKind::Word(word) if word.eq_ignore_ascii_case("EXISTS") => {
+                self.pos += 1;
+                Ok(Expr::Exists(self.parse_subquery()?))
+            }
+            TokenKind::Word(word) if word.eq_ignore_ascii_case("NULL") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Null))
+            }
+            _ if self.peek_identifier().is_some() => {
+                let name = self.parse_identifier()?;
+
+                if self.consume(&TokenKind::LeftParen) {
+                    return self.parse_function_call(name);
+                }
+
+                // table.column
+                if self.consume(&TokenKind::Dot) {
+                    let column = self.parse_identifier()?;
+                    return Ok(Expr::Column {
+                        table: Some(name),
+                        name: column,
+                    });
+                }
+
+                Ok(Expr::Column { table: None, name })
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // the rest of name([DISTINCT] args...) or name(*), after the opening
+    // parenthesis
+    fn parse_function_call(&mut self, name: String) -> Result<Expr, ParseError> {
+        let mut args = vec![];
+        let mut distinct = false;
+
+        if self.consume(&TokenKind::Star) {
+            // count(*) is written as a call without arguments
+        } else if self.peek().kind != TokenKind::RightParen {
+            distinct = self.consume_keyword("DISTINCT");
+            if !distinct {
+                self.consume_keyword("ALL");
+            }
+
+            args.push(self.parse_expr()?);
+            while self.consume(&TokenKind::Comma) {
+                args.push(self.parse_expr()?);
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        // OVER (definition) or OVER name
+        let over = match self.consume_keyword("OVER") {
+            true if self.consume(&TokenKind::LeftParen) => {
+                Some(Box::new(self.parse_window_definition()?))
+            }
+            true => Some(Box::new(WindowDefinition {
+                base: Some(self.parse_identifier()?),
+                ..WindowDefinition::default()
+            })),
+            false => None,
+        };
+
+        Ok(Expr::Function {
+            name,
+            args,
+            distinct,
+            over,
+        })
+    }
+
+    // works out a parameter's number the way sqlite does:
+    // - ? is one more than the largest number used so far
+    // - ?NNN is number NNN
+    // - :name, @name and $name get a new number the first time the name is
+    //   used, and the same number every time after that
+    fn parse_parameter(&mut self, text: &str, position: usize) -> Result<usize, ParseError> {
+        if text == "?" {
+            self.parameters.push(None);
+            return Ok(self.parameters.len());
+        }
+
+        if let Some(digits) = text.strip_prefix('?') {
+            let number = match digits.parse::<usize>() {
+                Ok(number) if (1..=MAX_PARAMETER).contains(&number) => number,
+                _ => {
+                    return Err(ParseError::new(
+                        format!("variable number must be between ?1 and ?{}", MAX_PARAMETER),
+                        position,
+                    ));
+                }
+            };
+            if number > self.parameters.len() {
+                self.parameters.resize(number, None);
+            }
+            return Ok(number);
+        }
+
+        if let Some(index) = self
+            .parameters
+            .iter()
+            .position(|name| name.as_deref() == Some(text))
+        {
+            return Ok(index + 1);
+        }
+
+        self.parameters.push(Some(text.to_string()));
+        Ok(self.parameters.len())
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParseError> {
+        match self.peek_identifier() {
+            Some(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            None => Err(self.unexpected()),
+        }
+    }
+
+    // returns the identifier at the current position without consuming it
+    fn peek_identifier(&self) -> Option<String> {
+        match &self.peek().kind {
+            TokenKind::QuotedIdentifier(name) => Some(name.clone()),
+            TokenKind::Word(word) if !RESERVED.iter().any(|kw| kw.eq_ignore_ascii_case(word)) => {
+                Some(word.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        // the last token is always Eof, so looking past the end just keeps
+        // returning it
+        let index = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.is_keyword_at(0, keyword)
+    }
+
+    fn is_keyword_at(&self, n: usize, keyword: &str) -> bool {
+        matches!(&self.peek_at(n).kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.consume_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn consume(&mut self, kind: &TokenKind) -> bool {
+        if &self.peek().kind == kind {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<(), ParseError> {
+        if self.consume(kind) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    // builds an error pointing at the current token, worded like sqlite's
+    fn unexpected(&self) -> ParseError {
+        let token = self.peek();
+
+        if token.kind == TokenKind::Eof {
+            return ParseError::new("incomplete input", token.start);
+        }
+
+        ParseError::new(
+            format!(
+                "near \"{}\": syntax error",
+                &self.sql[token.start..token.end]
+            ),
+            token.start,
+        )
+    }
+}
+
+fn binary(op: BinaryOperator, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+// integers that don't fit in an i64 become floats, the same as sqlite
+fn parse_number(text: &str, position: usize) -> Result<Value, ParseError> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16)
+            .map(|n| Value::Integer(n as i64))
+            .map_err(|_| ParseError::new(format!("hex literal too big: {}", text), position));
+    }
+
+    if !text.contains(['.', 'e', 'E'])
+        && let Ok(n) = text.parse::<i64>()
+    {
+        return Ok(Value::Integer(n));
+    }
+
+    text.parse::<f64>()
+        .map(Value::Float)
+        .map_err(|_| ParseError::new(format!("malformed number: {}", text), position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_select(sql: &str) -> Select {
+        match parse(sql).unwrap().0 {
+            Statement::Select(select) => select,
+        }
+    }
+
+    fn column(name: &str) -> Expr {
+        Expr::Column {
+            table: None,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_select_star() {
+        let select = parse_select("select * from albums;");
+
+        assert_eq!(select.columns, vec![ResultColumn::Star]);
+        assert_eq!(
+            select.from,
+            Some(FromClause {
+                table: TableOrSubquery::Table(TableName {
+                    name: String::from("albums"),
+                    alias: None,
+                }),
+                joins: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_result_columns() {
+        let select = parse_select("SELECT a.*, Title AS t, [Artist Id] id FROM \"my albums\" a");
+
+        assert_eq!(
+            select.columns,
+            vec![
+                ResultColumn::TableStar(String::from("a")),
+                ResultColumn::Expr {
+                    expr: column("Title"),
+                    alias: Some(String::from("t")),
+                    text: String::from("Title"),
+                },
+                ResultColumn::Expr {
+                    expr: column("Artist Id"),
+                    alias: Some(String::from("id")),
+                    text: String::from("[Artist Id]"),
+                },
+            ]
+        );
+        assert_eq!(
+            select.from,
+            Some(FromClause {
+                table: TableOrSubquery::Table(TableName {
+                    name: String::from("my albums"),
+                    alias: Some(String::from("a")),
+                }),
+                joins: vec![],
+            })
+        );
+        assert!(!select.distinct);
+
+        assert!(parse_select("SELECT DISTINCT Title FROM albums").distinct);
+        assert!(!parse_select("SELECT ALL Title FROM albums").distinct);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let select = parse_select("SELECT 1 +  2 * 3");
+
+        assert_eq!(
+            select.columns,
+            vec![ResultColumn::Expr {
+                expr: binary(
+                    BinaryOperator::Add,
+                    Expr::Literal(Value::Integer(1)),
+                    binary(
+                        BinaryOperator::Multiply,
+                        Expr::Literal(Value::Integer(2)),
+                        Expr::Literal(Value::Integer(3)),
+                    ),
+                ),
+                alias: None,
+                text: String::from("1 +  2 * 3"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let select =
+            parse_select("SELECT value -> '$.a' ->> 'b' || 'c' FROM json_each(t.d, '$') j");
+        let text = |s: &str| Expr::Literal(Value::Text(s.to_string()));
+
+        assert_eq!(
+            select.columns,
+            vec![ResultColumn::Expr {
+                expr: binary(
+                    BinaryOperator::Concat,
+                    binary(
+                        BinaryOperator::ExtractValue,
+                        binary(BinaryOperator::Extract, column("value"), text("$.a")),
+                        text("b"),
+                    ),
+                    text("c"),
+                ),
+                alias: None,
+                text: String::from("value -> '$.a' ->> 'b' || 'c'"),
+            }]
+        );
+        assert_eq!(
+            select.from.unwrap().table,
+            TableOrSubquery::Function {
+                name: String::from("json_each"),
+                args: vec![
+                    Expr::Column {
+                        table: Some(String::from("t")),
+                        name: String::from("d"),
+                    },
+                    text("$"),
+                ],
+                alias: Some(String::from("j")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_where() {
+        let select = parse_select(
+            "SELECT * FROM t WHERE a NOT BETWEEN 1 AND 5 AND b NOT IN (1, 2) OR c IS NOT NULL",
+        );
+
+        let between = Expr::Between {
+            expr: Box::new(column("a")),
+            low: Box::new(Expr::Literal(Value::Integer(1))),
+            high: Box::new(Expr::Literal(Value::Integer(5))),
+            negated: true,
+        };
+        let in_list = Expr::InList {
+            expr: Box::new(column("b")),
+            list: vec![
+                Expr::Literal(Value::Integer(1)),
+                Expr::Literal(Value::Integer(2)),
+            ],
+            negated: true,
+        };
+        let is_not_null = binary(
+            BinaryOperator::IsNot,
+            column("c"),
+            Expr::Literal(Value::Null),
+        );
+
+        assert_eq!(
+            select.where_clause,
+            Some(binary(
+                BinaryOperator::Or,
+                binary(BinaryOperator::And, between, in_list),
+                is_not_null,
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        let create_index = parse_create_index(
+            "CREATE UNIQUE INDEX IF NOT EXISTS [idx] ON \"t\" (a COLLATE NOCASE, b DESC) WHERE a IS NOT NULL",
+        )
+        .unwrap();
+
+        assert_eq!(
+            create_index,
+            CreateIndex {
+                name: String::from("idx"),
+                table: String::from("t"),
+                unique: true,
+                columns: vec![
+                    IndexedColumn {
+                        expr: column("a"),
+                        collation: Some(String::from("NOCASE")),
+                        descending: false,
+                    },
+                    IndexedColumn {
+                        expr: column("b"),
+                        collation: None,
+                        descending: true,
+                    },
+                ],
+                where_clause: Some(binary(
+                    BinaryOperator::IsNot,
+                    column("a"),
+                    Expr::Literal(Value::Null),
+                )),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!(parse_number("42", 0), Ok(Value::Integer(42)));
+        assert_eq!(parse_number("0x10", 0), Ok(Value::Integer(16)));
+        assert_eq!(parse_number("2.5", 0), Ok(Value::Float(2.5)));
+        assert_eq!(
+            parse_number("9223372036854775808", 0),
+            Ok(Value::Float(9223372036854775808.0))
+        );
+    }
+
+    #[test]
+    fn test_parse_order_by() {
+        let select = parse_select(
+            "SELECT * FROM albums ORDER BY 2, Title COLLATE NOCASE DESC NULLS FIRST, -x COLLATE rtrim",
+        );
+
+        assert_eq!(
+            select.order_by,
+            vec![
+                OrderingTerm {
+                    expr: Expr::Literal(Value::Integer(2)),
+                    collation: None,
+                    descending: false,
+                    nulls_first: None,
+                },
+                OrderingTerm {
+                    expr: column("Title"),
+                    collation: Some(String::from("NOCASE")),
+                    descending: true,
+                    nulls_first: Some(true),
+                },
+                OrderingTerm {
+                    expr: Expr::Unary {
+                        op: UnaryOperator::Negate,
+                        expr: Box::new(Expr::Collate {
+                            expr: Box::new(column("x")),
+                            collation: String::from("rtrim"),
+                        }),
+                    },
+                    collation: None,
+                    descending: false,
+                    nulls_first: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        let limit = |sql: &str| parse_select(sql).limit.unwrap();
+        let number = |n: i64| Expr::Literal(Value::Integer(n));
+
+        assert_eq!(
+            limit("SELECT * FROM albums LIMIT 10"),
+            Limit {
+                limit: number(10),
+                offset: None
+            }
+        );
+        assert_eq!(
+            limit("SELECT * FROM albums LIMIT 10 OFFSET 20"),
+            Limit {
+                limit: number(10),
+                offset: Some(number(20))
+            }
+        );
+        assert_eq!(
+            limit("SELECT * FROM albums ORDER BY 1 LIMIT 20, 10"),
+            Limit {
+                limit: number(10),
+                offset: Some(number(20))
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_group_by() {
+        let select = parse_select(
+            "SELECT count(*), max(DISTINCT Title) FROM albums GROUP BY ArtistId HAVING count(*) > 1",
+        );
+
+        let count = Expr::Function {
+            name: String::from("count"),
+            args: vec![],
+            distinct: false,
+            over: None,
+        };
+        assert_eq!(
+            select.columns[1],
+            ResultColumn::Expr {
+                expr: Expr::Function {
+                    name: String::from("max"),
+                    args: vec![column("Title")],
+                    distinct: true,
+                    over: None,
+                },
+                alias: None,
+                text: String::from("max(DISTINCT Title)"),
+            }
+        );
+        assert_eq!(select.group_by, vec![column("ArtistId")]);
+        assert_eq!(
+            select.having,
+            Some(binary(
+                BinaryOperator::Gt,
+                count,
+                Expr::Literal(Value::Integer(1))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_joins() {
+        let select = parse_select(
+            "SELECT * FROM albums a, artists JOIN tracks t ON t.AlbumId = a.AlbumId \
+             NATURAL LEFT OUTER JOIN genres CROSS JOIN media_types m USING (MediaTypeId, x)",
+        );
+
+        let table = |name: &str, alias: Option<&str>| {
+            TableOrSubquery::Table(TableName {
+                name: name.to_string(),
+                alias: alias.map(String::from),
+            })
+        };
+        let join = |kind, natural, table, constraint| Join {
+            kind,
+            natural,
+            table,
+            constraint,
+        };
+        let qualified = |table: &str, name: &str| Expr::Column {
+            table: Some(table.to_string()),
+            name: name.to_string(),
+        };
+
+        assert_eq!(
+            select.from,
+            Some(FromClause {
+                table: table("albums", Some("a")),
+                joins: vec![
+                    join(JoinKind::Inner, false, table("artists", None), None),
+                    join(
+                        JoinKind::Inner,
+                        false,
+                        table("tracks", Some("t")),
+                        Some(JoinConstraint::On(binary(
+                            BinaryOperator::Eq,
+                            qualified("t", "AlbumId"),
+                            qualified("a", "AlbumId")
+                        )))
+                    ),
+                    join(JoinKind::Left, true, table("genres", None), None),
+                    join(
+                        JoinKind::Inner,
+                        false,
+                        table("media_types", Some("m")),
+                        Some(JoinConstraint::Using(vec![
+                            String::from("MediaTypeId"),
+                            String::from("x")
+                        ]))
+                    ),
+                ],
+            })
+        );
+
+        let error = |sql| parse(sql).unwrap_err().message;
+        assert_eq!(
+            error("SELECT * FROM a NATURAL JOIN b USING (x)"),
+            "a NATURAL join may not have an ON or USING clause"
+        );
+        assert_eq!(
+            error("SELECT * FROM a RIGHT JOIN b"),
+            "RIGHT and FULL OUTER JOINs are not currently supported"
+        );
+        assert_eq!(error("SELECT * FROM a LEFT b"), "near \"b\": syntax error");
+        // RIGHT is still fine as an alias
+        assert!(parse("SELECT * FROM a right, b").is_ok());
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let select = parse_select(
+            "SELECT (SELECT 1), x NOT IN (SELECT y FROM b), NOT EXISTS (SELECT * FROM c) \
+             FROM (SELECT * FROM a) AS t",
+        );
+
+        let subquery = |sql: &str| Box::new(parse_select(sql));
+        let exprs: Vec<Expr> = select
+            .columns
+            .into_iter()
+            .map(|column| match column {
+                ResultColumn::Expr { expr, .. } => expr,
+                column => panic!("unexpected column {:?}", column),
+            })
+            .collect();
+        assert_eq!(
+            exprs,
+            vec![
+                Expr::Subquery(subquery("SELECT 1")),
+                Expr::InSelect {
+                    expr: Box::new(column("x")),
+                    select: subquery("SELECT y FROM b"),
+                    negated: true,
+                },
+                Expr::Unary {
+                    op: UnaryOperator::Not,
+                    expr: Box::new(Expr::Exists(subquery("SELECT * FROM c"))),
+                },
+            ]
+        );
+        assert_eq!(
+            select.from.unwrap().table,
+            TableOrSubquery::Subquery {
+                select: subquery("SELECT * FROM a"),
+                alias: Some(String::from("t")),
+            }
+        );
+
+        // a parenthesis that doesn't start a SELECT is still an expression
+        assert_eq!(parse_select("SELECT (1) IN (2)").columns.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_compound() {
+        let select = parse_select(
+            "SELECT 1 UNION SELECT 2 UNION ALL SELECT 3 INTERSECT SELECT 4 EXCEPT SELECT 5 \
+             ORDER BY 1 LIMIT 2",
+        );
+
+        let ops: Vec<CompoundOperator> = select.compound.iter().map(|part| part.op).collect();
+        assert_eq!(
+            ops,
+            [
+                CompoundOperator::Union,
+                CompoundOperator::UnionAll,
+                CompoundOperator::Intersect,
+                CompoundOperator::Except,
+            ]
+        );
+        assert_eq!(select.compound[3].select, parse_select("SELECT 5"));
+
+        // ORDER BY and LIMIT are for the whole compound
+        assert_eq!(select.order_by.len(), 1);
+        assert!(select.limit.is_some());
+
+        let error = parse("SELECT 1 ORDER BY 1 UNION SELECT 2").unwrap_err();
+        assert_eq!(
+            error.message,
+            "ORDER BY clause should come after UNION not before"
+        );
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let select = parse_select(
+            "SELECT rank() OVER (w PARTITION BY a ORDER BY b DESC ROWS BETWEEN 1 PRECEDING AND \
+             UNBOUNDED FOLLOWING), sum(c) OVER w FROM t WINDOW w AS (GROUPS 2 PRECEDING)",
+        );
+
+        let over = |column: &ResultColumn| match column {
+            ResultColumn::Expr {
+                expr: Expr::Function { over, .. },
+                ..
+            } => over.clone(),
+            _ => panic!("not a function call"),
+        };
+        assert_eq!(
+            over(&select.columns[0]),
+            Some(Box::new(WindowDefinition {
+                base: Some(String::from("w")),
+                partition_by: vec![column("a")],
+                order_by: vec![OrderingTerm {
+                    expr: column("b"),
+                    collation: None,
+                    descending: true,
+                    nulls_first: None,
+                }],
+                frame: Some(Frame {
+                    units: FrameUnits::Rows,
+                    start: FrameBound::Preceding(Expr::Literal(Value::Integer(1))),
+                    end: FrameBound::UnboundedFollowing,
+                }),
+            }))
+        );
+        assert_eq!(
+            over(&select.columns[1]).and_then(|over| over.base),
+            Some(String::from("w"))
+        );
+
+        // a frame with only a start ends at the current row
+        assert_eq!(
+            select.windows,
+            vec![NamedWindow {
+                name: String::from("w"),
+                definition: WindowDefinition {
+                    frame: Some(Frame {
+                        units: FrameUnits::Groups,
+                        start: FrameBound::Preceding(Expr::Literal(Value::Integer(2))),
+                        end: FrameBound::CurrentRow,
+                    }),
+                    ..WindowDefinition::default()
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let select = parse_select(
+            "WITH RECURSIVE t(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM t LIMIT 5), \
+             u AS MATERIALIZED (SELECT * FROM t) SELECT * FROM u",
+        );
+
+        let subquery = |sql: &str| Box::new(parse_select(sql));
+        assert_eq!(
+            select.with,
+            vec![
+                Cte {
+                    name: String::from("t"),
+                    columns: vec![String::from("n")],
+                    select: subquery("SELECT 1 UNION ALL SELECT n + 1 FROM t LIMIT 5"),
+                },
+                Cte {
+                    name: String::from("u"),
+                    columns: vec![],
+                    select: subquery("SELECT * FROM t"),
+                },
+            ]
+        );
+
+        // a subquery can have its own WITH
+        let select = parse_select("SELECT * FROM (WITH t AS (SELECT 1) SELECT * FROM t)");
+        assert!(matches!(
+            select.from.unwrap().table,
+            TableOrSubquery::Subquery { select, .. } if select.with.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let (statement, parameters) =
+            parse("SELECT ?, :id, ?5, ?, $name, :id, @x FROM albums WHERE ?2").unwrap();
+        let Statement::Select(select) = statement;
+
+        let numbers: Vec<Expr> = select
+            .columns
+            .into_iter()
+            .map(|column| match column {
+                ResultColumn::Expr { expr, .. } => expr,
+                column => panic!("unexpected column {:?}", column),
+            })
+            .collect();
+        assert_eq!(
+            numbers,
+            [1, 2, 5, 6, 7, 2, 8]
+                .into_iter()
+                .map(Expr::Parameter)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(select.where_clause, Some(Expr::Parameter(2)));
+
+        let name = |name: &str| Some(name.to_string());
+        assert_eq!(
+            parameters,
+            vec![
+                None,
+                name(":id"),
+                None,
+                None,
+                None,
+                None,
+                name("$name"),
+                name("@x")
+            ]
+        );
+
+        let error = parse("SELECT ?0").unwrap_err();
+        assert_eq!(
+            error.message,
+            "variable number must be between ?1 and ?32766"
+        );
+        assert_eq!(error.position, 7);
+    }
+
+    #[test]
+    fn test_parse_error_has_position() {
+        let error = parse("SELECT * FROM").unwrap_err();
+        assert_eq!(error.message, "incomplete input");
+        assert_eq!(error.position, 13);
+
+        let error = parse("SELECT Title t extra FROM albums").unwrap_err();
+        assert_eq!(error.message, "near \"extra\": syntax error");
+        assert_eq!(error.position, 15);
+    }
+}
@@ -0,0 +1,730 @@
+use crate::{
+    affinity::Affinity,
+    ast::{self, BinaryOperator},
+    collation::Collation,
+    expr::{self, Expr, Scope},
+    schema::{Index, Table},
+    value::Value,
+};
+
+// how we get at the rows of a table.
+//
+// the planner only looks at the parts of the WHERE clause it understands. it
+// never decides which rows match - the full condition is still checked
+// against every row it hands back, so an access path only has to return at
+// least the matching rows, never exactly them.
+#[derive(Debug)]
+pub enum Access {
+    // read every row
+    FullScan,
+    // WHERE rowid = 5 - a single row, found by descending the table b-tree
+    Rowid(Expr),
+    // WHERE rowid > 5 AND rowid <= 10 - seek to the first row and walk forwards
+    // until we are past the last one
+    RowidRange {
+        lower: Option<Bound>,
+        upper: Option<Bound>,
+    },
+    // WHERE ArtistId = 5 AND ... - walk the part of an index that matches, then
+    // fetch each row by its rowid. `equal` holds values for the first columns
+    // of the index, `lower` and `upper` limit the column after those.
+    Index {
+        index: Index,
+        equal: Vec<Expr>,
+        lower: Option<Bound>,
+        upper: Option<Bound>,
+    },
+    // a.x = b.y where b is the inner table of a join and nothing indexes y.
+    // instead of scanning b for every row of a, b is read once into a hash
+    // table keyed by y, and each row of a looks up `value` in it. keys that
+    // the collation of a.x = b.y says are equal end up in the same slot.
+    Hash {
+        column: usize,
+        value: Expr,
+        collation: Collation,
+    },
+}
+
+#[derive(Debug)]
+pub struct Bound {
+    pub value: Expr,
+    pub inclusive: bool,
+}
+
+impl Access {
+    // the values in an access path can be parameters, which have to be filled
+    // in before the path is used. the plan itself doesn't depend on them.
+    pub fn bind(&self, parameters: &[Value]) -> Access {
+        let bind_bound = |bound: &Option<Bound>| {
+            bound.as_ref().map(|bound| Bound {
+                value: bound.value.bind(parameters),
+                inclusive: bound.inclusive,
+            })
+        };
+
+        match self {
+            Access::FullScan => Access::FullScan,
+            Access::Rowid(value) => Access::Rowid(value.bind(parameters)),
+            Access::RowidRange { lower, upper } => Access::RowidRange {
+                lower: bind_bound(lower),
+                upper: bind_bound(upper),
+            },
+            Access::Index {
+                index,
+                equal,
+                lower,
+                upper,
+            } => Access::Index {
+                index: index.clone(),
+                equal: equal.iter().map(|value| value.bind(parameters)).collect(),
+                lower: bind_bound(lower),
+                upper: bind_bound(upper),
+            },
+            Access::Hash {
+                column,
+                value,
+                collation,
+            } => Access::Hash {
+                column: *column,
+                value: value.bind(parameters),
+                collation: *collation,
+            },
+        }
+    }
+}
+
+// a single `column op constant` test from the WHERE clause. a b-tree can only
+// help if it's sorted with the collation the test compares with.
+struct Constraint {
+    column: Target,
+    op: BinaryOperator,
+    value: Expr,
+    collation: Collation,
+}
+
+#[derive(Clone, PartialEq)]
+enum Target {
+    Rowid,
+    Column(usize),
+}
+
+// SELECT count(*) only needs to know how many entries a b-tree has, and an
+// index has an entry for every row of its table. index entries only hold the
+// indexed columns and the rowid, so the b-tree with the fewest columns is
+// likely to have the fewest pages to read. partial indexes leave rows out and
+// can't be used. None means counting the table itself.
+pub fn choose_count_index<'a>(table: &Table, indexes: &'a [Index]) -> Option<&'a Index> {
+    indexes
+        .iter()
+        .filter(|index| {
+            index.table_name.eq_ignore_ascii_case(&table.name)
+                && index.partial.is_none()
+                && index.columns.len() + 1 < table.column_names.len()
+        })
+        .min_by_key(|index| index.columns.len())
+}
+
+// picks the cheapest way to find the rows of `table` (known as `label` in the
+// query) that might satisfy all of `terms`:
+// 1. a rowid lookup if the terms pin down the rowid
+// 2. an index, if the terms constrain its first column(s) with =. the index
+//    matching the most columns wins, ties go to one that also has a range.
+// 3. a range of rowids
+// 4. an index with only a range on its first column
+// 5. for the inner table of a join, a hash table on a column compared with =
+// 6. a full scan
+//
+// `outer` holds the tables read before this one. their columns have a value
+// by the time this table is read, so they count as constants: in
+// albums JOIN artists ON artists.ArtistId = albums.ArtistId, each row of
+// artists is found by its rowid.
+pub fn choose_access(
+    table: &Table,
+    indexes: &[Index],
+    label: &str,
+    terms: &[&ast::Expr],
+    outer: &Scope,
+) -> Access {
+    let mut constraints = vec![];
+    for term in terms {
+        add_constraints(table, label, term, outer, &mut constraints);
+    }
+
+    // rowids are integers, but a comparison with a collation other than
+    // BINARY is left alone all the same
+    constraints.retain(|c| c.column != Target::Rowid || c.collation == Collation::Binary);
+
+    if let Some(c) = constraints
+        .iter()
+        .find(|c| c.column == Target::Rowid && c.op == BinaryOperator::Eq)
+    {
+        return Access::Rowid(c.value.clone());
+    }
+
+    let mut best: Option<(usize, Access)> = None;
+
+    for index in indexes
+        .iter()
+        .filter(|index| index.table_name.eq_ignore_ascii_case(&table.name) && usable(index))
+    {
+        let (score, access) = index_access(table, index, &constraints);
+        if score > 0 && best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, access));
+        }
+    }
+
+    // a range on the rowid beats a range on an index, because we don't have
+    // to look every row up again afterwards
+    let (lower, upper) = range(&constraints, &Target::Rowid, Collation::Binary);
+    if lower.is_some() || upper.is_some() {
+        match best {
+            Some((score, access)) if score > 1 => return access,
+            _ => return Access::RowidRange { lower, upper },
+        }
+    }
+
+    if let Some((_, access)) = best {
+        return access;
+    }
+
+    // the first table is only read once, a hash table wouldn't save anything
+    if outer.width() > 0
+        && let Some(c) = constraints
+            .iter()
+            .find(|c| matches!(c.column, Target::Column(_)) && c.op == BinaryOperator::Eq)
+        && let Target::Column(column) = c.column
+    {
+        return Access::Hash {
+            column,
+            value: c.value.clone(),
+            collation: c.collation,
+        };
+    }
+
+    Access::FullScan
+}
+
+// the first lower and upper bound the constraints put on a column, comparing
+// with `collation`
+fn range(
+    constraints: &[Constraint],
+    column: &Target,
+    collation: Collation,
+) -> (Option<Bound>, Option<Bound>) {
+    let mut lower = None;
+    let mut upper = None;
+
+    for c in constraints
+        .iter()
+        .filter(|c| c.column == *column && c.collation == collation)
+    {
+        let bound = |inclusive| {
+            Some(Bound {
+                value: c.value.clone(),
+                inclusive,
+            })
+        };
+        match c.op {
+            BinaryOperator::Gt if lower.is_none() => lower = bound(false),
+            BinaryOperator::GtEq if lower.is_none() => lower = bound(true),
+            BinaryOperator::Lt if upper.is_none() => upper = bound(false),
+            BinaryOperator::LtEq if upper.is_none() => upper = bound(true),
+            _ => {}
+        }
+    }
+
+    (lower, upper)
+}
+
+// works out how much of `index` the constraints can use. the score is twice
+// the number of equality columns, plus one if there is a range as well.
+fn index_access(table: &Table, index: &Index, constraints: &[Constraint]) -> (usize, Access) {
+    let position = |name: &str| {
+        table
+            .column_names
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    };
+
+    let mut equal = vec![];
+    let mut lower = None;
+    let mut upper = None;
+
+    for column in &index.columns {
+        let Some(target) = column.name.as_deref().and_then(position) else {
+            break;
+        };
+
+        // the rowid alias isn't stored in the row (or the index), so an index
+        // on it would be no use. sqlite doesn't let you create one anyway.
+        let target = match table.rowid_alias == Some(target) {
+            true => break,
+            false => Target::Column(target),
+        };
+
+        if let Some(c) = constraints.iter().find(|c| {
+            c.column == target && c.op == BinaryOperator::Eq && c.collation == column.collation
+        }) {
+            equal.push(c.value.clone());
+            continue;
+        }
+
+        // ranges only work on ascending columns, the cursor can only walk
+        // forwards through the index
+        if !column.descending {
+            (lower, upper) = range(constraints, &target, column.collation);
+        }
+        break;
+    }
+
+    let score = equal.len() * 2 + usize::from(lower.is_some() || upper.is_some());
+    let access = Access::Index {
+        index: index.clone(),
+        equal,
+        lower,
+        upper,
+    };
+    (score, access)
+}
+
+// partial indexes don't have an entry for every row, and indexes on
+// expressions don't store plain column values
+fn usable(index: &Index) -> bool {
+    index.partial.is_none() && index.columns.iter().all(|column| column.name.is_some())
+}
+
+// a AND b AND c -> [a, b, c]
+pub fn conjuncts<'e>(expr: &'e ast::Expr, terms: &mut Vec<&'e ast::Expr>) {
+    match expr {
+        ast::Expr::Binary {
+            op: BinaryOperator::And,
+            left,
+            right,
+        } => {
+            conjuncts(left, terms);
+            conjuncts(right, terms);
+        }
+        _ => terms.push(expr),
+    }
+}
+
+// recognises `column op constant`, `constant op column` and
+// `column BETWEEN constant AND constant` (which is two constraints)
+fn add_constraints(
+    table: &Table,
+    label: &str,
+    term: &ast::Expr,
+    outer: &Scope,
+    constraints: &mut Vec<Constraint>,
+) {
+    match term {
+        ast::Expr::Binary { op, left, right }
+            if matches!(
+                op,
+                BinaryOperator::Eq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq
+            ) =>
+        {
+            if let Some(column) = target(table, label, left)
+                && let Some(value) = constant(right, outer)
+                && let Some(value) = compared_value(table, &column, value, outer)
+            {
+                constraints.push(Constraint {
+                    collation: value
+                        .collation()
+                        .unwrap_or_else(|| declared_collation(table, &column)),
+                    column,
+                    op: *op,
+                    value,
+                });
+            } else if let Some(column) = target(table, label, right)
+                && let Some(value) = constant(left, outer)
+                && let Some(value) = compared_value(table, &column, value, outer)
+            {
+                // 5 < x is the same as x > 5. the left column's collation
+                // wins over ours.
+                constraints.push(Constraint {
+                    collation: value
+                        .collation()
+                        .or_else(|| outer.column_collation(&value))
+                        .unwrap_or_else(|| declared_collation(table, &column)),
+                    column,
+                    op: flip(*op),
+                    value,
+                });
+            }
+        }
+        ast::Expr::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } => {
+            if let Some(column) = target(table, label, expr)
+                && let Some(low) = constant(low, outer)
+                && let Some(high) = constant(high, outer)
+                && let Some(low) = compared_value(table, &column, low, outer)
+                && let Some(high) = compared_value(table, &column, high, outer)
+            {
+                let declared = declared_collation(table, &column);
+                let lower = Constraint {
+                    column: column.clone(),
+                    op: BinaryOperator::GtEq,
+                    collation: low.collation().unwrap_or(declared),
+                    value: low,
+                };
+                let upper = Constraint {
+                    column,
+                    op: BinaryOperator::LtEq,
+                    collation: high.collation().unwrap_or(declared),
+                    value: high,
+                };
+                constraints.push(lower);
+                constraints.push(upper);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn flip(op: BinaryOperator) -> BinaryOperator {
+    match op {
+        BinaryOperator::Lt => BinaryOperator::Gt,
+        BinaryOperator::LtEq => BinaryOperator::GtEq,
+        BinaryOperator::Gt => BinaryOperator::Lt,
+        BinaryOperator::GtEq => BinaryOperator::LtEq,
+        op => op,
+    }
+}
+
+// is this expression a column of our table (or its rowid)?
+fn target(table: &Table, label: &str, expr: &ast::Expr) -> Option<Target> {
+    let ast::Expr::Column {
+        table: table_name,
+        name,
+    } = expr
+    else {
+        return None;
+    };
+
+    if table_name
+        .as_ref()
+        .is_some_and(|table_name| !table_name.eq_ignore_ascii_case(label))
+    {
+        return None;
+    }
+
+    match table
+        .column_names
+        .iter()
+        .position(|column| column.eq_ignore_ascii_case(name))
+    {
+        Some(index) if table.rowid_alias == Some(index) => Some(Target::Rowid),
+        Some(index) => Some(Target::Column(index)),
+        None if expr::is_rowid_name(name) => Some(Target::Rowid),
+        None => None,
+    }
+}
+
+// the collation a column of our table was declared with. the rowid is an
+// integer, so it doesn't have one.
+fn declared_collation(table: &Table, column: &Target) -> Collation {
+    match column {
+        Target::Rowid => Collation::Binary,
+        Target::Column(i) => table.collations.get(*i).copied().unwrap_or_default(),
+    }
+}
+
+// the affinity a column of our table was declared with. rowids are integers.
+fn declared_affinity(table: &Table, column: &Target) -> Affinity {
+    match column {
+        Target::Rowid => Affinity::Integer,
+        Target::Column(i) => table.affinities.get(*i).copied().unwrap_or(Affinity::Blob),
+    }
+}
+
+// the value a column is compared with, converted the way the comparison
+// converts it (WHERE AlbumId = '5' looks for the integer 5). None when the
+// comparison would convert the column's values instead, which the b-trees
+// aren't sorted by.
+fn compared_value(table: &Table, column: &Target, value: Expr, outer: &Scope) -> Option<Expr> {
+    let declared = declared_affinity(table, column);
+    let affinity = Affinity::comparison(declared, outer.affinity(&value));
+    match declared.converts(affinity) {
+        true => None,
+        false => Some(outer.converted(value, affinity)),
+    }
+}
+
+// an expression that only refers to the outer tables (or none at all) has the
+// same value for every row of this table, so it can be worked out once before
+// the seek. compiling it with only the outer tables in scope fails if it
+// refers to anything else.
+fn constant(expr: &ast::Expr, outer: &Scope) -> Option<Expr> {
+    outer.clone().compile(expr).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, schema::IndexColumn};
+
+    fn albums() -> Table {
+        Table {
+            name: "albums".to_string(),
+            rootpage: 2,
+            column_names: vec![
+                "AlbumId".to_string(),
+                "Title".to_string(),
+                "ArtistId".to_string(),
+            ],
+            collations: vec![Collation::Binary; 3],
+            affinities: vec![Affinity::Integer, Affinity::Text, Affinity::Integer],
+            rowid_alias: Some(0),
+            unsupported: None,
+        }
+    }
+
+    fn index(name: &str, columns: &[&str]) -> Index {
+        Index {
+            name: name.to_string(),
+            table_name: "albums".to_string(),
+            rootpage: 3,
+            columns: columns
+                .iter()
+                .map(|column| IndexColumn {
+                    name: Some(column.to_string()),
+                    descending: false,
+                    collation: Collation::Binary,
+                })
+                .collect(),
+            unique: false,
+            partial: None,
+        }
+    }
+
+    fn plan(indexes: &[Index], condition: &str) -> Access {
+        plan_join(indexes, &Scope::new(), condition)
+    }
+
+    fn plan_join(indexes: &[Index], outer: &Scope, condition: &str) -> Access {
+        plan_table(&albums(), indexes, outer, condition)
+    }
+
+    fn plan_table(table: &Table, indexes: &[Index], outer: &Scope, condition: &str) -> Access {
+        let sql = format!("SELECT * FROM albums WHERE {}", condition);
+        let (ast::Statement::Select(select), _) = parser::parse(&sql).unwrap();
+        let mut terms = vec![];
+        conjuncts(select.where_clause.as_ref().unwrap(), &mut terms);
+        choose_access(table, indexes, "albums", &terms, outer)
+    }
+
+    #[test]
+    fn test_rowid_lookup() {
+        assert!(matches!(plan(&[], "rowid = 5"), Access::Rowid(_)));
+        assert!(matches!(plan(&[], "5 = AlbumId"), Access::Rowid(_)));
+        assert!(matches!(
+            plan(&[], "Title = 'x' AND albums.AlbumId = 1 + 1"),
+            Access::Rowid(_)
+        ));
+        assert!(matches!(plan(&[], "AlbumId = ArtistId"), Access::FullScan));
+        assert!(matches!(
+            plan(&[], "rowid > 5 AND AlbumId <= 10"),
+            Access::RowidRange {
+                lower: Some(_),
+                upper: Some(_)
+            }
+        ));
+        assert!(matches!(
+            plan(&[], "AlbumId = 1 OR Title = 'x'"),
+            Access::FullScan
+        ));
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let indexes = [
+            index("by_artist", &["ArtistId"]),
+            index("by_title", &["Title", "ArtistId"]),
+        ];
+
+        match plan(&indexes, "ArtistId = 5") {
+            Access::Index { index, equal, .. } => {
+                assert_eq!(index.name, "by_artist");
+                assert_eq!(equal.len(), 1);
+            }
+            access => panic!("expected an index lookup, got {:?}", access),
+        }
+
+        // the index that matches more columns wins
+        match plan(&indexes, "ArtistId = 5 AND Title = 'x'") {
+            Access::Index { index, equal, .. } => {
+                assert_eq!(index.name, "by_title");
+                assert_eq!(equal.len(), 2);
+            }
+            access => panic!("expected an index lookup, got {:?}", access),
+        }
+
+        assert!(matches!(
+            plan(&indexes, "ArtistId BETWEEN 1 AND 3"),
+            Access::Index {
+                lower: Some(_),
+                upper: Some(_),
+                ..
+            }
+        ));
+
+        match plan(&indexes, "10 > ArtistId AND ArtistId >= 2") {
+            Access::Index {
+                equal,
+                lower: Some(lower),
+                upper: Some(upper),
+                ..
+            } => {
+                assert!(equal.is_empty());
+                assert!(lower.inclusive);
+                assert!(!upper.inclusive);
+            }
+            access => panic!("expected an index range, got {:?}", access),
+        }
+
+        // ArtistId isn't the first column of by_title
+        assert!(matches!(
+            plan(&indexes[1..], "ArtistId = 5"),
+            Access::FullScan
+        ));
+    }
+
+    #[test]
+    fn test_values_are_converted_to_the_column_affinity() {
+        assert!(matches!(
+            plan(&[], "AlbumId = '5'"),
+            Access::Rowid(Expr::Literal(Value::Integer(5)))
+        ));
+
+        let indexes = [index("by_title", &["Title"])];
+        match plan(&indexes, "Title = 5") {
+            Access::Index { equal, .. } => {
+                assert_eq!(equal, [Expr::Literal(Value::Text(String::from("5")))]);
+            }
+            access => panic!("expected an index lookup, got {:?}", access),
+        }
+
+        // Title = ArtistId converts Title to a number, and by_title isn't
+        // sorted that way
+        let artists = Table {
+            name: "artists".to_string(),
+            rootpage: 4,
+            column_names: vec!["ArtistId".to_string()],
+            collations: vec![Collation::Binary],
+            affinities: vec![Affinity::Integer],
+            rowid_alias: Some(0),
+            unsupported: None,
+        };
+        let mut outer = Scope::new();
+        outer.add_table("artists", &artists);
+        assert!(matches!(
+            plan_join(&indexes, &outer, "albums.Title = artists.ArtistId"),
+            Access::FullScan
+        ));
+    }
+
+    #[test]
+    fn test_unusable_indexes_are_ignored() {
+        let mut partial = index("partial", &["ArtistId"]);
+        partial.partial = Some(ast::Expr::Literal(crate::value::Value::Integer(1)));
+
+        let mut nocase = index("nocase", &["ArtistId"]);
+        nocase.columns[0].collation = Collation::NoCase;
+
+        assert!(matches!(
+            plan(&[partial, nocase], "ArtistId = 5"),
+            Access::FullScan
+        ));
+
+        // a comparison with its own collation can't use a BINARY index
+        let indexes = [index("by_title", &["Title"])];
+        assert!(matches!(
+            plan(&indexes, "Title = 'x' COLLATE NOCASE"),
+            Access::FullScan
+        ));
+        assert!(matches!(
+            plan(&indexes, "rowid = 1 COLLATE NOCASE"),
+            Access::FullScan
+        ));
+    }
+
+    #[test]
+    fn test_index_with_a_collation() {
+        let mut by_title = index("by_title", &["Title"]);
+        by_title.columns[0].collation = Collation::NoCase;
+        let indexes = [by_title];
+
+        assert!(matches!(
+            plan(&indexes, "Title = 'x' COLLATE NOCASE"),
+            Access::Index { .. }
+        ));
+
+        // a column declared COLLATE NOCASE compares that way without being
+        // told to, unless a COLLATE says otherwise
+        let mut albums = albums();
+        albums.collations[1] = Collation::NoCase;
+        let plan = |condition| plan_table(&albums, &indexes, &Scope::new(), condition);
+        assert!(matches!(plan("Title = 'x'"), Access::Index { .. }));
+        assert!(matches!(
+            plan("Title > 'x'"),
+            Access::Index { lower: Some(_), .. }
+        ));
+        assert!(matches!(
+            plan("Title = 'x' COLLATE BINARY"),
+            Access::FullScan
+        ));
+    }
+
+    #[test]
+    fn test_join_lookup() {
+        let artists = Table {
+            name: "artists".to_string(),
+            rootpage: 4,
+            column_names: vec!["ArtistId".to_string(), "Name".to_string()],
+            collations: vec![Collation::Binary; 2],
+            affinities: vec![Affinity::Integer, Affinity::Text],
+            rowid_alias: Some(0),
+            unsupported: None,
+        };
+        let mut outer = Scope::new();
+        outer.add_table("artists", &artists);
+
+        // the outer table's columns are known when albums is read
+        match plan_join(&[], &outer, "albums.AlbumId = artists.ArtistId") {
+            Access::Rowid(Expr::Column(0)) => {}
+            access => panic!("expected a rowid lookup, got {:?}", access),
+        }
+
+        let indexes = [index("by_artist", &["ArtistId"])];
+        assert!(matches!(
+            plan_join(&indexes, &outer, "albums.ArtistId = artists.ArtistId"),
+            Access::Index { .. }
+        ));
+
+        // without an index, a hash table
+        match plan_join(&[], &outer, "Title = Name") {
+            Access::Hash {
+                column: 1,
+                value: Expr::Column(1),
+                collation: Collation::Binary,
+            } => {}
+            access => panic!("expected a hash join, got {:?}", access),
+        }
+        assert!(matches!(
+            plan_join(&[], &outer, "Title > Name"),
+            Access::FullScan
+        ));
+
+        // but not for the first table
+        assert!(matches!(plan(&[], "Title = 'x'"), Access::FullScan));
+    }
+}
@@ -0,0 +1,550 @@
+use crate::value::Value;
+
+// sqlite's printf() and format(). like C's printf, apart from a few extras:
+//
+//   %q  the text with every ' doubled, for putting inside a string literal
+//   %Q  the same, with quotes around it, or NULL without the quotes
+//   %w  the text with every " doubled, for putting inside an identifier
+//   %z  the same as %s
+//   ,   the flag that puts commas between thousands, e.g. %,d
+//   !   the flag that prints floats with up to 26 significant digits (as many
+//       as sqlite works out, see digits), takes the zeros off the end of %e
+//       and %f too, and keeps a .0 on whole numbers. widths of text count
+//       characters rather than bytes with it.
+//
+// arguments that are missing count as NULL, which is 0 to the number
+// conversions and empty text to the others. floats are printed with at most
+// 16 significant digits, the rest are zeros.
+pub fn format(format: &str, args: &[Value]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut next_arg = || args.next().cloned().unwrap_or(Value::Null);
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        // a % at the very end is printed as it is
+        if chars.peek().is_none() {
+            out.push('%');
+            break;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.sign = Some('+'),
+                ' ' => spec.sign = spec.sign.or(Some(' ')),
+                '0' => spec.zero = true,
+                '#' => spec.alternate = true,
+                ',' => spec.thousands = true,
+                '!' => spec.bang = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        // a * width or precision is taken from the arguments. a negative
+        // width means the value goes on the left.
+        if chars.next_if_eq(&'*').is_some() {
+            let width = next_arg().to_integer().unwrap_or(0);
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            spec.width = number(&mut chars);
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            spec.precision = Some(match chars.next_if_eq(&'*') {
+                Some(_) => next_arg().to_integer().unwrap_or(0).max(0) as usize,
+                None => number(&mut chars),
+            });
+        }
+        // sizes like %lld mean nothing here
+        while chars.next_if_eq(&'l').is_some() {}
+
+        let Some(conversion) = chars.next() else {
+            break;
+        };
+        let text = match conversion {
+            '%' => String::from("%"),
+            'd' | 'i' => spec.signed(next_arg().to_integer().unwrap_or(0)),
+            'u' => spec.unsigned(next_arg().to_integer().unwrap_or(0) as u64, 10, ""),
+            'x' => spec.unsigned(next_arg().to_integer().unwrap_or(0) as u64, 16, "0x"),
+            'X' => spec
+                .unsigned(next_arg().to_integer().unwrap_or(0) as u64, 16, "0X")
+                .to_uppercase(),
+            'o' => spec.unsigned(next_arg().to_integer().unwrap_or(0) as u64, 8, "0"),
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                spec.float(next_arg().to_float().unwrap_or(0.0), conversion)
+            }
+            's' | 'z' => spec.truncate(next_arg().to_text().unwrap_or_default()),
+            'c' => {
+                let c = next_arg()
+                    .to_text()
+                    .and_then(|text| text.chars().next())
+                    .unwrap_or_default();
+                // the precision repeats the character
+                c.to_string().repeat(spec.precision.unwrap_or(1).max(1))
+            }
+            'q' | 'Q' | 'w' => {
+                let quote = if conversion == 'w' { '"' } else { '\'' };
+                match next_arg().to_text() {
+                    None if conversion == 'Q' => String::from("NULL"),
+                    None if conversion == 'q' => String::from("(NULL)"),
+                    text => {
+                        let text = spec.truncate(text.unwrap_or_default());
+                        let escaped = text.replace(quote, &format!("{}{}", quote, quote));
+                        match conversion {
+                            'Q' => format!("'{}'", escaped),
+                            _ => escaped,
+                        }
+                    }
+                }
+            }
+            // sqlite stops at a conversion it doesn't know
+            _ => break,
+        };
+
+        // widths of text count bytes, unless the ! flag says characters
+        let bytes = matches!(conversion, 's' | 'z' | 'q' | 'Q' | 'w') && !spec.bang;
+        out.push_str(&spec.pad(text, bytes));
+    }
+
+    out
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    sign: Option<char>,
+    zero: bool,
+    alternate: bool,
+    thousands: bool,
+    bang: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn signed(&self, i: i64) -> String {
+        let mut digits = i.unsigned_abs().to_string();
+        if let Some(precision) = self.precision {
+            digits = format!("{:0>1$}", digits, precision);
+        }
+        if self.thousands {
+            digits = thousands(&digits);
+        }
+
+        let sign = match i < 0 {
+            true => Some('-'),
+            false => self.sign,
+        };
+        self.zero_pad(sign.map(String::from).unwrap_or_default(), digits)
+    }
+
+    fn unsigned(&self, u: u64, radix: u32, prefix: &str) -> String {
+        let mut digits = match radix {
+            16 => format!("{:x}", u),
+            8 => format!("{:o}", u),
+            _ => u.to_string(),
+        };
+        if let Some(precision) = self.precision {
+            digits = format!("{:0>1$}", digits, precision);
+        }
+
+        let prefix = match self.alternate && u != 0 {
+            true => prefix,
+            false => "",
+        };
+        self.zero_pad(prefix.to_string(), digits)
+    }
+
+    fn float(&self, f: f64, conversion: char) -> String {
+        let sign = match f.is_sign_negative() && f != 0.0 {
+            true => Some('-'),
+            false => self.sign,
+        };
+        let sign = sign.map(String::from).unwrap_or_default();
+        if f.is_nan() {
+            return String::from("NaN");
+        }
+        if f.is_infinite() {
+            return format!("{}Inf", sign);
+        }
+
+        let f = f.abs();
+        let max = match self.bang {
+            true => MAX_BANG_DIGITS,
+            false => MAX_DIGITS,
+        };
+        let precision = self.precision.unwrap_or(6);
+        let body = match conversion {
+            'f' => fixed(f, precision, max),
+            'e' | 'E' => scientific(f, precision, max),
+            _ => general(f, precision.max(1), max),
+        };
+
+        // # and ! always print the point. %g takes the zeros at the end of
+        // the fraction off unless there's a #, %e and %f only with a !. the
+        // point goes too if nothing is left after it, but ! keeps a 0 there.
+        let (mantissa, exponent) = match body.split_once('e') {
+            Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+            None => (body.as_str(), None),
+        };
+        let mut mantissa = mantissa.to_string();
+        if (self.alternate || self.bang) && !mantissa.contains('.') {
+            mantissa.push('.');
+        }
+        let trim = match conversion {
+            'g' | 'G' => !self.alternate,
+            _ => self.bang,
+        };
+        if trim && mantissa.contains('.') {
+            mantissa.truncate(mantissa.trim_end_matches('0').len());
+            if mantissa.ends_with('.') {
+                match self.bang {
+                    true => mantissa.push('0'),
+                    false => mantissa.truncate(mantissa.len() - 1),
+                }
+            }
+        }
+        let body = match exponent {
+            Some(exponent) => format!("{}e{}", mantissa, exponent),
+            None => mantissa,
+        };
+        let body = match conversion {
+            'E' | 'G' => body.to_uppercase(),
+            _ => body,
+        };
+
+        self.zero_pad(sign, body)
+    }
+
+    // the precision of %s is how many bytes to print at most, or characters
+    // with the ! flag. a character isn't cut in half though.
+    fn truncate(&self, mut text: String) -> String {
+        match self.precision {
+            Some(precision) if self.bang => text.chars().take(precision).collect(),
+            Some(precision) if precision < text.len() => {
+                let end = (0..=precision)
+                    .rev()
+                    .find(|&end| text.is_char_boundary(end))
+                    .unwrap_or(0);
+                text.truncate(end);
+                text
+            }
+            _ => text,
+        }
+    }
+
+    // zeros go between the sign or prefix and the digits, spaces go on the
+    // outside
+    fn zero_pad(&self, prefix: String, digits: String) -> String {
+        let len = prefix.chars().count() + digits.chars().count();
+        match self.zero && !self.left && len < self.width {
+            true => format!("{}{}{}", prefix, "0".repeat(self.width - len), digits),
+            false => prefix + &digits,
+        }
+    }
+
+    fn pad(&self, text: String, bytes: bool) -> String {
+        let len = match bytes {
+            true => text.len(),
+            false => text.chars().count(),
+        };
+        if len >= self.width {
+            return text;
+        }
+
+        let padding = " ".repeat(self.width - len);
+        match self.left {
+            true => text + &padding,
+            false => padding + &text,
+        }
+    }
+}
+
+// the most significant digits we print, see format
+const MAX_DIGITS: i32 = 16;
+const MAX_BANG_DIGITS: i32 = 26;
+
+// the significant digits sqlite knows of f, and the exponent of the first
+// one. like sqlite we scale f to a whole number that fits in a u64 (up to
+// 9.2e18), so there are 18 or 19 of them. the scaling is done with a pair of
+// doubles to keep the digits past the 17th close to sqlite's, which aren't
+// quite the exact ones: 0.1 comes out as 0.1000000000000000055 either way.
+fn decode(f: f64) -> (Vec<u8>, i32) {
+    const LIMIT: f64 = 9.223372036854775e18;
+    if f == 0.0 {
+        return (vec![b'0'], 0);
+    }
+
+    let mut rr = (f, 0.0);
+    let mut exponent = 0;
+    if rr.0 > LIMIT {
+        while rr.0 > 9.223372036854774e118 {
+            exponent += 100;
+            rr = multiply(rr, 1.0e-100, -1.9991899802602883e-117);
+        }
+        while rr.0 > 9.223372036854774e28 {
+            exponent += 10;
+            rr = multiply(rr, 1.0e-10, -3.643219731549774e-27);
+        }
+        while rr.0 > LIMIT {
+            exponent += 1;
+            rr = multiply(rr, 1.0e-1, -5.551115123125783e-18);
+        }
+    } else {
+        while rr.0 < 9.223372036854775e-83 {
+            exponent -= 100;
+            rr = multiply(rr, 1.0e100, -1.5902891109759918e83);
+        }
+        while rr.0 < 9.223372036854775e7 {
+            exponent -= 10;
+            rr = multiply(rr, 1.0e10, 0.0);
+        }
+        while rr.0 < 9.223372036854775e17 {
+            exponent -= 1;
+            rr = multiply(rr, 1.0e1, 0.0);
+        }
+    }
+
+    let whole = match rr.1 < 0.0 {
+        true => (rr.0 as u64).saturating_sub(-rr.1 as u64),
+        false => (rr.0 as u64).saturating_add(rr.1 as u64),
+    };
+    let digits = whole.to_string().into_bytes();
+    let exponent = exponent + digits.len() as i32 - 1;
+    (digits, exponent)
+}
+
+// x times y, where x is a value and its error term and so is y (with yy).
+// this is Dekker's algorithm, splitting each double in two halves whose
+// products are exact.
+fn multiply(x: (f64, f64), y: f64, yy: f64) -> (f64, f64) {
+    let half = |f: f64| f64::from_bits(f.to_bits() & 0xffff_ffff_fc00_0000);
+    let (hx, hy) = (half(x.0), half(y));
+    let (tx, ty) = (x.0 - hx, y - hy);
+
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let cc = p - c + q + tx * ty;
+    let cc = x.0 * yy + x.1 * y + cc;
+
+    let high = c + cc;
+    (high, c - high + cc)
+}
+
+// the first `count` significant digits of f (no more than `max`), and the
+// exponent of the first one. like sqlite we round what decode gives us, with
+// ties going up, so 0.125 rounds to 0.13 and 2.675 (really 2.67499999...) to
+// 2.67. there can be fewer digits than asked for, the rest are zeros.
+fn digits(f: f64, count: i32, max: i32) -> (String, i32) {
+    let (all, exponent) = decode(f);
+
+    let Ok(count) = usize::try_from(count.min(max)) else {
+        return (String::new(), exponent);
+    };
+    let (kept, exponent) = match count >= all.len() {
+        true => (all, exponent),
+        false => round(&all, count, exponent),
+    };
+
+    (String::from_utf8(kept).unwrap_or_default(), exponent)
+}
+
+// the first `count` of some digits, rounded with ties going up
+fn round(all: &[u8], count: usize, mut exponent: i32) -> (Vec<u8>, i32) {
+    let mut kept = all[..count].to_vec();
+    if all[count] >= b'5' {
+        // 0.999 rounds up to 1.00, which starts a digit earlier
+        match kept.iter().rposition(|&digit| digit != b'9') {
+            Some(i) => {
+                kept[i] += 1;
+                kept[i + 1..].fill(b'0');
+            }
+            None => {
+                kept.fill(b'0');
+                kept.insert(0, b'1');
+                kept.truncate(count.max(1));
+                exponent += 1;
+            }
+        }
+    }
+
+    (kept, exponent)
+}
+
+// f with `precision` digits after the point. past the `max` significant
+// digits we print, it's all zeros.
+fn fixed(f: f64, precision: usize, max: i32) -> String {
+    let (_, exponent) = decode(f);
+    let (digits, exponent) = digits(f, exponent + 1 + precision as i32, max);
+
+    let point = exponent + 1;
+    let digit = |i: i32| match usize::try_from(i) {
+        Ok(i) => digits.as_bytes().get(i).map_or('0', |&b| b as char),
+        Err(_) => '0',
+    };
+
+    let mut out: String = match point > 0 {
+        true => (0..point).map(digit).collect(),
+        false => String::from("0"),
+    };
+    if precision > 0 {
+        out.push('.');
+        out.extend((point..point + precision as i32).map(digit));
+    }
+    out
+}
+
+// f as d.ddde+XX with `precision` digits after the point
+fn scientific(f: f64, precision: usize, max: i32) -> String {
+    let (digits, exponent) = digits(f, precision as i32 + 1, max);
+    let digits = format!("{:0<1$}", digits, precision + 1);
+
+    let mut out = digits[..1].to_string();
+    if precision > 0 {
+        out.push('.');
+        out.push_str(&digits[1..]);
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", out, sign, exponent.abs())
+}
+
+// %g is %e when the exponent is small or large, and %f otherwise, with
+// `precision` significant digits either way
+fn general(f: f64, precision: usize, max: i32) -> String {
+    let (_, exponent) = digits(f, precision as i32, max);
+    match exponent < -4 || exponent >= precision as i32 {
+        true => scientific(f, precision - 1, max),
+        false => fixed(f, (precision as i32 - 1 - exponent) as usize, max),
+    }
+}
+
+// 1234567 -> 1,234,567
+fn thousands(digits: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn number(chars: &mut std::iter::Peekable<std::str::Chars>) -> usize {
+    let mut n: usize = 0;
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        n = n
+            .saturating_mul(10)
+            .saturating_add(digit as usize - '0' as usize);
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_printf() {
+        let text = |s: &str| Value::Text(String::from(s));
+
+        assert_eq!(
+            format(
+                "%-6d|%06d|%+d|%,d|%5.3d|%x|%#o|%u",
+                &[
+                    Value::Integer(42),
+                    Value::Integer(-42),
+                    Value::Integer(5),
+                    Value::Integer(1234567),
+                    Value::Integer(7),
+                    Value::Integer(255),
+                    Value::Integer(8),
+                    Value::Integer(-1),
+                ]
+            ),
+            "42    |-00042|+5|1,234,567|  007|ff|010|18446744073709551615"
+        );
+        assert_eq!(
+            format(
+                "%5s|%-5s|%.2s|%q|%Q|%Q|%.3c|%%",
+                &[
+                    text("ab"),
+                    text("ab"),
+                    text("abc"),
+                    text("it's"),
+                    text("x"),
+                    Value::Null,
+                    text("xyz"),
+                ]
+            ),
+            "   ab|ab   |ab|it''s|'x'|NULL|xxx|%"
+        );
+
+        // a % at the end is printed as it is
+        assert_eq!(format("%", &[]), "%");
+        assert_eq!(format("100%", &[]), "100%");
+
+        // missing arguments are NULL
+        assert_eq!(format("%s %d", &[]), " 0");
+        assert_eq!(
+            format("%*d|%.*f", &[5, 1, 2].map(Value::Integer)),
+            "    1|0.00"
+        );
+    }
+
+    #[test]
+    fn test_printf_floats() {
+        let floats = |format_string: &str, floats: &[f64]| {
+            let args: Vec<Value> = floats.iter().map(|&f| Value::Float(f)).collect();
+            format(format_string, &args)
+        };
+
+        assert_eq!(
+            floats(
+                "%f|%.2f|%10.3f|%-8.1f|%010.2f|%e|%E",
+                &[0.5, 2.675, 3.14259, 2.5, -3.14259, 12345.678, 1e20]
+            ),
+            "0.500000|2.67|     3.143|2.5     |-000003.14|1.234568e+04|1.000000E+20"
+        );
+        assert_eq!(
+            floats(
+                "%g|%g|%g|%G|%#g|%!g|%5.1g",
+                &[1e-5, 100000.0, 1000000.0, 1e20, 1.0, 2.0, 123.456]
+            ),
+            "1e-05|100000|1e+06|1E+20|1.00000|2.0|1e+02"
+        );
+
+        // ties round away from zero
+        assert_eq!(
+            floats("%.0f|%.2f|%.1f", &[2.5, 0.125, -0.25]),
+            "3|0.13|-0.3"
+        );
+
+        // only the first 16 digits are printed
+        assert_eq!(
+            floats("%.20f|%.20g", &[1.0 / 3.0, 0.1]),
+            "0.33333333333333330000|0.1"
+        );
+        assert_eq!(floats("%f", &[f64::INFINITY]), "Inf");
+
+        // ! prints up to 26 digits, takes the zeros off %e and %f and keeps
+        // a digit after the point
+        assert_eq!(
+            floats("%!.20g|%!.20f", &[0.1, 2.0 / 3.0]),
+            "0.1000000000000000055|0.6666666666666666296"
+        );
+        assert_eq!(
+            floats("%!e|%!f|%!.0f|%#.0e|%#g", &[1.0, 1.0, 2.0, 3.0, 0.5]),
+            "1.0e+00|1.0|2.0|3.e+00|0.500000"
+        );
+    }
+}
@@ -1,31 +1,2311 @@
-use crate::{btree, cell::Row, db::Db};
+use crate::{
+    affinity::Affinity,
+    aggregate::{self, Function, Grouping},
+    ast::{
+        self, BinaryOperator, CompoundOperator, Join, JoinConstraint, JoinKind, ResultColumn,
+        Select, Statement, TableOrSubquery,
+    },
+    btree::{self, IndexCursor, TableCursor},
+    cell::{BtreeKind, Row, RowFormat},
+    collation::Collation,
+    db::Db,
+    error::{Error, Result},
+    expr::{self, Expr, Scope},
+    json,
+    pager::Pager,
+    parser,
+    planner::{self, Access, Bound},
+    schema::Table,
+    sort::{self, SortKey},
+    value::Value,
+    window::{self, Window, Windows},
+};
+use std::{
+    borrow::Cow,
+    cell::{Cell, OnceCell, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    rc::Rc,
+};
 
-pub fn execute(db: &mut Db, query: String) -> (Vec<String>, Vec<Row>) {
-    let mut rows: Vec<Row> = vec![];
+// the result of a query: an iterator that produces one row at a time. rows are
+// read from the database file as the iterator is advanced, so nothing is held
+// in memory apart from the pages the cursors are currently on. reading a page
+// can fail part way through, which is why each row is a Result.
+pub struct Rows {
+    inner: Box<dyn Iterator<Item = Result<Row>>>,
+}
+
+impl Rows {
+    fn new(inner: impl Iterator<Item = Result<Row>> + 'static) -> Rows {
+        Rows {
+            inner: Box::new(inner),
+        }
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        self.inner.next()
+    }
+}
+
+// a query that has been parsed and planned but not run yet. running it
+// doesn't use it up, so the same query can be run any number of times.
+pub struct Query<'a> {
+    db: &'a Db,
+    column_names: Vec<String>,
+    // the name of each parameter, see parser::parse
+    parameters: Vec<Option<String>>,
+    plan: Plan,
+}
+
+// how to run a SELECT: the whole query, or a subquery of it
+struct Plan {
+    // one for each table in the FROM clause, in the order they're joined.
+    // empty without a FROM clause, where there is exactly one row with no
+    // columns.
+    scans: Rc<[Scan]>,
+    // the WHERE clause when there's no FROM. otherwise its terms are checked
+    // by the scans, see Scan::on.
+    condition: Option<Expr>,
+    // Some for aggregate queries. the projection, HAVING and ORDER BY are
+    // then evaluated against the rows grouping produces, see
+    // aggregate::grouped.
+    grouping: Option<Grouping>,
+    // set when the query only needs to know how many rows the table has
+    count: Option<Count>,
+    having: Option<Expr>,
+    // the window function calls of the query. their results are added to
+    // the end of each row before the projection, see window::windowed.
+    windows: Vec<Window>,
+    projection: Vec<Expr>,
+    // set for SELECT DISTINCT
+    distinct: Option<Distinct>,
+    // what to sort the rows by. empty when there's no ORDER BY, or when the
+    // scan already returns the rows in the right order.
+    order: Vec<(Expr, SortKey)>,
+    limit: Option<Expr>,
+    offset: Option<Expr>,
+    // a subquery's rows start with the row of the query it's in, see
+    // expr::Scope. this is how many values that takes up.
+    outer_width: usize,
+    // the SELECTs combined with this one by UNION, INTERSECT and EXCEPT. the
+    // order then refers to the output columns of the combined rows.
+    compound: Vec<(CompoundOperator, Plan)>,
+    // how the output columns compare when DISTINCT or a compound looks for
+    // duplicates
+    collations: Vec<Collation>,
+    // the affinity of each output column of the first SELECT, which is what
+    // a subquery's values are compared with
+    affinities: Vec<Affinity>,
+}
+
+// which table to read and how
+struct Scan {
+    root_page: u32,
+    num_columns: usize,
+    access: Access,
+    format: RowFormat,
+    // walk the table from the largest rowid to the smallest
+    reverse: bool,
+    // LEFT JOIN: a row of the tables before this one that no row of this
+    // table joins with still comes through, with NULL for each of this
+    // table's columns
+    left: bool,
+    // what a row of this table has to satisfy to join a row of the tables
+    // before it. WHERE terms are checked here too, by the first scan that has
+    // every table they refer to.
+    on: Vec<Expr>,
+    // WHERE terms that refer to a LEFT JOIN's table have to wait until its
+    // NULLs have been filled in
+    filter: Vec<Expr>,
+    source: Source,
+    // the table's rows by the value of the join column, for Access::Hash.
+    // built the first time it's needed.
+    hash_table: OnceCell<HashMap<Vec<u8>, Vec<Row>>>,
+}
+
+impl Scan {
+    fn bind(&self, parameters: &[Value]) -> Scan {
+        let bind = |terms: &[Expr]| terms.iter().map(|expr| expr.bind(parameters)).collect();
+
+        Scan {
+            root_page: self.root_page,
+            num_columns: self.num_columns,
+            access: self.access.bind(parameters),
+            format: self.format.clone(),
+            reverse: self.reverse,
+            left: self.left,
+            on: bind(&self.on),
+            filter: bind(&self.filter),
+            source: self.source.bind(parameters),
+            hash_table: OnceCell::new(),
+        }
+    }
+}
+
+// where a scan's rows come from
+enum Source {
+    Table,
+    // FROM (SELECT ...), or a table a WITH clause defines
+    Subquery(Rc<Subquery>),
+    // the row the step of a recursive table is working on, see Recursive
+    Queue(Queue),
+    // a table-valued function, like json_each(t.data). its arguments can
+    // refer to the tables before it.
+    Function(json::Table, Vec<Expr>),
+}
 
-    let parts: Vec<&str> = query.split_whitespace().collect();
+type Queue = Rc<RefCell<Vec<Row>>>;
 
-    let mut from_index: usize = 0;
-    let mut select_start_index: usize = 0;
+impl Source {
+    fn bind(&self, parameters: &[Value]) -> Source {
+        match self {
+            Source::Table => Source::Table,
+            Source::Subquery(subquery) => Source::Subquery(Rc::new(subquery.bind(parameters))),
+            // the same queue, which Recursive fills in as it goes
+            Source::Queue(queue) => Source::Queue(queue.clone()),
+            Source::Function(function, args) => Source::Function(
+                *function,
+                args.iter().map(|arg| arg.bind(parameters)).collect(),
+            ),
+        }
+    }
+}
+
+// a table of the FROM clause while the query is being planned
+struct FromTable<'a> {
+    table: Cow<'a, Table>,
+    label: String,
+    left: bool,
+    // the terms of a LEFT JOIN's ON or USING clause. an inner join's are no
+    // different from WHERE terms, so they go with those.
+    on: Vec<ast::Expr>,
+    // the tables before this one, see planner::choose_access
+    outer: Scope<'a>,
+    source: Source,
+}
+
+// SELECT count(*) FROM table, with no WHERE and nothing else that looks at
+// the rows. the entries of a b-tree are counted without decoding any of them,
+// see btree::count_entries.
+#[derive(Clone, Copy)]
+struct Count {
+    root_page: u32,
+    kind: BtreeKind,
+}
+
+// how SELECT DISTINCT finds the rows it has already output
+#[derive(Clone, Copy)]
+enum Distinct {
+    // every row output so far is kept in a hash set
+    Hash,
+    // the rows come from an index in an order that puts equal rows next to
+    // each other, so each only has to be compared with the one before
+    Adjacent,
+}
+
+impl<'a> Query<'a> {
+    pub fn column_names(&self) -> &[String] {
+        &self.column_names
+    }
 
-    if let Some(position) = parts.iter().position(|string| *string == "SELECT") {
-        select_start_index = position;
+    pub fn parameters(&self) -> &[Option<String>] {
+        &self.parameters
     }
-    // find the word FROM and select the token after it
-    if let Some(position) = parts.iter().position(|string| *string == "FROM") {
-        from_index = position;
+
+    // starts reading rows, with `parameters` standing in for ?1, ?2 and so on.
+    // the iterator doesn't borrow the query, so it can outlive it.
+    pub fn run(&self, parameters: &[Value]) -> Result<Rows> {
+        let plan = self.plan.bind(parameters);
+        let rows = plan.run(&self.db.pager, self.db.sort_memory, &[])?;
+        Ok(Rows::new(rows))
+    }
+}
+
+impl Plan {
+    // a copy of the plan with every parameter replaced by its value, which
+    // is what gets run
+    fn bind(&self, parameters: &[Value]) -> Plan {
+        let bind = |expr: &Option<Expr>| expr.as_ref().map(|expr| expr.bind(parameters));
+
+        Plan {
+            scans: self
+                .scans
+                .iter()
+                .map(|scan| scan.bind(parameters))
+                .collect(),
+            condition: bind(&self.condition),
+            grouping: self
+                .grouping
+                .as_ref()
+                .map(|grouping| grouping.bind(parameters)),
+            count: self.count,
+            distinct: self.distinct,
+            having: bind(&self.having),
+            windows: self
+                .windows
+                .iter()
+                .map(|window| window.bind(parameters))
+                .collect(),
+            projection: self
+                .projection
+                .iter()
+                .map(|expr| expr.bind(parameters))
+                .collect(),
+            order: self
+                .order
+                .iter()
+                .map(|(expr, key)| (expr.bind(parameters), *key))
+                .collect(),
+            limit: bind(&self.limit),
+            offset: bind(&self.offset),
+            outer_width: self.outer_width,
+            compound: self
+                .compound
+                .iter()
+                .map(|(op, plan)| (*op, plan.bind(parameters)))
+                .collect(),
+            collations: self.collations.clone(),
+            affinities: self.affinities.clone(),
+        }
     }
 
-    let table_name = parts[from_index + 1].to_string();
-    let condition = parts[select_start_index + 1].to_string();
+    // runs a bound plan. `outer` is the row of the query a subquery is in,
+    // and empty otherwise.
+    fn run(
+        &self,
+        pager: &Pager,
+        sort_memory: usize,
+        outer: &[Value],
+    ) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+        let (limit, offset) = limits(self.limit.as_ref(), self.offset.as_ref())?;
+        let order: Vec<Expr> = self.order.iter().map(|(expr, _)| expr.clone()).collect();
+
+        let rows = match self.compound.is_empty() {
+            true => self.select(pager, sort_memory, outer, order)?,
+            false => {
+                let mut rows = self.select(pager, sort_memory, outer, vec![])?;
+                for (op, plan) in &self.compound {
+                    let right = plan.select(pager, sort_memory, outer, vec![])?;
+                    rows = compound(*op, rows, right, &self.collations, sort_memory);
+                }
+
+                // the combined rows are sorted by their output columns
+                Box::new(rows.map(move |row| {
+                    let (_, row) = row?;
+                    let key = order
+                        .iter()
+                        .map(|expr| expr.eval(&row.values))
+                        .collect::<Result<_>>()?;
+                    Ok((key, row))
+                }))
+            }
+        };
+
+        let rows: Box<dyn Iterator<Item = Result<Row>>> = if self.order.is_empty() {
+            Box::new(rows.map(|row| row.map(|(_, row)| row)))
+        } else {
+            let keys = self.order.iter().map(|(_, key)| *key).collect();
+            Box::new(sort::sorted(rows, keys, sort_memory))
+        };
+
+        Ok(paginate(rows, limit, offset))
+    }
+
+    // the rows of one SELECT, before ORDER BY and LIMIT. each comes with the
+    // values of `order` for it.
+    fn select(
+        &self,
+        pager: &Pager,
+        sort_memory: usize,
+        outer: &[Value],
+        order: Vec<Expr>,
+    ) -> Result<KeyedRows> {
+        // the row expressions are evaluated against: the row of the query
+        // we're in, then the columns of each table followed by its rowid
+        let width = outer.len()
+            + self
+                .scans
+                .iter()
+                .map(|scan| scan.num_columns + 1)
+                .sum::<usize>();
+
+        let rows: Box<dyn Iterator<Item = Result<(i64, Vec<Value>)>>> = match self.scans.first() {
+            Some(first) => {
+                // a row's rowid is the rowid of the first table's row
+                let rowid = outer.len() + first.num_columns;
+
+                Box::new(
+                    join_rows(pager.clone(), self.scans.clone(), 0, outer.to_vec()).map(
+                        move |values| {
+                            let values = values?;
+                            let rowid = match values[rowid] {
+                                Value::Integer(rowid) => rowid,
+                                _ => 0,
+                            };
+                            Ok((rowid, values))
+                        },
+                    ),
+                )
+            }
+            None => {
+                let row = match &self.condition {
+                    Some(condition) if !is_true(condition, outer)? => None,
+                    _ => Some(Ok((0, outer.to_vec()))),
+                };
+                Box::new(row.into_iter())
+            }
+        };
+
+        let rows: Box<dyn Iterator<Item = Result<(i64, Vec<Value>)>>> = match &self.grouping {
+            Some(grouping) => {
+                let groups: Box<dyn Iterator<Item = Result<Vec<Value>>>> = match self.count {
+                    // one group, with every aggregate being count(*)
+                    Some(Count { root_page, kind }) => {
+                        let pager = pager.clone();
+                        let num_aggregates = grouping.aggregates.len();
+                        Box::new(std::iter::once_with(move || {
+                            let count = btree::count_entries(&pager, root_page, kind)?;
+                            let mut values = vec![Value::Null; width];
+                            values.resize(width + num_aggregates, Value::Integer(count as i64));
+                            Ok(values)
+                        }))
+                    }
+                    None => Box::new(aggregate::grouped(
+                        rows.map(|row| row.map(|(_, values)| values)),
+                        grouping.clone(),
+                        width,
+                        sort_memory,
+                    )),
+                };
+
+                let having = self.having.clone();
+                let outer = outer.to_vec();
+                Box::new(groups.filter_map(move |group| {
+                    let mut values = match group {
+                        Ok(values) => values,
+                        Err(e) => return Some(Err(e)),
+                    };
+
+                    // a group made up when there are no rows has NULLs for
+                    // the row of the query we're in, like for everything else
+                    values[..outer.len()].clone_from_slice(&outer);
+
+                    match having.as_ref().map(|having| is_true(having, &values)) {
+                        Some(Err(e)) => Some(Err(e)),
+                        Some(Ok(false)) => None,
+                        _ => Some(Ok((0, values))),
+                    }
+                }))
+            }
+            None => Box::new(rows),
+        };
+
+        let rows: Box<dyn Iterator<Item = Result<(i64, Vec<Value>)>>> =
+            match self.windows.is_empty() {
+                true => rows,
+                false => Box::new(window::windowed(rows, self.windows.clone())),
+            };
+
+        let projection = self.projection.clone();
+        let rows = rows.map(move |row| {
+            let (rowid, values) = row?;
+
+            // the values to sort by come along with each row
+            let key = order
+                .iter()
+                .map(|expr| expr.eval(&values))
+                .collect::<Result<_>>()?;
+
+            Ok((
+                key,
+                Row {
+                    rowid,
+                    values: projection
+                        .iter()
+                        .map(|expr| expr.eval(&values))
+                        .collect::<Result<_>>()?,
+                },
+            ))
+        });
+
+        Ok(match self.distinct {
+            None => Box::new(rows),
+            Some(Distinct::Hash) => {
+                let collations = self.collations.clone();
+                let mut seen = HashSet::new();
+                Box::new(rows.filter(move |row| {
+                    let Ok((_, row)) = row else {
+                        return true;
+                    };
+                    seen.insert(fold(&row.values, &collations))
+                }))
+            }
+            Some(Distinct::Adjacent) => {
+                let collations = self.collations.clone();
+                let mut last: Option<Vec<Value>> = None;
+                Box::new(rows.filter(move |row| {
+                    let Ok((_, row)) = row else {
+                        return true;
+                    };
+                    let values = fold(&row.values, &collations);
+                    if last.as_ref() == Some(&values) {
+                        return false;
+                    }
+                    last = Some(values);
+                    true
+                }))
+            }
+        })
+    }
+}
+
+// values that are == exactly when their collations say they're equal
+fn fold(values: &[Value], collations: &[Collation]) -> Vec<Value> {
+    values
+        .iter()
+        .zip(collations)
+        .map(|(value, collation)| collation.fold(value.clone()))
+        .collect()
+}
+
+// rows with the values they're sorted by
+type KeyedRows = Box<dyn Iterator<Item = Result<(Vec<Value>, Row)>>>;
+
+// combines the rows of two SELECTs. like sqlite we get rid of duplicates by
+// sorting on every column and dropping rows equal to the one before, which is
+// why the rows of UNION, INTERSECT and EXCEPT come out in order. the sort
+// keys aren't used after this, so they're left empty.
+fn compound(
+    op: CompoundOperator,
+    left: KeyedRows,
+    right: KeyedRows,
+    collations: &[Collation],
+    sort_memory: usize,
+) -> KeyedRows {
+    let rows = |keyed: KeyedRows| keyed.map(|row| row.map(|(_, row)| row));
+    let keyed = |rows: Box<dyn Iterator<Item = Result<Row>>>| -> KeyedRows {
+        Box::new(rows.map(|row| row.map(|row| (vec![], row))))
+    };
+
+    match op {
+        CompoundOperator::UnionAll => Box::new(left.chain(right)),
+        CompoundOperator::Union => keyed(distinct(
+            rows(left).chain(rows(right)),
+            collations,
+            sort_memory,
+        )),
+        CompoundOperator::Intersect | CompoundOperator::Except => {
+            let keep = op == CompoundOperator::Intersect;
+            let collations = collations.to_vec();
+
+            // the rows of the right side, looked up by their values. they're
+            // only read once the first row of the left side is.
+            let mut right = Some(rows(right));
+            let mut found: HashSet<Vec<u8>> = HashSet::new();
+
+            let left = distinct(rows(left), &collations, sort_memory);
+            keyed(Box::new(left.filter_map(move |row| {
+                if let Some(right) = right.take() {
+                    for row in right {
+                        match row {
+                            Ok(row) => {
+                                found.insert(aggregate::encode_key(&row.values, &collations))
+                            }
+                            Err(e) => return Some(Err(e)),
+                        };
+                    }
+                }
+
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return Some(Err(e)),
+                };
+                let key = aggregate::encode_key(&row.values, &collations);
+                (found.contains(&key) == keep).then_some(Ok(row))
+            })))
+        }
+    }
+}
+
+// the rows sorted on every column, with only one of each set of equal rows:
+// the last, as sqlite keeps
+fn distinct(
+    rows: impl Iterator<Item = Result<Row>> + 'static,
+    collations: &[Collation],
+    sort_memory: usize,
+) -> Box<dyn Iterator<Item = Result<Row>>> {
+    let keys: Vec<SortKey> = collations
+        .iter()
+        .map(|&collation| SortKey {
+            descending: false,
+            nulls_first: true,
+            collation,
+        })
+        .collect();
+
+    let rows = rows.map(|row| row.map(|row| (row.values.clone(), row)));
+    let mut rows = sort::sorted(rows, keys.clone(), sort_memory).peekable();
+
+    Box::new(std::iter::from_fn(move || {
+        let mut row = match rows.next()? {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        while let Some(Ok(next)) = rows.next_if(|next| {
+            next.as_ref().is_ok_and(|next| {
+                sort::compare_keys(&keys, &row.values, &next.values) == Ordering::Equal
+            })
+        }) {
+            row = next;
+        }
+        Some(Ok(row))
+    }))
+}
+
+// a WITH table written as `initial UNION [ALL] step`. the rows of initial go
+// into a queue. each row taken off the queue is output, and step is run with
+// the table holding just that row, adding the rows it returns to the end of
+// the queue. this goes on until the queue is empty, or until LIMIT rows have
+// been output: a table that would go on forever needs a LIMIT, here or in
+// the query that reads it.
+//
+// with UNION rather than UNION ALL, rows that have already been seen aren't
+// queued again.
+struct Recursive {
+    initial: Plan,
+    step: Plan,
+    all: bool,
+    // where step reads the table's row from
+    queue: Queue,
+    limit: Option<Expr>,
+    offset: Option<Expr>,
+}
+
+impl Recursive {
+    fn bind(&self, parameters: &[Value]) -> Recursive {
+        let bind = |expr: &Option<Expr>| expr.as_ref().map(|expr| expr.bind(parameters));
+
+        Recursive {
+            initial: self.initial.bind(parameters),
+            step: self.step.bind(parameters),
+            all: self.all,
+            queue: self.queue.clone(),
+            limit: bind(&self.limit),
+            offset: bind(&self.offset),
+        }
+    }
+
+    fn run(
+        self: &Rc<Self>,
+        pager: &Pager,
+        sort_memory: usize,
+    ) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+        let (limit, offset) = limits(self.limit.as_ref(), self.offset.as_ref())?;
+
+        let all = self.all;
+        let mut seen = HashSet::new();
+        let mut is_new = move |row: &Row| {
+            let collations = vec![Collation::Binary; row.values.len()];
+            all || seen.insert(aggregate::encode_key(&row.values, &collations))
+        };
+
+        let mut queue = VecDeque::new();
+        for row in self.initial.run(pager, sort_memory, &[])? {
+            let row = row?;
+            if is_new(&row) {
+                queue.push_back(row);
+            }
+        }
+
+        let table = self.queue.clone();
+        let recursive = self.clone();
+        let pager = pager.clone();
+        let rows = std::iter::from_fn(move || {
+            let row = queue.pop_front()?;
+
+            *table.borrow_mut() = vec![row.clone()];
+            let step = match recursive.step.run(&pager, sort_memory, &[]) {
+                Ok(step) => step,
+                Err(e) => return Some(Err(e)),
+            };
+            for next in step {
+                match next {
+                    Ok(next) if is_new(&next) => queue.push_back(next),
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            Some(Ok(row))
+        });
+
+        Ok(paginate(rows, limit, offset))
+    }
+}
+
+// what a subquery runs
+enum Body {
+    Select(Box<Plan>),
+    Recursive(Rc<Recursive>),
+}
+
+impl Body {
+    // the plan that says what the rows look like
+    fn plan(&self) -> &Plan {
+        match self {
+            Body::Select(plan) => plan,
+            Body::Recursive(recursive) => &recursive.initial,
+        }
+    }
+}
+
+// a SELECT used inside an expression, or in FROM, of another query. a
+// subquery that doesn't refer to the query it's in gives the same result
+// every time, so it's only run once: the first time its result is needed.
+pub struct Subquery {
+    body: Body,
+    pager: Pager,
+    sort_memory: usize,
+    // the columns of the queries around it that the subquery refers to
+    outer_columns: Vec<usize>,
+    // its value, or whether it has any rows for EXISTS
+    value: OnceCell<Value>,
+    // the values of x IN (SELECT ...)
+    set: OnceCell<ValueSet>,
+    // every row, for FROM (SELECT ...)
+    rows: OnceCell<Vec<Row>>,
+}
+
+// the values a subquery returned, keyed with aggregate::encode_key so that
+// IN can look them up
+struct ValueSet {
+    keys: HashSet<Vec<u8>>,
+    has_null: bool,
+    empty: bool,
+}
+
+impl Subquery {
+    fn new(body: Body, db: &Db, outer_columns: Vec<usize>) -> Subquery {
+        Subquery {
+            body,
+            pager: db.pager.clone(),
+            sort_memory: db.sort_memory,
+            outer_columns,
+            value: OnceCell::new(),
+            set: OnceCell::new(),
+            rows: OnceCell::new(),
+        }
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.body.plan().projection.len()
+    }
+
+    // the affinity of the value a subquery stands for, from its first column
+    pub fn affinity(&self) -> Affinity {
+        self.body.plan().affinities[0]
+    }
+
+    pub fn bind(&self, parameters: &[Value]) -> Subquery {
+        let body = match &self.body {
+            Body::Select(plan) => Body::Select(Box::new(plan.bind(parameters))),
+            Body::Recursive(recursive) => Body::Recursive(Rc::new(recursive.bind(parameters))),
+        };
+
+        Subquery {
+            body,
+            pager: self.pager.clone(),
+            sort_memory: self.sort_memory,
+            outer_columns: self.outer_columns.clone(),
+            value: OnceCell::new(),
+            set: OnceCell::new(),
+            rows: OnceCell::new(),
+        }
+    }
+
+    pub fn uses_column(&self, test: &impl Fn(usize) -> bool) -> bool {
+        self.outer_columns.iter().any(|&column| test(column))
+    }
+
+    fn correlated(&self) -> bool {
+        !self.outer_columns.is_empty()
+    }
+
+    fn run(&self, row: &[Value]) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+        match &self.body {
+            Body::Select(plan) => plan.run(&self.pager, self.sort_memory, &row[..plan.outer_width]),
+            Body::Recursive(recursive) => recursive.run(&self.pager, self.sort_memory),
+        }
+    }
+
+    // the first value of the first row, or NULL if there isn't one
+    pub fn value(&self, row: &[Value]) -> Result<Value> {
+        self.cached(&self.value, row, |rows| {
+            Ok(match rows.next().transpose()? {
+                Some(mut first) => first.values.swap_remove(0),
+                None => Value::Null,
+            })
+        })
+    }
+
+    pub fn exists(&self, row: &[Value]) -> Result<bool> {
+        let exists = self.cached(&self.value, row, |rows| {
+            Ok(Value::Integer(rows.next().transpose()?.is_some() as i64))
+        })?;
+        Ok(exists == Value::Integer(1))
+    }
+
+    fn cached(
+        &self,
+        cell: &OnceCell<Value>,
+        row: &[Value],
+        get: impl Fn(&mut dyn Iterator<Item = Result<Row>>) -> Result<Value>,
+    ) -> Result<Value> {
+        if let Some(value) = cell.get() {
+            return Ok(value.clone());
+        }
+
+        let value = get(&mut self.run(row)?)?;
+        if !self.correlated() {
+            let _ = cell.set(value.clone());
+        }
+        Ok(value)
+    }
+
+    // x IN (SELECT ...), with the same NULL handling as x IN (1, 2, 3). an
+    // uncorrelated subquery's values go into a hash set once, rather than
+    // being compared one by one for every row. they're converted with the
+    // affinity of the comparison first, x already has been.
+    pub fn contains(
+        &self,
+        value: Value,
+        collation: Option<Collation>,
+        affinity: Affinity,
+        row: &[Value],
+    ) -> Result<Value> {
+        let collation = collation
+            .or_else(|| self.body.plan().projection[0].collation())
+            .unwrap_or_default();
+
+        if self.correlated() {
+            let list = self
+                .run(row)?
+                .map(|row| {
+                    row.map(|mut row| (affinity.apply(row.values.swap_remove(0)), collation))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(expr::in_list(value, list.into_iter()));
+        }
+
+        if self.set.get().is_none() {
+            let mut set = ValueSet {
+                keys: HashSet::new(),
+                has_null: false,
+                empty: true,
+            };
+            for row in self.run(row)? {
+                let value = affinity.apply(row?.values.swap_remove(0));
+                set.empty = false;
+                match value.is_null() {
+                    true => set.has_null = true,
+                    false => {
+                        set.keys
+                            .insert(aggregate::encode_key(&[value], &[collation]));
+                    }
+                }
+            }
+            let _ = self.set.set(set);
+        }
+        let set = self.set.get().unwrap();
+
+        if set.empty {
+            return Ok(Value::Integer(0));
+        }
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+
+        let found = set
+            .keys
+            .contains(&aggregate::encode_key(&[value], &[collation]));
+        Ok(match (found, set.has_null) {
+            (true, _) => Value::Integer(1),
+            // x NOT IN (1, NULL) is NULL rather than true
+            (false, true) => Value::Null,
+            (false, false) => Value::Integer(0),
+        })
+    }
+
+    // all of the rows, for a subquery in FROM. a join may read them many
+    // times over, so they're kept rather than worked out again.
+    fn rows(&self) -> Result<&[Row]> {
+        if let Some(rows) = self.rows.get() {
+            return Ok(rows);
+        }
+
+        let rows = self.run(&[])?.collect::<Result<Vec<Row>>>()?;
+        Ok(self.rows.get_or_init(|| rows))
+    }
+}
+
+// two subqueries are only the same if they're the same object, which is all
+// comparing compiled expressions needs
+impl PartialEq for Subquery {
+    fn eq(&self, other: &Subquery) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Subquery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subquery")
+            .field("outer_columns", &self.outer_columns)
+            .finish_non_exhaustive()
+    }
+}
+
+// a negative limit means no limit, and a negative offset is the same as none
+fn limits(limit: Option<&Expr>, offset: Option<&Expr>) -> Result<(usize, usize)> {
+    let limit = match limit {
+        Some(limit) => usize::try_from(limit_value(limit)?).unwrap_or(usize::MAX),
+        None => usize::MAX,
+    };
+    let offset = match offset {
+        Some(offset) => usize::try_from(limit_value(offset)?).unwrap_or(0),
+        None => 0,
+    };
+    Ok((limit, offset))
+}
+
+// skips `offset` rows and stops after `limit`. rows are only read as they're
+// asked for, so once we have `limit` of them nothing more is read from the
+// table. a sort still has to read everything first, of course.
+fn paginate(
+    rows: impl Iterator<Item = Result<Row>> + 'static,
+    limit: usize,
+    offset: usize,
+) -> Box<dyn Iterator<Item = Result<Row>>> {
+    // skipping the offset only counts rows, an error still comes through
+    let mut skipped = 0;
+    let rows = rows.filter(move |row| {
+        if skipped < offset && row.is_ok() {
+            skipped += 1;
+            return false;
+        }
+        true
+    });
+
+    Box::new(rows.take(limit))
+}
+
+// LIMIT and OFFSET have to be integers. like sqlite we accept anything that
+// turns into one without losing anything, e.g. 10.0 or '10'.
+fn limit_value(expr: &Expr) -> Result<i64> {
+    let value = match expr.eval(&[])? {
+        Value::Text(text) => match text.trim().parse::<i64>() {
+            Ok(i) => Value::Integer(i),
+            Err(_) => text.trim().parse().map_or(Value::Null, Value::Float),
+        },
+        value => value,
+    };
+
+    match value {
+        Value::Integer(i) => Ok(i),
+        Value::Float(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => {
+            Ok(f as i64)
+        }
+        _ => Err(Error::Invalid(String::from("datatype mismatch"))),
+    }
+}
+
+// parses and plans a query. anything wrong with it that we can spot without
+// reading rows (a syntax error, an unknown table or column) shows up here.
+pub fn prepare<'a>(db: &'a Db, sql: &str) -> Result<Query<'a>> {
+    let (statement, parameters) = parser::parse(sql)?;
+
+    match statement {
+        Statement::Select(select) => {
+            let (column_names, plan) = plan_select(db, &select, &mut Scope::with_db(db))?;
+            Ok(Query {
+                db,
+                column_names,
+                parameters,
+                plan,
+            })
+        }
+    }
+}
+
+// plans a subquery of the query `scope` is for. the scope comes from
+// Scope::nest, and afterwards it knows which of the outer query's columns the
+// subquery refers to.
+pub fn prepare_subquery<'a>(
+    db: &'a Db,
+    select: &Select,
+    scope: &mut Scope<'a>,
+) -> Result<Subquery> {
+    let (_, plan) = plan_select(db, select, scope)?;
+    Ok(Subquery::new(
+        Body::Select(Box::new(plan)),
+        db,
+        scope.outer_columns().to_vec(),
+    ))
+}
+
+// the tables WITH clauses define that a query can read. a WITH clause can
+// reuse the name of an earlier one, so later tables come first.
+#[derive(Clone, Default)]
+pub struct CommonTables {
+    tables: Vec<Rc<CommonTable>>,
+}
+
+pub struct CommonTable {
+    name: String,
+    source: CommonSource,
+}
+
+enum CommonSource {
+    // planned again wherever the table is read, with the WITH tables that
+    // came before it in scope
+    Select {
+        cte: Rc<ast::Cte>,
+        visible: CommonTables,
+    },
+    // inside the step of a recursive table, the table is the row being
+    // worked on. the step may only read it once.
+    Queue {
+        columns: Vec<String>,
+        queue: Queue,
+        reads: Cell<usize>,
+    },
+    // a table can't be read by its own select, except as above
+    Circular,
+}
+
+impl CommonTables {
+    pub fn push(&mut self, table: CommonTable) {
+        self.tables.push(Rc::new(table));
+    }
+
+    fn find(&self, name: &str) -> Option<Rc<CommonTable>> {
+        self.tables
+            .iter()
+            .rev()
+            .find(|table| table.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    // the tables a subquery can read. the row of a recursive table is only
+    // there for the FROM clause of its step.
+    pub fn nested(&self) -> CommonTables {
+        let tables = self
+            .tables
+            .iter()
+            .map(|table| match table.source {
+                CommonSource::Queue { .. } => Rc::new(CommonTable {
+                    name: table.name.clone(),
+                    source: CommonSource::Circular,
+                }),
+                _ => table.clone(),
+            })
+            .collect();
+        CommonTables { tables }
+    }
+}
+
+// plans one read of a WITH table: the names of its columns and where its
+// rows come from. a table that's read twice is worked out twice.
+fn plan_common_table(db: &Db, common: &CommonTable) -> Result<(Vec<String>, Source)> {
+    let (cte, visible) = match &common.source {
+        CommonSource::Select { cte, visible } => (cte, visible),
+        CommonSource::Queue {
+            columns,
+            queue,
+            reads,
+        } => {
+            if reads.replace(reads.get() + 1) > 0 {
+                return Err(Error::Invalid(format!(
+                    "multiple references to recursive table: {}",
+                    common.name
+                )));
+            }
+            return Ok((columns.clone(), Source::Queue(queue.clone())));
+        }
+        CommonSource::Circular => {
+            return Err(Error::Invalid(format!(
+                "circular reference: {}",
+                common.name
+            )));
+        }
+    };
+
+    let scope_with = |source: CommonSource| {
+        let mut scope = Scope::with_db(db);
+        let tables = scope.common_tables_mut();
+        *tables = visible.clone();
+        tables.push(CommonTable {
+            name: cte.name.clone(),
+            source,
+        });
+        scope
+    };
+
+    // the names of the table's columns, given the output columns of its
+    // SELECT
+    let names = |column_names: Vec<String>| {
+        if cte.columns.is_empty() {
+            return Ok(column_names);
+        }
+        if cte.columns.len() != column_names.len() {
+            return Err(Error::Invalid(format!(
+                "table {} has {} values for {} columns",
+                cte.name,
+                column_names.len(),
+                cte.columns.len()
+            )));
+        }
+        Ok(cte.columns.clone())
+    };
+
+    // the table is recursive if its SELECT ends in UNION [ALL] and a step
+    // that reads the table. everything before the step is the initial
+    // select.
+    let select = &cte.select;
+    let recursive = match select.compound.split_last() {
+        Some((last, rest))
+            if matches!(
+                last.op,
+                CompoundOperator::Union | CompoundOperator::UnionAll
+            ) =>
+        {
+            let initial = Select {
+                compound: rest.to_vec(),
+                order_by: vec![],
+                limit: None,
+                ..(**select).clone()
+            };
+            let (column_names, initial) =
+                plan_select(db, &initial, &mut scope_with(CommonSource::Circular))?;
+            let column_names = names(column_names)?;
+
+            let queue = Queue::default();
+            let mut scope = scope_with(CommonSource::Queue {
+                columns: column_names.clone(),
+                queue: queue.clone(),
+                reads: Cell::new(0),
+            });
+            let (_, step) = plan_select(db, &last.select, &mut scope)?;
+
+            step.scans
+                .iter()
+                .any(|scan| matches!(scan.source, Source::Queue(_)))
+                .then_some((column_names, initial, last.op, step, queue))
+        }
+        _ => None,
+    };
+
+    let Some((column_names, initial, op, step, queue)) = recursive else {
+        let (column_names, plan) =
+            plan_select(db, select, &mut scope_with(CommonSource::Circular))?;
+        let subquery = Subquery::new(Body::Select(Box::new(plan)), db, vec![]);
+        return Ok((names(column_names)?, Source::Subquery(Rc::new(subquery))));
+    };
+
+    if !select.order_by.is_empty() {
+        return Err(Error::Unsupported(String::from("ORDER BY in a WITH table")));
+    }
+    if step.projection.len() != column_names.len() {
+        return Err(Error::Invalid(format!(
+            "SELECTs to the left and right of {} do not have the same number of result columns",
+            op
+        )));
+    }
+    if step.grouping.is_some() {
+        return Err(Error::Invalid(String::from(
+            "recursive aggregate queries not supported",
+        )));
+    }
+
+    // LIMIT and OFFSET at the end are for the whole table, not just the step
+    let (limit, offset) = plan_limit(select.limit.as_ref(), &Scope::with_db(db))?;
+
+    let recursive = Recursive {
+        initial,
+        step,
+        all: op == CompoundOperator::UnionAll,
+        queue,
+        limit,
+        offset,
+    };
+    let subquery = Subquery::new(Body::Recursive(Rc::new(recursive)), db, vec![]);
+    Ok((column_names, Source::Subquery(Rc::new(subquery))))
+}
+
+// works out the output column names and the plan of a SELECT. its tables are
+// added to `scope`, after those of any queries it's nested in.
+fn plan_select<'a>(
+    db: &'a Db,
+    select: &Select,
+    scope: &mut Scope<'a>,
+) -> Result<(Vec<String>, Plan)> {
+    // the tables of a WITH clause can be read by the rest of the query, and
+    // each one by the tables after it
+    for (i, cte) in select.with.iter().enumerate() {
+        if select.with[..i]
+            .iter()
+            .any(|other| other.name.eq_ignore_ascii_case(&cte.name))
+        {
+            return Err(Error::Invalid(format!(
+                "duplicate WITH table name: {}",
+                cte.name
+            )));
+        }
+
+        let table = CommonTable {
+            name: cte.name.clone(),
+            source: CommonSource::Select {
+                cte: Rc::new(cte.clone()),
+                visible: scope.common_tables().clone(),
+            },
+        };
+        scope.common_tables_mut().push(table);
+    }
+
+    match select.compound.is_empty() {
+        true => plan_core(db, select, scope),
+        false => plan_compound(db, select, scope),
+    }
+}
+
+// plans SELECTs combined with UNION, INTERSECT and EXCEPT. each one is
+// planned on its own in the scope the compound is in, and the plan of the
+// first one runs the rest and combines their rows.
+fn plan_compound<'a>(
+    db: &'a Db,
+    select: &Select,
+    scope: &mut Scope<'a>,
+) -> Result<(Vec<String>, Plan)> {
+    let first = Select {
+        with: vec![],
+        compound: vec![],
+        order_by: vec![],
+        limit: None,
+        ..select.clone()
+    };
+
+    let mut scopes = vec![scope.clone()];
+    let (column_names, mut plan) = plan_core(db, &first, &mut scopes[0])?;
+
+    let mut parts = vec![];
+    for part in &select.compound {
+        let mut part_scope = scope.clone();
+        let (_, part_plan) = plan_core(db, &part.select, &mut part_scope)?;
+        if part_plan.projection.len() != column_names.len() {
+            return Err(Error::Invalid(format!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                part.op
+            )));
+        }
+        scopes.push(part_scope);
+        parts.push((part.op, part_plan));
+    }
+
+    // a column compares the way the first SELECT that gives it a collation
+    // says, with a COLLATE or a column declared with one
+    let plans: Vec<&Plan> = std::iter::once(&plan)
+        .chain(parts.iter().map(|(_, plan)| plan))
+        .collect();
+    let collations: Vec<Collation> = (0..column_names.len())
+        .map(|i| {
+            plans
+                .iter()
+                .zip(&scopes)
+                .find_map(|(plan, scope)| {
+                    let expr = &plan.projection[i];
+                    expr.collation().or_else(|| scope.column_collation(expr))
+                })
+                .unwrap_or_default()
+        })
+        .collect();
+
+    // ORDER BY can only sort by output columns: a number, the name of a
+    // column of the first SELECT, or an expression one of the SELECTs
+    // outputs
+    let mut order = vec![];
+    for (i, term) in select.order_by.iter().enumerate() {
+        let column = match &term.expr {
+            ast::Expr::Literal(Value::Integer(n)) => {
+                output_position(*n, "ORDER BY", i, column_names.len())?
+            }
+            expr => {
+                let named = match expr {
+                    ast::Expr::Column { table: None, name } => column_names
+                        .iter()
+                        .position(|column| column.eq_ignore_ascii_case(name)),
+                    _ => None,
+                };
+                let found = named.or_else(|| {
+                    plans.iter().zip(&mut scopes).find_map(|(plan, scope)| {
+                        let expr = scope.compile(expr).ok()?;
+                        plan.projection.iter().position(|column| *column == expr)
+                    })
+                });
+                found.ok_or_else(|| {
+                    Error::Invalid(format!(
+                        "{} ORDER BY term does not match any column in the result set",
+                        ordinal(i + 1)
+                    ))
+                })?
+            }
+        };
+
+        let collation = match &term.collation {
+            Some(name) => Collation::from_name(name)?,
+            None => collations[column],
+        };
+
+        let key = SortKey {
+            descending: term.descending,
+            nulls_first: term.nulls_first.unwrap_or(!term.descending),
+            collation,
+        };
+        order.push((Expr::Column(column), key));
+    }
+
+    for part_scope in &scopes {
+        scope.add_outer_columns(part_scope);
+    }
+
+    (plan.limit, plan.offset) = plan_limit(select.limit.as_ref(), scope)?;
+    plan.order = order;
+    plan.compound = parts;
+    plan.collations = collations;
+    Ok((column_names, plan))
+}
+
+// plans one SELECT, ignoring the SELECTs it may be combined with
+fn plan_core<'a>(
+    db: &'a Db,
+    select: &Select,
+    scope: &mut Scope<'a>,
+) -> Result<(Vec<String>, Plan)> {
+    // the tables of the queries we're nested in come first
+    let base = scope.num_tables();
+    let outer_width = scope.width();
+
+    let mut from_tables: Vec<FromTable> = vec![];
+    // ON and USING terms of inner joins, which are checked like WHERE terms
+    let mut join_terms: Vec<ast::Expr> = vec![];
+
+    if let Some(from) = &select.from {
+        let tables = std::iter::once((None, &from.table))
+            .chain(from.joins.iter().map(|join| (Some(join), &join.table)));
+
+        for (join, source) in tables {
+            // a subquery's rows are read like those of a table with its
+            // output columns. it can't refer to the tables around it.
+            let (table, label, source) = match source {
+                TableOrSubquery::Table(name) => {
+                    let label = name.alias.as_ref().unwrap_or(&name.name).clone();
+
+                    // a WITH table hides a table of the database
+                    match scope.common_tables().find(&name.name) {
+                        Some(common) => {
+                            let (column_names, source) = plan_common_table(db, &common)?;
+                            (derived_table(&label, column_names), label, source)
+                        }
+                        None => (
+                            Cow::Borrowed(find_table(db, &name.name)?),
+                            label,
+                            Source::Table,
+                        ),
+                    }
+                }
+                TableOrSubquery::Subquery { select, alias } => {
+                    let (column_names, plan) = plan_select(db, select, &mut scope.detach())?;
+                    let label = match alias {
+                        Some(alias) => alias.clone(),
+                        None => format!("(subquery-{})", from_tables.len() + 1),
+                    };
+                    let subquery = Subquery::new(Body::Select(Box::new(plan)), db, vec![]);
+                    (
+                        derived_table(&label, column_names),
+                        label,
+                        Source::Subquery(Rc::new(subquery)),
+                    )
+                }
+                TableOrSubquery::Function { name, args, alias } => {
+                    let Some(function) = json::Table::find(name, args.len())? else {
+                        return Err(Error::NoSuchTable(name.clone()));
+                    };
+                    let args = args
+                        .iter()
+                        .map(|arg| scope.compile(arg))
+                        .collect::<Result<Vec<_>>>()?;
+                    let label = alias.as_ref().unwrap_or(name).clone();
+                    let column_names = json::TABLE_COLUMNS.map(String::from).to_vec();
+                    (
+                        derived_table(&label, column_names),
+                        label,
+                        Source::Function(function, args),
+                    )
+                }
+            };
+            let outer = scope.clone();
+
+            let mut on = vec![];
+            if let Some(Join {
+                constraint: Some(JoinConstraint::On(expr)),
+                ..
+            }) = join
+            {
+                let mut terms = vec![];
+                planner::conjuncts(expr, &mut terms);
+                on.extend(terms.into_iter().cloned());
+            }
+
+            // USING (x) means left.x = right.x, where left is the first table
+            // before this one that has an x. NATURAL is USING with every
+            // column both sides have.
+            let using: Vec<&String> = match join {
+                Some(Join {
+                    constraint: Some(JoinConstraint::Using(columns)),
+                    ..
+                }) => columns.iter().collect(),
+                Some(Join { natural: true, .. }) => table
+                    .column_names
+                    .iter()
+                    .filter(|name| scope.resolve_using(name).is_some())
+                    .collect(),
+                _ => vec![],
+            };
+
+            let mut merged = vec![];
+            for name in using {
+                let column = table
+                    .column_names
+                    .iter()
+                    .position(|column| column.eq_ignore_ascii_case(name));
+                let (Some(left), Some(column)) = (scope.resolve_using(name), column) else {
+                    return Err(Error::Invalid(format!(
+                        "cannot join using column {} - column not present in both tables",
+                        name
+                    )));
+                };
+
+                let qualified = |table: &str| {
+                    Box::new(ast::Expr::Column {
+                        table: Some(table.to_string()),
+                        name: name.clone(),
+                    })
+                };
+                on.push(ast::Expr::Binary {
+                    op: BinaryOperator::Eq,
+                    left: qualified(scope.table_name(left).unwrap_or_default()),
+                    right: qualified(&label),
+                });
+                merged.push(column);
+            }
+
+            match source {
+                Source::Table => scope.add_table(&label, &table),
+                _ => scope.add_subquery(&label, &table),
+            }
+            for column in merged {
+                scope.merge_column(base + from_tables.len(), column);
+            }
+
+            let left = join.is_some_and(|join| join.kind == JoinKind::Left);
+            if !left {
+                join_terms.append(&mut on);
+            }
+
+            from_tables.push(FromTable {
+                table,
+                label,
+                left,
+                on,
+                outer,
+                source,
+            });
+        }
+    }
+
+    // work out what each output column is called and how to compute it. this
+    // also tells the scope which columns we need to read from the table.
     let mut column_names: Vec<String> = vec![];
+    let mut projection: Vec<Expr> = vec![];
+    let mut aliases: Vec<Option<&String>> = vec![];
+    // the expression each output column was written as, None for *
+    let mut sources: Vec<Option<&ast::Expr>> = vec![];
+    let mut aggregates = vec![];
+    let mut windows = Windows::new(&select.windows)?;
+
+    for column in &select.columns {
+        match column {
+            ResultColumn::Star => {
+                for (name, index) in scope.expand_star(None)? {
+                    column_names.push(name);
+                    projection.push(Expr::Column(index));
+                    aliases.push(None);
+                    sources.push(None);
+                }
+            }
+            ResultColumn::TableStar(table_name) => {
+                for (name, index) in scope.expand_star(Some(table_name))? {
+                    column_names.push(name);
+                    projection.push(Expr::Column(index));
+                    aliases.push(None);
+                    sources.push(None);
+                }
+            }
+            ResultColumn::Expr { expr, alias, text } => {
+                let compiled =
+                    scope.compile_with_windows(expr, Some(&mut aggregates), &mut windows)?;
+                column_names.push(output_name(scope, expr, &compiled, alias, text));
+                projection.push(compiled);
+                aliases.push(alias.as_ref());
+                sources.push(Some(expr));
+            }
+        }
+    }
+
+    // each WHERE term is checked as soon as the tables it refers to have
+    // been read, so that rows which can't match are dropped before they're
+    // joined with the next table
+    let mut where_terms = vec![];
+    if let Some(condition) = &select.where_clause {
+        planner::conjuncts(condition, &mut where_terms);
+    }
+    where_terms.extend(&join_terms);
+
+    // the end of each table's part of the row
+    let ends: Vec<usize> = from_tables
+        .iter()
+        .scan(outer_width, |end, source| {
+            *end += source.table.column_names.len() + 1;
+            Some(*end)
+        })
+        .collect();
+    let level = |expr: &Expr| {
+        ends.iter()
+            .position(|&end| !expr.uses_column(&|column| column >= end))
+            .unwrap_or(0)
+    };
+
+    // without a FROM clause there's nothing to split the terms between
+    let condition = match from_tables.is_empty() {
+        true => select
+            .where_clause
+            .as_ref()
+            .map(|condition| scope.compile(condition))
+            .transpose()?,
+        false => None,
+    };
+
+    let mut on = vec![vec![]; from_tables.len()];
+    let mut filter = vec![vec![]; from_tables.len()];
+
+    for term in where_terms.iter().filter(|_| !from_tables.is_empty()) {
+        let expr = scope.compile(term)?;
+        let level = level(&expr);
+        match from_tables[level].left {
+            true => filter[level].push(expr),
+            false => on[level].push(expr),
+        }
+    }
+
+    for (i, source) in from_tables.iter().enumerate() {
+        for term in &source.on {
+            let expr = scope.compile(term)?;
+            if level(&expr) > i {
+                return Err(Error::Invalid(String::from(
+                    "ON clause references tables to its right",
+                )));
+            }
+            on[i].push(expr);
+        }
+    }
+
+    // GROUP BY is worked out from the rows of the table, before there are any
+    // groups, so it can't use aggregates
+    let mut group_by = vec![];
+    for (i, term) in select.group_by.iter().enumerate() {
+        let source = match term {
+            // GROUP BY 2 groups by the second output column
+            ast::Expr::Literal(Value::Integer(n)) => {
+                let n = output_position(*n, "GROUP BY", i, projection.len())?;
+                match sources[n] {
+                    Some(source) => source,
+                    None => {
+                        group_by.push(scope.with_column_collation(projection[n].clone()));
+                        continue;
+                    }
+                }
+            }
+            // GROUP BY name, where name is an output column's alias. unlike
+            // ORDER BY, a column of the table with the same name wins.
+            ast::Expr::Column { table: None, name }
+                if let Some(n) = aliases.iter().position(|alias| {
+                    alias.is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+                }) && matches!(scope.compile(term), Err(Error::NoSuchColumn(_))) =>
+            {
+                sources[n].unwrap_or(term)
+            }
+            term => term,
+        };
+
+        let mut found = vec![];
+        let expr = scope.compile_with_aggregates(source, &mut found)?;
+        if !found.is_empty() {
+            return Err(Error::Invalid(String::from(
+                "aggregate functions are not allowed in the GROUP BY clause",
+            )));
+        }
+        // rows go in the same group when the column's collation says their
+        // values are equal
+        group_by.push(scope.with_column_collation(expr));
+    }
+
+    let having = select
+        .having
+        .as_ref()
+        .map(|having| scope.compile_with_aggregates(having, &mut aggregates))
+        .transpose()?;
+
+    // a query is an aggregate query if it has a GROUP BY or uses an aggregate
+    // in its output columns or HAVING
+    let is_aggregate = !group_by.is_empty() || !aggregates.is_empty();
+    if having.is_some() && !is_aggregate {
+        return Err(Error::Invalid(String::from(
+            "HAVING clause on a non-aggregate query",
+        )));
+    }
+
+    let mut order = vec![];
+    for (i, term) in select.order_by.iter().enumerate() {
+        let expr = match &term.expr {
+            // ORDER BY 2 sorts by the second output column
+            ast::Expr::Literal(Value::Integer(n)) => {
+                projection[output_position(*n, "ORDER BY", i, projection.len())?].clone()
+            }
+            // ORDER BY name, where name is an output column's alias
+            ast::Expr::Column { table: None, name }
+                if let Some(n) = aliases.iter().position(|alias| {
+                    alias.is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+                }) =>
+            {
+                projection[n].clone()
+            }
+            // ORDER BY can only use aggregates if the query is already an
+            // aggregate query
+            expr if is_aggregate => {
+                scope.compile_with_windows(expr, Some(&mut aggregates), &mut windows)?
+            }
+            expr => scope.compile_with_windows(expr, None, &mut windows)?,
+        };
+
+        // without a COLLATE of its own, a term sorts with the collation of
+        // the column it is, if it was declared with one
+        let collation = match &term.collation {
+            Some(name) => Collation::from_name(name)?,
+            None => expr
+                .collation()
+                .or_else(|| scope.column_collation(&expr))
+                .unwrap_or_default(),
+        };
+
+        let key = SortKey {
+            descending: term.descending,
+            nulls_first: term.nulls_first.unwrap_or(!term.descending),
+            collation,
+        };
+        order.push((expr, key));
+    }
+
+    // now that we know how many aggregates there are, the results of the
+    // window functions can go after them
+    if !windows.calls.is_empty() {
+        let base = scope.width() + aggregates.len();
+        let map = |column: usize| match column.checked_sub(window::PLACEHOLDER) {
+            Some(k) => base + k,
+            None => column,
+        };
+        for expr in &mut projection {
+            *expr = expr.map_columns(&map);
+        }
+        for (expr, _) in &mut order {
+            *expr = expr.map_columns(&map);
+        }
+    }
+    let windows = windows.calls;
+
+    let grouping = is_aggregate.then_some(Grouping {
+        group_by,
+        aggregates,
+    });
+
+    let (limit, offset) = plan_limit(select.limit.as_ref(), scope)?;
+
+    let mut scans: Vec<Scan> = vec![];
+    for (i, source) in from_tables.iter_mut().enumerate() {
+        let terms: Vec<&ast::Expr> = where_terms.iter().copied().chain(&source.on).collect();
+        let table = &*source.table;
+
+        // a subquery has no indexes and no rowids to seek to, but a hash join
+        // can still save reading all of its rows for every outer row. the
+        // row of a recursive table changes all the time, so it's just read.
+        let access = match &source.source {
+            Source::Table => {
+                planner::choose_access(table, &db.indexes, &source.label, &terms, &source.outer)
+            }
+            Source::Subquery(_) => {
+                match planner::choose_access(table, &[], &source.label, &terms, &source.outer) {
+                    access @ Access::Hash { .. } => access,
+                    _ => Access::FullScan,
+                }
+            }
+            Source::Queue(_) | Source::Function(..) => Access::FullScan,
+        };
+
+        scans.push(Scan {
+            root_page: table.rootpage as u32,
+            num_columns: table.column_names.len(),
+            access,
+            format: table.row_format(Some(scope.used_columns(base + i))),
+            reverse: false,
+            left: source.left,
+            on: std::mem::take(&mut on[i]),
+            filter: std::mem::take(&mut filter[i]),
+            source: std::mem::replace(&mut source.source, Source::Table),
+            hash_table: OnceCell::new(),
+        });
+    }
+
+    // a table is stored in rowid order, so ORDER BY rowid just means reading
+    // it forwards or backwards. rowids are unique, so any terms after it
+    // don't matter. a join reads the first table in order too, it just
+    // reads the other tables for each of its rows. window functions put the
+    // rows in their own order though.
+    if let Some(scan) = scans.first_mut()
+        && grouping.is_none()
+        && windows.is_empty()
+        && matches!(scan.source, Source::Table)
+        && let Some((Expr::Column(column), key)) = order.first()
+        && let Some(column) = column.checked_sub(outer_width)
+        && (column == scan.num_columns || Some(column) == scan.format.rowid_alias)
+        && !matches!(scan.access, Access::Index { .. })
+    {
+        scan.reverse = key.descending;
+        order.clear();
+    }
+
+    // output columns compare with their COLLATE, or else the collation of
+    // the column they are
+    let collations: Vec<Collation> = projection
+        .iter()
+        .map(|expr| {
+            expr.collation()
+                .or_else(|| scope.column_collation(expr))
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let affinities = projection.iter().map(|expr| scope.affinity(expr)).collect();
+
+    // DISTINCT can do without a hash set when the rows come from an index
+    // sorted on the output columns
+    let distinct = select
+        .distinct
+        .then(|| match (scans.first(), from_tables.first()) {
+            (Some(scan), Some(source))
+                if grouping.is_none()
+                    && windows.is_empty()
+                    && in_index_order(
+                        scan,
+                        &source.table,
+                        &projection,
+                        &collations,
+                        outer_width,
+                    ) =>
+            {
+                Distinct::Adjacent
+            }
+            _ => Distinct::Hash,
+        });
+
+    // the aggregates' results come after the columns of the table, and the
+    // count only works if nothing reads those columns
+    let width = scope.width();
+    let count = match (&from_tables[..], &grouping) {
+        ([source], Some(grouping))
+            if matches!(scans[0].source, Source::Table)
+                && select.where_clause.is_none()
+                && windows.is_empty()
+                && grouping.group_by.is_empty()
+                && grouping.aggregates.iter().all(|aggregate| {
+                    aggregate.function == Function::Count && aggregate.args.is_empty()
+                })
+                && !projection
+                    .iter()
+                    .chain(&having)
+                    .chain(order.iter().map(|(expr, _)| expr))
+                    .any(|expr| expr.uses_column(&|column| column < width)) =>
+        {
+            Some(
+                match planner::choose_count_index(&source.table, &db.indexes) {
+                    Some(index) => Count {
+                        root_page: index.rootpage as u32,
+                        kind: BtreeKind::Index,
+                    },
+                    None => Count {
+                        root_page: source.table.rootpage as u32,
+                        kind: BtreeKind::Table,
+                    },
+                },
+            )
+        }
+        _ => None,
+    };
+
+    let plan = Plan {
+        scans: scans.into(),
+        condition,
+        grouping,
+        count,
+        having,
+        windows,
+        projection,
+        distinct,
+        order,
+        limit,
+        offset,
+        outer_width,
+        compound: vec![],
+        collations,
+        affinities,
+    };
+    Ok((column_names, plan))
+}
+
+// LIMIT and OFFSET can't refer to columns, so they're compiled without any
+// tables in scope
+fn plan_limit(limit: Option<&ast::Limit>, scope: &Scope) -> Result<(Option<Expr>, Option<Expr>)> {
+    let Some(limit) = limit else {
+        return Ok((None, None));
+    };
+
+    Ok((
+        Some(scope.detach().compile(&limit.limit)?),
+        limit
+            .offset
+            .as_ref()
+            .map(|offset| scope.detach().compile(offset))
+            .transpose()?,
+    ))
+}
+
+// whether reading a table through an index returns rows with the same
+// output columns one after the other. that's the case when the output
+// columns are the columns the index is sorted on first. the columns WHERE
+// fixes to one value are the same in every row, so they can be left out.
+fn in_index_order(
+    scan: &Scan,
+    table: &Table,
+    projection: &[Expr],
+    collations: &[Collation],
+    outer_width: usize,
+) -> bool {
+    let Access::Index { index, equal, .. } = &scan.access else {
+        return false;
+    };
+
+    // where each output column is in the index
+    let mut positions = vec![];
+    for (expr, &collation) in projection.iter().zip(collations) {
+        let expr = match expr {
+            Expr::Collate(expr, _) => expr,
+            expr => expr,
+        };
+        let Expr::Column(column) = expr else {
+            return false;
+        };
+        let Some(name) = column
+            .checked_sub(outer_width)
+            .and_then(|column| table.column_names.get(column))
+        else {
+            return false;
+        };
+
+        // an index with another collation can put equal values apart
+        let position = index.columns.iter().position(|column| {
+            column
+                .name
+                .as_ref()
+                .is_some_and(|column| column.eq_ignore_ascii_case(name))
+                && column.collation == collation
+        });
+        match position {
+            Some(position) => positions.push(position),
+            None => return false,
+        }
+    }
+
+    let last = positions.iter().copied().max().unwrap_or(0);
+    (equal.len()..=last).all(|position| positions.contains(&position))
+}
+
+// table names are case-insensitive in sqlite
+fn find_table<'a>(db: &'a Db, name: &str) -> Result<&'a Table> {
+    let Some(table) = db
+        .tables
+        .iter()
+        .find(|table| table.name.eq_ignore_ascii_case(name))
+    else {
+        return Err(Error::NoSuchTable(name.to_string()));
+    };
+
+    if let Some(feature) = &table.unsupported {
+        return Err(Error::Unsupported(feature.clone()));
+    }
+    Ok(table)
+}
+
+// a subquery in FROM looks like a table with its output columns, and no
+// rowid that can be referred to
+fn derived_table(name: &str, column_names: Vec<String>) -> Cow<'static, Table> {
+    Cow::Owned(Table {
+        name: name.to_string(),
+        rootpage: 0,
+        collations: vec![Collation::Binary; column_names.len()],
+        affinities: vec![Affinity::Blob; column_names.len()],
+        column_names,
+        rowid_alias: None,
+        unsupported: None,
+    })
+}
+
+// turns ORDER BY 2 or GROUP BY 2 into an index into the output columns
+fn output_position(n: i64, clause: &str, term: usize, num_columns: usize) -> Result<usize> {
+    match usize::try_from(n).ok().and_then(|n| n.checked_sub(1)) {
+        Some(n) if n < num_columns => Ok(n),
+        _ => Err(Error::Invalid(format!(
+            "{} {} term out of range - should be between 1 and {}",
+            ordinal(term + 1),
+            clause,
+            num_columns
+        ))),
+    }
+}
+
+// 1st, 2nd, 3rd, 4th, ..., 11th, 12th, 13th, ..., 21st
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+// the rows of scans[level..] that join with `outer`, a row of the tables
+// before them. this is a nested loop join: each row of the outer tables looks
+// its matches up in the next table, and each of those in the table after
+// that, which is why the planner tries so hard to make the lookup a seek.
+fn join_rows(
+    pager: Pager,
+    scans: Rc<[Scan]>,
+    level: usize,
+    outer: Vec<Value>,
+) -> Box<dyn Iterator<Item = Result<Vec<Value>>>> {
+    let Some(scan) = scans.get(level) else {
+        return Box::new(std::iter::once(Ok(outer)));
+    };
+
+    let fetched = match fetch_rows(&pager, scan, &outer) {
+        Ok(rows) => rows,
+        Err(e) => return Box::new(std::iter::once(Err(e))),
+    };
+
+    let matches = {
+        let scans = scans.clone();
+        let outer = outer.clone();
+        fetched.filter_map(move |row| {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            let scan = &scans[level];
+
+            let mut values = outer.clone();
+            values.extend(scope_row(&row, scan.num_columns));
+            all_true(&scan.on, &values)
+                .map(|matched| matched.then_some(values))
+                .transpose()
+        })
+    };
+
+    let mut matches = matches.peekable();
+    let rows: Box<dyn Iterator<Item = Result<Vec<Value>>>> =
+        if scan.left && matches.peek().is_none() {
+            let mut values = outer;
+            values.resize(values.len() + scan.num_columns + 1, Value::Null);
+            Box::new(std::iter::once(Ok(values)))
+        } else {
+            Box::new(matches)
+        };
+
+    let filter_scans = scans.clone();
+    Box::new(
+        rows.filter_map(move |row| {
+            let values = match row {
+                Ok(values) => values,
+                Err(e) => return Some(Err(e)),
+            };
+            all_true(&filter_scans[level].filter, &values)
+                .map(|matched| matched.then_some(values))
+                .transpose()
+        })
+        .flat_map(move |row| match row {
+            Ok(values) => join_rows(pager.clone(), scans.clone(), level + 1, values),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }),
+    )
+}
+
+// only rows where a condition is true are kept. NULL counts as not true, so
+// "WHERE NULL" returns nothing.
+fn is_true(expr: &Expr, values: &[Value]) -> Result<bool> {
+    Ok(expr.eval(values)?.truthiness() == Some(true))
+}
+
+fn all_true(terms: &[Expr], values: &[Value]) -> Result<bool> {
+    for expr in terms {
+        if !is_true(expr, values)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
 
-    if condition == "*"
-        && let Some(table) = db.tables.iter().find(|t| t.name == table_name)
+// reads the rows the planner asked for, lazily. these are the rows that might
+// match the WHERE clause - the caller still has to check it. the values the
+// access path looks for can refer to `outer`, the row of the tables read
+// before this one.
+fn fetch_rows(
+    pager: &Pager,
+    scan: &Scan,
+    outer: &[Value],
+) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+    let reverse = scan.reverse;
+    let mut cursor = TableCursor::new(pager.clone(), scan.root_page, scan.format.clone());
+
+    Ok(match &scan.access {
+        // a subquery's rows are only worked out once, however many times
+        // they're read. a subquery that's read first is only read once, so
+        // its rows can come straight from it - which lets the query's LIMIT
+        // stop a recursive table that would otherwise go on forever.
+        Access::FullScan if let Source::Subquery(subquery) = &scan.source => match outer.is_empty()
+        {
+            true => subquery.run(&[])?,
+            false => Box::new(subquery.rows()?.to_vec().into_iter().map(Ok)),
+        },
+        Access::FullScan if let Source::Queue(queue) = &scan.source => {
+            Box::new(queue.borrow().clone().into_iter().map(Ok))
+        }
+        Access::FullScan if let Source::Function(function, args) = &scan.source => {
+            let args = args
+                .iter()
+                .map(|arg| arg.eval(outer))
+                .collect::<Result<Vec<_>>>()?;
+            let rows = function.rows(&args)?;
+            Box::new(rows.into_iter().map(|values| Ok(Row { rowid: 0, values })))
+        }
+        Access::FullScan => match reverse {
+            false => Box::new(walk(cursor, TableCursor::first, false)),
+            true => Box::new(walk(cursor, TableCursor::last, true)),
+        },
+        Access::Rowid(value) => match rowid_value(value.eval(outer)?) {
+            Some(rowid) => {
+                Box::new(std::iter::once(cursor.seek(rowid)).flat_map(Result::transpose))
+            }
+            None => Box::new(std::iter::empty()),
+        },
+        Access::RowidRange { lower, upper } => {
+            let Some((first, last)) = rowid_range(lower.as_ref(), upper.as_ref(), outer)? else {
+                return Ok(Box::new(std::iter::empty()));
+            };
+
+            let start = move |cursor: &mut TableCursor| match reverse {
+                false => cursor.seek_ge(first),
+                true => cursor.seek_le(last),
+            };
+
+            Box::new(walk(cursor, start, reverse).take_while(move |row| {
+                row.as_ref()
+                    .map_or(true, |row| (first..=last).contains(&row.rowid))
+            }))
+        }
+        Access::Hash {
+            column,
+            value,
+            collation,
+        } => {
+            // NULL never equals anything
+            let value = value.eval(outer)?;
+            if value.is_null() {
+                return Ok(Box::new(std::iter::empty()));
+            }
+
+            let rows = hash_table(pager, scan, *column, *collation)?;
+            let key = aggregate::encode_key(&[value], &[*collation]);
+            let rows = rows.get(&key).cloned().unwrap_or_default();
+            Box::new(rows.into_iter().map(Ok))
+        }
+        Access::Index {
+            index,
+            equal,
+            lower,
+            upper,
+        } => {
+            let equal: Vec<Value> = equal
+                .iter()
+                .map(|expr| expr.eval(outer))
+                .collect::<Result<_>>()?;
+            let lower = match lower {
+                Some(bound) => Some((bound.value.eval(outer)?, bound.inclusive)),
+                None => None,
+            };
+            let upper = match upper {
+                Some(bound) => Some((bound.value.eval(outer)?, bound.inclusive)),
+                None => None,
+            };
+
+            // nothing is = or < or > NULL
+            if equal.iter().any(Value::is_null)
+                || lower.as_ref().is_some_and(|(value, _)| value.is_null())
+                || upper.as_ref().is_some_and(|(value, _)| value.is_null())
+            {
+                return Ok(Box::new(std::iter::empty()));
+            }
+
+            let descending: Vec<bool> = index.columns.iter().map(|c| c.descending).collect();
+            let collations: Vec<Collation> = index.columns.iter().map(|c| c.collation).collect();
+
+            // a unique index has at most one entry for each key
+            let single = index.unique && equal.len() == index.columns.len();
+
+            // where to start. NULLs sort first in an index, so if we only have
+            // an upper bound we skip past them - NULL < 5 isn't true.
+            let mut probe = equal.clone();
+            let inclusive = match &lower {
+                Some((value, inclusive)) => {
+                    probe.push(value.clone());
+                    *inclusive
+                }
+                None if upper.is_some() => {
+                    probe.push(Value::Null);
+                    false
+                }
+                None => true,
+            };
+
+            let mut entries = IndexCursor::new(pager.clone(), index.rootpage as u32);
+            entries.seek(&probe, &descending, &collations, inclusive)?;
+
+            let in_range = move |key: &[Value]| {
+                if btree::compare_keys(key, &equal, &descending, &collations) != Ordering::Equal {
+                    return false;
+                }
+
+                match &upper {
+                    Some((value, inclusive)) => {
+                        match collations[equal.len()].compare(&key[equal.len()], value) {
+                            Ordering::Greater => false,
+                            Ordering::Equal => *inclusive,
+                            Ordering::Less => true,
+                        }
+                    }
+                    None => true,
+                }
+            };
+
+            let entries = entries
+                .take_while(move |entry| entry.as_ref().map_or(true, |entry| in_range(&entry.key)))
+                .take(if single { 1 } else { usize::MAX });
+
+            // look each row up in the table by the rowid the index gives us
+            Box::new(entries.filter_map(move |entry| match entry {
+                Ok(entry) => cursor.seek(entry.rowid).transpose(),
+                Err(e) => Some(Err(e)),
+            }))
+        }
+    })
+}
+
+// the rows of the scan's table by the value of `column`, for a hash join.
+// the whole table is read the first time an outer row looks something up.
+fn hash_table<'s>(
+    pager: &Pager,
+    scan: &'s Scan,
+    column: usize,
+    collation: Collation,
+) -> Result<&'s HashMap<Vec<u8>, Vec<Row>>> {
+    if let Some(rows) = scan.hash_table.get() {
+        return Ok(rows);
+    }
+
+    let all: Box<dyn Iterator<Item = Result<Row>>> = match &scan.source {
+        Source::Subquery(subquery) => Box::new(subquery.rows()?.iter().cloned().map(Ok)),
+        _ => {
+            let cursor = TableCursor::new(pager.clone(), scan.root_page, scan.format.clone());
+            Box::new(walk(cursor, TableCursor::first, false))
+        }
+    };
+
+    let mut rows: HashMap<Vec<u8>, Vec<Row>> = HashMap::new();
+    for row in all {
+        let row = row?;
+        let value = match row.values.get(column) {
+            Some(value) if !value.is_null() => value,
+            _ => continue,
+        };
+        let key = aggregate::encode_key(std::slice::from_ref(value), &[collation]);
+        rows.entry(key).or_default().push(row);
+    }
+
+    Ok(scan.hash_table.get_or_init(|| rows))
+}
+
+// steps through a table one row at a time, starting wherever `start` puts
+// the cursor
+fn walk(
+    mut cursor: TableCursor,
+    start: impl FnOnce(&mut TableCursor) -> Result<Option<Row>>,
+    reverse: bool,
+) -> impl Iterator<Item = Result<Row>> {
+    let mut start = Some(start);
+
+    std::iter::from_fn(move || {
+        let row = match start.take() {
+            Some(start) => start(&mut cursor),
+            None if reverse => cursor.prev(),
+            None => cursor.next(),
+        };
+        row.transpose()
+    })
+}
+
+// rowids are integers, so `rowid = 2.0` finds row 2 but `rowid = 2.5` or
+// `rowid = 'abc'` can't find anything
+fn rowid_value(value: Value) -> Option<i64> {
+    match value {
+        Value::Integer(rowid) => Some(rowid),
+        Value::Float(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => {
+            Some(f as i64)
+        }
+        _ => None,
+    }
+}
+
+// turns `rowid > x AND rowid <= y` into the first and last rowid that could
+// match, or None if none can. numbers sort before text and blobs, so
+// `rowid < 'abc'` is true for every row and `rowid > 'abc'` for none.
+fn rowid_range(
+    lower: Option<&Bound>,
+    upper: Option<&Bound>,
+    outer: &[Value],
+) -> Result<Option<(i64, i64)>> {
+    let eval = |bound: Option<&Bound>| -> Result<Option<(Value, bool)>> {
+        match bound {
+            Some(bound) => Ok(Some((bound.value.eval(outer)?, bound.inclusive))),
+            None => Ok(None),
+        }
+    };
+    let (lower, upper) = (eval(lower)?, eval(upper)?);
+
+    Ok(rowid_bounds(lower, upper))
+}
+
+fn rowid_bounds(lower: Option<(Value, bool)>, upper: Option<(Value, bool)>) -> Option<(i64, i64)> {
+    let first = match lower {
+        None => i64::MIN,
+        Some((Value::Integer(i), true)) => i,
+        Some((Value::Integer(i), false)) => i.checked_add(1)?,
+        Some((Value::Float(f), inclusive)) => {
+            let first = if !inclusive && f.fract() == 0.0 {
+                f + 1.0
+            } else {
+                f.ceil()
+            };
+            if first > i64::MAX as f64 {
+                return None;
+            }
+            first as i64
+        }
+        Some(_) => return None,
+    };
+
+    let last = match upper {
+        None => i64::MAX,
+        Some((Value::Integer(i), true)) => i,
+        Some((Value::Integer(i), false)) => i.checked_sub(1)?,
+        Some((Value::Float(f), inclusive)) => {
+            let last = if !inclusive && f.fract() == 0.0 {
+                f - 1.0
+            } else {
+                f.floor()
+            };
+            if last < i64::MIN as f64 {
+                return None;
+            }
+            last as i64
+        }
+        Some((Value::Null, _)) => return None,
+        Some(_) => i64::MAX,
+    };
+
+    Some((first, last))
+}
+
+// sqlite names output columns like this:
+// - SELECT Title AS name  -> name
+// - SELECT title          -> Title (the name the table declares)
+// - SELECT 1 +  2         -> 1 +  2 (the expression exactly as written)
+fn output_name(
+    scope: &Scope,
+    expr: &ast::Expr,
+    compiled: &Expr,
+    alias: &Option<String>,
+    text: &str,
+) -> String {
+    if let Some(alias) = alias {
+        return alias.clone();
+    }
+
+    if let (ast::Expr::Column { .. }, Expr::Column(index)) = (expr, compiled)
+        && let Some(name) = scope.column_name(*index)
     {
-        column_names = table.column_names.clone();
-        btree::traverse(&mut db.file, table.rootpage as u32, db.page_size, &mut rows);
+        return name.to_string();
     }
 
-    (column_names, rows)
+    text.to_string()
+}
+
+// lays a row out the way the scope expects: every column of the table followed
+// by the rowid. rows written before a column was added with ALTER TABLE have
+// fewer values than the table has columns - the missing ones are NULL.
+fn scope_row(row: &Row, num_columns: usize) -> Vec<Value> {
+    let mut values = row.values.clone();
+    values.resize(num_columns, Value::Null);
+    values.push(Value::Integer(row.rowid));
+    values
 }
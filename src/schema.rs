@@ -1,61 +1,488 @@
-use std::fs::File;
+use crate::{
+    affinity::Affinity,
+    ast,
+    btree::TableCursor,
+    cell::{Row, RowFormat},
+    collation::Collation,
+    error::{Error, Result},
+    lexer::{Token, TokenKind, tokenize},
+    pager::Pager,
+    parser,
+};
 
-use crate::{btree, cell::Row};
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Table {
     pub name: String,
     pub rootpage: i64,
     pub column_names: Vec<String>,
+    // the collation each column was declared with (COLLATE NOCASE), BINARY
+    // for the ones without
+    pub collations: Vec<Collation>,
+    // the affinity each column's declared type gives it, see Affinity
+    pub affinities: Vec<Affinity>,
+    // the position of the INTEGER PRIMARY KEY column, if there is one. sqlite
+    // doesn't store that column in the record (it's always NULL there) because
+    // its value is the rowid.
+    pub rowid_alias: Option<usize>,
+    // set when the table is stored in a way we can't read, e.g. WITHOUT ROWID
+    // tables are index b-trees. querying it is an Unsupported error.
+    pub unsupported: Option<String>,
+}
+
+impl Table {
+    // how rows of this table should be decoded, reading only `wanted` columns
+    pub fn row_format(&self, wanted: Option<&[bool]>) -> RowFormat {
+        RowFormat {
+            wanted: wanted.map(|wanted| wanted.to_vec()),
+            rowid_alias: self.rowid_alias,
+            affinities: self.affinities.clone(),
+        }
+    }
 }
 
-pub fn parse_tables(file: &mut File, page_size: u16) -> Vec<Table> {
+// an index b-tree holds a sorted copy of some of a table's columns (the "key"),
+// each entry followed by the rowid of the row it came from
+#[derive(Debug, Clone)]
+pub struct Index {
+    // nothing looks indexes up by name yet, but it makes plans readable
+    #[allow(dead_code)]
+    pub name: String,
+    pub table_name: String,
+    pub rootpage: i64,
+    pub columns: Vec<IndexColumn>,
+    pub unique: bool,
+    // CREATE INDEX ... WHERE <condition> only indexes rows matching the condition
+    pub partial: Option<ast::Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexColumn {
+    // None when the index is on an expression rather than a plain column
+    pub name: Option<String>,
+    pub descending: bool,
+    // the COLLATE of the index column, or else the one the table column was
+    // declared with
+    pub collation: Collation,
+}
+
+pub fn parse_schema(pager: &Pager) -> Result<(Vec<Table>, Vec<Index>)> {
     let mut sqlite_master_rows: Vec<Row> = vec![];
 
-    // read sqlite_master table
-    btree::traverse(file, 1, page_size, &mut sqlite_master_rows);
+    // read sqlite_master table, which always starts on page 1
+    let mut cursor = TableCursor::new(pager.clone(), 1, RowFormat::default());
+    let mut row = cursor.first()?;
+    while let Some(r) = row {
+        sqlite_master_rows.push(r);
+        row = cursor.next()?;
+    }
 
     let mut tables: Vec<Table> = vec![];
+    let mut unique_keys: Vec<Vec<Vec<String>>> = vec![];
+
+    // save the table name and references
+    // sqlite_master has the columns type, name, tbl_name, rootpage and sql.
+    // rows that don't look like that are skipped rather than trusted.
+    fn text(row: &Row, i: usize) -> Option<&str> {
+        row.values.get(i).and_then(|v| v.as_text())
+    }
+    let rootpage = |row: &Row| row.values.get(3).and_then(|v| v.as_integer());
+
     // save the table name and references
     for row in &sqlite_master_rows {
         // The table schema lives in the 5th column in sqlite_master
-        if let Some(table_schema) = row.values[4].as_text() {
-            let column_names = parse_column_names(table_schema);
-
-            if row.values[0].as_text().unwrap() == "table" {
-                tables.push(Table {
-                    name: String::from(row.values[1].as_text().unwrap()),
-                    rootpage: row.values[3].as_integer().unwrap(),
-                    column_names,
-                })
+        if let Some(table_schema) = text(row, 4)
+            && text(row, 0) == Some("table")
+            && let (Some(name), Some(rootpage)) = (text(row, 1), rootpage(row))
+        {
+            let mut table = Table {
+                name: String::from(name),
+                rootpage,
+                column_names: vec![],
+                collations: vec![],
+                affinities: vec![],
+                rowid_alias: None,
+                unsupported: None,
+            };
+
+            match parse_table_definition(table_schema) {
+                Ok(definition) => {
+                    table.column_names = definition.column_names;
+                    table.collations = definition.collations;
+                    table.affinities = definition.affinities;
+                    table.rowid_alias = definition.rowid_alias;
+                    table.unsupported = definition.unsupported;
+                    unique_keys.push(definition.unique_keys);
+                }
+                // the other tables can still be read
+                Err(e) => {
+                    table.unsupported = Some(format!("table definition of {} ({})", name, e));
+                    unique_keys.push(vec![]);
+                }
             }
+
+            tables.push(table);
         }
     }
 
-    tables
+    let mut indexes: Vec<Index> = vec![];
+
+    for row in &sqlite_master_rows {
+        if text(row, 0) != Some("index") {
+            continue;
+        }
+
+        let (Some(name), Some(table_name), Some(rootpage)) =
+            (text(row, 1), text(row, 2), rootpage(row))
+        else {
+            continue;
+        };
+        let name = String::from(name);
+        let table_name = String::from(table_name);
+        let table = tables.iter().position(|t| t.name == table_name);
+
+        // an index column sorts with the collation of the table column unless
+        // it has a COLLATE of its own
+        let declared = |column: &str| {
+            table
+                .and_then(|table| {
+                    let table = &tables[table];
+                    let i = table
+                        .column_names
+                        .iter()
+                        .position(|name| name.eq_ignore_ascii_case(column))?;
+                    table.collations.get(i).copied()
+                })
+                .unwrap_or_default()
+        };
+
+        let index = match text(row, 4) {
+            Some(sql) => {
+                // an index we can't understand can still be ignored safely,
+                // the table itself can always be scanned instead
+                let Ok(create_index) = parser::parse_create_index(sql) else {
+                    continue;
+                };
+
+                let columns = create_index
+                    .columns
+                    .into_iter()
+                    .map(|column| {
+                        let name = match column.expr {
+                            ast::Expr::Column { name, .. } => Some(name),
+                            _ => None,
+                        };
+                        let collation = match &column.collation {
+                            Some(collation) => Collation::from_name(collation)?,
+                            None => name.as_deref().map(declared).unwrap_or_default(),
+                        };
+                        Ok(IndexColumn {
+                            name,
+                            descending: column.descending,
+                            collation,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>();
+                let Ok(columns) = columns else {
+                    continue;
+                };
+
+                Index {
+                    name,
+                    table_name,
+                    rootpage,
+                    columns,
+                    unique: create_index.unique,
+                    partial: create_index.where_clause,
+                }
+            }
+            // indexes that sqlite creates for PRIMARY KEY and UNIQUE constraints
+            // have no sql. they're named sqlite_autoindex_<table>_<n>, where n
+            // counts the constraints in the order they appear in the table.
+            None => {
+                let Some(key) = autoindex_number(&name, &table_name)
+                    .and_then(|n| unique_keys[table?].get(n - 1))
+                else {
+                    continue;
+                };
+
+                Index {
+                    name,
+                    table_name,
+                    rootpage,
+                    columns: key
+                        .iter()
+                        .map(|column| IndexColumn {
+                            name: Some(column.clone()),
+                            descending: false,
+                            collation: declared(column),
+                        })
+                        .collect(),
+                    unique: true,
+                    partial: None,
+                }
+            }
+        };
+
+        indexes.push(index);
+    }
+
+    Ok((tables, indexes))
+}
+
+fn autoindex_number(index_name: &str, table_name: &str) -> Option<usize> {
+    index_name
+        .strip_prefix("sqlite_autoindex_")?
+        .strip_prefix(table_name)?
+        .strip_prefix('_')?
+        .parse()
+        .ok()
+}
+
+struct TableDefinition {
+    column_names: Vec<String>,
+    collations: Vec<Collation>,
+    affinities: Vec<Affinity>,
+    rowid_alias: Option<usize>,
+    // the columns of every PRIMARY KEY (except a rowid alias) and UNIQUE
+    // constraint, in the order they were declared
+    unique_keys: Vec<Vec<String>>,
+    unsupported: Option<String>,
 }
 
-fn parse_column_names(table_definition: &str) -> Vec<String> {
-    // Find content between first ( and last )
-    let start = table_definition.find('(').unwrap() + 1;
-    let end = table_definition.rfind(')').unwrap();
-    let inner = &table_definition[start..end];
+// CREATE TABLE name (
+//     column_name type constraints...,
+//     ...,
+//     table constraints...
+// )
+//
+// we split what's between the outer brackets on commas (ignoring commas inside
+// nested brackets, like NUMERIC(10,2)). each part is either a column or a
+// table constraint such as PRIMARY KEY (a, b).
+fn parse_table_definition(table_definition: &str) -> Result<TableDefinition> {
+    let tokens = tokenize(table_definition)?;
 
-    inner
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
+    // CREATE VIRTUAL TABLE t USING module(...) - the module decides how the
+    // table is stored, so there's nothing for us to read
+    if tokens.get(1).is_some_and(|t| is_keyword(t, "VIRTUAL")) {
+        return Ok(TableDefinition {
+            column_names: vec![],
+            collations: vec![],
+            affinities: vec![],
+            rowid_alias: None,
+            unique_keys: vec![],
+            unsupported: Some(String::from("virtual tables")),
+        });
+    }
+
+    let Some(start) = tokens.iter().position(|t| t.kind == TokenKind::LeftParen) else {
+        return Err(Error::Parse {
+            message: String::from("expected a column list"),
+            position: table_definition.len(),
+        });
+    };
+
+    let mut parts: Vec<&[Token]> = vec![];
+    let mut depth = 0;
+    let mut part_start = start + 1;
+
+    for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+        match token.kind {
+            TokenKind::LeftParen => depth += 1,
+            TokenKind::RightParen if depth == 0 => {
+                parts.push(&tokens[part_start..i]);
+                break;
+            }
+            TokenKind::RightParen => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                parts.push(&tokens[part_start..i]);
+                part_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let mut column_names = vec![];
+    let mut column_types = vec![];
+    let mut collations = vec![];
+    let mut rowid_alias = None;
+    // (is it the primary key?, columns)
+    let mut keys: Vec<(bool, Vec<String>)> = vec![];
+    let mut table_primary_key = None;
+    let mut unsupported = None;
+
+    for part in parts.into_iter().filter(|part| !part.is_empty()) {
         // Skip constraints (FOREIGN KEY, PRIMARY KEY, etc.)
-        .filter(|s| {
-            !s.starts_with("FOREIGN")
-                && !s.starts_with("PRIMARY")
-                && !s.starts_with("UNIQUE")
-                && !s.starts_with("CHECK")
-        })
-        // Get just the column name (first word)
-        .filter_map(|s| s.split_whitespace().next())
-        .map(|s| s.to_string())
-        .collect()
+        if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|keyword| is_keyword(&part[0], keyword))
+        {
+            if let Some(columns) = constraint_columns(part, "PRIMARY") {
+                table_primary_key = Some(columns.clone());
+                keys.push((true, columns));
+            } else if let Some(columns) = constraint_columns(part, "UNIQUE") {
+                keys.push((false, columns));
+            }
+            continue;
+        }
+
+        let Some(name) = identifier(&part[0]) else {
+            continue;
+        };
+
+        // the type is every word after the name up to the first constraint
+        let column_type: Vec<&str> = part[1..]
+            .iter()
+            .map_while(|token| match &token.kind {
+                TokenKind::Word(word) if !is_constraint_keyword(word) => Some(word.as_str()),
+                _ => None,
+            })
+            .collect();
+        let column_type = column_type.join(" ");
+
+        if let Some(i) = part.iter().position(|t| is_keyword(t, "PRIMARY")) {
+            // "INTEGER PRIMARY KEY" makes the column an alias for the rowid. it has to
+            // be spelled exactly INTEGER (INT doesn't count) and "PRIMARY KEY DESC"
+            // doesn't count either - both are quirks sqlite keeps for compatibility.
+            if column_type.eq_ignore_ascii_case("INTEGER")
+                && !part.get(i + 2).is_some_and(|t| is_keyword(t, "DESC"))
+            {
+                rowid_alias = Some(column_names.len());
+            }
+            keys.push((true, vec![name.clone()]));
+        }
+        if part.iter().any(|t| is_keyword(t, "UNIQUE")) {
+            keys.push((false, vec![name.clone()]));
+        }
+
+        if is_virtual_column(part) {
+            unsupported = Some(String::from("tables with VIRTUAL generated columns"));
+        }
+
+        // a collation sqlite doesn't know would have stopped it creating the
+        // table, unless the program that did registered its own
+        let collation = match part.iter().position(|t| is_keyword(t, "COLLATE")) {
+            Some(i) => match part.get(i + 1).and_then(identifier) {
+                Some(name) => Collation::from_name(&name)?,
+                None => Collation::Binary,
+            },
+            None => Collation::Binary,
+        };
+
+        column_names.push(name);
+        column_types.push(column_type);
+        collations.push(collation);
+    }
+
+    // PRIMARY KEY (id) as a table constraint works too, as long as it's a
+    // single INTEGER column
+    if let Some([primary_key]) = table_primary_key.as_deref()
+        && let Some(i) = column_names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(primary_key))
+        && column_types[i].eq_ignore_ascii_case("INTEGER")
+    {
+        rowid_alias = Some(i);
+    }
+
+    // WITHOUT ROWID tables don't have a rowid to alias. they are stored as
+    // index b-trees keyed by the primary key, which we can't read as a table.
+    if tokens.iter().any(|t| is_keyword(t, "WITHOUT")) {
+        rowid_alias = None;
+        unsupported = Some(String::from("WITHOUT ROWID tables"));
+    }
+
+    // a rowid alias doesn't need an index, the table is already sorted by it
+    let unique_keys = keys
+        .into_iter()
+        .filter(|(primary, _)| !(*primary && rowid_alias.is_some()))
+        .map(|(_, columns)| columns)
+        .collect();
+
+    let affinities = column_types
+        .iter()
+        .map(|column_type| Affinity::from_type(column_type))
+        .collect();
+
+    Ok(TableDefinition {
+        column_names,
+        collations,
+        affinities,
+        rowid_alias,
+        unique_keys,
+        unsupported,
+    })
+}
+
+// [CONSTRAINT name] PRIMARY KEY (a, b) -> [a, b], or the same for UNIQUE (a, b)
+fn constraint_columns(part: &[Token], keyword: &str) -> Option<Vec<String>> {
+    let i = part.iter().position(|t| is_keyword(t, keyword))?;
+    let open = i + part[i..]
+        .iter()
+        .position(|t| t.kind == TokenKind::LeftParen)?;
+    let close = open
+        + part[open..]
+            .iter()
+            .position(|t| t.kind == TokenKind::RightParen)?;
+
+    Some(
+        part[open + 1..close]
+            .split(|t| t.kind == TokenKind::Comma)
+            .filter_map(|column| column.first().and_then(identifier))
+            .collect(),
+    )
+}
+
+// `x AS (a * 2)` or `x GENERATED ALWAYS AS (a * 2) VIRTUAL` - a generated
+// column that isn't STORED is computed whenever it is read and takes up no
+// space in the record, so every column after it would be decoded from the
+// wrong place. an AS inside brackets (say CHECK (CAST(x AS INT))) doesn't
+// count.
+fn is_virtual_column(part: &[Token]) -> bool {
+    let mut depth = 0;
+    let mut generated = false;
+
+    for token in part {
+        match token.kind {
+            TokenKind::LeftParen => depth += 1,
+            TokenKind::RightParen => depth -= 1,
+            _ if depth == 0 && is_keyword(token, "AS") => generated = true,
+            _ if depth == 0 && is_keyword(token, "STORED") => return false,
+            _ => {}
+        }
+    }
+
+    generated
+}
+
+fn identifier(token: &Token) -> Option<String> {
+    match &token.kind {
+        TokenKind::Word(name) | TokenKind::QuotedIdentifier(name) | TokenKind::String(name) => {
+            Some(name.clone())
+        }
+        _ => None,
+    }
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    matches!(&token.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+}
+
+fn is_constraint_keyword(word: &str) -> bool {
+    [
+        "CONSTRAINT",
+        "PRIMARY",
+        "NOT",
+        "NULL",
+        "UNIQUE",
+        "CHECK",
+        "DEFAULT",
+        "COLLATE",
+        "REFERENCES",
+        "GENERATED",
+        "AS",
+    ]
+    .iter()
+    .any(|keyword| keyword.eq_ignore_ascii_case(word))
 }
 
 #[test]
@@ -75,7 +502,146 @@ fn test_parse_column_names() {
         )
     ";
 
-    let result = parse_column_names(data);
+    let result = parse_table_definition(data).unwrap().column_names;
 
     assert_eq!("customer_id", *result.first().unwrap());
 }
+
+#[test]
+fn test_parse_column_names_unquotes() {
+    let data = "CREATE TABLE \"albums\" ([AlbumId] INTEGER, \"Title\" TEXT, `ArtistId` INTEGER)";
+
+    let result = parse_table_definition(data).unwrap().column_names;
+
+    assert_eq!(result, vec!["AlbumId", "Title", "ArtistId"]);
+}
+
+#[test]
+fn test_parse_rowid_alias() {
+    let alias = |sql: &str| parse_table_definition(sql).unwrap().rowid_alias;
+
+    assert_eq!(
+        alias("CREATE TABLE t ([Id] INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, Name TEXT)"),
+        Some(0)
+    );
+    assert_eq!(
+        alias("CREATE TABLE t (Name TEXT, Id integer, Total NUMERIC(10,2), PRIMARY KEY (Id))"),
+        Some(1)
+    );
+    assert_eq!(alias("CREATE TABLE t (Id INT PRIMARY KEY)"), None);
+    assert_eq!(alias("CREATE TABLE t (Id INTEGER PRIMARY KEY DESC)"), None);
+    assert_eq!(
+        alias("CREATE TABLE t (a INTEGER, b INTEGER, PRIMARY KEY (a, b))"),
+        None
+    );
+    assert_eq!(
+        alias("CREATE TABLE t (Id INTEGER PRIMARY KEY) WITHOUT ROWID"),
+        None
+    );
+}
+
+#[test]
+fn test_unreadable_tables_are_unsupported() {
+    let unsupported = |sql: &str| parse_table_definition(sql).unwrap().unsupported;
+
+    assert_eq!(unsupported("CREATE TABLE t (Id INTEGER PRIMARY KEY)"), None);
+    assert_eq!(
+        unsupported("CREATE TABLE t (Id INTEGER PRIMARY KEY) WITHOUT ROWID").as_deref(),
+        Some("WITHOUT ROWID tables")
+    );
+    assert_eq!(
+        unsupported("CREATE VIRTUAL TABLE t USING fts5(body)").as_deref(),
+        Some("virtual tables")
+    );
+    assert_eq!(
+        unsupported("CREATE TABLE t (a INT, b INT AS (a * 2), c TEXT)").as_deref(),
+        Some("tables with VIRTUAL generated columns")
+    );
+    assert_eq!(
+        unsupported("CREATE TABLE t (a INT, b INT GENERATED ALWAYS AS (a * 2) VIRTUAL)").as_deref(),
+        Some("tables with VIRTUAL generated columns")
+    );
+    assert_eq!(
+        unsupported("CREATE TABLE t (a INT, b INT AS (a * 2) STORED, c TEXT)"),
+        None
+    );
+    assert_eq!(
+        unsupported("CREATE TABLE t (a INT CHECK (CAST(a AS TEXT) != ''))"),
+        None
+    );
+}
+
+#[test]
+fn test_parse_unique_keys() {
+    let data = "
+        CREATE TABLE t (
+            id INTEGER PRIMARY KEY,
+            email TEXT UNIQUE,
+            a INT,
+            b INT,
+            CONSTRAINT [ab] UNIQUE ([a], b),
+            FOREIGN KEY (a) REFERENCES other (id)
+        )
+    ";
+
+    let result = parse_table_definition(data).unwrap();
+
+    assert_eq!(result.unique_keys, vec![vec!["email"], vec!["a", "b"]]);
+}
+
+#[test]
+fn test_index_columns_inherit_collations() {
+    let pager = Pager::open("tests/collate.db").unwrap();
+
+    let (tables, indexes) = parse_schema(&pager).unwrap();
+
+    assert_eq!(
+        tables[0].collations,
+        [Collation::Binary, Collation::NoCase, Collation::Binary]
+    );
+    let collation = |name: &str| {
+        let index = indexes.iter().find(|index| index.name == name).unwrap();
+        index.columns[0].collation
+    };
+    // words_n is on n, declared COLLATE NOCASE, and words_plain is on
+    // plain COLLATE NOCASE
+    assert_eq!(collation("words_n"), Collation::NoCase);
+    assert_eq!(collation("words_plain"), Collation::NoCase);
+}
+
+#[test]
+fn test_parse_schema_indexes() {
+    let pager = Pager::open("tests/chinook.db").unwrap();
+
+    let (_, indexes) = parse_schema(&pager).unwrap();
+
+    let album_artist = indexes
+        .iter()
+        .find(|index| index.name == "IFK_AlbumArtistId")
+        .unwrap();
+    assert_eq!(album_artist.table_name, "albums");
+    assert_eq!(album_artist.rootpage, 22);
+    assert!(!album_artist.unique);
+    assert_eq!(
+        album_artist.columns,
+        vec![IndexColumn {
+            name: Some(String::from("ArtistId")),
+            descending: false,
+            collation: Collation::Binary,
+        }]
+    );
+
+    let autoindex = indexes
+        .iter()
+        .find(|index| index.name == "sqlite_autoindex_playlist_track_1")
+        .unwrap();
+    assert!(autoindex.unique);
+    assert_eq!(
+        autoindex
+            .columns
+            .iter()
+            .map(|column| column.name.clone().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["PlaylistId", "TrackId"]
+    );
+}
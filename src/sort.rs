@@ -0,0 +1,410 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io::{self, BufWriter, Write},
+    rc::Rc,
+};
+
+use crate::{
+    cell::Row,
+    collation::Collation,
+    error::Result,
+    spill::{self, RecordReader, TempFile},
+    value::Value,
+};
+
+// how much memory a sort or grouping may use before it starts writing rows to
+// temp files
+pub const DEFAULT_SORT_MEMORY: usize = 16 * 1024 * 1024;
+
+// how one ORDER BY term sorts its values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+// compares two rows by the values of their ORDER BY terms. values of different
+// types sort NULL < numbers < text < blobs, except that NULLs can be moved to
+// the end with NULLS LAST.
+pub fn compare_keys(keys: &[SortKey], left: &[Value], right: &[Value]) -> Ordering {
+    for ((key, left), right) in keys.iter().zip(left).zip(right) {
+        let ordering = match (left.is_null(), right.is_null()) {
+            (true, true) => Ordering::Equal,
+            (true, false) if key.nulls_first => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) if key.nulls_first => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) if key.descending => key.collation.compare(left, right).reverse(),
+            (false, false) => key.collation.compare(left, right),
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+// sorts `input` by the keys that come with each row. nothing is read until the
+// first sorted row is asked for, and then all of the input is read at once.
+pub fn sorted(
+    input: impl Iterator<Item = Result<(Vec<Value>, Row)>>,
+    keys: Vec<SortKey>,
+    memory: usize,
+) -> impl Iterator<Item = Result<Row>> {
+    let mut input = Some(input);
+    let mut output: Option<Box<dyn Iterator<Item = Result<Row>>>> = None;
+
+    std::iter::from_fn(move || {
+        if output.is_none() {
+            let mut sorter = Sorter::new(keys.clone(), memory);
+            for item in input.take()? {
+                let result = item.and_then(|(key, row)| sorter.push(key, row));
+                if let Err(e) = result {
+                    return Some(Err(e));
+                }
+            }
+
+            match sorter.finish() {
+                Ok(rows) => output = Some(rows),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        output.as_mut()?.next()
+    })
+}
+
+// an external merge sort. rows are collected in memory until they take up more
+// than the memory budget, then they are sorted and written out to a temp file
+// as a "run". at the end the runs are merged: we keep the next row of every
+// run in a heap and repeatedly take the smallest.
+//
+// ties keep the order the rows were pushed in. the in memory sort is stable,
+// and when two runs have equal rows the earlier run wins.
+struct Sorter {
+    keys: Rc<[SortKey]>,
+    memory: usize,
+    items: Vec<Item>,
+    // roughly how much memory `items` takes up
+    size: usize,
+    runs: Vec<TempFile>,
+}
+
+struct Item {
+    key: Vec<Value>,
+    row: Row,
+}
+
+impl Sorter {
+    fn new(keys: Vec<SortKey>, memory: usize) -> Sorter {
+        Sorter {
+            keys: keys.into(),
+            memory,
+            items: vec![],
+            size: 0,
+            runs: vec![],
+        }
+    }
+
+    fn push(&mut self, key: Vec<Value>, row: Row) -> Result<()> {
+        self.size += item_size(&key, &row);
+        self.items.push(Item { key, row });
+
+        if self.size > self.memory {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Box<dyn Iterator<Item = Result<Row>>>> {
+        if self.runs.is_empty() {
+            self.sort();
+            return Ok(Box::new(self.items.into_iter().map(|item| Ok(item.row))));
+        }
+
+        if !self.items.is_empty() {
+            self.spill()?;
+        }
+
+        let mut merge = Merge {
+            keys: self.keys,
+            readers: vec![],
+            heap: BinaryHeap::new(),
+        };
+
+        for (i, run) in self.runs.into_iter().enumerate() {
+            let mut reader = RunReader::new(run)?;
+            if let Some(item) = reader.read()? {
+                merge.heap.push(merge.head(item, i));
+            }
+            merge.readers.push(reader);
+        }
+
+        Ok(Box::new(merge))
+    }
+
+    fn sort(&mut self) {
+        let keys = &self.keys;
+        self.items
+            .sort_by(|left, right| compare_keys(keys, &left.key, &right.key));
+    }
+
+    // sorts what we have in memory and writes it out as a new run
+    fn spill(&mut self) -> Result<()> {
+        self.sort();
+
+        let run = TempFile::create()?;
+        let mut out = BufWriter::new(&run.file);
+        for item in self.items.drain(..) {
+            write_item(&mut out, &item)?;
+        }
+        out.flush()?;
+        drop(out);
+
+        self.runs.push(run);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// a rough guess at how much memory a row takes up
+fn item_size(key: &[Value], row: &Row) -> usize {
+    size_of::<Item>() + spill::values_size(key) + spill::values_size(&row.values)
+}
+
+// each item in a run is written as a record holding the rowid, the number of
+// key values, the key values and then the row's values
+fn write_item(out: &mut impl Write, item: &Item) -> io::Result<()> {
+    let mut values = Vec::with_capacity(2 + item.key.len() + item.row.values.len());
+    values.push(Value::Integer(item.row.rowid));
+    values.push(Value::Integer(item.key.len() as i64));
+    values.extend(item.key.iter().cloned());
+    values.extend(item.row.values.iter().cloned());
+
+    spill::write_record(out, &values)
+}
+
+struct RunReader {
+    reader: RecordReader,
+}
+
+impl RunReader {
+    fn new(run: TempFile) -> Result<RunReader> {
+        Ok(RunReader {
+            reader: RecordReader::new(run)?,
+        })
+    }
+
+    fn read(&mut self) -> Result<Option<Item>> {
+        let Some(mut values) = self.reader.read()? else {
+            return Ok(None);
+        };
+
+        let (Some(Value::Integer(rowid)), Some(Value::Integer(key_len))) =
+            (values.first(), values.get(1))
+        else {
+            return Err(spill::corrupt().into());
+        };
+        let (rowid, key_len) = (*rowid, *key_len as usize);
+        if key_len > values.len() - 2 {
+            return Err(spill::corrupt().into());
+        }
+
+        let row_values = values.split_off(2 + key_len);
+        let key = values.split_off(2);
+
+        Ok(Some(Item {
+            key,
+            row: Row {
+                rowid,
+                values: row_values,
+            },
+        }))
+    }
+}
+
+struct Merge {
+    keys: Rc<[SortKey]>,
+    readers: Vec<RunReader>,
+    heap: BinaryHeap<Head>,
+}
+
+// the next row of one of the runs
+struct Head {
+    item: Item,
+    run: usize,
+    keys: Rc<[SortKey]>,
+}
+
+impl Merge {
+    fn head(&self, item: Item, run: usize) -> Head {
+        Head {
+            item,
+            run,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        let Head { item, run, .. } = self.heap.pop()?;
+
+        match self.readers[run].read() {
+            Ok(Some(next)) => {
+                let head = self.head(next, run);
+                self.heap.push(head);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.heap.clear();
+                return Some(Err(e));
+            }
+        }
+
+        Some(Ok(item.row))
+    }
+}
+
+// BinaryHeap pops the largest item, so the smallest row has to compare as
+// the largest
+impl Ord for Head {
+    fn cmp(&self, other: &Head) -> Ordering {
+        compare_keys(&self.keys, &self.item.key, &other.item.key)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascending() -> SortKey {
+        SortKey {
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        }
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        let values = [
+            Value::Null,
+            Value::Integer(1),
+            Value::Float(1.5),
+            Value::Text(String::from("a")),
+            Value::Blob(vec![0]),
+        ];
+
+        // NULL < numbers < text < blobs
+        for pair in values.windows(2) {
+            assert_eq!(
+                compare_keys(&[ascending()], &pair[..1], &pair[1..]),
+                Ordering::Less
+            );
+        }
+
+        // descending with NULLS FIRST only flips the non-NULL values
+        let key = SortKey {
+            descending: true,
+            ..ascending()
+        };
+        assert_eq!(
+            compare_keys(&[key], &values[..1], &values[1..2]),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_keys(&[key], &values[1..2], &values[2..3]),
+            Ordering::Greater
+        );
+
+        // later keys break ties
+        let keys = [
+            ascending(),
+            SortKey {
+                descending: true,
+                ..ascending()
+            },
+        ];
+        assert_eq!(
+            compare_keys(
+                &keys,
+                &[Value::Integer(1), Value::Integer(1)],
+                &[Value::Integer(1), Value::Integer(2)]
+            ),
+            Ordering::Greater
+        );
+    }
+
+    // a tiny memory budget makes every few rows spill to a file
+    #[test]
+    fn test_sort_spills_to_disk() {
+        let input: Vec<(i64, Option<&str>)> = (0..1000)
+            .map(|i| ((i * 7919) % 1000, [Some("x"), None][i as usize % 2]))
+            .collect();
+
+        let keys = vec![
+            SortKey {
+                descending: true,
+                nulls_first: false,
+                collation: Collation::Binary,
+            },
+            ascending(),
+        ];
+
+        let rows = sorted(
+            input.iter().enumerate().map(|(i, &(n, text))| {
+                let key = vec![text.into(), Value::Integer(n / 10)];
+                let row = Row {
+                    rowid: i as i64,
+                    values: vec![Value::Integer(n), text.into()],
+                };
+                Ok((key, row))
+            }),
+            keys.clone(),
+            1000,
+        )
+        .collect::<Result<Vec<Row>>>()
+        .unwrap();
+
+        assert_eq!(rows.len(), 1000);
+
+        // the same order an in memory sort gives, ties included
+        let mut expected: Vec<usize> = (0..1000).collect();
+        expected.sort_by(|&a, &b| {
+            let key = |i: usize| vec![input[i].1.into(), Value::Integer(input[i].0 / 10)];
+            compare_keys(&keys, &key(a), &key(b))
+        });
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.rowid as usize)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(rows[0].values[1], Value::Text(String::from("x")));
+        assert_eq!(rows[999].values[1], Value::Null);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{self, AtomicUsize},
+};
+
+use crate::{cell, error::Result, value::Value};
+
+// helpers for the operations that may have to move rows out of memory, like
+// sorting and grouping. rows are written to temp files as records, the same
+// format the database uses, so every value survives the trip unchanged.
+
+// a file in the system's temp directory that is deleted when it's dropped
+pub struct TempFile {
+    path: PathBuf,
+    pub file: File,
+}
+
+impl TempFile {
+    pub fn create() -> io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let n = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("sqlite-temp-{}-{}", process::id(), n));
+
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok(TempFile { path, file }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// a rough guess at how much memory some values take up, used to decide when
+// it's time to move rows out of memory
+pub fn values_size(values: &[Value]) -> usize {
+    values
+        .iter()
+        .map(|value| {
+            size_of::<Value>()
+                + match value {
+                    Value::Text(text) => text.len(),
+                    Value::Blob(bytes) => bytes.len(),
+                    _ => 0,
+                }
+        })
+        .sum()
+}
+
+// each record is written as its size (4 bytes, big-endian) followed by the
+// record itself
+pub fn write_record(out: &mut impl Write, values: &[Value]) -> io::Result<()> {
+    let record = cell::encode_record(values);
+    out.write_all(&(record.len() as u32).to_be_bytes())?;
+    out.write_all(&record)
+}
+
+// reads back the records written to a temp file, from the start
+pub struct RecordReader {
+    reader: BufReader<File>,
+    // deletes the file once we're done with it
+    _file: TempFile,
+}
+
+impl RecordReader {
+    pub fn new(temp: TempFile) -> Result<RecordReader> {
+        let mut file = temp.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(RecordReader {
+            reader: BufReader::new(file),
+            _file: temp,
+        })
+    }
+
+    pub fn read(&mut self) -> Result<Option<Vec<Value>>> {
+        let mut size = [0; 4];
+        match self.reader.read_exact(&mut size) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut record = vec![0; u32::from_be_bytes(size) as usize];
+        self.reader.read_exact(&mut record)?;
+
+        match cell::parse_record(&record, None) {
+            Some(values) => Ok(Some(values)),
+            None => Err(corrupt().into()),
+        }
+    }
+}
+
+// we wrote the file ourselves, so this would mean someone else has been
+// writing to it
+pub fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "temp file is corrupt")
+}
@@ -14,7 +14,14 @@
 // | ≥12, even | BLOB, size = (code-12)/2 |
 // | ≥13, odd | TEXT, size = (code-13)/2 |
 
-#[derive(PartialEq, Debug)]
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
+
+use crate::printf;
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Null,
     Integer(i64),
@@ -36,57 +43,436 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    // sqlite has no boolean type. a value is "true" if it is a non-zero number,
+    // and text/blobs are converted to a number first ('1abc' is true, 'abc' is
+    // false). NULL is neither true nor false, so it gets None.
+    pub fn truthiness(&self) -> Option<bool> {
+        match self.to_numeric() {
+            Value::Integer(i) => Some(i != 0),
+            Value::Float(f) => Some(f != 0.0),
+            _ => None,
+        }
+    }
+
+    // converts text and blobs into numbers the way sqlite does when they're
+    // used in arithmetic: take the longest prefix that looks like a number,
+    // and fall back to 0 when there isn't one. '12abc' + 1 == 13
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Text(s) => parse_numeric_prefix(s),
+            Value::Blob(b) => parse_numeric_prefix(&String::from_utf8_lossy(b)),
+            other => other.clone(),
+        }
+    }
+
+    pub fn to_integer(&self) -> Option<i64> {
+        match self.to_numeric() {
+            Value::Integer(i) => Some(i),
+            Value::Float(f) => Some(f as i64),
+            _ => None,
+        }
+    }
+
+    pub fn to_float(&self) -> Option<f64> {
+        match self.to_numeric() {
+            Value::Integer(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    // the text form of a value, as used by || and when printing results
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Integer(i) => Some(i.to_string()),
+            Value::Float(f) => Some(format_float(*f)),
+            Value::Text(s) => Some(s.clone()),
+            Value::Blob(b) => Some(String::from_utf8_lossy(b).to_string()),
+        }
+    }
+
+    // sqlite's sort order across types:
+    //
+    //   NULL < INTEGER/REAL < TEXT < BLOB
+    //
+    // integers and floats are compared by their numeric value (so 1 == 1.0),
+    // text and blobs are compared byte by byte.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Float(b)) => compare_integer_float(*a, *b),
+            (Value::Float(a), Value::Integer(b)) => compare_integer_float(*b, *a).reverse(),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Integer(_) | Value::Float(_), _) => Ordering::Less,
+            (_, Value::Integer(_) | Value::Float(_)) => Ordering::Greater,
+
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Text(_), Value::Blob(_)) => Ordering::Less,
+            (Value::Blob(_), Value::Text(_)) => Ordering::Greater,
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+        }
+    }
 }
 
-pub fn parse_type_code(type_code: u64, data: &[u8]) -> (Value, usize) {
+// two values are equal when sqlite would see them as the same value, e.g. for
+// DISTINCT: numbers by their numeric value, so 1 == 1.0, and NULL == NULL.
+// text and blobs are never equal to each other, even with the same bytes.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(i), Value::Float(f)) | (Value::Float(f), Value::Integer(i)) => {
+                compare_integer_float(*i, *f) == Ordering::Equal
+            }
+            (Value::Float(a), Value::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (Value::Text(a), Value::Text(b)) => a == b,
+            (Value::Blob(a), Value::Blob(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+// equal values have to hash the same, so a float that holds an integer is
+// hashed as that integer
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Null => 0.hash(state),
+            Value::Integer(i) => (1, i).hash(state),
+            Value::Float(f) => match exact_integer(*f) {
+                Some(i) => (1, i).hash(state),
+                None if f.is_nan() => (2, f64::NAN.to_bits()).hash(state),
+                None => (2, f.to_bits()).hash(state),
+            },
+            Value::Text(s) => (3, s).hash(state),
+            Value::Blob(b) => (4, b).hash(state),
+        }
+    }
+}
+
+// the integer a float holds exactly, if it holds one
+fn exact_integer(f: f64) -> Option<i64> {
+    (f.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&f))
+        .then_some(f as i64)
+}
+
+// conversions from rust types, so that parameters can be bound with plain
+// values, e.g. statement.bind(1, "AC/DC"). sqlite has no boolean type, true
+// and false are stored as 1 and 0. None is NULL.
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Integer(i)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Value {
+        Value::Integer(i as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(i: u32) -> Value {
+        Value::Integer(i as i64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Integer(b as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Float(f)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Value {
+        Value::Blob(bytes)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Value {
+        Value::Blob(bytes.to_vec())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// comparing an i64 with an f64 by casting the integer to a float loses precision
+// for big integers, so only do that when the float is outside the i64 range
+fn compare_integer_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() {
+        return Ordering::Greater;
+    }
+    if f >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if f < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+
+    let truncated = f as i64;
+    match i.cmp(&truncated) {
+        Ordering::Equal => (truncated as f64)
+            .partial_cmp(&f)
+            .unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+fn parse_numeric_prefix(text: &str) -> Value {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let mut end = 0;
+
+    if end < bytes.len() && (bytes[end] == b'+' || bytes[end] == b'-') {
+        end += 1;
+    }
+    let digits_start = end;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    let mut is_integer = true;
+    if end < bytes.len() && bytes[end] == b'.' {
+        is_integer = false;
+        end += 1;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+    // a lone sign or dot isn't a number
+    if !text[digits_start..end].bytes().any(|b| b.is_ascii_digit()) {
+        return Value::Integer(0);
+    }
+    if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+        let mut exponent = end + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            is_integer = false;
+            end = exponent;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+    }
+
+    let number = &text[..end];
+    if is_integer && let Ok(i) = number.parse::<i64>() {
+        return Value::Integer(i);
+    }
+
+    Value::Float(number.parse::<f64>().unwrap_or(0.0))
+}
+
+// sqlite prints floats with up to 15 significant digits (printf's "%!.15g"),
+// and always keeps a decimal point so that 1.0 doesn't look like an integer:
+//
+//   1.0      -> 1.0
+//   0.1+0.2  -> 0.3
+//   1e20     -> 1.0e+20
+//
+// it's printf itself, so ties round away from zero the same way.
+pub fn format_float(f: f64) -> String {
+    printf::format("%!.15g", &[Value::Float(f)])
+}
+
+// how many bytes a value with this type code takes up in a record
+// type codes 10 and 11 are reserved and never appear in a valid record, so
+// these return None for them
+pub fn type_code_size(type_code: u64) -> Option<usize> {
     match type_code {
-        0 => (Value::Null, 0),
-        1 => (Value::Integer(data[0] as i8 as i64), 1),
-        2 => (
-            Value::Integer(i16::from_be_bytes([data[0], data[1]]) as i64),
-            2,
-        ),
-        3 => (
-            Value::Integer(i32::from_be_bytes([0, data[0], data[1], data[2]]) as i64),
-            3,
-        ),
-        4 => (
-            Value::Integer(i32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i64),
-            4,
-        ),
-        5 => (
-            // TODO: need to handle sign extension. this code currently only supports positive
-            // integers
-            Value::Integer(i64::from_be_bytes([
-                0, 0, data[0], data[1], data[2], data[3], data[4], data[5],
-            ])),
-            6,
-        ),
-        6 => (
-            Value::Integer(i64::from_be_bytes([
-                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-            ])),
-            8,
-        ),
-        7 => (
-            Value::Float(f64::from_be_bytes([
-                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-            ])),
-            8,
-        ),
-        8 => (Value::Integer(0), 0),
-        9 => (Value::Integer(1), 0),
-        n if n >= 12 && n % 2 == 0 => {
-            let len = ((n - 12) / 2) as usize;
-            (Value::Blob(data[..len].to_vec()), len)
-        }
-        n if n >= 13 && n % 2 == 1 => {
-            let len = ((n - 13) / 2) as usize;
-            (
-                Value::Text(String::from_utf8_lossy(&data[..len]).to_string()),
-                len,
-            )
-        }
-        _ => panic!("Unknown type code: {}", type_code),
+        0 | 8 | 9 => Some(0),
+        1 => Some(1),
+        2 => Some(2),
+        3 => Some(3),
+        4 => Some(4),
+        5 => Some(6),
+        6 | 7 => Some(8),
+        n if n >= 12 => Some(((n - 12) / 2) as usize),
+        _ => None,
+    }
+}
+
+// decodes one value of a record from the start of data. returns the value and
+// how many bytes it took up, or None if data is too short for it.
+pub fn parse_type_code(type_code: u64, data: &[u8]) -> Option<(Value, usize)> {
+    let size = type_code_size(type_code)?;
+    let data = data.get(..size)?;
+
+    // integers are big-endian two's complement. the 1 to 6 byte sizes are
+    // sign-extended to 8 bytes.
+    let integer = || {
+        let mut bytes = if data[0] & 0x80 != 0 {
+            [0xFF; 8]
+        } else {
+            [0; 8]
+        };
+        bytes[8 - size..].copy_from_slice(data);
+        Value::Integer(i64::from_be_bytes(bytes))
+    };
+
+    let value = match type_code {
+        0 => Value::Null,
+        1..=6 => integer(),
+        7 => Value::Float(f64::from_be_bytes(data.try_into().ok()?)),
+        8 => Value::Integer(0),
+        9 => Value::Integer(1),
+        n if n % 2 == 0 => Value::Blob(data.to_vec()),
+        _ => Value::Text(String::from_utf8_lossy(data).to_string()),
+    };
+
+    Some((value, size))
+}
+
+// the opposite of parse_type_code: appends the value's bytes to `data` and
+// returns the type code that describes them. integers use as few bytes as
+// they fit in.
+pub fn write_type_code(value: &Value, data: &mut Vec<u8>) -> u64 {
+    match value {
+        Value::Null => 0,
+        Value::Integer(0) => 8,
+        Value::Integer(1) => 9,
+        Value::Integer(i) => {
+            let (type_code, size) = match *i {
+                -0x80..=0x7F => (1, 1),
+                -0x8000..=0x7FFF => (2, 2),
+                -0x80_0000..=0x7F_FFFF => (3, 3),
+                -0x8000_0000..=0x7FFF_FFFF => (4, 4),
+                -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => (5, 6),
+                _ => (6, 8),
+            };
+            data.extend_from_slice(&i.to_be_bytes()[8 - size..]);
+            type_code
+        }
+        Value::Float(f) => {
+            data.extend_from_slice(&f.to_be_bytes());
+            7
+        }
+        Value::Text(text) => {
+            data.extend_from_slice(text.as_bytes());
+            text.len() as u64 * 2 + 13
+        }
+        Value::Blob(bytes) => {
+            data.extend_from_slice(bytes);
+            bytes.len() as u64 * 2 + 12
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_across_types() {
+        let ordered = [
+            Value::Null,
+            Value::Integer(-5),
+            Value::Float(1.5),
+            Value::Integer(2),
+            Value::Text(String::from("A")),
+            Value::Text(String::from("a")),
+            Value::Blob(vec![0]),
+        ];
+
+        for pair in ordered.windows(2) {
+            assert_eq!(pair[0].compare(&pair[1]), Ordering::Less);
+        }
+        assert_eq!(
+            Value::Integer(1).compare(&Value::Float(1.0)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_equal_values_hash_the_same() {
+        let hash = |value: &Value| {
+            let mut hasher = std::hash::DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let equal = [
+            (Value::Integer(1), Value::Float(1.0)),
+            (Value::Integer(0), Value::Float(-0.0)),
+            (Value::Null, Value::Null),
+            (Value::Float(f64::NAN), Value::Float(-f64::NAN)),
+        ];
+        for (a, b) in equal {
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+
+        assert_ne!(Value::Integer(1), Value::Float(1.5));
+        assert_ne!(Value::Integer(i64::MAX), Value::Float(i64::MAX as f64));
+        assert_ne!(Value::Integer(1), Value::Text(String::from("1")));
+        assert_ne!(Value::Text(String::from("a")), Value::Blob(b"a".to_vec()));
+        assert_ne!(Value::Null, Value::Integer(0));
+    }
+
+    #[test]
+    fn test_to_numeric() {
+        assert_eq!(
+            Value::Text(String::from("12abc")).to_numeric(),
+            Value::Integer(12)
+        );
+        assert!(matches!(
+            Value::Text(String::from(" 2.5e1x")).to_numeric(),
+            Value::Float(25.0)
+        ));
+        assert_eq!(
+            Value::Text(String::from("abc")).to_numeric(),
+            Value::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(format_float(1.0), "1.0");
+        assert_eq!(format_float(0.1 + 0.2), "0.3");
+        assert_eq!(format_float(100.0), "100.0");
+        assert_eq!(format_float(1e15), "1.0e+15");
+        assert_eq!(format_float(1.5e-5), "1.5e-05");
+        assert_eq!(format_float(123456789.12345679), "123456789.123457");
+        assert_eq!(format_float(-2.25), "-2.25");
+        // a tie at the 15th digit rounds away from zero, not to even
+        assert_eq!(format_float(870615500306946.5), "870615500306947.0");
+        assert_eq!(format_float(f64::NEG_INFINITY), "-Inf");
     }
 }
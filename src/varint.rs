@@ -34,6 +34,12 @@ pub fn parse_varint(bytes: &[u8]) -> (u64, usize) {
         // max 9 bytes
         bytes_read += 1;
 
+        // the 9th byte has no continuation bit: all 8 of its bits are data
+        if bytes_read == 9 {
+            value = (value << 8) | byte as u64;
+            break;
+        }
+
         // 0x80 == 8 = 1000 + 0 = 0000 == 10000000
         // doing a bitwise AND (&) operation compares each value
         // at the same posision. For example:
@@ -83,10 +89,62 @@ pub fn parse_varint(bytes: &[u8]) -> (u64, usize) {
     (value, bytes_read)
 }
 
+// the opposite of parse_varint: appends `value` to `out` as 1 to 9 bytes.
+// values up to 56 bits take 7 bits per byte. anything bigger takes all 9
+// bytes, and the last one holds 8 bits instead of 7.
+pub fn write_varint(value: u64, out: &mut Vec<u8>) {
+    if value >> 56 != 0 {
+        for i in (1..9).rev() {
+            out.push((value >> (i * 7 + 1)) as u8 | 0x80);
+        }
+        out.push(value as u8);
+        return;
+    }
+
+    // the number of 7 bit groups we need, at least one even for 0
+    let groups = (64 - value.leading_zeros()).div_ceil(7).max(1);
+    for i in (0..groups).rev() {
+        let byte = (value >> (i * 7)) as u8 & 0x7F;
+        out.push(if i == 0 { byte } else { byte | 0x80 });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_write_varint() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16384,
+            u32::MAX as u64,
+            (1 << 56) - 1,
+            1 << 56,
+            i64::MAX as u64,
+            -1i64 as u64,
+            -50000150i64 as u64,
+            u64::MAX,
+        ] {
+            let mut bytes = vec![];
+            write_varint(value, &mut bytes);
+            assert_eq!(parse_varint(&bytes), (value, bytes.len()));
+        }
+
+        let mut bytes = vec![];
+        write_varint(300, &mut bytes);
+        assert_eq!(bytes, [0x82, 0x2C]);
+
+        let mut bytes = vec![];
+        write_varint(u64::MAX, &mut bytes);
+        assert_eq!(bytes, [0xFF; 9]);
+        assert_eq!(parse_varint(&bytes), (u64::MAX, 9));
+    }
+
     #[test]
     fn test_parse_varint() {
         let result = parse_varint(&[0x82, 0x2C]);
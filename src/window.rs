@@ -0,0 +1,795 @@
+use std::{cmp::Ordering, iter, ops::Range};
+
+use crate::{
+    aggregate::Aggregate,
+    ast::{self, FrameUnits},
+    error::{Error, Result},
+    expr::Expr,
+    sort::{self, SortKey},
+    value::Value,
+};
+
+// the functions that only work as window functions. the aggregate functions
+// work as window functions too, see Call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl Function {
+    // None if the name isn't one of these functions
+    pub fn find(name: &str, num_args: usize) -> Result<Option<Function>> {
+        let (function, arities): (Function, &[usize]) = match name.to_ascii_lowercase().as_str() {
+            "row_number" => (Function::RowNumber, &[0]),
+            "rank" => (Function::Rank, &[0]),
+            "dense_rank" => (Function::DenseRank, &[0]),
+            "percent_rank" => (Function::PercentRank, &[0]),
+            "cume_dist" => (Function::CumeDist, &[0]),
+            "ntile" => (Function::Ntile, &[1]),
+            "lag" => (Function::Lag, &[1, 2, 3]),
+            "lead" => (Function::Lead, &[1, 2, 3]),
+            "first_value" => (Function::FirstValue, &[1]),
+            "last_value" => (Function::LastValue, &[1]),
+            "nth_value" => (Function::NthValue, &[2]),
+            _ => return Ok(None),
+        };
+
+        if !arities.contains(&num_args) {
+            return Err(Error::Invalid(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+
+        Ok(Some(function))
+    }
+}
+
+// window function calls compile to columns past the end of the row, from
+// here on. once the planner knows how many aggregates the query has it moves
+// them to just after those, see Expr::map_columns.
+pub const PLACEHOLDER: usize = usize::MAX / 2;
+
+// the window function calls of a query, collected as it's compiled, and the
+// windows its WINDOW clause names
+#[derive(Clone, Default)]
+pub struct Windows {
+    pub calls: Vec<Window>,
+    named: Vec<ast::NamedWindow>,
+}
+
+impl Windows {
+    // a named window can add to one named before it
+    pub fn new(named: &[ast::NamedWindow]) -> Result<Windows> {
+        let mut windows = Windows::default();
+        for window in named {
+            let definition = windows.resolve(&window.definition)?;
+            windows.named.push(ast::NamedWindow {
+                name: window.name.clone(),
+                definition,
+            });
+        }
+        Ok(windows)
+    }
+
+    // fills in the named window a definition is based on. it can only add an
+    // ORDER BY and a frame the named window doesn't have.
+    pub fn resolve(&self, definition: &ast::WindowDefinition) -> Result<ast::WindowDefinition> {
+        let Some(name) = &definition.base else {
+            return Ok(definition.clone());
+        };
+        let Some(base) = self
+            .named
+            .iter()
+            .find(|window| window.name.eq_ignore_ascii_case(name))
+        else {
+            return Err(Error::Invalid(format!("no such window: {}", name)));
+        };
+        let base = &base.definition;
+
+        let overrides = if !definition.partition_by.is_empty() {
+            Some("PARTITION clause")
+        } else if !definition.order_by.is_empty() && !base.order_by.is_empty() {
+            Some("ORDER BY clause")
+        } else if base.frame.is_some()
+            && (definition.frame.is_some() || !definition.order_by.is_empty())
+        {
+            Some("frame specification")
+        } else {
+            None
+        };
+        if let Some(overrides) = overrides {
+            return Err(Error::Invalid(format!(
+                "cannot override {} of window: {}",
+                overrides, name
+            )));
+        }
+
+        Ok(ast::WindowDefinition {
+            base: None,
+            partition_by: base.partition_by.clone(),
+            order_by: match definition.order_by.is_empty() {
+                true => base.order_by.clone(),
+                false => definition.order_by.clone(),
+            },
+            frame: definition.frame.clone().or_else(|| base.frame.clone()),
+        })
+    }
+}
+
+// one window function call in a query, e.g. rank() OVER (ORDER BY Total DESC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub call: Call,
+    pub spec: Spec,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Function(Function, Vec<Expr>),
+    // sum(Total) OVER (...) works out the aggregate over the rows of the
+    // frame
+    Aggregate(Aggregate),
+}
+
+// which rows a window function sees: the rows of the partition, in order,
+// and for each row the frame of rows around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spec {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<(Expr, SortKey)>,
+    pub frame: Frame,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: Bound,
+    pub end: Bound,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+impl Frame {
+    // RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW, the frame when the
+    // window doesn't give one
+    pub fn default_frame() -> Frame {
+        Frame {
+            units: FrameUnits::Range,
+            start: Bound::UnboundedPreceding,
+            end: Bound::CurrentRow,
+        }
+    }
+
+    // checks that the frame makes sense, e.g. that it doesn't end before it
+    // starts
+    pub fn new(
+        units: FrameUnits,
+        start: Bound,
+        end: Bound,
+        num_order_terms: usize,
+    ) -> Result<Frame> {
+        let ends_before_start = matches!(
+            (&start, &end),
+            (Bound::UnboundedFollowing, _)
+                | (_, Bound::UnboundedPreceding)
+                | (Bound::CurrentRow, Bound::Preceding(_))
+                | (Bound::Following(_), Bound::Preceding(_) | Bound::CurrentRow)
+        );
+        if ends_before_start {
+            return Err(Error::Invalid(String::from(
+                "unsupported frame specification",
+            )));
+        }
+
+        let has_offset = [&start, &end]
+            .iter()
+            .any(|bound| matches!(bound, Bound::Preceding(_) | Bound::Following(_)));
+        if units == FrameUnits::Range && has_offset && num_order_terms != 1 {
+            return Err(Error::Invalid(String::from(
+                "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression",
+            )));
+        }
+
+        Ok(Frame { units, start, end })
+    }
+}
+
+impl Window {
+    pub fn bind(&self, parameters: &[Value]) -> Window {
+        let bind = |exprs: &[Expr]| exprs.iter().map(|expr| expr.bind(parameters)).collect();
+        let bind_bound = |bound: &Bound| match bound {
+            Bound::Preceding(offset) => Bound::Preceding(offset.bind(parameters)),
+            Bound::Following(offset) => Bound::Following(offset.bind(parameters)),
+            bound => bound.clone(),
+        };
+
+        Window {
+            call: match &self.call {
+                Call::Function(function, args) => Call::Function(*function, bind(args)),
+                Call::Aggregate(aggregate) => Call::Aggregate(aggregate.bind(parameters)),
+            },
+            spec: Spec {
+                partition_by: bind(&self.spec.partition_by),
+                order_by: self
+                    .spec
+                    .order_by
+                    .iter()
+                    .map(|(expr, key)| (expr.bind(parameters), *key))
+                    .collect(),
+                frame: Frame {
+                    units: self.spec.frame.units,
+                    start: bind_bound(&self.spec.frame.start),
+                    end: bind_bound(&self.spec.frame.end),
+                },
+            },
+        }
+    }
+}
+
+// works out the window functions for the rows of a query, after any grouping.
+// each row comes out with the result of every window function added to the
+// end, in the order of `windows`.
+//
+// every row has to be read first. the rows are then sorted by the PARTITION
+// BY and ORDER BY of each window in turn, which leaves them in the order of
+// the first window, like they are in sqlite.
+pub fn windowed(
+    input: impl Iterator<Item = Result<(i64, Vec<Value>)>>,
+    windows: Vec<Window>,
+) -> impl Iterator<Item = Result<(i64, Vec<Value>)>> {
+    let mut input = Some(input);
+    let mut output: Option<std::vec::IntoIter<(i64, Vec<Value>)>> = None;
+
+    iter::from_fn(move || {
+        if output.is_none() {
+            let rows = input.take()?.collect::<Result<Vec<_>>>();
+            match rows.and_then(|rows| evaluate(rows, &windows)) {
+                Ok(rows) => output = Some(rows.into_iter()),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        output.as_mut()?.next().map(Ok)
+    })
+}
+
+fn evaluate(
+    mut rows: Vec<(i64, Vec<Value>)>,
+    windows: &[Window],
+) -> Result<Vec<(i64, Vec<Value>)>> {
+    let Some((_, first)) = rows.first() else {
+        return Ok(rows);
+    };
+    let base = first.len();
+    for (_, values) in &mut rows {
+        values.resize(base + windows.len(), Value::Null);
+    }
+
+    // windows with the same spec are worked out together
+    let mut specs: Vec<&Spec> = vec![];
+    for window in windows {
+        if !specs.contains(&&window.spec) {
+            specs.push(&window.spec);
+        }
+    }
+
+    for spec in specs.into_iter().rev() {
+        let partition_keys: Vec<SortKey> = spec
+            .partition_by
+            .iter()
+            .map(|expr| SortKey {
+                descending: false,
+                nulls_first: true,
+                collation: expr.collation().unwrap_or_default(),
+            })
+            .collect();
+        let order_keys: Vec<SortKey> = spec.order_by.iter().map(|(_, key)| *key).collect();
+
+        // each row with its PARTITION BY values and its ORDER BY values
+        let mut keyed = rows
+            .into_iter()
+            .map(|row| {
+                let partition = spec
+                    .partition_by
+                    .iter()
+                    .map(|expr| expr.eval(&row.1))
+                    .collect::<Result<Vec<_>>>()?;
+                let order = spec
+                    .order_by
+                    .iter()
+                    .map(|(expr, _)| expr.eval(&row.1))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((partition, order, row))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        keyed.sort_by(|left, right| {
+            sort::compare_keys(&partition_keys, &left.0, &right.0)
+                .then_with(|| sort::compare_keys(&order_keys, &left.1, &right.1))
+        });
+
+        let mut start = 0;
+        while start < keyed.len() {
+            let end = start
+                + keyed[start..]
+                    .iter()
+                    .position(|row| {
+                        sort::compare_keys(&partition_keys, &row.0, &keyed[start].0)
+                            != Ordering::Equal
+                    })
+                    .unwrap_or(keyed.len() - start);
+
+            let partition = &mut keyed[start..end];
+            let order: Vec<Vec<Value>> = partition.iter().map(|row| row.1.clone()).collect();
+            let values: Vec<&Vec<Value>> = partition.iter().map(|row| &row.2.1).collect();
+            let partition_rows = Partition::new(&values, &order, &order_keys);
+
+            let mut results = vec![];
+            for (k, window) in windows.iter().enumerate() {
+                if &window.spec == spec {
+                    results.push((k, partition_rows.evaluate(window)?));
+                }
+            }
+            for (k, results) in results {
+                for (row, result) in partition.iter_mut().zip(results) {
+                    row.2.1[base + k] = result;
+                }
+            }
+
+            start = end;
+        }
+
+        rows = keyed.into_iter().map(|(_, _, row)| row).collect();
+    }
+
+    Ok(rows)
+}
+
+// the rows of one partition, in the order of the window
+struct Partition<'a> {
+    rows: &'a [&'a Vec<Value>],
+    // the ORDER BY values of each row
+    order: &'a [Vec<Value>],
+    order_keys: &'a [SortKey],
+    // the groups of peers: rows with equal ORDER BY values. without an ORDER
+    // BY every row is a peer of every other.
+    groups: Vec<Range<usize>>,
+    // the group each row is in
+    group_of: Vec<usize>,
+}
+
+impl<'a> Partition<'a> {
+    fn new(
+        rows: &'a [&'a Vec<Value>],
+        order: &'a [Vec<Value>],
+        order_keys: &'a [SortKey],
+    ) -> Partition<'a> {
+        let mut groups: Vec<Range<usize>> = vec![];
+        let mut group_of = vec![];
+        for i in 0..rows.len() {
+            match groups.last_mut() {
+                Some(group)
+                    if sort::compare_keys(order_keys, &order[group.start], &order[i])
+                        == Ordering::Equal =>
+                {
+                    group.end = i + 1
+                }
+                _ => groups.push(i..i + 1),
+            }
+            group_of.push(groups.len() - 1);
+        }
+
+        Partition {
+            rows,
+            order,
+            order_keys,
+            groups,
+            group_of,
+        }
+    }
+
+    // the result of a window function for each row of the partition
+    fn evaluate(&self, window: &Window) -> Result<Vec<Value>> {
+        let n = self.rows.len();
+        let arg = |args: &[Expr], i: usize, row: usize| args[i].eval(self.rows[row]);
+
+        let Call::Function(function, args) = &window.call else {
+            return self.aggregate(window);
+        };
+
+        let mut results = Vec::with_capacity(n);
+        for row in 0..n {
+            let group = &self.groups[self.group_of[row]];
+            let rank = group.start + 1;
+
+            results.push(match function {
+                Function::RowNumber => Value::Integer(row as i64 + 1),
+                Function::Rank => Value::Integer(rank as i64),
+                Function::DenseRank => Value::Integer(self.group_of[row] as i64 + 1),
+                Function::PercentRank if n > 1 => Value::Float((rank - 1) as f64 / (n - 1) as f64),
+                Function::PercentRank => Value::Float(0.0),
+                Function::CumeDist => Value::Float(group.end as f64 / n as f64),
+                Function::Ntile => {
+                    // the number of buckets comes from the first row
+                    let buckets = match arg(args, 0, 0)?.to_numeric() {
+                        Value::Integer(i) if i > 0 => i as usize,
+                        Value::Float(f) if f >= 1.0 => f as usize,
+                        _ => {
+                            return Err(Error::Invalid(String::from(
+                                "argument of ntile must be a positive integer",
+                            )));
+                        }
+                    };
+                    Value::Integer(ntile(row, n, buckets) as i64)
+                }
+                Function::Lag | Function::Lead => {
+                    let offset = match args.get(1) {
+                        Some(_) => offset_value(arg(args, 1, row)?),
+                        None => Some(1),
+                    };
+                    let offset = match function {
+                        Function::Lag => offset.map(|offset| -offset),
+                        _ => offset,
+                    };
+
+                    let target = offset
+                        .and_then(|offset| (row as i64).checked_add(offset))
+                        .and_then(|target| usize::try_from(target).ok())
+                        .filter(|&target| target < n);
+                    match (target, args.get(2)) {
+                        (Some(target), _) => arg(args, 0, target)?,
+                        (None, Some(_)) => arg(args, 2, row)?,
+                        (None, None) => Value::Null,
+                    }
+                }
+                Function::FirstValue | Function::LastValue | Function::NthValue => {
+                    let frame = self.frame(&window.spec.frame, row)?;
+                    let target = match function {
+                        Function::FirstValue => Some(frame.start),
+                        Function::LastValue => frame.end.checked_sub(1),
+                        _ => {
+                            let nth = match offset_value(arg(args, 1, row)?) {
+                                Some(nth) if nth > 0 => nth as usize,
+                                _ => {
+                                    return Err(Error::Invalid(String::from(
+                                        "second argument to nth_value must be a positive integer",
+                                    )));
+                                }
+                            };
+                            frame.start.checked_add(nth - 1)
+                        }
+                    };
+                    match target {
+                        Some(target) if frame.contains(&target) => arg(args, 0, target)?,
+                        _ => Value::Null,
+                    }
+                }
+            });
+        }
+
+        Ok(results)
+    }
+
+    // an aggregate over the frame of each row. frames usually only grow at
+    // the end as we go, so the rows are added to the aggregate as they come
+    // into the frame. when the start of the frame moves, the aggregate is
+    // started again.
+    fn aggregate(&self, window: &Window) -> Result<Vec<Value>> {
+        let Call::Aggregate(aggregate) = &window.call else {
+            unreachable!("not an aggregate")
+        };
+
+        let args = self
+            .rows
+            .iter()
+            .map(|row| {
+                aggregate
+                    .args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut results = Vec::with_capacity(self.rows.len());
+        let mut state = aggregate.start();
+        let mut added = 0..0;
+        let mut size = 0;
+
+        for row in 0..self.rows.len() {
+            let frame = self.frame(&window.spec.frame, row)?;
+            if frame.start != added.start || frame.end < added.end {
+                state = aggregate.start();
+                added = frame.start..frame.start;
+            }
+
+            for args in &args[added.end.max(frame.start)..frame.end] {
+                aggregate.step(&mut state, args, &mut size);
+            }
+            added.end = added.end.max(frame.end);
+
+            results.push(aggregate.finish(&state)?);
+        }
+
+        Ok(results)
+    }
+
+    // the rows in the frame of a row
+    fn frame(&self, frame: &Frame, row: usize) -> Result<Range<usize>> {
+        let start = self.bound(frame.units, &frame.start, row, true)?;
+        let end = self.bound(frame.units, &frame.end, row, false)?;
+        Ok(start..end.max(start))
+    }
+
+    // where a frame starts, or where it ends (exclusive)
+    fn bound(&self, units: FrameUnits, bound: &Bound, row: usize, start: bool) -> Result<usize> {
+        let n = self.rows.len();
+        let group = self.group_of[row];
+
+        // where the frame starts or ends for a row, or a group
+        let edge = |row: usize| if start { row } else { row + 1 };
+        let group_edge = |group: usize| {
+            let group = &self.groups[group];
+            if start { group.start } else { group.end }
+        };
+
+        let (offset, preceding) = match bound {
+            Bound::UnboundedPreceding => return Ok(0),
+            Bound::UnboundedFollowing => return Ok(n),
+            Bound::CurrentRow => {
+                return Ok(match units {
+                    FrameUnits::Rows => edge(row),
+                    _ => group_edge(group),
+                });
+            }
+            Bound::Preceding(offset) => (offset.eval(&[])?, true),
+            Bound::Following(offset) => (offset.eval(&[])?, false),
+        };
+
+        let which = if start { "starting" } else { "ending" };
+        if units == FrameUnits::Range {
+            return self.range_bound(offset, preceding, row, start, which);
+        }
+
+        let offset = match frame_offset(offset).and_then(offset_value) {
+            Some(offset) if offset >= 0 => offset as usize,
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "frame {} offset must be a non-negative integer",
+                    which
+                )));
+            }
+        };
+
+        Ok(match (units, preceding) {
+            (FrameUnits::Rows, true) => edge(row).saturating_sub(offset),
+            (FrameUnits::Rows, false) => edge(row).saturating_add(offset).min(n),
+            // an end before the first group is an empty frame at the start
+            (_, true) => match group.checked_sub(offset) {
+                Some(group) => group_edge(group),
+                None => 0,
+            },
+            (_, false) => match group.checked_add(offset) {
+                Some(group) if group < self.groups.len() => group_edge(group),
+                _ => n,
+            },
+        })
+    }
+
+    // RANGE <offset> PRECEDING is the first row whose ORDER BY value is at
+    // least the current row's minus the offset, or at most it plus the
+    // offset when the ORDER BY is descending. a row whose value isn't a
+    // number only has its peers.
+    fn range_bound(
+        &self,
+        offset: Value,
+        preceding: bool,
+        row: usize,
+        start: bool,
+        which: &str,
+    ) -> Result<usize> {
+        let offset = match frame_offset(offset) {
+            Some(offset @ (Value::Integer(0..) | Value::Float(0.0..))) => offset,
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "frame {} offset must be a non-negative number",
+                    which
+                )));
+            }
+        };
+
+        let group = &self.groups[self.group_of[row]];
+        let key = &self.order_keys[0];
+        let value = &self.order[row][0];
+
+        // towards the start of the partition is down for an ascending ORDER
+        // BY and up for a descending one
+        let subtract = preceding != key.descending;
+        let Some(target) = offset_number(value, &offset, subtract) else {
+            return Ok(if start { group.start } else { group.end });
+        };
+
+        // the first row past the bound
+        let target = std::slice::from_ref(&target);
+        Ok(self.order.partition_point(|order| {
+            let ordering = sort::compare_keys(std::slice::from_ref(key), &order[..1], target);
+            match start {
+                true => ordering == Ordering::Less,
+                false => ordering != Ordering::Greater,
+            }
+        }))
+    }
+}
+
+// the value a number of rows, or an offset, is given as. anything that isn't
+// a whole number is None.
+fn offset_value(value: Value) -> Option<i64> {
+    match value.to_numeric() {
+        _ if value.is_null() => None,
+        Value::Integer(i) => Some(i),
+        Value::Float(f) if f.fract() == 0.0 && f.abs() < 9223372036854775808.0 => Some(f as i64),
+        _ => None,
+    }
+}
+
+// frame offsets have to be numbers, or text that reads as one
+fn frame_offset(value: Value) -> Option<Value> {
+    match &value {
+        Value::Integer(_) | Value::Float(_) => Some(value),
+        Value::Text(text) if text.trim().parse::<f64>().is_ok_and(f64::is_finite) => {
+            Some(value.to_numeric())
+        }
+        _ => None,
+    }
+}
+
+// value - offset or value + offset, when value is a number
+fn offset_number(value: &Value, offset: &Value, subtract: bool) -> Option<Value> {
+    let offset = match subtract {
+        true => match offset {
+            Value::Integer(i) => Value::Integer(i.checked_neg()?),
+            Value::Float(f) => Value::Float(-f),
+            _ => return None,
+        },
+        false => offset.clone(),
+    };
+
+    Some(match (value, offset) {
+        (Value::Integer(a), Value::Integer(b)) => match a.checked_add(b) {
+            Some(sum) => Value::Integer(sum),
+            None => Value::Float(*a as f64 + b as f64),
+        },
+        (Value::Integer(a), Value::Float(b)) => Value::Float(*a as f64 + b),
+        (Value::Float(a), offset) => Value::Float(a + offset.to_float()?),
+        _ => return None,
+    })
+}
+
+// which of `buckets` nearly equal buckets a row goes in, counting from 1. the
+// first buckets get one row more when the rows don't divide evenly.
+fn ntile(row: usize, num_rows: usize, buckets: usize) -> usize {
+    let size = num_rows / buckets;
+    let larger = num_rows % buckets;
+
+    if size == 0 {
+        return row + 1;
+    }
+    if row < larger * (size + 1) {
+        row / (size + 1) + 1
+    } else {
+        larger + (row - larger * (size + 1)) / size + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collation::Collation;
+
+    #[test]
+    fn test_ntile() {
+        let buckets = |num_rows, buckets| {
+            (0..num_rows)
+                .map(|row| ntile(row, num_rows, buckets))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(buckets(7, 3), [1, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(buckets(6, 3), [1, 1, 2, 2, 3, 3]);
+        assert_eq!(buckets(2, 5), [1, 2]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let rows: Vec<Vec<Value>> = [1, 1, 2, 4, 5]
+            .into_iter()
+            .map(|i| vec![Value::Integer(i)])
+            .collect();
+        let refs: Vec<&Vec<Value>> = rows.iter().collect();
+        let keys = [SortKey {
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        }];
+        let partition = Partition::new(&refs, &rows, &keys);
+
+        let number = |n| Expr::Literal(Value::Integer(n));
+        let frames = |units, start, end| {
+            let frame = Frame::new(units, start, end, 1).unwrap();
+            (0..rows.len())
+                .map(|row| partition.frame(&frame, row).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // the default frame reaches the last peer of the row
+        assert_eq!(
+            frames(
+                FrameUnits::Range,
+                Bound::UnboundedPreceding,
+                Bound::CurrentRow
+            ),
+            [0..2, 0..2, 0..3, 0..4, 0..5]
+        );
+        assert_eq!(
+            frames(
+                FrameUnits::Rows,
+                Bound::Preceding(number(1)),
+                Bound::Following(number(1))
+            ),
+            [0..2, 0..3, 1..4, 2..5, 3..5]
+        );
+        assert_eq!(
+            frames(
+                FrameUnits::Groups,
+                Bound::Preceding(number(1)),
+                Bound::CurrentRow
+            ),
+            [0..2, 0..2, 0..3, 2..4, 3..5]
+        );
+        assert_eq!(
+            frames(
+                FrameUnits::Range,
+                Bound::Preceding(number(1)),
+                Bound::Following(number(1))
+            ),
+            [0..3, 0..3, 0..3, 3..5, 3..5]
+        );
+
+        assert!(
+            Frame::new(
+                FrameUnits::Rows,
+                Bound::Following(number(1)),
+                Bound::CurrentRow,
+                0
+            )
+            .is_err()
+        );
+        assert!(
+            Frame::new(
+                FrameUnits::Range,
+                Bound::Preceding(number(1)),
+                Bound::CurrentRow,
+                2
+            )
+            .is_err()
+        );
+    }
+}
@@ -1,31 +1,1749 @@
 use sqlite::run;
-use sqlite::{Row, Value};
+use sqlite::{Clock, Connection, Error, Row, Value};
+
+// runs a query and reads every row it returns
+fn run_all(file_path: &str, query: &str) -> (Vec<String>, Vec<Row>) {
+    let (column_names, rows) = run(file_path, query).unwrap();
+    let rows = rows.collect::<sqlite::Result<Vec<Row>>>().unwrap();
+    (column_names, rows)
+}
 
 #[test]
 fn test_select_all_albums() {
     let file_path = String::from("tests/chinook.db");
     let query = String::from("SELECT * FROM albums");
 
-    let (column_names, rows) = run(&file_path, &query);
+    let (column_names, rows) = run_all(&file_path, &query);
 
     println!("{:?}", rows.first().unwrap());
 
     let target_row: Row = Row {
         rowid: 1,
         values: vec![
-            Value::Null,
+            Value::Integer(1),
             Value::Text(String::from("For Those About To Rock We Salute You")),
             Value::Integer(1),
         ],
     };
 
     let target_column_names: Vec<String> = vec![
-        String::from("[AlbumId]"),
-        String::from("[Title]"),
-        String::from("[ArtistId]"),
+        String::from("AlbumId"),
+        String::from("Title"),
+        String::from("ArtistId"),
     ];
 
     assert_eq!(target_column_names, column_names);
     assert_eq!(rows.first().unwrap(), &target_row);
     assert_eq!(rows.len(), 347);
 }
+
+#[test]
+fn test_select_keywords_are_case_insensitive() {
+    let file_path = String::from("tests/chinook.db");
+    let query = String::from("select *from Albums -- every album\n;");
+
+    let (column_names, rows) = run_all(&file_path, &query);
+
+    assert_eq!(column_names.len(), 3);
+    assert_eq!(rows.len(), 347);
+}
+
+#[test]
+fn test_unsupported_syntax_is_a_parse_error() {
+    let file_path = String::from("tests/chinook.db");
+    let query = String::from("SELECT * FROM albums )");
+
+    match run(&file_path, &query) {
+        Err(Error::Parse { message, position }) => {
+            assert_eq!(message, "near \")\": syntax error");
+            assert_eq!(position, 21);
+        }
+        Err(e) => panic!("expected a parse error, got {}", e),
+        Ok(_) => panic!("expected a parse error"),
+    }
+}
+
+#[test]
+fn test_errors_are_returned() {
+    let error = |file_path: &str, query: &str| match run(file_path, query) {
+        Err(e) => e,
+        Ok(_) => panic!("expected {} to fail", query),
+    };
+
+    assert!(matches!(
+        error("tests/chinook.db", "SELECT * FROM nope"),
+        Error::NoSuchTable(name) if name == "nope"
+    ));
+    assert!(matches!(
+        error("tests/chinook.db", "SELECT Nope FROM albums"),
+        Error::NoSuchColumn(name) if name == "Nope"
+    ));
+    assert_eq!(
+        error("tests/chinook.db", "SELECT albums.Nope FROM albums").to_string(),
+        "no such column: albums.Nope"
+    );
+    assert!(matches!(
+        error("Cargo.toml", "SELECT 1"),
+        Error::NotADatabase
+    ));
+    assert!(matches!(
+        error("tests/missing.db", "SELECT 1"),
+        Error::Io(_)
+    ));
+}
+
+#[test]
+fn test_select_where() {
+    let file_path = String::from("tests/chinook.db");
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT * FROM albums WHERE ArtistId = 90 AND Title <> 'Killers'"),
+    );
+    assert_eq!(rows.len(), 20);
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT * FROM albums WHERE rowid BETWEEN 10 AND 19 OR ArtistId IN (1, 2)"),
+    );
+    assert_eq!(rows.len(), 14);
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT * FROM customers WHERE Company IS NULL AND NOT (Country = 'USA')"),
+    );
+    assert_eq!(rows.len(), 39);
+}
+
+#[test]
+fn test_select_columns() {
+    let file_path = String::from("tests/chinook.db");
+    let query = String::from(
+        "SELECT Title, a.ArtistId AS artist, ArtistId * 2 + 1, 'x' || Title FROM albums a WHERE rowid = 2",
+    );
+
+    let (column_names, rows) = run_all(&file_path, &query);
+
+    assert_eq!(
+        column_names,
+        vec!["Title", "artist", "ArtistId * 2 + 1", "'x' || Title"]
+    );
+    assert_eq!(
+        rows,
+        vec![Row {
+            rowid: 2,
+            values: vec![
+                Value::Text(String::from("Balls to the Wall")),
+                Value::Integer(2),
+                Value::Integer(5),
+                Value::Text(String::from("xBalls to the Wall")),
+            ],
+        }]
+    );
+}
+
+#[test]
+fn test_select_table_star_and_literals() {
+    let file_path = String::from("tests/chinook.db");
+
+    let (column_names, rows) = run_all(
+        &file_path,
+        &String::from("SELECT genres.*, 1.5 FROM genres WHERE GenreId = 1"),
+    );
+    assert_eq!(column_names, vec!["GenreId", "Name", "1.5"]);
+    assert_eq!(
+        rows[0].values[1..],
+        [Value::Text(String::from("Rock")), Value::Float(1.5)]
+    );
+
+    let (column_names, rows) = run_all(&file_path, &String::from("SELECT 1 + 1 AS two"));
+    assert_eq!(column_names, vec!["two"]);
+    assert_eq!(rows[0].values, vec![Value::Integer(2)]);
+}
+
+#[test]
+fn test_rowid_alias_is_not_the_first_column() {
+    let file_path = String::from("tests/chinook.db");
+    let query = String::from("SELECT Title, AlbumId FROM albums WHERE AlbumId = 5");
+
+    let (_, rows) = run_all(&file_path, &query);
+
+    assert_eq!(
+        rows[0].values,
+        vec![Value::Text(String::from("Big Ones")), Value::Integer(5)]
+    );
+}
+
+// negative.db has rowids from -1000 to 990 in steps of 10, plus -50000150,
+// i64::MIN and i64::MAX
+#[test]
+fn test_negative_rowids() {
+    let file_path = "tests/negative.db";
+
+    let (_, rows) = run_all(file_path, "SELECT count(*) FROM numbers WHERE a < 0");
+    assert_eq!(values(&rows, 0), vec![Value::Integer(102)]);
+
+    let (_, rows) = run_all(file_path, "SELECT count(*) FROM numbers WHERE a > -10");
+    assert_eq!(values(&rows, 0), vec![Value::Integer(101)]);
+
+    let (_, rows) = run_all(file_path, "SELECT min(a), max(a) FROM numbers");
+    assert_eq!(values(&rows, 0), vec![Value::Integer(i64::MIN)]);
+    assert_eq!(values(&rows, 1), vec![Value::Integer(i64::MAX)]);
+
+    let (_, rows) = run_all(file_path, "SELECT b FROM numbers ORDER BY rowid LIMIT 2");
+    assert_eq!(values(&rows, 0), vec![text("min"), text("small")]);
+
+    // through the index on b, whose entries end with the rowid
+    let (_, rows) = run_all(file_path, "SELECT a FROM numbers WHERE b = 'small'");
+    assert_eq!(values(&rows, 0), vec![Value::Integer(-50000150)]);
+}
+
+// collate.db has a column n declared COLLATE NOCASE with an index on it,
+// and a column plain with the same values and an index on plain COLLATE
+// NOCASE. each of 'abc', 'ABC' and 'Abc' is in 142 or 143 rows.
+#[test]
+fn test_declared_collations() {
+    let file_path = "tests/collate.db";
+    let count = |query: &str| values(&run_all(file_path, query).1, 0);
+
+    // seeks through the NOCASE indexes, and a scan that compares the same way
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE n = 'abc'"),
+        vec![Value::Integer(428)]
+    );
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE n > 'abc' AND n < 'c'"),
+        vec![Value::Integer(143)]
+    );
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE +n = 'abc'"),
+        vec![Value::Integer(428)]
+    );
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE plain = 'abc' COLLATE NOCASE"),
+        vec![Value::Integer(428)]
+    );
+
+    // a COLLATE wins over the declared collation
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE n = 'abc' COLLATE BINARY"),
+        vec![Value::Integer(142)]
+    );
+    assert_eq!(
+        count("SELECT count(*) FROM words WHERE plain = 'abc'"),
+        vec![Value::Integer(142)]
+    );
+
+    // ids 1 to 7 are 'ABC', 'Abc', 'xyz', 'XYZ', 'b', NULL and 'abc'
+    let ids = |query: &str| {
+        values(&run_all(file_path, query).1, 0)
+            .into_iter()
+            .map(|id| id.as_integer().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ids("SELECT id FROM words WHERE id < 8 ORDER BY n, id"),
+        [6, 1, 2, 7, 5, 3, 4]
+    );
+    assert_eq!(
+        ids("SELECT id FROM words WHERE id < 8 ORDER BY n COLLATE BINARY, id"),
+        [6, 1, 2, 4, 7, 5, 3]
+    );
+
+    // groups of values that are equal ignoring case, and one for NULL
+    let (_, rows) = run_all(file_path, "SELECT count(*) FROM words GROUP BY n");
+    assert_eq!(values(&rows, 0), [143, 428, 143, 286].map(Value::Integer));
+    assert_eq!(
+        count("SELECT count(DISTINCT n) FROM words"),
+        vec![Value::Integer(3)]
+    );
+    let (_, rows) = run_all(file_path, "SELECT min(n), max(n) FROM words");
+    assert_eq!(values(&rows, 0), [text("ABC")]);
+    assert_eq!(values(&rows, 1), [text("xyz")]);
+
+    // with a hash set, and reading the rows in the order of words_n
+    let (_, rows) = run_all(file_path, "SELECT DISTINCT n FROM words");
+    assert_eq!(
+        values(&rows, 0),
+        [text("ABC"), text("xyz"), text("b"), Value::Null]
+    );
+    let (_, rows) = run_all(file_path, "SELECT DISTINCT n FROM words WHERE n > 'a'");
+    assert_eq!(values(&rows, 0), [text("ABC"), text("b"), text("xyz")]);
+}
+
+// a column's declared type decides how the values it's compared with are
+// converted, so an INTEGER column equals '5' where the value is 5
+#[test]
+fn test_column_affinity() {
+    let file_path = "tests/chinook.db";
+    let first = |query: &str| values(&run_all(file_path, query).1, 0);
+
+    assert_eq!(
+        first("SELECT Title FROM albums WHERE AlbumId = '5'"),
+        [text("Big Ones")]
+    );
+    // through the index on ArtistId
+    assert_eq!(
+        first("SELECT count(*) FROM albums WHERE ArtistId = '90'"),
+        [Value::Integer(21)]
+    );
+    assert_eq!(
+        first("SELECT AlbumId FROM albums WHERE AlbumId IN ('5', 6)"),
+        [5, 6].map(Value::Integer)
+    );
+    assert_eq!(
+        first("SELECT Name FROM tracks WHERE rowid = '3000'"),
+        [text("God Part II")]
+    );
+    assert_eq!(
+        first("SELECT TrackId FROM tracks WHERE Milliseconds = '343719'"),
+        [Value::Integer(1)]
+    );
+    assert_eq!(
+        first(
+            "SELECT count(*) FROM tracks JOIN albums ON albums.AlbumId = tracks.AlbumId \
+             WHERE albums.AlbumId = '5'"
+        ),
+        [Value::Integer(15)]
+    );
+    // a TEXT column turns the number into text instead
+    assert_eq!(
+        first("SELECT count(*) FROM invoices WHERE BillingPostalCode = 2010"),
+        [Value::Integer(7)]
+    );
+    assert_eq!(
+        first("SELECT count(*) FROM customers WHERE SupportRepId IN (SELECT '3')"),
+        [Value::Integer(21)]
+    );
+    // +x has no affinity, and text is never equal to a number
+    assert_eq!(
+        first("SELECT count(*) FROM albums WHERE +AlbumId = '5'"),
+        [Value::Integer(0)]
+    );
+
+    // 9223372036854775808 is too big for an integer, its negative isn't
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT -9223372036854775808, typeof(-9223372036854775808), -9223372036854775808.0",
+    );
+    assert_eq!(
+        rows[0].values,
+        [
+            Value::Integer(i64::MIN),
+            text("integer"),
+            Value::Float(-9223372036854775808.0)
+        ]
+    );
+
+    // affinity.db has a REAL column holding 5, 2.5, -1 and 7, where sqlite
+    // stores the whole numbers as integers
+    let (_, rows) = run_all(
+        "tests/affinity.db",
+        "SELECT value, typeof(value) FROM readings WHERE value = '5' OR id = 3",
+    );
+    assert_eq!(values(&rows, 0), [Value::Float(5.0), Value::Float(-1.0)]);
+    assert_eq!(values(&rows, 1), [text("real"), text("real")]);
+
+    // a column declared without a type has BLOB affinity, which isn't the same
+    // as having none: compared with a TEXT column, neither side is converted
+    let (_, rows) = run_all(
+        "tests/affinity.db",
+        "SELECT r1.id, r2.id FROM readings r1, readings r2 WHERE r1.raw = r2.label",
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values, [Value::Integer(1), Value::Integer(1)]);
+}
+
+// overflow.db has a page size of 512 with 8 reserved bytes per page, and rows
+// whose body/data columns are far too big to fit on a single page
+#[test]
+fn test_select_overflowing_rows() {
+    let file_path = String::from("tests/overflow.db");
+    let query = String::from("SELECT id, body, data FROM notes WHERE id IN (2, 39, 40)");
+
+    let (_, rows) = run_all(&file_path, &query);
+
+    let mut body = "x".repeat(3900);
+    body.push_str("39");
+
+    assert_eq!(
+        rows.iter()
+            .map(|row| row.values.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            vec![
+                Value::Integer(2),
+                Value::Text(String::from("short 2")),
+                Value::Null
+            ],
+            vec![Value::Integer(39), Value::Text(body), Value::Null],
+            vec![
+                Value::Integer(40),
+                Value::Text(String::from("short 40")),
+                Value::Blob(vec![b'b'; 2000])
+            ],
+        ]
+    );
+
+    let (_, rows) = run_all(&file_path, &String::from("SELECT * FROM notes"));
+    assert_eq!(rows.len(), 40);
+}
+
+// these WHERE clauses can be answered with a rowid lookup or an index seek
+// instead of a full scan. the results should be the same either way.
+#[test]
+fn test_select_with_index_lookups() {
+    let file_path = String::from("tests/chinook.db");
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT AlbumId FROM albums WHERE ArtistId = 90"),
+    );
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        (94..=114).collect::<Vec<_>>()
+    );
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT TrackId FROM tracks WHERE AlbumId BETWEEN 10 AND 11"),
+    );
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        (85..=110).collect::<Vec<_>>()
+    );
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT Name FROM tracks WHERE rowid = 3000.0"),
+    );
+    assert_eq!(
+        rows[0].values,
+        vec![Value::Text(String::from("God Part II"))]
+    );
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT Name FROM tracks WHERE TrackId = 3504 OR GenreId < 3"),
+    );
+    assert_eq!(rows.len(), 1427);
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT Name FROM tracks WHERE GenreId < 3"),
+    );
+    assert_eq!(rows.len(), 1427);
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT Name FROM tracks WHERE AlbumId = NULL"),
+    );
+    assert_eq!(rows.len(), 0);
+
+    let (_, rows) = run_all(
+        &String::from("tests/overflow.db"),
+        &String::from("SELECT id FROM notes WHERE body = 'short 4'"),
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values, vec![Value::Integer(4)]);
+}
+
+#[test]
+fn test_select_rowid_ranges() {
+    let file_path = String::from("tests/chinook.db");
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT TrackId FROM tracks WHERE rowid > 3400.5 AND TrackId < 3450"),
+    );
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        (3401..3450).collect::<Vec<_>>()
+    );
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT TrackId FROM tracks WHERE rowid >= 3500"),
+    );
+    assert_eq!(rows.len(), 4);
+
+    let (_, rows) = run_all(
+        &file_path,
+        &String::from("SELECT TrackId FROM tracks WHERE rowid < 'abc'"),
+    );
+    assert_eq!(rows.len(), 3503);
+}
+
+// rows come out one at a time, so asking for the first few of a big table
+// doesn't read the rest
+#[test]
+fn test_rows_are_streamed() {
+    let (column_names, rows) = run("tests/chinook.db", "SELECT Name FROM tracks").unwrap();
+
+    assert_eq!(column_names, vec![String::from("Name")]);
+
+    let first: Vec<Row> = rows.take(2).map(|row| row.unwrap()).collect();
+    assert_eq!(
+        first.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        first[0].values,
+        vec![Value::Text(String::from(
+            "For Those About To Rock (We Salute You)"
+        ))]
+    );
+}
+
+// one connection can prepare many statements, and each statement can be run
+// more than once
+#[test]
+fn test_prepared_statements() {
+    let connection = Connection::open("tests/chinook.db").unwrap();
+
+    let statement = connection
+        .prepare("SELECT AlbumId, Title AS name FROM albums WHERE ArtistId = 1")
+        .unwrap();
+    assert_eq!(statement.column_count(), 2);
+    assert_eq!(statement.column_name(1), Some("name"));
+    assert_eq!(statement.column_name(2), None);
+
+    for _ in 0..2 {
+        let rows = statement
+            .query()
+            .unwrap()
+            .collect::<sqlite::Result<Vec<Row>>>()
+            .unwrap();
+        assert_eq!(
+            rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+    }
+    assert_eq!(statement.execute().unwrap(), 2);
+
+    let statement = connection.prepare("SELECT * FROM artists").unwrap();
+    assert_eq!(statement.column_names(), ["ArtistId", "Name"]);
+    assert_eq!(statement.execute().unwrap(), 275);
+
+    // mistakes in the query are reported when it's prepared
+    assert!(matches!(
+        connection.prepare("SELECT Nope FROM artists"),
+        Err(Error::NoSuchColumn(_))
+    ));
+}
+
+#[test]
+fn test_bound_parameters() {
+    let connection = Connection::open("tests/chinook.db").unwrap();
+    let ids = |statement: &sqlite::Statement| {
+        statement
+            .query()
+            .unwrap()
+            .map(|row| row.unwrap().rowid)
+            .collect::<Vec<_>>()
+    };
+
+    // positional, reused with different values
+    let mut statement = connection
+        .prepare("SELECT Title FROM albums WHERE ArtistId = ? AND AlbumId > ?")
+        .unwrap();
+    assert_eq!(statement.parameter_count(), 2);
+    statement.bind(1, 1).unwrap();
+    statement.bind(2, 0).unwrap();
+    assert_eq!(ids(&statement), vec![1, 4]);
+    statement.bind(2, 1).unwrap();
+    assert_eq!(ids(&statement), vec![4]);
+    statement.bind(1, 8).unwrap();
+    statement.bind(2, Value::Null).unwrap();
+    assert_eq!(ids(&statement), Vec::<i64>::new());
+
+    // named, including a name used twice and a rowid lookup
+    let mut statement = connection
+        .prepare("SELECT Name FROM artists WHERE rowid = :id OR (Name = $name AND :id IS NULL)")
+        .unwrap();
+    assert_eq!(statement.parameter_count(), 2);
+    assert_eq!(statement.parameter_name(2), Some("$name"));
+    statement.bind_named(":id", 3).unwrap();
+    statement.bind_named("$name", "Aerosmith").unwrap();
+    assert_eq!(ids(&statement), vec![3]);
+    statement.bind_named(":id", None::<i64>).unwrap();
+    assert_eq!(ids(&statement), vec![3]);
+
+    // a bound value is converted with the column's affinity like a literal,
+    // so text that looks like a number finds it, through the rowid or an index
+    let mut statement = connection
+        .prepare("SELECT Title FROM albums WHERE AlbumId = ?")
+        .unwrap();
+    statement.bind(1, "5").unwrap();
+    assert_eq!(ids(&statement), vec![5]);
+    let mut statement = connection
+        .prepare("SELECT Title FROM albums WHERE ArtistId = ? AND AlbumId < ?")
+        .unwrap();
+    statement.bind(1, "90").unwrap();
+    statement.bind(2, " 96 ").unwrap();
+    assert_eq!(ids(&statement), vec![94, 95]);
+
+    // text can't be mistaken for sql
+    let mut statement = connection
+        .prepare("SELECT * FROM artists WHERE Name = ?1 OR Name = ?1")
+        .unwrap();
+    statement.bind(1, "x' OR 1 = 1 --").unwrap();
+    assert_eq!(ids(&statement), Vec::<i64>::new());
+
+    // mistakes
+    assert!(matches!(statement.bind(2, 1), Err(Error::Parameter(_))));
+    assert!(matches!(statement.bind(0, 1), Err(Error::Parameter(_))));
+    assert!(matches!(
+        statement.bind_named(":nope", 1),
+        Err(Error::Parameter(_))
+    ));
+    statement.clear_bindings();
+    match statement.query() {
+        Err(e) => assert_eq!(e.to_string(), "parameter ?1 is not bound"),
+        Ok(_) => panic!("ran with an unbound parameter"),
+    }
+}
+
+fn values(rows: &[Row], column: usize) -> Vec<Value> {
+    rows.iter().map(|row| row.values[column].clone()).collect()
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(String::from(s))
+}
+
+#[test]
+fn test_order_by() {
+    let file_path = "tests/chinook.db";
+
+    // several keys, each with its own direction
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT AlbumId, Title FROM albums WHERE ArtistId IN (50, 58) ORDER BY ArtistId DESC, 2",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        [
+            58, 59, 60, 61, 43, 62, 63, 64, 65, 66, 50, 156, 148, 35, 149, 150, 151, 152, 153, 154,
+            155
+        ]
+        .into_iter()
+        .map(Value::Integer)
+        .collect::<Vec<_>>()
+    );
+
+    // NULLs sort first by default, and can be moved
+    let query = "SELECT Composer FROM tracks WHERE AlbumId = 85 ORDER BY Composer";
+    let (_, rows) = run_all(file_path, query);
+    assert_eq!(rows.first().unwrap().values[0], Value::Null);
+    let (_, rows) = run_all(file_path, &format!("{} NULLS LAST", query));
+    assert_eq!(rows.last().unwrap().values[0], Value::Null);
+    let (_, rows) = run_all(file_path, &format!("{} DESC", query));
+    assert_eq!(rows.last().unwrap().values[0], Value::Null);
+
+    // collations, and sorting by an alias. BINARY puts "AC/DC" before
+    // "Aaron", NOCASE puts it after.
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT ArtistId, Name AS n FROM artists \
+         WHERE Name COLLATE NOCASE BETWEEN 'a' AND 'aerosmith' ORDER BY n COLLATE NOCASE DESC",
+    );
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        vec![3, 260, 2, 239, 257, 222, 215, 214, 1, 202, 230, 43]
+    );
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT Name FROM artists WHERE ArtistId IN (1, 202) ORDER BY 1",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        vec![text("AC/DC"), text("Aaron Goldberg")]
+    );
+
+    // reading the table backwards instead of sorting
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT TrackId FROM tracks WHERE TrackId > 3500 ORDER BY TrackId DESC",
+    );
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        vec![3503, 3502, 3501]
+    );
+
+    match run(file_path, "SELECT Name FROM artists ORDER BY 2") {
+        Err(e) => assert_eq!(
+            e.to_string(),
+            "1st ORDER BY term out of range - should be between 1 and 1"
+        ),
+        Ok(_) => panic!("sorted by a column that doesn't exist"),
+    }
+}
+
+// sorting more rows than fit in memory spills them to temp files, which
+// mustn't change the result
+#[test]
+fn test_order_by_spills_to_disk() {
+    let query = "SELECT TrackId, Name, Composer FROM tracks ORDER BY Composer DESC, Name, TrackId";
+
+    let mut connection = Connection::open("tests/chinook.db").unwrap();
+    let in_memory = connection
+        .prepare(query)
+        .unwrap()
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+
+    connection.set_sort_memory(10_000);
+    let spilled = connection
+        .prepare(query)
+        .unwrap()
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+
+    assert_eq!(in_memory.len(), 3503);
+    assert_eq!(in_memory, spilled);
+    assert_eq!(in_memory[0].values[2], text("roger glover"));
+    assert_eq!(in_memory[3502].values[2], Value::Null);
+}
+
+#[test]
+fn test_limit_and_offset() {
+    let file_path = "tests/chinook.db";
+
+    let (_, rows) = run_all(file_path, "SELECT Name FROM artists LIMIT 3 OFFSET 2");
+    assert_eq!(
+        rows.iter().map(|row| row.rowid).collect::<Vec<_>>(),
+        vec![3, 4, 5]
+    );
+
+    // LIMIT <offset>, <limit>, after a sort
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT Name FROM artists ORDER BY Name DESC LIMIT 1, 2",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        vec![text("Youssou N'Dour"), text("Yo-Yo Ma")]
+    );
+
+    // a negative limit is no limit
+    let (_, rows) = run_all(file_path, "SELECT Name FROM artists LIMIT -1 OFFSET 270");
+    assert_eq!(rows.len(), 5);
+
+    let connection = Connection::open(file_path).unwrap();
+    let mut statement = connection
+        .prepare("SELECT Name FROM artists LIMIT ?")
+        .unwrap();
+    statement.bind(1, "2").unwrap();
+    assert_eq!(statement.execute().unwrap(), 2);
+    statement.bind(1, 2.5).unwrap();
+    assert!(matches!(statement.query(), Err(Error::Invalid(_))));
+}
+
+// the rows a LIMIT doesn't need are never read: with the page holding the last
+// track broken, reading every track fails but reading the first few works
+#[test]
+fn test_limit_stops_reading_early() {
+    let mut data = std::fs::read("tests/chinook.db").unwrap();
+    let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+
+    // the name of the last track, and also of an album, which comes first
+    let name = b"Koyaanisqatsi";
+    let offset = (0..data.len() - name.len())
+        .rev()
+        .find(|&i| &data[i..i + name.len()] == name)
+        .unwrap();
+    let page_start = offset / page_size * page_size;
+    data[page_start..page_start + page_size].fill(0);
+
+    let file_path = std::env::temp_dir().join(format!("limit-{}.db", std::process::id()));
+    std::fs::write(&file_path, data).unwrap();
+    let file_path = file_path.to_str().unwrap();
+
+    let (_, rows) = run_all(file_path, "SELECT Name FROM tracks LIMIT 5");
+    assert_eq!(rows.len(), 5);
+
+    let (_, rows) = run(file_path, "SELECT Name FROM tracks").unwrap();
+    let result = rows.collect::<sqlite::Result<Vec<Row>>>();
+    assert!(matches!(result, Err(Error::CorruptPage { .. })));
+
+    std::fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn test_aggregates() {
+    let file_path = "tests/chinook.db";
+
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT count(*), count(Composer), count(DISTINCT Composer), sum(Milliseconds), \
+         max(Name) FROM tracks",
+    );
+    assert_eq!(column_names[0], "count(*)");
+    assert_eq!(
+        rows[0].values,
+        vec![
+            Value::Integer(3503),
+            Value::Integer(2525),
+            Value::Integer(852),
+            Value::Integer(1378778040),
+            text("Último Pau-De-Arara"),
+        ]
+    );
+
+    // groups come out in GROUP BY order, and ORDER BY can use an aggregate's
+    // alias
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT ArtistId, count(*) c FROM albums GROUP BY 1 ORDER BY c DESC, 1 LIMIT 3",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        vec![Value::Integer(90), Value::Integer(22), Value::Integer(58)]
+    );
+    assert_eq!(
+        values(&rows, 1),
+        vec![Value::Integer(21), Value::Integer(14), Value::Integer(11)]
+    );
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT AlbumId, group_concat(TrackId) FROM tracks WHERE AlbumId < 3 GROUP BY AlbumId",
+    );
+    assert_eq!(
+        values(&rows, 1),
+        vec![text("1,6,7,8,9,10,11,12,13,14"), text("2")]
+    );
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT BillingCountry FROM invoices GROUP BY BillingCountry \
+         HAVING sum(Total) > 150 ORDER BY sum(Total) DESC",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        vec![
+            text("USA"),
+            text("Canada"),
+            text("France"),
+            text("Brazil"),
+            text("Germany")
+        ]
+    );
+
+    // a bare column comes from the row that set the max
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT Title, max(AlbumId) FROM albums WHERE ArtistId < 3 GROUP BY ArtistId",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        vec![text("Let There Be Rock"), text("Restless and Wild")]
+    );
+
+    // without GROUP BY there's always one row
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT count(*), sum(AlbumId), Title FROM albums WHERE 0",
+    );
+    assert_eq!(
+        rows[0].values,
+        vec![Value::Integer(0), Value::Null, Value::Null]
+    );
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT count(*) FROM albums WHERE 0 GROUP BY ArtistId",
+    );
+    assert!(rows.is_empty());
+
+    for (query, message) in [
+        (
+            "SELECT Title FROM albums WHERE count(*) > 1",
+            "misuse of aggregate: count()",
+        ),
+        (
+            "SELECT count(max(AlbumId)) FROM albums",
+            "misuse of aggregate function max()",
+        ),
+        (
+            "SELECT ArtistId FROM albums GROUP BY count(*)",
+            "aggregate functions are not allowed in the GROUP BY clause",
+        ),
+        (
+            "SELECT ArtistId FROM albums HAVING ArtistId > 1",
+            "HAVING clause on a non-aggregate query",
+        ),
+        (
+            "SELECT sum() FROM albums",
+            "wrong number of arguments to function sum()",
+        ),
+        (
+            "SELECT ArtistId FROM albums GROUP BY 2",
+            "1st GROUP BY term out of range - should be between 1 and 1",
+        ),
+        (
+            "SELECT nosuch(Title) FROM albums",
+            "no such function: nosuch",
+        ),
+    ] {
+        match run(file_path, query) {
+            Err(Error::Invalid(e)) => assert_eq!(e, message),
+            _ => panic!("expected an error from {}", query),
+        }
+    }
+}
+
+#[test]
+fn test_group_by_spills_to_disk() {
+    let query = "SELECT Composer, AlbumId, count(*), sum(Milliseconds), max(Name) \
+                 FROM tracks GROUP BY Composer, AlbumId";
+
+    let mut connection = Connection::open("tests/chinook.db").unwrap();
+    let in_memory = connection
+        .prepare(query)
+        .unwrap()
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+
+    connection.set_sort_memory(10_000);
+    let spilled = connection
+        .prepare(query)
+        .unwrap()
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+
+    assert_eq!(in_memory.len(), 1098);
+    assert_eq!(in_memory, spilled);
+    assert_eq!(
+        in_memory[1].values[..4],
+        [
+            Value::Null,
+            Value::Integer(8),
+            Value::Integer(14),
+            Value::Integer(2906926)
+        ]
+    );
+}
+
+// a corrupt file whose interior pages point back at themselves is reported
+// instead of being walked round and round forever
+#[test]
+fn test_looping_child_pointers_are_corrupt() {
+    let mut data = std::fs::read("tests/chinook.db").unwrap();
+    let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+
+    // the rightmost children of the roots of tracks and IFK_TrackAlbumId
+    for root in [20u32, 30] {
+        let page_start = (root as usize - 1) * page_size;
+        data[page_start + 8..page_start + 12].copy_from_slice(&root.to_be_bytes());
+    }
+
+    let file_path = std::env::temp_dir().join(format!("loop-{}.db", std::process::id()));
+    std::fs::write(&file_path, data).unwrap();
+    let file_path = file_path.to_str().unwrap();
+
+    for query in [
+        "SELECT * FROM tracks",
+        "SELECT * FROM tracks WHERE TrackId = 5000",
+        "SELECT TrackId FROM tracks ORDER BY TrackId DESC",
+        "SELECT TrackId FROM tracks WHERE AlbumId = 500",
+        "SELECT count(*) FROM tracks",
+    ] {
+        let result =
+            run(file_path, query).and_then(|(_, rows)| rows.collect::<sqlite::Result<Vec<Row>>>());
+        assert!(
+            matches!(result, Err(Error::CorruptPage { .. })),
+            "{}",
+            query
+        );
+    }
+
+    std::fs::remove_file(file_path).unwrap();
+}
+
+// counting rows only reads page headers: with every cell of one page broken,
+// reading the rows fails but counting them works
+#[test]
+fn test_count_does_not_decode_rows() {
+    let mut data = std::fs::read("tests/chinook.db").unwrap();
+    let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+
+    // the last artist, which only appears in the artists table
+    let name = b"Youssou N'Dour";
+    let offset = (0..data.len() - name.len())
+        .rev()
+        .find(|&i| &data[i..i + name.len()] == name)
+        .unwrap();
+    let page_start = offset / page_size * page_size;
+    let num_cells = u16::from_be_bytes([data[page_start + 3], data[page_start + 4]]) as usize;
+    data[page_start + 8 + num_cells * 2..page_start + page_size].fill(0xFF);
+
+    let file_path = std::env::temp_dir().join(format!("count-{}.db", std::process::id()));
+    std::fs::write(&file_path, data).unwrap();
+    let file_path = file_path.to_str().unwrap();
+
+    let (_, rows) = run_all(file_path, "SELECT count(*), count(*) * 2 FROM artists");
+    assert_eq!(
+        rows[0].values,
+        vec![Value::Integer(275), Value::Integer(550)]
+    );
+
+    let (_, rows) = run(file_path, "SELECT count(Name) FROM artists").unwrap();
+    let result = rows.collect::<sqlite::Result<Vec<Row>>>();
+    assert!(matches!(result, Err(Error::CorruptPage { .. })));
+
+    std::fs::remove_file(file_path).unwrap();
+
+    // counted from the smallest index
+    let (_, rows) = run_all("tests/chinook.db", "SELECT count(*) FROM tracks");
+    assert_eq!(rows[0].values, vec![Value::Integer(3503)]);
+
+    // a column outside of count(*) needs a row to come from
+    let (_, rows) = run_all("tests/chinook.db", "SELECT count(*), Title FROM albums");
+    assert_eq!(
+        rows[0].values,
+        vec![
+            Value::Integer(347),
+            text("For Those About To Rock We Salute You")
+        ]
+    );
+}
+
+#[test]
+fn test_joins() {
+    let file_path = "tests/chinook.db";
+
+    // artists is looked up by rowid for each album
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT a.Title, r.Name FROM albums a JOIN artists r ON r.ArtistId = a.ArtistId \
+         WHERE a.AlbumId IN (1, 4)",
+    );
+    assert_eq!(column_names, vec!["Title", "Name"]);
+    assert_eq!(
+        values(&rows, 0),
+        vec![
+            text("For Those About To Rock We Salute You"),
+            text("Let There Be Rock")
+        ]
+    );
+    assert_eq!(values(&rows, 1), vec![text("AC/DC"), text("AC/DC")]);
+    assert_eq!(rows.iter().map(|row| row.rowid).collect::<Vec<_>>(), [1, 4]);
+
+    // tracks is found through its AlbumId index
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT count(*) FROM tracks t, albums a WHERE t.AlbumId = a.AlbumId AND a.ArtistId = 1",
+    );
+    assert_eq!(rows[0].values, vec![Value::Integer(18)]);
+
+    // nothing indexes Composer, so this one is a hash join
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT count(*) FROM tracks a JOIN tracks b ON a.Composer = b.Composer \
+         WHERE a.TrackId < 10",
+    );
+    assert_eq!(rows[0].values, vec![Value::Integer(53)]);
+
+    // artists without albums still come through, with NULLs
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT r.Name, a.Title FROM artists r LEFT JOIN albums a ON a.ArtistId = r.ArtistId \
+         WHERE r.ArtistId BETWEEN 24 AND 26",
+    );
+    assert_eq!(
+        values(&rows, 1),
+        vec![text("Chill: Brazil (Disc 1)"), Value::Null, Value::Null]
+    );
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT e.LastName, m.LastName FROM employees e \
+         LEFT JOIN employees m ON e.ReportsTo = m.EmployeeId ORDER BY e.EmployeeId LIMIT 3",
+    );
+    assert_eq!(
+        values(&rows, 1),
+        vec![Value::Null, text("Adams"), text("Edwards")]
+    );
+}
+
+#[test]
+fn test_using_and_natural_joins() {
+    let file_path = "tests/chinook.db";
+
+    // the column they're joined on only appears once in *
+    for query in [
+        "SELECT * FROM albums JOIN artists USING (ArtistId) WHERE AlbumId = 1",
+        "SELECT * FROM albums NATURAL JOIN artists WHERE AlbumId = 1",
+    ] {
+        let (column_names, rows) = run_all(file_path, query);
+        assert_eq!(column_names, vec!["AlbumId", "Title", "ArtistId", "Name"]);
+        assert_eq!(rows[0].values[3], text("AC/DC"));
+    }
+
+    // but it's still there in table.*
+    let (column_names, _) = run_all(
+        file_path,
+        "SELECT artists.*, albums.* FROM albums NATURAL JOIN artists WHERE AlbumId = 1",
+    );
+    assert_eq!(
+        column_names,
+        vec!["ArtistId", "Name", "AlbumId", "Title", "ArtistId"]
+    );
+
+    let error = |query| match run(file_path, query) {
+        Err(Error::Invalid(message)) => message,
+        result => panic!(
+            "expected an error, got {:?}",
+            result.map(|(names, _)| names)
+        ),
+    };
+    assert_eq!(
+        error("SELECT ArtistId FROM albums, artists"),
+        "ambiguous column name: ArtistId"
+    );
+    assert_eq!(
+        error("SELECT * FROM albums JOIN artists USING (Title)"),
+        "cannot join using column Title - column not present in both tables"
+    );
+    assert_eq!(
+        error("SELECT * FROM albums a LEFT JOIN artists r ON r.ArtistId = t.TrackId, tracks t"),
+        "ON clause references tables to its right"
+    );
+}
+
+#[test]
+fn test_subqueries() {
+    let file_path = "tests/chinook.db";
+
+    // a correlated subquery is run again for each artist
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT Name, (SELECT count(*) FROM albums WHERE albums.ArtistId = artists.ArtistId) n \
+         FROM artists ORDER BY n DESC, Name LIMIT 3",
+    );
+    assert_eq!(column_names, vec!["Name", "n"]);
+    assert_eq!(
+        values(&rows, 0),
+        vec![
+            text("Iron Maiden"),
+            text("Led Zeppelin"),
+            text("Deep Purple")
+        ]
+    );
+    assert_eq!(
+        values(&rows, 1),
+        vec![Value::Integer(21), Value::Integer(14), Value::Integer(11)]
+    );
+
+    let count = |query| run_all(file_path, query).1[0].values[0].clone();
+    assert_eq!(
+        count(
+            "SELECT count(*) FROM artists a \
+             WHERE NOT EXISTS (SELECT 1 FROM albums WHERE albums.ArtistId = a.ArtistId)"
+        ),
+        Value::Integer(71)
+    );
+    assert_eq!(
+        count(
+            "SELECT count(*) FROM tracks WHERE Milliseconds > (SELECT avg(Milliseconds) FROM tracks)"
+        ),
+        Value::Integer(494)
+    );
+    assert_eq!(
+        count(
+            "SELECT count(*) FROM albums \
+             WHERE ArtistId IN (SELECT ArtistId FROM artists WHERE Name >= 'A' AND Name < 'B')"
+        ),
+        Value::Integer(27)
+    );
+
+    // IN treats NULLs the way it does with a list, and an empty scalar
+    // subquery is NULL
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT 'x' NOT IN (SELECT Composer FROM tracks), 'AC/DC' IN (SELECT Composer FROM tracks), \
+         (SELECT Name FROM artists WHERE 0)",
+    );
+    assert_eq!(
+        rows[0].values,
+        vec![Value::Null, Value::Integer(1), Value::Null]
+    );
+
+    // a subquery in FROM is joined like a table
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT ar.Name, t.n FROM (SELECT ArtistId, count(*) n FROM albums GROUP BY ArtistId) t \
+         JOIN artists ar USING (ArtistId) ORDER BY t.n DESC LIMIT 1",
+    );
+    assert_eq!(column_names, vec!["Name", "n"]);
+    assert_eq!(
+        rows[0].values,
+        vec![text("Iron Maiden"), Value::Integer(21)]
+    );
+
+    let error = |query| match run(file_path, query) {
+        Err(error) => error.to_string(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(
+        error("SELECT (SELECT AlbumId, Title FROM albums)"),
+        "sub-select returns 2 columns - expected 1"
+    );
+    assert_eq!(
+        error("SELECT rowid FROM (SELECT 1)"),
+        "no such column: rowid"
+    );
+}
+
+#[test]
+fn test_common_table_expressions() {
+    let file_path = "tests/chinook.db";
+
+    // a table can be read by the tables after it, and more than once
+    let (column_names, rows) = run_all(
+        file_path,
+        "WITH counts AS (SELECT ArtistId, count(*) n FROM albums GROUP BY ArtistId), \
+              top AS (SELECT * FROM counts WHERE n > 10) \
+         SELECT ar.Name, top.n, (SELECT count(*) FROM counts) FROM top \
+         JOIN artists ar USING (ArtistId) ORDER BY top.n DESC",
+    );
+    assert_eq!(column_names[..2], ["Name", "n"]);
+    assert_eq!(
+        values(&rows, 0),
+        vec![
+            text("Iron Maiden"),
+            text("Led Zeppelin"),
+            text("Deep Purple")
+        ]
+    );
+    assert_eq!(values(&rows, 2), vec![Value::Integer(204); 3]);
+
+    // everyone who reports to Adams, breadth first
+    let (column_names, rows) = run_all(
+        file_path,
+        "WITH RECURSIVE chain(id, name, depth) AS ( \
+             SELECT EmployeeId, LastName, 0 FROM employees WHERE ReportsTo IS NULL \
+             UNION ALL \
+             SELECT e.EmployeeId, e.LastName, c.depth + 1 FROM chain c \
+             JOIN employees e ON e.ReportsTo = c.id \
+         ) SELECT name, depth FROM chain",
+    );
+    assert_eq!(column_names, vec!["name", "depth"]);
+    assert_eq!(
+        values(&rows, 0),
+        [
+            "Adams", "Edwards", "Mitchell", "Peacock", "Park", "Johnson", "King", "Callahan"
+        ]
+        .map(text)
+    );
+    assert_eq!(
+        values(&rows, 1),
+        [0, 1, 1, 2, 2, 2, 2, 2].map(Value::Integer)
+    );
+
+    // a table that never ends is stopped by a LIMIT, its own or the query's,
+    // and UNION drops rows that have already been seen
+    let count = |query| run_all(file_path, query).1.len();
+    assert_eq!(
+        count(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n LIMIT 10) SELECT * FROM n"
+        ),
+        10
+    );
+    assert_eq!(
+        count("WITH n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT * FROM n LIMIT 7"),
+        7
+    );
+    assert_eq!(
+        count("WITH n(i) AS (SELECT 1 UNION SELECT i % 3 + 1 FROM n) SELECT * FROM n"),
+        3
+    );
+
+    let error = |query| match run(file_path, query) {
+        Err(error) => error.to_string(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(
+        error("WITH t(x) AS (SELECT x FROM t) SELECT * FROM t"),
+        "circular reference: t"
+    );
+    assert_eq!(
+        error("WITH t(x) AS (SELECT 1 UNION ALL SELECT t.x FROM t, t u) SELECT * FROM t"),
+        "multiple references to recursive table: t"
+    );
+    assert_eq!(
+        error("WITH t(x, y) AS (SELECT 1) SELECT * FROM t"),
+        "table t has 1 values for 2 columns"
+    );
+}
+
+#[test]
+fn test_compound_selects() {
+    let file_path = "tests/chinook.db";
+
+    // UNION drops duplicates, which leaves its rows in order. ORDER BY and
+    // LIMIT are for the whole result.
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT Country FROM customers UNION SELECT BillingCountry FROM invoices \
+         ORDER BY 1 DESC LIMIT 3",
+    );
+    assert_eq!(column_names, vec!["Country"]);
+    assert_eq!(
+        values(&rows, 0),
+        ["United Kingdom", "USA", "Sweden"].map(text)
+    );
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT FirstName FROM customers INTERSECT SELECT FirstName FROM employees",
+    );
+    assert_eq!(values(&rows, 0), ["Robert", "Steve"].map(text));
+
+    let count = |query| run_all(file_path, query).1.len();
+    assert_eq!(
+        count("SELECT Country FROM customers UNION ALL SELECT BillingCountry FROM invoices"),
+        471
+    );
+    assert_eq!(
+        count("SELECT ArtistId FROM artists EXCEPT SELECT ArtistId FROM albums"),
+        71
+    );
+
+    // 1 and 1.0 are the same value, and the last one seen is kept
+    let (_, rows) = run_all(file_path, "SELECT 1 UNION SELECT 1.0");
+    assert!(matches!(rows[0].values[..], [Value::Float(1.0)]));
+
+    let error = |query| match run(file_path, query) {
+        Err(error) => error.to_string(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(
+        error("SELECT Name FROM artists UNION SELECT AlbumId, Title FROM albums"),
+        "SELECTs to the left and right of UNION do not have the same number of result columns"
+    );
+    assert_eq!(
+        error("SELECT Name FROM artists UNION SELECT Title FROM albums ORDER BY ArtistId"),
+        "1st ORDER BY term does not match any column in the result set"
+    );
+}
+
+#[test]
+fn test_select_distinct() {
+    let file_path = "tests/chinook.db";
+
+    // the first of each row is kept, in the order they're read
+    let (column_names, rows) = run_all(file_path, "SELECT DISTINCT Country FROM customers");
+    assert_eq!(column_names, vec!["Country"]);
+    assert_eq!(rows.len(), 24);
+    assert_eq!(
+        values(&rows[..3], 0),
+        ["Brazil", "Germany", "Canada"].map(text)
+    );
+
+    let count = |query| run_all(file_path, query).1.len();
+    assert_eq!(
+        count("SELECT DISTINCT AlbumId, MediaTypeId FROM tracks"),
+        348
+    );
+
+    // read through an index, equal rows come one after the other
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT DISTINCT ArtistId FROM albums WHERE ArtistId BETWEEN 50 AND 52",
+    );
+    assert_eq!(values(&rows, 0), [50, 51, 52].map(Value::Integer));
+
+    // 1 and 1.0 are the same value, and so are NULLs. text never equals a
+    // number or a blob.
+    assert_eq!(
+        count(
+            "SELECT DISTINCT * FROM (SELECT 1 UNION ALL SELECT 1.0 UNION ALL SELECT NULL \
+             UNION ALL SELECT NULL UNION ALL SELECT '1' UNION ALL SELECT x'31')"
+        ),
+        4
+    );
+
+    // text is compared with the column's collation
+    assert_eq!(
+        count("SELECT DISTINCT x COLLATE NOCASE FROM (SELECT 'a' x UNION ALL SELECT 'A')"),
+        1
+    );
+}
+
+#[test]
+fn test_window_functions() {
+    let file_path = "tests/chinook.db";
+
+    // without a frame, a window ends at the last row that ties with the
+    // current one
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT Total, rank() OVER w, ntile(3) OVER w, sum(Total) OVER w FROM invoices \
+         WHERE CustomerId = 3 WINDOW w AS (ORDER BY Total)",
+    );
+    assert_eq!(
+        column_names,
+        vec![
+            "Total",
+            "rank() OVER w",
+            "ntile(3) OVER w",
+            "sum(Total) OVER w"
+        ]
+    );
+    assert_eq!(values(&rows, 1), [1, 2, 3, 4, 5, 6, 7].map(Value::Integer));
+    assert_eq!(values(&rows, 2), [1, 1, 1, 2, 2, 3, 3].map(Value::Integer));
+    assert!(matches!(rows[6].values[3], Value::Float(total) if (total - 39.62).abs() < 1e-9));
+
+    // windows are worked out after grouping, and can use aggregates
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT BillingCountry, count(*), rank() OVER (ORDER BY count(*) DESC), \
+         dense_rank() OVER (ORDER BY count(*) DESC) FROM invoices GROUP BY BillingCountry \
+         ORDER BY 3, 1 LIMIT 5",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        ["USA", "Canada", "Brazil", "France", "Germany"].map(text)
+    );
+    assert_eq!(values(&rows, 2), [1, 2, 3, 3, 5].map(Value::Integer));
+    assert_eq!(values(&rows, 3), [1, 2, 3, 3, 4].map(Value::Integer));
+
+    // the longest track of each album
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT AlbumId, Name FROM (SELECT AlbumId, Name, row_number() OVER \
+         (PARTITION BY AlbumId ORDER BY Milliseconds DESC) AS rn FROM tracks) \
+         WHERE rn = 1 LIMIT 2",
+    );
+    assert_eq!(
+        values(&rows, 1),
+        [
+            "For Those About To Rock (We Salute You)",
+            "Balls to the Wall"
+        ]
+        .map(text)
+    );
+
+    // frames, and functions that look at other rows of the partition
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT InvoiceId, count(*) OVER (ORDER BY InvoiceId ROWS BETWEEN 1 PRECEDING AND \
+         1 FOLLOWING), lag(InvoiceId) OVER (ORDER BY InvoiceId), lead(InvoiceId, 2, 0) OVER \
+         (ORDER BY InvoiceId), last_value(InvoiceId) OVER (ORDER BY InvoiceId GROUPS BETWEEN \
+         CURRENT ROW AND UNBOUNDED FOLLOWING) FROM invoices WHERE CustomerId = 2",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        [1, 12, 67, 196, 219, 241, 293].map(Value::Integer)
+    );
+    assert_eq!(values(&rows, 1), [2, 3, 3, 3, 3, 3, 2].map(Value::Integer));
+    assert_eq!(
+        values(&rows[..3], 2),
+        [Value::Null, Value::Integer(1), Value::Integer(12)]
+    );
+    assert_eq!(values(&rows[4..], 3), [293, 0, 0].map(Value::Integer));
+    assert_eq!(values(&rows[..1], 4), [Value::Integer(293)]);
+
+    // partitions and the order are compared with the column's NOCASE
+    let (_, rows) = run_all(
+        "tests/collate.db",
+        "SELECT n, row_number() OVER (PARTITION BY n ORDER BY id), \
+         count(*) OVER (ORDER BY n RANGE CURRENT ROW), rank() OVER (ORDER BY n), \
+         max(n) OVER () FROM words WHERE id IN (1, 2, 7) ORDER BY id",
+    );
+    assert_eq!(values(&rows, 0), [text("ABC"), text("Abc"), text("abc")]);
+    assert_eq!(values(&rows, 1), [1, 2, 3].map(Value::Integer));
+    assert_eq!(values(&rows, 2), [3, 3, 3].map(Value::Integer));
+    assert_eq!(values(&rows, 3), [1, 1, 1].map(Value::Integer));
+    assert_eq!(values(&rows, 4), [text("ABC"), text("ABC"), text("ABC")]);
+
+    let error = |query| match run(file_path, query) {
+        Err(error) => error.to_string(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(
+        error("SELECT InvoiceId FROM invoices WHERE row_number() OVER () > 1"),
+        "misuse of window function row_number()"
+    );
+    assert_eq!(
+        error("SELECT sum(Total) OVER w FROM invoices"),
+        "no such window: w"
+    );
+    assert_eq!(
+        error("SELECT sum(Total) OVER (ROWS 1 FOLLOWING) FROM invoices"),
+        "unsupported frame specification"
+    );
+}
+
+#[test]
+fn test_scalar_functions() {
+    let file_path = "tests/chinook.db";
+
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT upper(Name), length(Name), substr(Name, -4), printf('%-5.2s|%03d', Name, ArtistId) \
+         FROM artists WHERE ArtistId = 1",
+    );
+    assert_eq!(
+        column_names,
+        vec![
+            "upper(Name)",
+            "length(Name)",
+            "substr(Name, -4)",
+            "printf('%-5.2s|%03d', Name, ArtistId)"
+        ]
+    );
+    assert_eq!(
+        rows[0].values,
+        [
+            text("AC/DC"),
+            Value::Integer(5),
+            text("C/DC"),
+            text("AC   |001")
+        ]
+    );
+
+    // functions can be used anywhere an expression can, and around aggregates
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT round(avg(Total), 2), typeof(sum(Total)), coalesce(NULL, max(Total, 20)) \
+         FROM invoices WHERE abs(Total - 14) < 1",
+    );
+    assert_eq!(
+        rows[0].values,
+        [Value::Float(13.88), text("real"), Value::Integer(20)]
+    );
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT ifnull(Composer, 'unknown'), iif(Milliseconds > 300000, 'long', 'short'), \
+         nullif(GenreId, 1), quote(Name), hex(substr(Name, 1, 2)) FROM tracks WHERE TrackId = 63",
+    );
+    assert_eq!(
+        rows[0].values,
+        [
+            text("unknown"),
+            text("short"),
+            Value::Integer(2),
+            text("'Desafinado'"),
+            text("4465")
+        ]
+    );
+
+    let error = |query| match run(file_path, query) {
+        Err(error) => error.to_string(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(
+        error("SELECT no_such_function(1)"),
+        "no such function: no_such_function"
+    );
+    assert_eq!(
+        error("SELECT substr('abc')"),
+        "wrong number of arguments to function substr()"
+    );
+    assert_eq!(
+        error("SELECT abs(Total) OVER () FROM invoices"),
+        "abs() may not be used as a window function"
+    );
+
+    // the smallest integer is a literal too, which abs() can't make positive
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT typeof(-9223372036854775808), printf('%'), round(0.49999999999999994)",
+    );
+    assert_eq!(
+        rows[0].values,
+        [text("integer"), text("%"), Value::Float(1.0)]
+    );
+    let result = run(file_path, "SELECT abs(-9223372036854775808)")
+        .and_then(|(_, rows)| rows.collect::<sqlite::Result<Vec<Row>>>());
+    assert_eq!(result.unwrap_err().to_string(), "integer overflow");
+
+    // nullif, min and max compare with the collation a column was declared
+    // with, like a comparison would
+    let (_, rows) = run_all(
+        "tests/collate.db",
+        "SELECT nullif(n, 'abc'), max(n, 'abd'), min(n COLLATE BINARY, 'abd') \
+         FROM words WHERE id = 1",
+    );
+    assert_eq!(rows[0].values, [Value::Null, text("abd"), text("ABC")]);
+    let (_, rows) = run_all(
+        "tests/collate.db",
+        "SELECT nullif(n, 'ABC') IS NULL, count(*) FROM words GROUP BY 1",
+    );
+    assert_eq!(values(&rows, 1), [429, 571].map(Value::Integer));
+}
+
+#[test]
+fn test_date_time_functions() {
+    let file_path = "tests/chinook.db";
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT strftime('%Y-%m', InvoiceDate) AS month, count(*), round(sum(Total), 2) \
+         FROM invoices WHERE InvoiceDate < '2009-04-01' GROUP BY month",
+    );
+    assert_eq!(
+        values(&rows, 0),
+        [text("2009-01"), text("2009-02"), text("2009-03")]
+    );
+    assert_eq!(values(&rows, 1), [6, 7, 7].map(Value::Integer));
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT timediff(HireDate, BirthDate), date(HireDate, 'weekday 1'), \
+         date(HireDate, 'start of month', '+1 month', '-1 day'), unixepoch(HireDate) \
+         FROM employees WHERE EmployeeId = 1",
+    );
+    assert_eq!(
+        rows[0].values,
+        [
+            text("+0040-05-27 00:00:00.000"),
+            text("2002-08-19"),
+            text("2002-08-31"),
+            Value::Integer(1029283200)
+        ]
+    );
+
+    // 'now' and 'localtime' read the connection's clock, which a test can fix
+    let mut connection = Connection::open(file_path).unwrap();
+    connection.set_clock(Clock {
+        now: Some(1_709_642_096_789),
+        utc_offset: Some(-5 * 3600),
+    });
+    let statement = connection
+        .prepare(
+            "SELECT datetime('now'), datetime('now', 'localtime'), \
+             datetime('2024-03-05 07:00', 'utc'), strftime('%s', 'now', 'subsec')",
+        )
+        .unwrap();
+    let rows = statement
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+    assert_eq!(
+        rows[0].values,
+        [
+            text("2024-03-05 12:34:56"),
+            text("2024-03-05 07:34:56"),
+            text("2024-03-05 12:00:00"),
+            text("1709642096.789")
+        ]
+    );
+
+    // anything that isn't a time is NULL rather than an error
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT date('yesterday'), date('2024-03-05', '+1 fortnight'), strftime('%Q', 'now')",
+    );
+    assert_eq!(rows[0].values, [Value::Null, Value::Null, Value::Null]);
+}
+
+#[test]
+fn test_json_functions() {
+    let file_path = "tests/chinook.db";
+
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT json_group_array(json_object('id', AlbumId, 'title', Title)) \
+         FROM albums WHERE ArtistId = 1",
+    );
+    assert_eq!(
+        rows[0].values,
+        [text(
+            "[{\"id\":1,\"title\":\"For Those About To Rock We Salute You\"},\
+             {\"id\":4,\"title\":\"Let There Be Rock\"}]"
+        )]
+    );
+
+    // json_each's arguments can refer to the tables before it
+    let (_, rows) = run_all(
+        file_path,
+        "SELECT a.Name, j.value ->> 'title' FROM artists a JOIN json_each((\
+         SELECT json_group_array(json_object('id', AlbumId, 'title', Title)) \
+         FROM albums WHERE ArtistId = a.ArtistId)) j \
+         WHERE a.ArtistId < 4 AND j.value ->> 'id' > 3",
+    );
+    assert_eq!(values(&rows, 0), [text("AC/DC"), text("Aerosmith")]);
+    assert_eq!(
+        values(&rows, 1),
+        [text("Let There Be Rock"), text("Big Ones")]
+    );
+
+    let (column_names, rows) = run_all(
+        file_path,
+        "SELECT * FROM json_tree('{\"name\":\"AC/DC\",\"albums\":[1,4]}')",
+    );
+    assert_eq!(
+        column_names,
+        [
+            "key", "value", "type", "atom", "id", "parent", "fullkey", "path"
+        ]
+    );
+    assert_eq!(
+        values(&rows, 6),
+        ["$", "$.name", "$.albums", "$.albums[0]", "$.albums[1]"].map(text)
+    );
+    assert_eq!(
+        rows[3].values,
+        [
+            Value::Integer(0),
+            Value::Integer(1),
+            text("integer"),
+            Value::Integer(1),
+            Value::Integer(21),
+            Value::Integer(13),
+            text("$.albums[0]"),
+            text("$.albums"),
+        ]
+    );
+
+    let connection = Connection::open(file_path).unwrap();
+    let mut statement = connection
+        .prepare(
+            "SELECT json_extract(?1, '$.a[1]'), ?1 -> 'a', ?1 ->> '$.b', \
+             json_set(?1, '$.a[#]', 3, '$.c', json('{}')), json_remove(?1, '$.a')",
+        )
+        .unwrap();
+    statement.bind(1, "{\"a\":[1,2],\"b\":\"x\"}").unwrap();
+    let rows = statement
+        .query()
+        .unwrap()
+        .collect::<sqlite::Result<Vec<Row>>>()
+        .unwrap();
+    assert_eq!(
+        rows[0].values,
+        [
+            Value::Integer(2),
+            text("[1,2]"),
+            text("x"),
+            text("{\"a\":[1,2,3],\"b\":\"x\",\"c\":{}}"),
+            text("{\"b\":\"x\"}"),
+        ]
+    );
+
+    let error = |query| {
+        let (_, rows) = run(file_path, query).unwrap();
+        match rows.collect::<sqlite::Result<Vec<Row>>>() {
+            Err(Error::Invalid(message)) => message,
+            result => panic!("expected an error, got {:?}", result),
+        }
+    };
+    assert_eq!(error("SELECT json('{\"a\":')"), "malformed JSON");
+    assert_eq!(
+        error("SELECT * FROM json_each('[]', 'a')"),
+        "bad JSON path: 'a'"
+    );
+}